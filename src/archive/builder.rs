@@ -1,33 +1,185 @@
+use crate::archive::disk::{self, ChildKind, DiskMetadata, DiskSource};
 use crate::archive::header::{
-    unix_to_filetime, ArchiveHeader, FileEntry, FolderInfo,
+    system_time_to_filetime, unix_mode_to_attributes, unix_to_filetime, ArchiveHeader, FileEntry,
+    FolderCodec, FolderEncryption, FolderInfo, SubStreamInfo, ENCODED_HEADER_THRESHOLD, S_IFLNK,
 };
+use crate::archive::options::EntryOptions;
+use crate::archive::path::{check_name, NonUtf8Names, PathNormalization};
+use crate::archive::pattern::ExcludePattern;
+use crate::archive::progress::{ArchiveReport, FinishStats, ProgressCallback, ProgressTracker};
+use crate::archive::reader::SevenZipReader;
+use crate::archive::scan::{ScanEntry, ScanReport};
 use crate::archive::writer::{write_signature_header, SIGNATURE_HEADER_SIZE};
-use crate::compression::lzma2::{encode_properties_byte, Lzma2Config, LZMA2_END_MARKER};
+use crate::compression::block::{
+    check_blocks_crc, combined_crc, count_crc_bytes, crc32_combine, repack_blocks,
+    split_owned_into_blocks, CompressedBlock, RawBlock,
+};
+#[cfg(feature = "bzip2")]
+use crate::compression::bzip2::StreamJoiner;
+use crate::compression::cdc::split_content_defined;
+use crate::compression::filter::{apply_filters, validate_filters};
+use crate::compression::lzma2::{
+    compress_block, encode_properties_byte, write_uncompressed_chunks, BlockPolicy, Codec,
+    Lzma2Config, LZMA2_END_MARKER,
+};
+use crate::encryption::{encrypted_size, AesEncryptConfig, AesEncryptor};
 use crate::error::{Result, SevenZipError};
-use crate::compression::block::RawBlock;
-use crate::threading::scheduler::compress_blocks_parallel;
-use std::io::{Read, Seek, SeekFrom, Write};
+use crate::io::seek::CrcWriter;
+use crate::io::spool::SpoolWriter;
+use crate::io::volume::VolumeWriter;
+use crate::recovery::{RecoveryEncoder, RecoveryTee};
+use crate::threading::scheduler::{
+    build_pool_or_fallback, compress_blocks_parallel_results_in, compress_blocks_sequential,
+    compress_blocks_streaming_in, effective_num_threads, validate_max_in_flight,
+    validate_num_threads,
+};
+use rayon::ThreadPool;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default capacity of the buffer in front of the output (64 KiB); see
+/// [`SevenZipWriter::set_write_buffer_size`].
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 64 << 10;
+
+/// Default archive name of the entry holding the archive comment; see
+/// [`SevenZipWriter::set_comment`].
+pub const DEFAULT_COMMENT_NAME: &str = "[Content_Comment].txt";
+
+/// Source of the current time; see [`SevenZipWriter::set_clock`].
+pub type Clock = Box<dyn Fn() -> SystemTime + Send + Sync>;
 
 /// Metadata for a non-empty file, separated from its raw data so the data
 /// can be moved into RawBlocks without cloning.
 struct FileMeta {
     name: String,
     mtime: Option<u64>,
+    attributes: Option<u32>,
     uncompressed_size: u64,
-    crc: u32,
+    /// `None` when the file was read without hashing: its CRC is then
+    /// combined from those of its blocks once they are compressed, unless
+    /// CRCs are not stored at all.
+    crc: Option<u32>,
+    /// SHA-256 of the data, when `set_compute_sha256` is enabled.
+    sha256: Option<[u8; 32]>,
     /// Number of compressed blocks belonging to this file.
     block_count: usize,
 }
 
+/// How an entry's data is read into blocks.
+#[derive(Clone, Copy)]
+struct ReadOptions {
+    block_size: usize,
+    /// Entries of known size up to this many bytes are kept in one block.
+    intra_file_solid_limit: u64,
+    /// Hashes the data for the CRC32 stored in the header.
+    store_crc: bool,
+    compute_sha256: bool,
+    /// Leaves the CRC of disk files to the compression threads, which hash
+    /// every block anyway. Only valid when the file's data makes up its
+    /// folder unchanged: outside solid mode, without filters.
+    defer_crc: bool,
+}
+
+impl ReadOptions {
+    /// Block size for an entry of `size` bytes.
+    fn block_size_for(&self, size: u64) -> usize {
+        if size <= self.intra_file_solid_limit {
+            usize::MAX
+        } else {
+            self.block_size
+        }
+    }
+}
+
+/// Hashes an entry's data as it is read: its CRC32 and its SHA-256, each
+/// when requested.
+struct ContentHasher {
+    crc: Option<crc32fast::Hasher>,
+    sha256: Option<Sha256>,
+}
+
+impl ContentHasher {
+    fn new(compute_crc: bool, compute_sha256: bool) -> Self {
+        Self {
+            crc: compute_crc.then(crc32fast::Hasher::new),
+            sha256: compute_sha256.then(Sha256::new),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        if let Some(crc) = self.crc.as_mut() {
+            count_crc_bytes(data.len());
+            crc.update(data);
+        }
+        if let Some(sha256) = self.sha256.as_mut() {
+            sha256.update(data);
+        }
+    }
+
+    fn finalize(self) -> (Option<u32>, Option<[u8; 32]>) {
+        (
+            self.crc.map(crc32fast::Hasher::finalize),
+            self.sha256.map(|sha256| sha256.finalize().into()),
+        )
+    }
+}
+
+/// Files sharing one folder: a range into the file metadata list, the
+/// number of compressed blocks making up the folder's packed stream, and
+/// the index of the folder's configuration.
+struct FolderPlan {
+    files: std::ops::Range<usize>,
+    block_count: usize,
+    config: usize,
+}
+
+/// An entry without data (empty file or directory); gets no folder.
+struct EmptyEntry {
+    name: String,
+    mtime: Option<u64>,
+    attributes: Option<u32>,
+    is_directory: bool,
+}
+
+/// Entries read by step 1 of `finish()`, in archive order.
+#[derive(Default)]
+struct ReadEntries {
+    file_metas: Vec<FileMeta>,
+    raw_blocks: Vec<RawBlock>,
+    empty_entries: Vec<EmptyEntry>,
+}
+
 /// Input entry queued for inclusion in the archive.
 enum PendingEntry {
     File {
-        disk_path: std::path::PathBuf,
+        source: DiskSource,
         archive_name: String,
+        /// Stored instead of the file's own modification time.
+        mtime: Option<u64>,
+        options: Option<EntryOptions>,
     },
     Bytes {
         archive_name: String,
         data: Vec<u8>,
+        mtime: Option<u64>,
+        attributes: Option<u32>,
+        options: Option<EntryOptions>,
+    },
+    Reader {
+        archive_name: String,
+        reader: Box<dyn Read>,
+        expected_crc: Option<u32>,
+    },
+    Directory {
+        archive_name: String,
+        mtime: Option<u64>,
+        attributes: Option<u32>,
     },
 }
 
@@ -45,23 +197,133 @@ enum PendingEntry {
 /// ```
 pub struct SevenZipWriter<W: Write + Seek> {
     writer: W,
+    /// Stream position of the SignatureHeader. Non-zero when the archive is
+    /// embedded after other data in the output.
+    base_offset: u64,
     entries: Vec<PendingEntry>,
     config: Lzma2Config,
     num_threads: Option<usize>,
+    /// Caller's pool, used instead of building one per archive.
+    thread_pool: Option<Arc<ThreadPool>>,
+    sequential_fallback: bool,
+    max_blocks_in_flight: Option<usize>,
+    solid: bool,
+    solid_block_limit: Option<u64>,
+    encryption: Option<AesEncryptConfig>,
+    recovery_percent: u8,
+    compress_header: Option<bool>,
+    /// Re-reads and checks the output after `finish()`; only settable when
+    /// the writer is also readable.
+    validator: Option<OutputValidator<W>>,
+    /// Re-reads every folder after `finish()`; same constraint as above.
+    folder_verifier: Option<OutputValidator<W>>,
+    progress: Option<ProgressCallback>,
+    cancel: Option<Arc<AtomicBool>>,
+    reproducible: bool,
+    clock: Option<Clock>,
+    /// Header of the archive being appended to (see `open_append`).
+    existing: Option<ArchiveHeader>,
+    exclude: Vec<ExcludePattern>,
+    path_normalization: PathNormalization,
+    /// Normalized names of all queued entries, and of the existing entries
+    /// when appending.
+    names: HashSet<String>,
+    allow_duplicates: bool,
+    strict_names: bool,
+    /// Names queued despite a reserved Windows device name component.
+    name_warnings: Vec<String>,
+    streaming_output: bool,
+    compute_sha256: bool,
+    intra_file_solid_limit: u64,
+    auto_dict: bool,
+    pack_crc: bool,
+    store_crc: bool,
+    paranoid: bool,
+    skip_errors: bool,
+    error_on_empty: bool,
+    /// Archive comment and the name of the entry it is stored in.
+    comment: Option<String>,
+    comment_name: String,
+    write_buffer_size: usize,
+    /// Forces the output to stable storage at the end of `finish()`; only
+    /// settable when the writer is a `File`.
+    sync_on_finish: Option<OutputSync<W>>,
+}
+
+/// Checks the archive written at a base offset, given the expected number
+/// of entries.
+type OutputValidator<W> = fn(&mut W, u64, usize) -> Result<()>;
+
+/// Forces the written archive to stable storage.
+type OutputSync<W> = fn(&mut W) -> std::io::Result<()>;
+
+impl PendingEntry {
+    fn options(&self) -> Option<&EntryOptions> {
+        match self {
+            PendingEntry::File { options, .. } | PendingEntry::Bytes { options, .. } => {
+                options.as_ref()
+            }
+            PendingEntry::Reader { .. } | PendingEntry::Directory { .. } => None,
+        }
+    }
 }
 
 impl<W: Write + Seek> SevenZipWriter<W> {
     /// Creates a new archive writer. Writes a 32-byte placeholder for the SignatureHeader.
+    ///
+    /// The archive starts at the writer's current position, so it can be
+    /// embedded after existing data; all header offsets are relative to it.
     pub fn new(mut writer: W) -> Result<Self> {
+        let base_offset = writer.stream_position()?;
+
         // Write 32 zero bytes as placeholder for the SignatureHeader
         writer.write_all(&[0u8; 32])?;
 
-        Ok(Self {
+        Ok(Self::with_writer(writer, base_offset))
+    }
+
+    fn with_writer(writer: W, base_offset: u64) -> Self {
+        Self {
             writer,
+            base_offset,
             entries: Vec::new(),
             config: Lzma2Config::default(),
             num_threads: None,
-        })
+            thread_pool: None,
+            sequential_fallback: true,
+            max_blocks_in_flight: None,
+            solid: false,
+            solid_block_limit: None,
+            encryption: None,
+            recovery_percent: 0,
+            compress_header: None,
+            validator: None,
+            folder_verifier: None,
+            progress: None,
+            cancel: None,
+            reproducible: false,
+            clock: None,
+            existing: None,
+            exclude: Vec::new(),
+            path_normalization: PathNormalization::default(),
+            names: HashSet::new(),
+            allow_duplicates: false,
+            strict_names: false,
+            name_warnings: Vec::new(),
+            streaming_output: false,
+            compute_sha256: false,
+            intra_file_solid_limit: 0,
+            auto_dict: false,
+            pack_crc: false,
+            store_crc: true,
+            paranoid: false,
+            skip_errors: false,
+            error_on_empty: false,
+            comment: None,
+            comment_name: DEFAULT_COMMENT_NAME.to_string(),
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            sync_on_finish: None,
+        }
     }
 
     /// Sets the LZMA2 compression configuration.
@@ -71,269 +333,1752 @@ impl<W: Write + Seek> SevenZipWriter<W> {
 
     /// Sets the number of threads for parallel compression.
     /// If `None` (the default), uses the number of available logical CPUs.
+    /// `Some(0)` is rejected by `finish()` with `SevenZipError::InvalidState`.
     pub fn set_num_threads(&mut self, num_threads: Option<usize>) {
         self.num_threads = num_threads;
     }
 
-    /// Queues a file from disk for inclusion in the archive.
-    pub fn add_file(&mut self, disk_path: &str, archive_name: &str) -> Result<()> {
-        let path = std::path::Path::new(disk_path);
-        if !path.exists() {
-            return Err(SevenZipError::FileNotFound(disk_path.to_string()));
+    /// Compresses on the caller's rayon pool instead of building a pool for
+    /// each archive. The pool's size then takes the place of
+    /// `set_num_threads`.
+    pub fn set_thread_pool(&mut self, pool: &Arc<ThreadPool>) {
+        self.thread_pool = Some(Arc::clone(pool));
+    }
+
+    /// Whether `finish()` compresses on the calling thread when it cannot
+    /// build its thread pool, e.g. in a sandbox that forbids spawning
+    /// threads (default: `true`). When `false`, it fails with
+    /// `SevenZipError::Threading` instead.
+    pub fn set_allow_sequential_fallback(&mut self, allow: bool) {
+        self.sequential_fallback = allow;
+    }
+
+    /// Compresses at most `max` blocks at once, whatever the number of
+    /// threads, to bound peak memory: each block in flight holds an
+    /// encoder's state and output buffer (see
+    /// [`estimated_memory_per_thread`](Lzma2Config::estimated_memory_per_thread)).
+    /// Block sizes do not depend on it, so the archive is the same as
+    /// without a cap. Unbounded by default; `0` is rejected by `finish()`
+    /// with `SevenZipError::InvalidState`.
+    pub fn set_max_blocks_in_flight(&mut self, max: usize) {
+        self.max_blocks_in_flight = Some(max);
+    }
+
+    /// Enables solid mode: all non-empty files are packed into a single
+    /// folder instead of one folder per file.
+    ///
+    /// File data is concatenated and re-split into blocks across file
+    /// boundaries, so small files share an LZMA2 dictionary. This greatly
+    /// improves the ratio for many small or similar files, at the cost of
+    /// having to decompress preceding files to extract a later one.
+    ///
+    /// The dictionary is never reset between files, only between blocks
+    /// (which are compressed in parallel). A later file can therefore match
+    /// against earlier ones as long as both fall in the same block; raise
+    /// `Lzma2Config::block_size` to widen that window.
+    pub fn set_solid(&mut self, solid: bool) {
+        self.solid = solid;
+    }
+
+    /// Caps the uncompressed size of each solid folder (default: one folder
+    /// for everything). In solid mode, a new folder is started whenever the
+    /// next file would take the current one past `limit`, so extracting a
+    /// single file decompresses at most about `limit` bytes of other files.
+    ///
+    /// Files are never split across folders: a file larger than `limit`
+    /// gets a folder of its own. Has no effect outside solid mode.
+    pub fn set_solid_block_limit(&mut self, limit: u64) {
+        self.solid_block_limit = Some(limit);
+    }
+
+    /// Encrypts file data with AES-256, using 7-Zip's password-based key
+    /// derivation. File names and the rest of the header stay readable.
+    pub fn set_encryption(&mut self, config: AesEncryptConfig) {
+        self.encryption = Some(config);
+    }
+
+    /// Appends a Reed-Solomon recovery record after the archive, sized at
+    /// `percent`% of the archive (default 0, no record). Values above 100
+    /// are rejected by `finish()`.
+    ///
+    /// 7-Zip ignores the trailing record; [`repair`](crate::repair) uses it
+    /// to rebuild damaged parts of the archive.
+    pub fn set_recovery_percent(&mut self, percent: u8) {
+        self.recovery_percent = percent;
+    }
+
+    /// Controls LZMA2 compression of the archive header itself, stored
+    /// behind a `kEncodedHeader` record as 7-Zip does.
+    ///
+    /// By default, headers over 1 KiB are compressed when that makes them
+    /// smaller. `true` always compresses the header, `false` never does.
+    pub fn set_compress_header(&mut self, compress: bool) {
+        self.compress_header = Some(compress);
+    }
+
+    /// Sets a callback receiving [`Progress`](crate::Progress) updates during
+    /// `finish()`: after each block is compressed (from the worker threads)
+    /// and after each folder's files are written.
+    pub fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.progress = Some(callback);
+    }
+
+    /// Sets a flag that cancels `finish()` when it becomes `true`. It is
+    /// checked before each input entry is read, each block is compressed and
+    /// each folder is written; `finish()` then returns
+    /// `SevenZipError::Cancelled`.
+    ///
+    /// The output is left holding a best-effort partial archive, which the
+    /// caller should delete.
+    pub fn set_cancel_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.cancel = Some(flag);
+    }
+
+    /// Enables reproducible output: the same inputs, added in the same
+    /// order, always produce a byte-identical archive (default: false).
+    ///
+    /// Modification times are not stored (no kMTime property), and
+    /// encryption uses no salt and derives IVs from the key and each
+    /// folder's content instead of drawing them at random (see [`AesEncryptor::new_deterministic`]).
+    /// This takes precedence over `SOURCE_DATE_EPOCH` (see `finish`).
+    pub fn set_reproducible(&mut self, reproducible: bool) {
+        self.reproducible = reproducible;
+    }
+
+    /// Stamps entries that have no modification time of their own (data
+    /// added from memory or a reader, directories) with the time `clock`
+    /// returns, read once by `finish()`. By default there is no clock and
+    /// such entries are stored without one; pass
+    /// `Box::new(SystemTime::now)` for the real time, or a fixed time to
+    /// make tests deterministic.
+    ///
+    /// Reproducible mode and `SOURCE_DATE_EPOCH` apply on top of it.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = Some(clock);
+    }
+
+    /// Writes each folder as soon as its blocks are compressed, instead of
+    /// compressing every block before writing anything (default: false).
+    ///
+    /// Compression runs ahead of the writer by at most two blocks per
+    /// thread, so compressed blocks no longer all live in memory at once;
+    /// only the blocks of the folder being written are held together. The
+    /// archive bytes are identical to the default mode.
+    pub fn set_streaming_output(&mut self, streaming: bool) {
+        self.streaming_output = streaming;
+    }
+
+    /// Also computes the SHA-256 of every file's data, reported in
+    /// `FinishStats::sha256` (default: false).
+    ///
+    /// The hash is computed while the data is read, alongside its CRC32,
+    /// and is not stored in the archive: 7z only records CRC32s.
+    pub fn set_compute_sha256(&mut self, compute: bool) {
+        self.compute_sha256 = compute;
+    }
+
+    /// Keeps files of up to `limit` bytes in a single block (default 0:
+    /// files are split into blocks of `Lzma2Config::block_size`).
+    ///
+    /// Each block is an independent LZMA2 stream whose dictionary starts
+    /// empty, so splitting a file loses the matches that would cross block
+    /// boundaries. A file kept whole compresses as well as it would with
+    /// single-threaded 7-Zip, but on one thread: below the limit, ratio is
+    /// traded for speed. Streams added with `add_reader` have no known
+    /// size and are always split. Solid archives re-split data across file
+    /// boundaries and ignore this setting.
+    pub fn set_intra_file_solid_limit(&mut self, limit: u64) {
+        self.intra_file_solid_limit = limit;
+    }
+
+    /// Shrinks the dictionary to fit the data (default: false).
+    ///
+    /// Each block is compressed on its own, so a dictionary larger than
+    /// the largest block only costs encoder memory. With this set, each
+    /// configuration's LZMA2 or LZMA dictionary is reduced to
+    /// [`Lzma2Config::dict_size_for`] its largest block (at most its
+    /// largest file, or folder with LZMA), and the coder properties in the
+    /// header record the smaller size. Block sizes are chosen first, so
+    /// they do not change.
+    pub fn set_auto_dict(&mut self, auto_dict: bool) {
+        self.auto_dict = auto_dict;
+    }
+
+    /// Records the CRC32 of each folder's packed stream in the header
+    /// (default: false), as 7z's PackInfo allows alongside the CRCs of the
+    /// uncompressed data. A damaged compressed byte can then be found with
+    /// [`SevenZipReader::verify_pack_crcs`](crate::SevenZipReader::verify_pack_crcs)
+    /// without decompressing anything. Costs 4 header bytes per folder.
+    pub fn set_pack_crc(&mut self, pack_crc: bool) {
+        self.pack_crc = pack_crc;
+    }
+
+    /// Computes the CRC32 of every file and records it in the header
+    /// (default: true). Without CRCs, no data is hashed, neither while
+    /// reading nor by the compression threads, and the header leaves out
+    /// the kCRC records of the files and folders, so extraction can no
+    /// longer detect corrupt data; 7-Zip and
+    /// [`SevenZipReader`](crate::SevenZipReader) extract such archives
+    /// without checking them. [`set_paranoid`](Self::set_paranoid) then has
+    /// nothing to check the blocks against. Entries added with an expected
+    /// CRC are still checked against it.
+    ///
+    /// Packed stream CRCs ([`set_pack_crc`](Self::set_pack_crc)) and the
+    /// header's own CRC are not affected.
+    pub fn set_store_crc(&mut self, store_crc: bool) {
+        self.store_crc = store_crc;
+    }
+
+    /// Skips files that cannot be read during `finish()` instead of failing
+    /// (default: false), e.g. for best-effort backups of large trees.
+    ///
+    /// A queued file that has vanished (`SevenZipError::FileVanished`),
+    /// cannot be opened or fails to read is left out of the archive and
+    /// reported with its error in `FinishStats::skipped`; any other error
+    /// still fails `finish()`.
+    pub fn set_skip_errors(&mut self, skip: bool) {
+        self.skip_errors = skip;
+    }
+
+    /// Makes `finish()` fail with `SevenZipError::InvalidState` when the
+    /// archive would list no entries, instead of writing an empty archive
+    /// (default: false).
+    ///
+    /// An archive comment counts as an entry, and so do the entries kept
+    /// when appending.
+    pub fn set_error_on_empty(&mut self, error: bool) {
+        self.error_on_empty = error;
+    }
+
+    /// Sets the capacity of the buffer `finish()` writes the archive
+    /// through (default [`DEFAULT_WRITE_BUFFER_SIZE`]); `0` writes straight
+    /// to the output.
+    ///
+    /// Small writes, such as block boundaries and header fields, are
+    /// gathered into fewer calls on the output, which matters for a raw
+    /// `File`. Writes larger than the buffer bypass it. The output is the
+    /// same with any size.
+    pub fn set_write_buffer_size(&mut self, size: usize) {
+        self.write_buffer_size = size;
+    }
+
+    /// Cross-checks every folder's compressed blocks against the data read:
+    /// the per-block CRCs, combined in write order, must equal the CRC of
+    /// the folder's files, or `finish()` fails with
+    /// `SevenZipError::InvalidState` (default: only in debug builds).
+    ///
+    /// This guards against blocks being reordered or lost on their way
+    /// through the thread pool. It only combines CRCs already computed, so
+    /// it costs almost nothing. Folders with filters are not checked, as
+    /// their blocks hold filtered data.
+    ///
+    /// Outside solid mode, the CRC of a disk file is normally combined from
+    /// those of its blocks, computed by the compression threads, instead of
+    /// in a serial pass while reading. Paranoid mode keeps the read-time
+    /// CRC so that the check stays independent of the blocks.
+    pub fn set_paranoid(&mut self, paranoid: bool) {
+        self.paranoid = paranoid;
+    }
+
+    /// Sets how archive names are normalized (see [`PathNormalization`]).
+    /// Names are normalized when entries are queued, so this applies to
+    /// entries added afterwards.
+    pub fn set_path_normalization(&mut self, normalization: PathNormalization) {
+        self.path_normalization = normalization;
+    }
+
+    /// Removes `n` leading components from archive names, as with tar's
+    /// `--strip-components` (default: 0), e.g. `/home/me/project/src/x.rs`
+    /// becomes `src/x.rs` with `n = 3`.
+    ///
+    /// Like the rest of [`PathNormalization`], this applies to entries
+    /// queued afterwards. Queueing a name with `n` components or fewer fails
+    /// with `SevenZipError::StrippedAway`; `add_path_recursive` skips such
+    /// entries instead.
+    pub fn set_path_strip_components(&mut self, n: usize) {
+        self.path_normalization.strip_components = n;
+    }
+
+    /// Sets how file names found on disk by `add_path_recursive` that are
+    /// not valid UTF-8 become archive names (default:
+    /// [`NonUtf8Names::Reject`]); see [`PathNormalization::non_utf8_names`].
+    pub fn set_non_utf8_names(&mut self, policy: NonUtf8Names) {
+        self.path_normalization.non_utf8_names = policy;
+    }
+
+    /// Allows several entries with the same archive name (default: false).
+    ///
+    /// By default, queueing a name that is already taken fails with
+    /// `SevenZipError::DuplicateEntry`, since extractors disagree on which
+    /// of the entries wins.
+    pub fn set_allow_duplicates(&mut self, allow: bool) {
+        self.allow_duplicates = allow;
+    }
+
+    /// Rejects archive names using a reserved Windows device name, such as
+    /// `CON` or `dir/aux.txt`, instead of accepting them with a warning
+    /// (default: false).
+    ///
+    /// Such entries cannot be extracted under their name on Windows. Names
+    /// containing control characters are always rejected with
+    /// `SevenZipError::InvalidState`; with strict names, so are reserved
+    /// ones. Otherwise they are queued and listed by
+    /// [`name_warnings`](Self::name_warnings).
+    pub fn set_strict_names(&mut self, strict: bool) {
+        self.strict_names = strict;
+    }
+
+    /// Warnings about queued names that use a reserved Windows device name,
+    /// when strict names are off.
+    pub fn name_warnings(&self) -> &[String] {
+        &self.name_warnings
+    }
+
+    /// Stores `text` as the archive comment.
+    ///
+    /// The 7z format has no archive comment that 7-Zip reads or writes, so
+    /// the comment is stored as an ordinary text entry, named
+    /// [`DEFAULT_COMMENT_NAME`] unless changed with
+    /// [`set_comment_name`](Self::set_comment_name), and every extractor
+    /// lists and extracts it like any other file. It is added by
+    /// `finish()`, after the queued entries; calling this again replaces
+    /// the text.
+    pub fn set_comment(&mut self, text: &str) {
+        self.comment = Some(text.to_string());
+    }
+
+    /// Sets the archive name of the entry [`set_comment`](Self::set_comment)
+    /// stores the comment in. The name is checked by `finish()` like any
+    /// other, so it must not clash with a queued entry.
+    pub fn set_comment_name(&mut self, name: &str) {
+        self.comment_name = name.to_string();
+    }
+
+    /// Normalizes an archive name and claims it for a new entry.
+    fn queue_name(&mut self, archive_name: &str) -> Result<String> {
+        let name = self.path_normalization.normalize(archive_name)?;
+        self.claim_name(&name)?;
+        Ok(name)
+    }
+
+    /// Checks a normalized name and records it, failing if it is invalid,
+    /// or already taken and duplicates are not allowed.
+    fn claim_name(&mut self, name: &str) -> Result<()> {
+        let reserved = check_name(name)?.map(|reserved| {
+            format!("archive name '{name}' uses the reserved Windows device name '{reserved}'")
+        });
+        if self.strict_names {
+            if let Some(message) = reserved {
+                return Err(SevenZipError::InvalidState(message));
+            }
+        }
+        if !self.names.insert(name.to_string()) && !self.allow_duplicates {
+            return Err(SevenZipError::DuplicateEntry(name.to_string()));
         }
+        // Only warned about once the entry is sure to be queued
+        self.name_warnings.extend(reserved);
+        Ok(())
+    }
+
+    /// Queues a file from disk for inclusion in the archive. `disk_path`
+    /// need not be valid UTF-8.
+    pub fn add_file(&mut self, disk_path: impl AsRef<Path>, archive_name: &str) -> Result<()> {
+        self.queue_file(disk_path, archive_name, None, None)
+    }
+
+    /// Like [`add_file`](Self::add_file), storing `mtime` as the entry's
+    /// modification time instead of the file's own. Times before the Unix
+    /// epoch are rejected with `SevenZipError::InvalidState`.
+    pub fn add_file_with_mtime(
+        &mut self,
+        disk_path: impl AsRef<Path>,
+        archive_name: &str,
+        mtime: SystemTime,
+    ) -> Result<()> {
+        let mtime = explicit_mtime(archive_name, mtime)?;
+        self.queue_file(disk_path, archive_name, Some(mtime), None)
+    }
+
+    /// Like [`add_file`](Self::add_file), compressing the file with its own
+    /// codec and filters instead of the archive-wide ones.
+    pub fn add_file_with_options(
+        &mut self,
+        disk_path: impl AsRef<Path>,
+        archive_name: &str,
+        options: EntryOptions,
+    ) -> Result<()> {
+        self.queue_file(disk_path, archive_name, None, Some(options))
+    }
+
+    /// Queues every `(disk_path, archive_name)` pair, in iteration order, as
+    /// with [`add_file`](Self::add_file). Names are checked and errors
+    /// handled as in [`add_entries`](Self::add_entries).
+    pub fn add_files<I>(&mut self, files: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (PathBuf, String)>,
+    {
+        for (disk_path, archive_name) in files {
+            self.queue_file(disk_path, &archive_name, None, None)?;
+        }
+        Ok(())
+    }
+
+    fn queue_file(
+        &mut self,
+        disk_path: impl AsRef<Path>,
+        archive_name: &str,
+        mtime: Option<u64>,
+        options: Option<EntryOptions>,
+    ) -> Result<()> {
+        let source = DiskSource::new(disk_path.as_ref())?;
+        let archive_name = self.queue_name(archive_name)?;
         self.entries.push(PendingEntry::File {
-            disk_path: path.to_path_buf(),
-            archive_name: archive_name.to_string(),
+            source,
+            archive_name,
+            mtime,
+            options,
         });
         Ok(())
     }
 
     /// Queues in-memory data for inclusion in the archive.
     pub fn add_bytes(&mut self, archive_name: &str, data: &[u8]) -> Result<()> {
+        self.add_bytes_owned(archive_name, data.to_vec())
+    }
+
+    /// Like [`add_bytes`](Self::add_bytes), but takes ownership of the data
+    /// instead of copying it.
+    pub fn add_bytes_owned(&mut self, archive_name: &str, data: Vec<u8>) -> Result<()> {
+        self.queue_bytes(archive_name, data, None, None)
+    }
+
+    /// Queues every `(archive_name, data)` pair, in iteration order, as with
+    /// [`add_bytes_owned`](Self::add_bytes_owned): the data is moved, not
+    /// copied.
+    ///
+    /// Names are checked as for single entries, so a name repeated within
+    /// the batch or already queued fails with `SevenZipError::DuplicateEntry`
+    /// unless duplicates are allowed. Queuing stops at the first error; the
+    /// entries before it stay queued.
+    pub fn add_entries<I>(&mut self, entries: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (String, Vec<u8>)>,
+    {
+        for (archive_name, data) in entries {
+            self.queue_bytes(&archive_name, data, None, None)?;
+        }
+        Ok(())
+    }
+
+    /// Like [`add_bytes`](Self::add_bytes), storing `mtime` as the entry's
+    /// modification time (in-memory entries otherwise have none). Times
+    /// before the Unix epoch or past the last FILETIME (year 60056) are
+    /// rejected with `SevenZipError::InvalidState`.
+    pub fn add_bytes_with_mtime(
+        &mut self,
+        archive_name: &str,
+        data: &[u8],
+        mtime: SystemTime,
+    ) -> Result<()> {
+        let mtime = explicit_mtime(archive_name, mtime)?;
+        self.queue_bytes(archive_name, data.to_vec(), Some(mtime), None)
+    }
+
+    /// Like [`add_bytes`](Self::add_bytes), compressing the data with its
+    /// own codec and filters instead of the archive-wide ones.
+    pub fn add_bytes_with_options(
+        &mut self,
+        archive_name: &str,
+        data: &[u8],
+        options: EntryOptions,
+    ) -> Result<()> {
+        self.queue_bytes(archive_name, data.to_vec(), None, Some(options))
+    }
+
+    fn queue_bytes(
+        &mut self,
+        archive_name: &str,
+        data: Vec<u8>,
+        mtime: Option<u64>,
+        options: Option<EntryOptions>,
+    ) -> Result<()> {
+        let archive_name = self.queue_name(archive_name)?;
         self.entries.push(PendingEntry::Bytes {
-            archive_name: archive_name.to_string(),
-            data: data.to_vec(),
+            archive_name,
+            data,
+            mtime,
+            attributes: None,
+            options,
         });
         Ok(())
     }
 
+    /// Queues a symbolic link pointing at `target`.
+    ///
+    /// As in 7-Zip, the target path is stored as the entry's data and the
+    /// entry is marked as a link by the Unix `S_IFLNK` mode in its
+    /// attributes, so extracting on Unix recreates the link.
+    pub fn add_symlink(&mut self, archive_name: &str, target: &str) -> Result<()> {
+        if target.is_empty() {
+            return Err(SevenZipError::InvalidState(format!(
+                "symlink '{archive_name}' has an empty target"
+            )));
+        }
+        let archive_name = self.queue_name(archive_name)?;
+        self.entries.push(PendingEntry::Bytes {
+            archive_name,
+            data: target.as_bytes().to_vec(),
+            mtime: None,
+            attributes: Some(unix_mode_to_attributes(S_IFLNK | 0o777, false)),
+            options: None,
+        });
+        Ok(())
+    }
+
+    /// Queues an empty directory entry.
+    pub fn add_directory(&mut self, archive_name: &str) -> Result<()> {
+        let archive_name = self.queue_name(archive_name)?;
+        self.entries.push(PendingEntry::Directory {
+            archive_name,
+            mtime: None,
+            attributes: None,
+        });
+        Ok(())
+    }
+
+    /// Recursively queues a directory tree from disk.
+    ///
+    /// Every subdirectory becomes a directory entry and every regular file is
+    /// queued as with `add_file`. Archive names are `archive_prefix` joined
+    /// with the path relative to `root`, using forward slashes; a non-empty
+    /// prefix also gets its own directory entry. Entries are visited in
+    /// name order so the archive layout is stable across runs.
+    ///
+    /// Symlinks are stored as links (see `add_symlink`), never followed.
+    /// Special files (sockets, FIFOs, ...) and files or links whose name is
+    /// stripped away entirely (see `set_path_strip_components`) are
+    /// skipped; their paths are returned so the caller can report them.
+    /// Directories stripped away get no entry, but their contents are still
+    /// added. Entries matching an exclude pattern (see
+    /// `set_exclude_patterns`) are left out silently. File names and link
+    /// targets that are not valid UTF-8 are converted as set with
+    /// `set_non_utf8_names`.
+    pub fn add_path_recursive(
+        &mut self,
+        root: &std::path::Path,
+        archive_prefix: &str,
+    ) -> Result<Vec<std::path::PathBuf>> {
+        let metadata = disk::metadata(root)
+            .map_err(|_| SevenZipError::FileNotFound(root.display().to_string()))?;
+        if !metadata.is_directory {
+            return Err(SevenZipError::InvalidState(format!(
+                "not a directory: {}",
+                root.display()
+            )));
+        }
+
+        // Names are built from the prefix as given and normalized whole,
+        // so leading components are stripped only once.
+        let prefix = if archive_prefix.trim_matches('/').is_empty() {
+            ""
+        } else {
+            archive_prefix.trim_end_matches('/')
+        };
+        if !prefix.is_empty() {
+            if let Some(archive_name) = self.normalize_or_strip(prefix)? {
+                self.claim_name(&archive_name)?;
+                self.entries.push(PendingEntry::Directory {
+                    archive_name,
+                    mtime: metadata.mtime,
+                    attributes: metadata.attributes,
+                });
+            }
+        }
+
+        let mut skipped = Vec::new();
+        self.walk_directory(root, prefix, "", &mut skipped)?;
+        Ok(skipped)
+    }
+
+    /// Normalizes an archive name, or returns `None` for a name that is
+    /// stripped away entirely.
+    fn normalize_or_strip(&self, name: &str) -> Result<Option<String>> {
+        match self.path_normalization.normalize(name) {
+            Ok(name) => Ok(Some(name)),
+            Err(SevenZipError::StrippedAway(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn walk_directory(
+        &mut self,
+        dir: &std::path::Path,
+        prefix: &str,
+        relative: &str,
+        skipped: &mut Vec<std::path::PathBuf>,
+    ) -> Result<()> {
+        for child in disk::read_dir_sorted(dir)? {
+            let file_name = self.path_normalization.name_from_os(&child.file_name)?;
+            let relative_name = if relative.is_empty() {
+                file_name
+            } else {
+                format!("{relative}/{file_name}")
+            };
+            let full_name = if prefix.is_empty() {
+                relative_name.clone()
+            } else {
+                format!("{prefix}/{relative_name}")
+            };
+            let archive_name = self.normalize_or_strip(&full_name)?;
+
+            let is_directory = child.kind == ChildKind::Directory;
+            let is_excluded = self
+                .exclude
+                .iter()
+                .any(|p| p.matches(&relative_name, is_directory));
+            if is_excluded {
+                // Excluded directories are not descended into
+                continue;
+            }
+            if is_directory {
+                if let Some(archive_name) = archive_name {
+                    self.claim_name(&archive_name)?;
+                    let metadata = child.metadata()?;
+                    self.entries.push(PendingEntry::Directory {
+                        archive_name,
+                        mtime: metadata.mtime,
+                        attributes: metadata.attributes,
+                    });
+                }
+                self.walk_directory(&child.path, prefix, &relative_name, skipped)?;
+                continue;
+            }
+            let Some(archive_name) = archive_name else {
+                skipped.push(child.path);
+                continue;
+            };
+            match child.kind {
+                ChildKind::Symlink => {
+                    let target = child.link_target()?;
+                    let target = self.path_normalization.name_from_os(target.as_os_str())?;
+                    self.add_symlink(&full_name, &target)?;
+                }
+                ChildKind::File => {
+                    self.claim_name(&archive_name)?;
+                    self.entries.push(PendingEntry::File {
+                        source: DiskSource::existing(child.path),
+                        archive_name,
+                        mtime: None,
+                        options: None,
+                    });
+                }
+                ChildKind::Directory | ChildKind::Other => skipped.push(child.path),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets gitignore-style patterns excluding entries from
+    /// `add_path_recursive` (default: none), e.g. `*.tmp`, `target/` or
+    /// `docs/**/*.pdf`. Patterns are matched against paths relative to the
+    /// directory being added; see [`ExcludePattern`] for the syntax.
+    /// Excluded directories are skipped without being read.
+    pub fn set_exclude_patterns(&mut self, patterns: Vec<String>) {
+        self.exclude = patterns.iter().map(|p| ExcludePattern::new(p)).collect();
+    }
+
+    /// Queues a stream of unknown length, such as a socket or a pipe.
+    ///
+    /// The reader is only consumed during `finish()`, where it is read until
+    /// EOF straight into blocks, with its size and CRC32 computed on the fly;
+    /// it is never buffered as a whole.
+    pub fn add_reader(&mut self, archive_name: &str, reader: Box<dyn Read>) -> Result<()> {
+        let archive_name = self.queue_name(archive_name)?;
+        self.entries.push(PendingEntry::Reader {
+            archive_name,
+            reader,
+            expected_crc: None,
+        });
+        Ok(())
+    }
+
+    /// Queues a reader whose content must match `expected_crc`.
+    ///
+    /// The reader is streamed into blocks during `finish()` and its CRC32 is
+    /// computed incrementally. If the final CRC differs from `expected_crc`,
+    /// `finish()` fails with `SevenZipError::CrcMismatch` before any data is
+    /// written for this entry.
+    pub fn add_reader_expecting_crc(
+        &mut self,
+        archive_name: &str,
+        reader: Box<dyn Read>,
+        expected_crc: u32,
+    ) -> Result<()> {
+        let archive_name = self.queue_name(archive_name)?;
+        self.entries.push(PendingEntry::Reader {
+            archive_name,
+            reader,
+            expected_crc: Some(expected_crc),
+        });
+        Ok(())
+    }
+
+    /// Resolves all queued entries into a manifest without reading file
+    /// contents or compressing anything. Disk files are stat'ed for their
+    /// current size, and flagged when sparse since 7z stores holes as zeros.
+    pub fn scan(&self) -> Result<ScanReport> {
+        let mut report = ScanReport::default();
+        for entry in &self.entries {
+            let scan_entry = match entry {
+                PendingEntry::File {
+                    source,
+                    archive_name,
+                    ..
+                } => {
+                    let metadata = source.metadata().map_err(|_| {
+                        SevenZipError::FileNotFound(source.path().display().to_string())
+                    })?;
+                    ScanEntry {
+                        archive_name: archive_name.clone(),
+                        disk_path: Some(source.path().to_path_buf()),
+                        size: Some(metadata.size),
+                        is_directory: false,
+                        is_sparse: metadata.is_sparse,
+                    }
+                }
+                PendingEntry::Bytes {
+                    archive_name, data, ..
+                } => ScanEntry {
+                    archive_name: archive_name.clone(),
+                    disk_path: None,
+                    size: Some(data.len() as u64),
+                    is_directory: false,
+                    is_sparse: false,
+                },
+                PendingEntry::Reader { archive_name, .. } => ScanEntry {
+                    archive_name: archive_name.clone(),
+                    disk_path: None,
+                    size: None,
+                    is_directory: false,
+                    is_sparse: false,
+                },
+                PendingEntry::Directory { archive_name, .. } => ScanEntry {
+                    archive_name: archive_name.clone(),
+                    disk_path: None,
+                    size: Some(0),
+                    is_directory: true,
+                    is_sparse: false,
+                },
+            };
+            report.push(scan_entry);
+        }
+        Ok(report)
+    }
+
+    /// Estimates the peak memory `finish()` needs with `num_threads`
+    /// compression threads (`None` = one per logical CPU), in bytes.
+    ///
+    /// All input is read into blocks before compression starts, and each
+    /// block is replaced by its compressed form as it finishes, so the
+    /// queued input size is counted once, plus
+    /// [`estimated_memory_per_thread`](Lzma2Config::estimated_memory_per_thread)
+    /// per thread, or per block in flight if
+    /// [`set_max_blocks_in_flight`](Self::set_max_blocks_in_flight) allows
+    /// fewer. Streams added with `add_reader` have no known size and are
+    /// not counted. Returns `SevenZipError::FileNotFound` for queued files
+    /// that cannot be read.
+    pub fn estimated_peak_memory(&self, num_threads: Option<usize>) -> Result<u64> {
+        let threads =
+            effective_num_threads(num_threads).min(self.max_blocks_in_flight.unwrap_or(usize::MAX));
+        let queued = self.scan()?.total_bytes;
+        Ok(queued + threads as u64 * self.config.estimated_memory_per_thread())
+    }
+
     /// Finalizes the archive: compresses data, writes it, builds and writes the header,
     /// then seeks back to write the real SignatureHeader. Consumes self.
-    pub fn finish(mut self) -> Result<W> {
-        let block_size = self.config.effective_block_size();
-        let mut file_metas: Vec<FileMeta> = Vec::new();
-        let mut raw_blocks: Vec<RawBlock> = Vec::new();
-        let mut empty_files: Vec<(String, Option<u64>)> = Vec::new();
+    ///
+    /// With nothing queued, this writes a valid empty archive: a header
+    /// listing no entries, which 7-Zip tests and lists as 0 files.
+    ///
+    /// If the `SOURCE_DATE_EPOCH` environment variable is set (seconds since
+    /// the Unix epoch), modification times later than it are clamped to it
+    /// and entries without one get it, as is conventional for reproducible
+    /// builds. A malformed value is rejected with
+    /// `SevenZipError::InvalidState`.
+    ///
+    /// Compression and write errors for a folder name its files, as in
+    /// `file 'src/x.rs': ...`, or `files 'a' to 'z': ...` when solid.
+    ///
+    /// Queued disk files are read one at a time on the calling thread, so
+    /// at most one input file is open at once, whatever the thread count.
+    pub fn finish(self) -> Result<W> {
+        self.finish_with_stats().map(|(writer, _)| writer)
+    }
+
+    /// Like [`finish`](Self::finish), also returning the archive's sizes,
+    /// file and folder counts and the time taken. `FinishStats::archive_size`
+    /// gives the archive's length without querying the output.
+    pub fn finish_with_stats(self) -> Result<(W, FinishStats)> {
+        self.finish_with_stats_and_report()
+            .map(|(writer, stats, _)| (writer, stats))
+    }
+
+    /// Like [`finish`](Self::finish), also returning what was written: each
+    /// folder's codec, dictionary size, filters, sizes and CRC, and each
+    /// entry's size, CRC and folder.
+    pub fn finish_with_report(self) -> Result<(W, ArchiveReport)> {
+        self.finish_with_stats_and_report()
+            .map(|(writer, _, report)| (writer, report))
+    }
+
+    fn finish_with_stats_and_report(mut self) -> Result<(W, FinishStats, ArchiveReport)> {
+        let started = Instant::now();
+        if self.error_on_empty
+            && self.entries.is_empty()
+            && self.comment.is_none()
+            && self.existing.as_ref().is_none_or(|h| h.files.is_empty())
+        {
+            return Err(SevenZipError::InvalidState("no entries".to_string()));
+        }
+        if let Some(comment) = self.comment.take() {
+            let comment_name = std::mem::take(&mut self.comment_name);
+            let archive_name = self.queue_name(&comment_name)?;
+            self.entries.push(PendingEntry::Bytes {
+                archive_name,
+                data: comment.into_bytes(),
+                mtime: None,
+                attributes: None,
+                options: None,
+            });
+        }
+        if let Some(existing) = self.existing.as_ref().filter(|_| self.entries.is_empty()) {
+            // Nothing to append: leave the archive untouched
+            let report = ArchiveReport::new(existing);
+            let end = self.writer.seek(SeekFrom::End(0))?;
+            let stats = FinishStats {
+                total_uncompressed: 0,
+                total_compressed: 0,
+                num_files: 0,
+                num_folders: 0,
+                archive_size: end - self.base_offset,
+                wall_time: started.elapsed(),
+                slowest_files: Vec::new(),
+                sha256: Vec::new(),
+                skipped: Vec::new(),
+            };
+            return Ok((self.writer, stats, report));
+        }
+        // Entries with equal options share a configuration; index 0 is the
+        // archive-wide one.
+        let mut entry_options: Vec<&EntryOptions> = Vec::new();
+        let entry_configs: Vec<usize> = self
+            .entries
+            .iter()
+            .map(|entry| match entry.options() {
+                None => 0,
+                Some(options) => {
+                    1 + entry_options
+                        .iter()
+                        .position(|o| *o == options)
+                        .unwrap_or_else(|| {
+                            entry_options.push(options);
+                            entry_options.len() - 1
+                        })
+                }
+            })
+            .collect();
+        let mut configs: Vec<Lzma2Config> = std::iter::once(self.config.clone())
+            .chain(entry_options.iter().map(|o| o.apply(&self.config)))
+            .map(|config| Lzma2Config {
+                block_crc: self.store_crc,
+                ..config
+            })
+            .collect();
+
+        validate_num_threads(self.num_threads)?;
+        validate_max_in_flight(self.max_blocks_in_flight)?;
+        for config in &configs {
+            validate_filters(&config.filters)?;
+            config.validate()?;
+            #[cfg(feature = "bzip2")]
+            if let Codec::Bzip2 { level } = config.codec {
+                crate::compression::bzip2::validate_level(level)?;
+            }
+        }
+        if (self.validator.is_some() || self.folder_verifier.is_some())
+            && (self.encryption.is_some() || configs.iter().any(|c| !c.filters.is_empty()))
+        {
+            return Err(SevenZipError::InvalidState(
+                "output verification cannot read back encrypted or filtered archives".to_string(),
+            ));
+        }
+        if self.existing.is_some() && self.recovery_percent > 0 {
+            return Err(SevenZipError::InvalidState(
+                "a recovery record cannot be added when appending".to_string(),
+            ));
+        }
+        let source_date_epoch = source_date_epoch()?;
+        let now = match self.clock.as_ref() {
+            Some(clock) => Some(system_time_to_filetime(clock()).ok_or_else(|| {
+                SevenZipError::InvalidState(
+                    "clock time is before the Unix epoch or past the FILETIME range".to_string(),
+                )
+            })?),
+            None => None,
+        };
+        // Adaptive block sizes depend on the queued input size and on the
+        // threads compressing it.
+        let adaptive = configs
+            .iter()
+            .any(|c| matches!(c.block_policy, Some(BlockPolicy::Adaptive(_))));
+        let total_size = if adaptive {
+            self.scan()?.total_bytes
+        } else {
+            0
+        };
+        let num_threads = match self.thread_pool.as_deref() {
+            Some(pool) => pool.current_num_threads(),
+            None => effective_num_threads(self.num_threads),
+        };
+        let encryptor = self
+            .encryption
+            .as_ref()
+            .map(|config| {
+                if self.reproducible {
+                    AesEncryptor::new_deterministic(config)
+                } else {
+                    AesEncryptor::new(config)
+                }
+            })
+            .transpose()?;
+        let mut recovery = match self.recovery_percent {
+            0 => None,
+            percent => Some(RecoveryEncoder::new(percent)?),
+        };
+        if let Some(recovery) = recovery.as_mut() {
+            // The SignatureHeader placeholder written by `new()`
+            recovery.update(&[0u8; SIGNATURE_HEADER_SIZE as usize])?;
+        }
+
+        let mut read_entries = ReadEntries::default();
+        // Configuration index of each file in `file_metas`
+        let mut file_configs: Vec<usize> = Vec::new();
+        let mut skipped: Vec<(PathBuf, SevenZipError)> = Vec::new();
 
         // 1. Build RawBlocks from all entries.
         //    - Disk files: read by chunks directly into RawBlocks (never hold
         //      the full file as a single Vec), compute CRC incrementally.
         //    - Memory entries: move or split data (zero-copy for single block).
-        for entry in self.entries {
+        for (entry, config) in self.entries.into_iter().zip(entry_configs) {
+            check_cancelled(self.cancel.as_deref())?;
+            let read_options = ReadOptions {
+                block_size: block_size(&configs[config], total_size, num_threads),
+                intra_file_solid_limit: self.intra_file_solid_limit,
+                store_crc: self.store_crc,
+                compute_sha256: self.compute_sha256,
+                // Paranoid mode checks the blocks against a CRC of its own
+                defer_crc: !self.solid && configs[config].filters.is_empty() && !self.paranoid,
+            };
             match entry {
                 PendingEntry::File {
-                    disk_path,
+                    source,
                     archive_name,
+                    mtime,
+                    ..
                 } => {
-                    Self::read_file_into_blocks(
-                        &disk_path,
+                    let first_block = read_entries.raw_blocks.len();
+                    let read = Self::read_file_into_blocks(
+                        &source,
                         archive_name,
-                        block_size,
-                        &mut file_metas,
-                        &mut raw_blocks,
-                        &mut empty_files,
-                    )?;
+                        mtime,
+                        read_options,
+                        &mut read_entries,
+                    );
+                    if let Err(err) = read {
+                        let io_error = matches!(
+                            err,
+                            SevenZipError::Io(_)
+                                | SevenZipError::FileNotFound(_)
+                                | SevenZipError::FileVanished(_)
+                        );
+                        if !(self.skip_errors && io_error) {
+                            return Err(err);
+                        }
+                        // Drop whatever was read before the error
+                        read_entries.raw_blocks.truncate(first_block);
+                        skipped.push((source.path().to_path_buf(), err));
+                    }
                 }
                 PendingEntry::Bytes {
                     archive_name,
                     data,
+                    mtime,
+                    attributes,
+                    ..
                 } => {
                     Self::split_bytes_into_blocks(
                         archive_name,
                         data,
-                        block_size,
-                        &mut file_metas,
-                        &mut raw_blocks,
-                        &mut empty_files,
+                        mtime,
+                        attributes,
+                        read_options,
+                        &mut read_entries,
                     );
                 }
+                PendingEntry::Reader {
+                    archive_name,
+                    mut reader,
+                    expected_crc,
+                } => {
+                    Self::read_stream_into_blocks(
+                        &mut reader,
+                        archive_name,
+                        expected_crc,
+                        read_options,
+                        &mut read_entries,
+                    )?;
+                }
+                PendingEntry::Directory {
+                    archive_name,
+                    mtime,
+                    attributes,
+                } => {
+                    read_entries.empty_entries.push(EmptyEntry {
+                        name: archive_name,
+                        mtime,
+                        attributes,
+                        is_directory: true,
+                    });
+                }
             }
+            file_configs.resize(read_entries.file_metas.len(), config);
         }
+        let ReadEntries {
+            mut file_metas,
+            mut raw_blocks,
+            empty_entries,
+        } = read_entries;
+
+        // 2. Group files into folders: one per file, or in solid mode one per
+        //    run of consecutive files sharing a configuration (a single
+        //    folder without per-entry options), cut short at the solid block
+        //    limit. Solid blocks are repacked across file boundaries so
+        //    consecutive files share an LZMA2 dictionary within each block.
+        let folder_plans = if self.solid {
+            let limit = self.solid_block_limit.unwrap_or(u64::MAX);
+            let mut runs = Vec::new();
+            let mut start = 0;
+            for run in file_configs.chunk_by(|a, b| a == b) {
+                let mut files = start..start;
+                let mut size = 0u64;
+                for meta in &file_metas[start..start + run.len()] {
+                    if !files.is_empty() && size.saturating_add(meta.uncompressed_size) > limit {
+                        runs.push((files.clone(), run[0]));
+                        files.start = files.end;
+                        size = 0;
+                    }
+                    files.end += 1;
+                    size += meta.uncompressed_size;
+                }
+                start = files.end;
+                runs.push((files, run[0]));
+            }
 
-        // 2. Compress all blocks in parallel using a dedicated thread pool.
-        let compressed_blocks = if raw_blocks.is_empty() {
-            Vec::new()
+            let mut blocks = std::mem::take(&mut raw_blocks).into_iter();
+            let mut plans = Vec::new();
+            for (files, config) in runs {
+                let input_blocks = file_metas[files.clone()]
+                    .iter()
+                    .map(|m| m.block_count)
+                    .sum();
+                let input = blocks.by_ref().take(input_blocks);
+                let repacked = match configs[config].chunk_limits() {
+                    Some(limits) => split_content_defined(input, limits, raw_blocks.len()),
+                    None => repack_blocks(
+                        input,
+                        block_size(&configs[config], total_size, num_threads),
+                        raw_blocks.len(),
+                    ),
+                };
+                plans.push(FolderPlan {
+                    files,
+                    block_count: repacked.len(),
+                    config,
+                });
+                raw_blocks.extend(repacked);
+            }
+            plans
         } else {
-            compress_blocks_parallel(raw_blocks, &self.config, self.num_threads)?
+            //    Content-defined blocks are cut file by file, for files past
+            //    the intra-file solid limit; the blocks after them are
+            //    renumbered.
+            if configs.iter().any(|c| c.chunk_limits().is_some()) {
+                let mut blocks = std::mem::take(&mut raw_blocks).into_iter();
+                for (meta, &config) in file_metas.iter_mut().zip(&file_configs) {
+                    let file_blocks = blocks.by_ref().take(meta.block_count);
+                    let first = raw_blocks.len();
+                    match configs[config].chunk_limits() {
+                        Some(limits) if meta.uncompressed_size > self.intra_file_solid_limit => {
+                            let chunks = split_content_defined(file_blocks, limits, first);
+                            meta.block_count = chunks.len();
+                            raw_blocks.extend(chunks);
+                        }
+                        _ => raw_blocks.extend(file_blocks.enumerate().map(|(i, mut block)| {
+                            block.block_index = first + i;
+                            block
+                        })),
+                    }
+                }
+            }
+            file_metas
+                .iter()
+                .zip(&file_configs)
+                .enumerate()
+                .map(|(i, (meta, &config))| FolderPlan {
+                    files: i..i + 1,
+                    block_count: meta.block_count,
+                    config,
+                })
+                .collect()
         };
 
-        // 3. Write compressed data directly to the output, one file at a time.
+        //    Block sizes are settled, so dictionaries can shrink to the
+        //    largest block each configuration compresses.
+        if self.auto_dict {
+            let mut largest_block = vec![0u64; configs.len()];
+            let mut rest = raw_blocks.as_slice();
+            for plan in &folder_plans {
+                let (folder_blocks, tail) = rest.split_at(plan.block_count);
+                for block in folder_blocks {
+                    let largest = &mut largest_block[plan.config];
+                    *largest = (*largest).max(block.data.len() as u64);
+                }
+                rest = tail;
+            }
+            for (config, largest) in configs.iter_mut().zip(largest_block) {
+                if config.compressor.is_none()
+                    && matches!(config.codec, Codec::Lzma2 | Codec::Lzma1)
+                {
+                    config.dict_size = Some(config.dict_size_for(largest));
+                }
+            }
+        }
+
+        //    Filters run over each folder's blocks in order, carrying their
+        //    state across block boundaries, before blocks are compressed
+        //    independently.
+        let mut rest = raw_blocks.as_mut_slice();
+        for plan in &folder_plans {
+            let (folder_blocks, tail) = rest.split_at_mut(plan.block_count);
+            apply_filters(&configs[plan.config].filters, folder_blocks);
+            rest = tail;
+        }
+
+        // 3. Compress all blocks in parallel using a dedicated thread pool.
+        let progress = ProgressTracker::new(
+            self.progress.as_ref(),
+            file_metas.len(),
+            file_metas.iter().map(|m| m.uncompressed_size).sum(),
+        );
+        //    In streaming mode, folders are written (step 4) while later
+        //    blocks are still being compressed.
+        //
+        // 4. Write compressed data directly to the output, one folder at a time.
         //    Each compressed block is written and immediately dropped (freed).
-        //    For multi-block files, intermediate LZMA2 end markers are stripped
-        //    inline — no concatenation buffer is allocated.
-        let pack_position = 0u64;
-        let mut folders = Vec::new();
-        let mut file_entries = Vec::new();
-        let properties_byte = encode_properties_byte(self.config.effective_dict_size());
-
-        let mut block_iter = compressed_blocks.into_iter();
-
-        for meta in &file_metas {
-            let compressed_size = Self::write_file_blocks(
-                &mut self.writer,
-                &mut block_iter,
-                meta.block_count,
-            )?;
-
-            folders.push(FolderInfo {
-                compressed_size,
-                uncompressed_size: meta.uncompressed_size,
-                uncompressed_crc: meta.crc,
-                lzma2_properties_byte: properties_byte,
-            });
-            file_entries.push(FileEntry {
-                name: meta.name.clone(),
-                uncompressed_size: meta.uncompressed_size,
-                compressed_size,
-                crc: meta.crc,
-                has_data: true,
-                modified_time: meta.mtime,
-            });
+        //    For multi-block folders, intermediate LZMA2 end markers are stripped
+        //    inline — no concatenation buffer is allocated. Everything up to
+        //    the header also feeds the recovery record, if enabled. Seeking
+        //    the buffered output flushes it first.
+        let buffered = BufWriter::with_capacity(self.write_buffer_size, &mut self.writer);
+        let mut out = RecoveryTee::new(buffered, recovery.as_mut());
+        // When appending, existing folders and entries are kept as-is and the
+        // new folders follow their packed streams.
+        let (pack_position, mut folders, mut file_entries) = match self.existing.take() {
+            Some(existing) => (existing.pack_position, existing.folders, existing.files),
+            None => (0, Vec::new(), Vec::new()),
+        };
+        let existing_folders = folders.len();
+        let existing_files = file_entries.len();
+        let mut file_times: Vec<(String, Duration)> = Vec::new();
+        let check_block_crcs = self.paranoid || cfg!(debug_assertions);
+        let pack_crc = self.pack_crc;
+
+        let mut write_folders = |block_iter: &mut dyn Iterator<Item = Result<CompressedBlock>>| {
+            for plan in &folder_plans {
+                check_cancelled(self.cancel.as_deref())?;
+                let metas = &file_metas[plan.files.clone()];
+                let in_folder = |err| in_folder_of(metas, err);
+                let blocks: Vec<CompressedBlock> = block_iter
+                    .take(plan.block_count)
+                    .collect::<Result<_>>()
+                    .map_err(in_folder)?;
+                if blocks.len() != plan.block_count {
+                    return Err(SevenZipError::Compression(
+                        "unexpected end of compressed blocks".to_string(),
+                    ));
+                }
+                // Files read without hashing are alone in their folder. Their
+                // blocks are hashed only when CRCs are stored
+                let crcs: Vec<Option<u32>> = metas
+                    .iter()
+                    .map(|m| m.crc.or_else(|| combined_crc(&blocks)))
+                    .collect();
+                let uncompressed_crc = metas.iter().zip(&crcs).try_fold(0, |crc, (m, file_crc)| {
+                    Some(crc32_combine(crc, (*file_crc)?, m.uncompressed_size))
+                });
+                if let Some(uncompressed_crc) = uncompressed_crc {
+                    if check_block_crcs && configs[plan.config].filters.is_empty() {
+                        check_blocks_crc(&blocks, uncompressed_crc)?;
+                    }
+                }
+
+                let folder_time: Duration = blocks.iter().map(|b| b.compress_time).sum();
+                let folder_size: u64 = metas.iter().map(|m| m.uncompressed_size).sum();
+                for meta in metas {
+                    let share = meta.uncompressed_size as f64 / folder_size as f64;
+                    file_times.push((meta.name.clone(), folder_time.mul_f64(share)));
+                }
+
+                // Fall back to Copy when no block of the folder shrank
+                let codec = if blocks.iter().all(|b| b.raw_fallback.is_some()) {
+                    FolderCodec::Copy
+                } else {
+                    folder_codec(&configs[plan.config])
+                };
+                let mut pack_writer = CrcWriter::new(&mut out);
+                let (compressed_size, encryption) = match &encryptor {
+                    Some(encryptor) => {
+                        let (mut aes_writer, properties) = encryptor
+                            .encrypt_folder(&mut pack_writer, || folder_digest(&blocks))
+                            .map_err(in_folder)?;
+                        let coded_size = Self::write_folder_blocks(&mut aes_writer, blocks, &codec)
+                            .map_err(in_folder)?;
+                        aes_writer.finish().map_err(|e| in_folder(e.into()))?;
+                        let encryption = FolderEncryption {
+                            properties,
+                            coded_size,
+                        };
+                        (encrypted_size(coded_size), Some(encryption))
+                    }
+                    None => (
+                        Self::write_folder_blocks(&mut pack_writer, blocks, &codec)
+                            .map_err(in_folder)?,
+                        None,
+                    ),
+                };
+                let packed_crc = pack_crc.then(|| pack_writer.crc());
+                progress.add_files(metas.len());
+
+                folders.push(FolderInfo {
+                    compressed_size,
+                    packed_crc,
+                    uncompressed_size: folder_size,
+                    uncompressed_crc,
+                    codec,
+                    filters: configs[plan.config].filters.clone(),
+                    encryption,
+                    substreams: metas
+                        .iter()
+                        .zip(&crcs)
+                        .map(|(m, &crc)| SubStreamInfo {
+                            size: m.uncompressed_size,
+                            crc,
+                        })
+                        .collect(),
+                });
+
+                for (meta, crc) in metas.iter().zip(crcs) {
+                    file_entries.push(FileEntry {
+                        name: meta.name.clone(),
+                        uncompressed_size: meta.uncompressed_size,
+                        // Per-file packed size is only meaningful for unshared folders
+                        compressed_size: if metas.len() == 1 { compressed_size } else { 0 },
+                        crc,
+                        has_data: true,
+                        is_directory: false,
+                        modified_time: meta.mtime,
+                        attributes: meta.attributes,
+                    });
+                }
+            }
+            Ok(())
+        };
+        let on_block_done = |bytes| progress.add_bytes(bytes);
+        let block_configs: Vec<usize> = folder_plans
+            .iter()
+            .flat_map(|plan| std::iter::repeat_n(plan.config, plan.block_count))
+            .collect();
+        let config_of_block = |block_index: usize| &configs[block_configs[block_index]];
+        // Without the caller's pool, one is built for this archive unless
+        // there is at most one block. Where threads cannot be spawned, the
+        // blocks are compressed on this thread instead.
+        let own_pool = match self.thread_pool {
+            Some(_) => None,
+            None if raw_blocks.len() <= 1 => None,
+            None => build_pool_or_fallback(
+                self.num_threads,
+                raw_blocks.len(),
+                self.max_blocks_in_flight,
+                self.sequential_fallback,
+            )?,
+        };
+        match self.thread_pool.as_deref().or(own_pool.as_ref()) {
+            None => write_folders(&mut compress_blocks_sequential(
+                raw_blocks,
+                &config_of_block,
+                &on_block_done,
+                self.cancel.as_deref(),
+            ))?,
+            Some(pool) if self.streaming_output => compress_blocks_streaming_in(
+                pool,
+                raw_blocks,
+                &config_of_block,
+                &on_block_done,
+                self.cancel.as_deref(),
+                self.max_blocks_in_flight,
+                write_folders,
+            )?,
+            Some(pool) => {
+                let compressed_blocks = compress_blocks_parallel_results_in(
+                    pool,
+                    raw_blocks,
+                    &config_of_block,
+                    &on_block_done,
+                    self.cancel.as_deref(),
+                    self.max_blocks_in_flight,
+                )?;
+                write_folders(&mut compressed_blocks.into_iter())?;
+            }
         }
 
-        // 4. Add empty file entries (no folder for these)
-        for (name, mtime) in &empty_files {
+        // The packed streams must end where the header will say they do,
+        // or the SignatureHeader would point into the wrong bytes
+        let packed_size: u64 = folders.iter().map(|f| f.compressed_size).sum();
+        let data_start = self.base_offset + SIGNATURE_HEADER_SIZE;
+        let data_end = data_start + pack_position + packed_size;
+        check_position(out.get_mut(), data_end, "packed data")?;
+
+        let mut sha256: Vec<(String, [u8; 32])> = file_metas
+            .iter()
+            .filter_map(|m| Some((m.name.clone(), m.sha256?)))
+            .collect();
+
+        // 5. Add empty file and directory entries (no folder for these)
+        for entry in empty_entries {
+            if self.compute_sha256 && !entry.is_directory {
+                sha256.push((entry.name.clone(), Sha256::digest([]).into()));
+            }
             file_entries.push(FileEntry {
-                name: name.clone(),
+                name: entry.name,
                 uncompressed_size: 0,
                 compressed_size: 0,
-                crc: 0,
+                crc: None,
                 has_data: false,
-                modified_time: *mtime,
+                is_directory: entry.is_directory,
+                modified_time: entry.mtime,
+                attributes: entry.attributes,
             });
         }
 
-        // 5. Build and serialize the header
+        if let Some(now) = now {
+            for entry in &mut file_entries[existing_files..] {
+                entry.modified_time.get_or_insert(now);
+            }
+        }
+        if self.reproducible {
+            for entry in &mut file_entries[existing_files..] {
+                entry.modified_time = None;
+            }
+        } else if let Some(epoch) = source_date_epoch {
+            for entry in &mut file_entries[existing_files..] {
+                entry.modified_time = Some(entry.modified_time.map_or(epoch, |t| t.min(epoch)));
+            }
+        }
+
+        // 6. Build and serialize the header
         let header = ArchiveHeader {
             folders,
             files: file_entries,
             pack_position,
         };
-        let header_bytes = header.serialize()?;
-        let header_crc = crc32fast::hash(&header_bytes);
+        let mut header_bytes = header.serialize()?;
 
-        // 6. Write the header
-        let header_offset_from_sig_end = self.writer.stream_position()? - SIGNATURE_HEADER_SIZE;
-        self.writer.write_all(&header_bytes)?;
+        // 7. Write the header. A compressed header is packed right after the
+        //    file data, followed by the kEncodedHeader record describing it.
+        let mut header_offset_from_sig_end = pack_position + packed_size;
+        let auto = self.compress_header.is_none();
+        if self
+            .compress_header
+            .unwrap_or(header_bytes.len() > ENCODED_HEADER_THRESHOLD)
+        {
+            let (packed, encoded) =
+                Self::encode_header(&header_bytes, &self.config, header_offset_from_sig_end)?;
+            if !auto || packed.len() + encoded.len() < header_bytes.len() {
+                out.write_all(&packed)?;
+                header_offset_from_sig_end += packed.len() as u64;
+                header_bytes = encoded;
+            }
+        }
+        let header_crc = crc32fast::hash(&header_bytes);
+        out.write_all(&header_bytes)?;
+        out.flush()?;
+        let archive_end = data_start + header_offset_from_sig_end + header_bytes.len() as u64;
+        check_position(out.get_mut(), archive_end, "header")?;
+        drop(out);
 
-        // 7. Seek back and write the real SignatureHeader
-        self.writer.seek(SeekFrom::Start(0))?;
+        // 8. Seek back and write the real SignatureHeader
+        let mut signature_header = Vec::with_capacity(SIGNATURE_HEADER_SIZE as usize);
         write_signature_header(
-            &mut self.writer,
+            &mut signature_header,
+            header.required_version(),
             header_offset_from_sig_end,
             header_bytes.len() as u64,
             header_crc,
         )?;
+        self.writer.seek(SeekFrom::Start(self.base_offset))?;
+        self.writer.write_all(&signature_header)?;
+        check_position(&mut self.writer, data_start, "signature header")?;
+
+        // 9. Seek to the end of the archive so the writer is in a clean
+        //    state, and append the recovery record now that the
+        //    SignatureHeader is final.
+        self.writer.seek(SeekFrom::Start(archive_end))?;
+        if let Some(mut recovery) = recovery {
+            recovery.patch_start(&signature_header)?;
+            self.writer.write_all(&recovery.finish()?)?;
+        }
+
+        // 10. Verify-after-write and strict modes: read the archive back from
+        //     the output and check it
+        for validate in [self.folder_verifier, self.validator].into_iter().flatten() {
+            let end = self.writer.stream_position()?;
+            validate(&mut self.writer, self.base_offset, header.files.len())?;
+            self.writer.seek(SeekFrom::Start(end))?;
+        }
+
+        // 11. Flush buffered output and, if requested, sync it to disk
+        self.writer.flush()?;
+        if let Some(sync) = self.sync_on_finish {
+            sync(&mut self.writer)?;
+        }
+
+        let archive_size = self.writer.stream_position()? - self.base_offset;
+        let new_folders = &header.folders[existing_folders..];
+        file_times.sort_by_key(|&(_, time)| std::cmp::Reverse(time));
+        let stats = FinishStats {
+            total_uncompressed: new_folders.iter().map(|f| f.uncompressed_size).sum(),
+            total_compressed: new_folders.iter().map(|f| f.compressed_size).sum(),
+            num_files: file_metas.len(),
+            num_folders: new_folders.len(),
+            archive_size,
+            wall_time: started.elapsed(),
+            slowest_files: file_times,
+            sha256,
+            skipped,
+        };
+        Ok((self.writer, stats, ArchiveReport::new(&header)))
+    }
 
-        // 8. Seek to end so the writer is in a clean state
-        self.writer.seek(SeekFrom::End(0))?;
+    /// LZMA2-compresses a serialized header. Returns the packed stream, to be
+    /// written at `pack_position`, and the kEncodedHeader record pointing at it.
+    fn encode_header(
+        header_bytes: &[u8],
+        config: &Lzma2Config,
+        pack_position: u64,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        // A dictionary larger than the header only costs decoder memory
+        let dict_size = (header_bytes.len() as u32).clamp(1 << 16, config.effective_dict_size());
+        let header_config = Lzma2Config {
+            dict_size: Some(dict_size),
+            // BZip2, stored and custom-compressed archives keep an LZMA2
+            // header, as 7-Zip writes them
+            codec: match config.codec {
+                Codec::Lzma1 => Codec::Lzma1,
+                _ => Codec::Lzma2,
+            },
+            compressor: None,
+            ..config.clone()
+        };
+        let packed = compress_block(header_bytes, &header_config)?;
 
-        Ok(self.writer)
+        let header_crc = crc32fast::hash(header_bytes);
+        let encoded = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: packed.len() as u64,
+                packed_crc: None,
+                uncompressed_size: header_bytes.len() as u64,
+                uncompressed_crc: Some(header_crc),
+                codec: folder_codec(&header_config),
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: header_bytes.len() as u64,
+                    crc: Some(header_crc),
+                }],
+            }],
+            files: vec![],
+            pack_position,
+        }
+        .serialize_encoded()?;
+
+        Ok((packed, encoded))
     }
 
     /// Reads a disk file by chunks directly into RawBlocks, computing CRC
     /// incrementally. The full file is never loaded as a single allocation.
     fn read_file_into_blocks(
-        disk_path: &std::path::Path,
+        source: &DiskSource,
         archive_name: String,
-        block_size: usize,
-        file_metas: &mut Vec<FileMeta>,
-        raw_blocks: &mut Vec<RawBlock>,
-        empty_files: &mut Vec<(String, Option<u64>)>,
+        mtime_override: Option<u64>,
+        options: ReadOptions,
+        out: &mut ReadEntries,
     ) -> Result<()> {
-        let metadata = std::fs::metadata(disk_path)?;
-        let mtime = metadata
-            .modified()
-            .ok()
-            .and_then(|t| {
-                t.duration_since(std::time::UNIX_EPOCH)
-                    .ok()
-                    .map(|d| unix_to_filetime(d.as_secs()))
-            });
-        let file_size = metadata.len();
+        // Set apart from other I/O errors: the file existed when queued
+        let vanished = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                SevenZipError::FileVanished(source.path().display().to_string())
+            }
+            _ => SevenZipError::Io(e),
+        };
+        let DiskMetadata {
+            size: file_size,
+            mtime,
+            attributes,
+            ..
+        } = source.metadata().map_err(vanished)?;
+        let mtime = mtime_override.or(mtime);
 
         if file_size == 0 {
-            empty_files.push((archive_name, mtime));
+            out.empty_entries.push(EmptyEntry {
+                name: archive_name,
+                mtime,
+                attributes,
+                is_directory: false,
+            });
             return Ok(());
         }
 
-        let mut file = std::fs::File::open(disk_path)?;
-        let mut hasher = crc32fast::Hasher::new();
-        let first_block = raw_blocks.len();
+        let mut file = source.open().map_err(vanished)?;
+        // A SHA-256 needs a pass over the data here anyway
+        let mut hasher = ContentHasher::new(
+            options.store_crc && (!options.defer_crc || options.compute_sha256),
+            options.compute_sha256,
+        );
+        let first_block = out.raw_blocks.len();
+        let block_size = options.block_size_for(file_size);
+        if block_size == usize::MAX && usize::try_from(file_size).is_err() {
+            // Only reachable where usize is narrower than 64 bits
+            return Err(SevenZipError::InvalidState(format!(
+                "'{archive_name}' is {file_size} bytes, too large to read as a single block \
+                 on this target"
+            )));
+        }
         let mut remaining = file_size;
 
         while remaining > 0 {
-            let chunk_len = block_size.min(remaining as usize);
+            // Compared as u64: `remaining` may not fit in a usize
+            let chunk_len = (block_size as u64).min(remaining) as usize;
             let mut buf = vec![0u8; chunk_len];
             file.read_exact(&mut buf)?;
             hasher.update(&buf);
-            raw_blocks.push(RawBlock {
+            out.raw_blocks.push(RawBlock {
                 data: buf,
-                block_index: raw_blocks.len(),
+                block_index: out.raw_blocks.len(),
             });
             remaining -= chunk_len as u64;
         }
 
-        file_metas.push(FileMeta {
+        let (crc, sha256) = hasher.finalize();
+        out.file_metas.push(FileMeta {
             name: archive_name,
             mtime,
+            attributes,
             uncompressed_size: file_size,
-            crc: hasher.finalize(),
-            block_count: raw_blocks.len() - first_block,
+            crc,
+            sha256,
+            block_count: out.raw_blocks.len() - first_block,
+        });
+
+        Ok(())
+    }
+
+    /// Reads a stream of unknown length into RawBlocks until EOF, computing
+    /// CRC and size incrementally. If `expected_crc` is set, the final CRC is
+    /// checked against it.
+    fn read_stream_into_blocks(
+        reader: &mut dyn Read,
+        archive_name: String,
+        expected_crc: Option<u32>,
+        options: ReadOptions,
+        out: &mut ReadEntries,
+    ) -> Result<()> {
+        let mut hasher = ContentHasher::new(
+            options.store_crc || expected_crc.is_some(),
+            options.compute_sha256,
+        );
+        let first_block = out.raw_blocks.len();
+        let mut uncompressed_size = 0u64;
+
+        loop {
+            // `take` + `read_to_end` keeps reading across short reads until
+            // the block is full or the stream hits EOF.
+            let mut buf = Vec::new();
+            reader
+                .take(options.block_size as u64)
+                .read_to_end(&mut buf)?;
+            if buf.is_empty() {
+                break;
+            }
+            hasher.update(&buf);
+            uncompressed_size += buf.len() as u64;
+            out.raw_blocks.push(RawBlock {
+                data: buf,
+                block_index: out.raw_blocks.len(),
+            });
+        }
+
+        let (crc, sha256) = hasher.finalize();
+        if let (Some(expected), Some(crc)) = (expected_crc, crc) {
+            if crc != expected {
+                return Err(SevenZipError::CrcMismatch {
+                    name: archive_name,
+                    expected,
+                    actual: crc,
+                });
+            }
+        }
+
+        if uncompressed_size == 0 {
+            out.empty_entries.push(EmptyEntry {
+                name: archive_name,
+                mtime: None,
+                attributes: None,
+                is_directory: false,
+            });
+            return Ok(());
+        }
+
+        out.file_metas.push(FileMeta {
+            name: archive_name,
+            mtime: None,
+            attributes: None,
+            uncompressed_size,
+            crc: crc.filter(|_| options.store_crc),
+            sha256,
+            block_count: out.raw_blocks.len() - first_block,
         });
 
         Ok(())
     }
 
     /// Splits in-memory data into RawBlocks. Single-block data is moved
-    /// directly (zero copy); larger data is split into chunks.
+    /// directly (zero copy); larger data is split in place, so it is never
+    /// held twice.
     fn split_bytes_into_blocks(
         archive_name: String,
         data: Vec<u8>,
-        block_size: usize,
-        file_metas: &mut Vec<FileMeta>,
-        raw_blocks: &mut Vec<RawBlock>,
-        empty_files: &mut Vec<(String, Option<u64>)>,
+        mtime: Option<u64>,
+        attributes: Option<u32>,
+        options: ReadOptions,
+        out: &mut ReadEntries,
     ) {
         if data.is_empty() {
-            empty_files.push((archive_name, None));
+            out.empty_entries.push(EmptyEntry {
+                name: archive_name,
+                mtime,
+                attributes,
+                is_directory: false,
+            });
             return;
         }
 
         let uncompressed_size = data.len() as u64;
-        let crc = crc32fast::hash(&data);
-        let first_block = raw_blocks.len();
+        let mut hasher = ContentHasher::new(options.store_crc, options.compute_sha256);
+        hasher.update(&data);
+        let (crc, sha256) = hasher.finalize();
+        let first_block = out.raw_blocks.len();
+        let block_size = options.block_size_for(uncompressed_size);
 
         if data.len() <= block_size {
-            raw_blocks.push(RawBlock {
+            out.raw_blocks.push(RawBlock {
                 data,
                 block_index: first_block,
             });
         } else {
-            for chunk in data.chunks(block_size) {
-                raw_blocks.push(RawBlock {
-                    data: chunk.to_vec(),
-                    block_index: raw_blocks.len(),
-                });
-            }
+            out.raw_blocks
+                .extend(split_owned_into_blocks(data, block_size, first_block));
         }
 
-        file_metas.push(FileMeta {
+        out.file_metas.push(FileMeta {
             name: archive_name,
-            mtime: None,
+            mtime,
+            attributes,
             uncompressed_size,
             crc,
-            block_count: raw_blocks.len() - first_block,
+            sha256,
+            block_count: out.raw_blocks.len() - first_block,
         });
     }
 
-    /// Writes a file's compressed blocks directly to the output, stripping
+    /// Writes a folder's compressed blocks directly to the output, stripping
     /// intermediate LZMA2 end markers inline. Each block is dropped (freed)
     /// immediately after writing. Returns total bytes written.
-    fn write_file_blocks(
-        writer: &mut W,
-        block_iter: &mut impl Iterator<Item = crate::compression::block::CompressedBlock>,
-        block_count: usize,
+    ///
+    /// For `FolderCodec::Copy`, every block's `raw_fallback` data is written
+    /// instead of its LZMA2 stream. LZMA folders hold a single block, which
+    /// is written as-is, and BZip2 blocks are joined into one stream.
+    ///
+    /// LZMA2 blocks kept raw in a folder that did not fall back to Copy have
+    /// no compressed data left, and are written as uncompressed chunks.
+    fn write_folder_blocks(
+        writer: &mut impl Write,
+        blocks: Vec<CompressedBlock>,
+        codec: &FolderCodec,
     ) -> Result<u64> {
         let mut compressed_size = 0u64;
-        let last_index = block_count - 1;
+        let last_index = blocks.len().saturating_sub(1);
 
-        for i in 0..block_count {
-            let block = block_iter.next().ok_or_else(|| {
-                SevenZipError::Compression("unexpected end of compressed blocks".to_string())
-            })?;
+        #[cfg(feature = "bzip2")]
+        if *codec == FolderCodec::Bzip2 {
+            // BZip2 blocks are spliced into a single stream at the bit level
+            let mut joiner = StreamJoiner::new();
+            for block in blocks {
+                joiner.push(&block.compressed_data, block.uncompressed_size)?;
+                let data = joiner.take_output();
+                writer.write_all(&data)?;
+                compressed_size += data.len() as u64;
+            }
+            let tail = joiner.finish();
+            writer.write_all(&tail)?;
+            return Ok(compressed_size + tail.len() as u64);
+        }
 
-            if i < last_index {
+        for (i, block) in blocks.into_iter().enumerate() {
+            if *codec == FolderCodec::Copy {
+                let data = block.raw_fallback.ok_or_else(|| {
+                    SevenZipError::Compression(
+                        "stored folder contains a block without raw data".to_string(),
+                    )
+                })?;
+                writer.write_all(&data)?;
+                compressed_size += data.len() as u64;
+            } else if let Some(raw) = block
+                .raw_fallback
+                .as_ref()
+                .filter(|_| block.compressed_data.is_empty())
+            {
+                if !matches!(codec, FolderCodec::Lzma2 { .. }) {
+                    return Err(SevenZipError::Compression(
+                        "block without compressed data in a folder of several blocks".to_string(),
+                    ));
+                }
+                compressed_size += write_uncompressed_chunks(writer, raw)?;
+                if i == last_index {
+                    writer.write_all(&[LZMA2_END_MARKER])?;
+                    compressed_size += 1;
+                }
+            } else if i < last_index && matches!(codec, FolderCodec::Lzma2 { .. }) {
                 // Intermediate block: strip the trailing LZMA2 end marker
                 let data = &block.compressed_data;
                 if data.last() != Some(&LZMA2_END_MARKER) {
@@ -355,3 +2100,313 @@ impl<W: Write + Seek> SevenZipWriter<W> {
         Ok(compressed_size)
     }
 }
+
+impl SevenZipWriter<VolumeWriter> {
+    /// Creates an archive split into volumes of at most `volume_size` bytes,
+    /// named `<base>.001`, `<base>.002`, ... as 7-Zip does. Extract by
+    /// pointing 7-Zip at the first volume.
+    ///
+    /// The volume paths are available from the writer returned by `finish()`
+    /// (see [`VolumeWriter::volume_paths`]).
+    pub fn create_volumes(base: impl AsRef<std::path::Path>, volume_size: u64) -> Result<Self> {
+        Self::new(VolumeWriter::create(base, volume_size)?)
+    }
+}
+
+impl SevenZipWriter<File> {
+    /// Makes `finish()` call [`File::sync_all`] once the archive is
+    /// completely written, so that a successful return means the data and
+    /// metadata have reached stable storage, as far as the operating system
+    /// can guarantee. Off by default: without it, `finish()` only flushes
+    /// and the archive may still sit in the OS page cache, to be lost on a
+    /// power failure or crash.
+    ///
+    /// The directory entry of a newly created file is not synced; callers
+    /// that need it durable must sync the parent directory themselves.
+    pub fn set_sync_on_finish(&mut self, sync: bool) {
+        self.sync_on_finish = sync.then_some(sync_file as OutputSync<File>);
+    }
+
+    /// Finishes the archive and syncs it to stable storage, as
+    /// [`set_sync_on_finish(true)`](Self::set_sync_on_finish) followed by
+    /// `finish()` would.
+    pub fn finish_and_sync(mut self) -> Result<File> {
+        self.set_sync_on_finish(true);
+        self.finish()
+    }
+}
+
+impl<W: Write> SevenZipWriter<SpoolWriter<W>> {
+    /// Creates an archive writer for an output that cannot seek, such as
+    /// stdout or a socket.
+    ///
+    /// The archive is assembled in a [`SpoolWriter`] (in memory up to 64 MiB,
+    /// then in a temporary file) and only copied to `output`, SignatureHeader
+    /// first, once it is complete: expect temporary storage as large as the
+    /// archive. Finish with [`finish_streaming`](Self::finish_streaming), or
+    /// call [`SpoolWriter::into_inner`] on the writer `finish()` returns.
+    pub fn new_streaming(output: W) -> Result<Self> {
+        Self::new(SpoolWriter::new(output))
+    }
+
+    /// Finishes the archive and writes it to the output in one forward
+    /// pass, returning the output.
+    pub fn finish_streaming(self) -> Result<W> {
+        Ok(self.finish()?.into_inner()?)
+    }
+}
+
+impl SevenZipWriter<Cursor<Vec<u8>>> {
+    /// Creates an archive writer that builds the archive in memory.
+    ///
+    /// ```
+    /// use sevenzip_mt::{SevenZipReader, SevenZipWriter};
+    /// use std::io::Cursor;
+    ///
+    /// let mut archive = SevenZipWriter::in_memory();
+    /// archive.add_bytes("hello.txt", b"Hello, world!").unwrap();
+    /// let bytes = archive.finish_to_vec().unwrap();
+    ///
+    /// let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    /// assert_eq!(reader.read_file(0).unwrap(), b"Hello, world!");
+    /// ```
+    pub fn in_memory() -> Self {
+        let mut cursor = Cursor::new(vec![0u8; 32]);
+        cursor.set_position(32);
+        Self::with_writer(cursor, 0)
+    }
+
+    /// Finishes the archive and returns its bytes.
+    pub fn finish_to_vec(self) -> Result<Vec<u8>> {
+        Ok(self.finish()?.into_inner())
+    }
+}
+
+impl<W: Read + Write + Seek> SevenZipWriter<W> {
+    /// Opens an archive written by this crate, starting at the writer's
+    /// current position, to add more entries to it.
+    ///
+    /// Existing folders and entries are kept untouched; `finish()` writes
+    /// the new data after them, over the old header, followed by a combined
+    /// header. Finishing without adding anything leaves the archive as it
+    /// was. Archives the reader cannot parse (e.g. encrypted or filtered
+    /// ones) are rejected, and a recovery record cannot be added.
+    ///
+    /// If the old archive was longer than the new one, the writer is left
+    /// at the new end and the stale tail remains; truncate a file there
+    /// with `File::set_len`.
+    pub fn open_append(mut writer: W) -> Result<Self> {
+        let base_offset = writer.stream_position()?;
+        let header = SevenZipReader::new(&mut writer)?.into_header();
+
+        let packed_size: u64 = header.folders.iter().map(|f| f.compressed_size).sum();
+        writer.seek(SeekFrom::Start(
+            base_offset + SIGNATURE_HEADER_SIZE + header.pack_position + packed_size,
+        ))?;
+
+        let mut archive = Self::with_writer(writer, base_offset);
+        archive.names = header.files.iter().map(|f| f.name.clone()).collect();
+        archive.existing = Some(header);
+        Ok(archive)
+    }
+
+    /// Enables strict mode: after writing, `finish()` re-opens the output
+    /// with [`SevenZipReader`], checks the entry count and decompresses every
+    /// entry to verify its CRC, all in-process. Any discrepancy is returned
+    /// as an error; the caller should then discard the output.
+    ///
+    /// Requires a readable writer, e.g. a `Cursor` or a `File` opened for
+    /// both reading and writing (`File::create` is write-only). Encrypted
+    /// and filtered archives cannot be read back and are rejected.
+    pub fn set_strict_validation(&mut self, strict: bool) {
+        self.validator = strict.then_some(validate_output::<W> as OutputValidator<W>);
+    }
+
+    /// Enables a verification pass after writing: `finish()` re-reads each
+    /// folder's packed data from the output, decompresses it and checks the
+    /// CRC32 of the whole folder, returning
+    /// `SevenZipError::VerificationFailed` on a mismatch. This doubles the
+    /// I/O, so it is off by default.
+    ///
+    /// Lighter than [`set_strict_validation`](Self::set_strict_validation),
+    /// which also checks every entry; the same writer requirements apply.
+    pub fn set_verify_after_write(&mut self, verify: bool) {
+        self.folder_verifier = verify.then_some(verify_folders_output::<W> as OutputValidator<W>);
+    }
+}
+
+fn sync_file(file: &mut File) -> std::io::Result<()> {
+    file.sync_all()
+}
+
+/// Re-reads the archive at `base_offset` and checks every entry's CRC.
+fn validate_output<R: Read + Seek>(
+    reader: &mut R,
+    base_offset: u64,
+    expected_entries: usize,
+) -> Result<()> {
+    reader.seek(SeekFrom::Start(base_offset))?;
+    let mut archive = SevenZipReader::new(reader)?;
+    if archive.entries().len() != expected_entries {
+        return Err(SevenZipError::HeaderError(format!(
+            "archive lists {} entries, expected {expected_entries}",
+            archive.entries().len()
+        )));
+    }
+    archive.verify()?;
+    Ok(())
+}
+
+/// Re-reads the archive at `base_offset` and checks every folder's CRC.
+fn verify_folders_output<R: Read + Seek>(
+    reader: &mut R,
+    base_offset: u64,
+    _expected_entries: usize,
+) -> Result<()> {
+    reader.seek(SeekFrom::Start(base_offset))?;
+    SevenZipReader::new(reader)?.verify_folders()?;
+    Ok(())
+}
+
+/// The header codec for folders compressed with `config`.
+fn folder_codec(config: &Lzma2Config) -> FolderCodec {
+    if let Some(compressor) = &config.compressor {
+        return FolderCodec::Custom {
+            coder_id: compressor.coder_id().to_vec(),
+            properties: compressor.properties(),
+        };
+    }
+    match config.codec {
+        Codec::Lzma2 => FolderCodec::Lzma2 {
+            properties_byte: encode_properties_byte(config.effective_dict_size()),
+        },
+        Codec::Lzma1 => FolderCodec::Lzma1 {
+            properties: config.lzma1_properties(),
+        },
+        #[cfg(feature = "bzip2")]
+        Codec::Bzip2 { .. } => FolderCodec::Bzip2,
+        Codec::Copy => FolderCodec::Copy,
+    }
+}
+
+/// SHA-256 over a folder's blocks as they are held for writing, from which
+/// reproducible encryption derives the folder's IV.
+fn folder_digest(blocks: &[CompressedBlock]) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    for block in blocks {
+        let raw = block.raw_fallback.as_deref().unwrap_or_default();
+        for part in [&block.compressed_data[..], raw] {
+            sha.update((part.len() as u64).to_le_bytes());
+            sha.update(part);
+        }
+    }
+    sha.finalize().into()
+}
+
+/// Block size files are split into for `config`, given the archive's
+/// total input size and thread count. LZMA streams cannot be concatenated,
+/// so with LZMA or a custom compressor each file is read as one block.
+fn block_size(config: &Lzma2Config, total_size: u64, num_threads: usize) -> usize {
+    if config.codec == Codec::Lzma1 || config.compressor.is_some() {
+        usize::MAX
+    } else {
+        config.block_size_for(total_size, num_threads)
+    }
+}
+
+/// Returns `SevenZipError::Cancelled` once the cancel flag is set.
+fn check_cancelled(cancel: Option<&AtomicBool>) -> Result<()> {
+    if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+        return Err(SevenZipError::Cancelled);
+    }
+    Ok(())
+}
+
+/// Prefixes a compression or write error for a folder with the names of
+/// its files, so the caller can tell which entry failed. Other errors,
+/// such as `Cancelled`, pass through unchanged.
+fn in_folder_of(metas: &[FileMeta], err: SevenZipError) -> SevenZipError {
+    let files = match metas {
+        [meta] => format!("file '{}'", meta.name),
+        [first, .., last] => format!("files '{}' to '{}'", first.name, last.name),
+        [] => return err,
+    };
+    match err {
+        SevenZipError::Compression(msg) => SevenZipError::Compression(format!("{files}: {msg}")),
+        SevenZipError::Io(e) => {
+            SevenZipError::Io(std::io::Error::new(e.kind(), format!("{files}: {e}")))
+        }
+        other => other,
+    }
+}
+
+/// Returns `SevenZipError::InvalidState` unless `writer` is at `expected`
+/// after writing `section`: a writer that reports bytes as written without
+/// storing them (or a disk filling up under one) would otherwise leave a
+/// SignatureHeader pointing at the wrong offsets.
+fn check_position<S: Seek>(writer: &mut S, expected: u64, section: &str) -> Result<()> {
+    let actual = writer.stream_position()?;
+    if actual != expected {
+        return Err(SevenZipError::InvalidState(format!(
+            "output is at offset {actual} after writing the {section}, expected {expected}"
+        )));
+    }
+    Ok(())
+}
+
+/// Converts a modification time given for an entry to a Windows FILETIME.
+fn explicit_mtime(archive_name: &str, mtime: SystemTime) -> Result<u64> {
+    system_time_to_filetime(mtime).ok_or_else(|| {
+        SevenZipError::InvalidState(format!(
+            "modification time of '{archive_name}' is before the Unix epoch or past the FILETIME range"
+        ))
+    })
+}
+
+/// Reads the `SOURCE_DATE_EPOCH` environment variable as a Windows
+/// FILETIME. An unset or empty variable means no override.
+fn source_date_epoch() -> Result<Option<u64>> {
+    let value = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) if !value.trim().is_empty() => value,
+        _ => return Ok(None),
+    };
+    // Larger values would overflow the FILETIME
+    let secs = value.trim().parse::<u64>().ok();
+    match secs.and_then(|secs| unix_to_filetime(secs, 0)) {
+        Some(filetime) => Ok(Some(filetime)),
+        None => Err(SevenZipError::InvalidState(format!(
+            "invalid SOURCE_DATE_EPOCH '{value}': expected seconds since the Unix epoch"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::block::CRC_BYTES;
+
+    /// Bytes hashed on this thread while archiving a disk file: a single
+    /// block, so it is read and compressed on this thread.
+    fn crc_bytes_hashed(store_crc: bool, solid: bool) -> u64 {
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        input.write_all(&[7; 100_000]).unwrap();
+        let mut archive = SevenZipWriter::in_memory();
+        archive.set_store_crc(store_crc);
+        archive.set_solid(solid);
+        archive
+            .add_file(input.path().to_str().unwrap(), "disk.bin")
+            .unwrap();
+
+        let before = CRC_BYTES.get();
+        archive.finish_to_vec().unwrap();
+        CRC_BYTES.get() - before
+    }
+
+    #[test]
+    fn test_store_crc_disabled_hashes_nothing() {
+        for solid in [false, true] {
+            assert!(crc_bytes_hashed(true, solid) >= 100_000, "solid: {solid}");
+            assert_eq!(crc_bytes_hashed(false, solid), 0, "solid: {solid}");
+        }
+    }
+}
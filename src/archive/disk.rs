@@ -0,0 +1,237 @@
+use crate::archive::header::system_time_to_filetime;
+#[cfg(unix)]
+use crate::archive::header::unix_mode_to_attributes;
+use crate::error::{Result, SevenZipError};
+use std::ffi::OsString;
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A file on disk queued for the archive with
+/// [`add_file`](crate::SevenZipWriter::add_file) or found by
+/// [`add_path_recursive`](crate::SevenZipWriter::add_path_recursive).
+///
+/// Reading from the filesystem goes through this module only: an archive
+/// built from [`add_bytes`](crate::SevenZipWriter::add_bytes) and
+/// [`add_reader`](crate::SevenZipWriter::add_reader) alone never touches
+/// it, so the writer can be driven from any in-memory or streamed source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskSource {
+    path: PathBuf,
+}
+
+/// What the archive records about a file or directory on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskMetadata {
+    /// Size in bytes, as of the last `metadata` call.
+    pub size: u64,
+    /// Modification time as a Windows FILETIME.
+    pub mtime: Option<u64>,
+    /// 7z attributes: the Unix mode on Unix, the read-only, hidden and
+    /// similar flags on Windows, `None` elsewhere.
+    pub attributes: Option<u32>,
+    pub is_directory: bool,
+    /// Occupies less disk space than its size (see
+    /// [`ScanEntry::is_sparse`](crate::ScanEntry::is_sparse)).
+    pub is_sparse: bool,
+}
+
+impl DiskSource {
+    /// Refers to the file at `path`, returning `SevenZipError::FileNotFound`
+    /// if nothing exists there.
+    pub fn new(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Err(SevenZipError::FileNotFound(path.display().to_string()));
+        }
+        Ok(Self::existing(path.to_path_buf()))
+    }
+
+    /// Refers to a path just found on disk, without checking it again.
+    pub(crate) fn existing(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the file's current size, modification time and attributes.
+    pub fn metadata(&self) -> io::Result<DiskMetadata> {
+        metadata(&self.path)
+    }
+
+    /// Opens the file for reading.
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+}
+
+/// Reads the size, modification time and attributes of `path`, following
+/// symlinks.
+pub fn metadata(path: &Path) -> io::Result<DiskMetadata> {
+    std::fs::metadata(path).map(|metadata| DiskMetadata::from_std(&metadata))
+}
+
+impl DiskMetadata {
+    fn from_std(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime: metadata_mtime(metadata),
+            attributes: metadata_attributes(metadata),
+            is_directory: metadata.is_dir(),
+            is_sparse: is_sparse(metadata),
+        }
+    }
+}
+
+/// A directory entry returned by [`read_dir_sorted`].
+#[derive(Debug)]
+pub struct DirChild {
+    pub path: PathBuf,
+    pub file_name: OsString,
+    pub kind: ChildKind,
+}
+
+/// The type of a [`DirChild`], without following symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildKind {
+    Directory,
+    File,
+    Symlink,
+    /// Sockets, FIFOs, devices and the like.
+    Other,
+}
+
+impl DirChild {
+    pub fn metadata(&self) -> io::Result<DiskMetadata> {
+        std::fs::symlink_metadata(&self.path).map(|metadata| DiskMetadata::from_std(&metadata))
+    }
+
+    /// The target of a [`ChildKind::Symlink`].
+    pub fn link_target(&self) -> io::Result<PathBuf> {
+        std::fs::read_link(&self.path)
+    }
+}
+
+/// Lists the entries of `dir` in file name order, so that archive layouts
+/// are stable across runs.
+pub fn read_dir_sorted(dir: &Path) -> io::Result<Vec<DirChild>> {
+    let mut children = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let kind = if file_type.is_dir() {
+            ChildKind::Directory
+        } else if file_type.is_symlink() {
+            ChildKind::Symlink
+        } else if file_type.is_file() {
+            ChildKind::File
+        } else {
+            ChildKind::Other
+        };
+        children.push(DirChild {
+            path: entry.path(),
+            file_name: entry.file_name(),
+            kind,
+        });
+    }
+    children.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    Ok(children)
+}
+
+/// Extracts a file's attributes as 7z attributes: its Unix mode on Unix,
+/// its read-only, hidden and similar flags on Windows. `None` elsewhere.
+fn metadata_attributes(metadata: &Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(unix_mode_to_attributes(
+            metadata.permissions().mode(),
+            metadata.is_dir(),
+        ))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        Some(crate::archive::header::windows_attributes(
+            metadata.file_attributes(),
+        ))
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Extracts the modification time of a file as a Windows FILETIME.
+fn metadata_mtime(metadata: &Metadata) -> Option<u64> {
+    metadata.modified().ok().and_then(system_time_to_filetime)
+}
+
+/// Whether a file occupies less disk space than its size, by more than
+/// one filesystem block (so that small inline or tail-packed files are not
+/// flagged). Transparent filesystem compression can also trigger this.
+/// Always `false` where allocated size is unavailable.
+fn is_sparse(metadata: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // st_blocks is always in 512-byte units
+        let allocated = metadata.blocks().saturating_mul(512);
+        metadata.is_file() && allocated.saturating_add(metadata.blksize()) <= metadata.len()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_read_dir_sorted() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("b.txt"), b"bb").unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"a").unwrap();
+        std::fs::create_dir(dir.path().join("c")).unwrap();
+
+        let children = read_dir_sorted(dir.path()).unwrap();
+        let listed: Vec<(&str, ChildKind)> = children
+            .iter()
+            .map(|c| (c.file_name.to_str().unwrap(), c.kind))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("a.txt", ChildKind::File),
+                ("b.txt", ChildKind::File),
+                ("c", ChildKind::Directory)
+            ]
+        );
+        let metadata = children[1].metadata().unwrap();
+        assert_eq!(metadata.size, 2);
+        assert!(!metadata.is_directory);
+        assert!(metadata.mtime.is_some());
+        assert!(children[2].metadata().unwrap().is_directory);
+    }
+
+    #[test]
+    fn test_disk_source_missing_file() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing.txt");
+        assert!(matches!(
+            DiskSource::new(&missing),
+            Err(SevenZipError::FileNotFound(_))
+        ));
+
+        std::fs::write(&missing, b"now here").unwrap();
+        let source = DiskSource::new(&missing).unwrap();
+        assert_eq!(source.path(), missing);
+        assert_eq!(source.metadata().unwrap().size, 8);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::archive::writer::FormatVersion;
+use crate::compression::filter::Filter;
+use crate::compression::lzma2::decode_dict_size;
+use crate::encryption::AES_CODER_ID;
 use crate::error::{Result, SevenZipError};
 use crate::io::writer::{
     write_bool_vector, write_number, write_u32_le, write_u64_le, write_utf16le_string,
@@ -22,6 +26,11 @@ pub const K_EMPTY_FILE: u8 = 0x0F;
 pub const K_NAME: u8 = 0x11;
 pub const K_M_TIME: u8 = 0x14;
 pub const K_ATTRIBUTES: u8 = 0x15;
+pub const K_ENCODED_HEADER: u8 = 0x17;
+
+/// Serialized headers larger than this are LZMA2-compressed behind a
+/// `kEncodedHeader` record by default.
+pub const ENCODED_HEADER_THRESHOLD: usize = 1024;
 
 /// 7z file signature bytes.
 pub const SIGNATURE: [u8; 6] = [b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C];
@@ -29,22 +38,210 @@ pub const SIGNATURE: [u8; 6] = [b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C];
 /// LZMA2 coder ID in 7z format.
 pub const LZMA2_CODER_ID: u8 = 0x21;
 
+/// LZMA coder ID in 7z format.
+pub const LZMA_CODER_ID: [u8; 3] = [0x03, 0x01, 0x01];
+
+/// BZip2 coder ID in 7z format.
+pub const BZIP2_CODER_ID: [u8; 3] = [0x04, 0x02, 0x02];
+
+/// Copy (stored, uncompressed) coder ID in 7z format.
+pub const COPY_CODER_ID: u8 = 0x00;
+
+/// Coder used for a folder's packed stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FolderCodec {
+    /// LZMA2, with the properties byte encoding its dictionary size.
+    Lzma2 { properties_byte: u8 },
+    /// LZMA, with the lc/lp/pb byte and the little-endian dictionary size.
+    Lzma1 { properties: [u8; 5] },
+    /// BZip2, which has no properties.
+    Bzip2,
+    /// Data stored as-is.
+    Copy,
+    /// A method this crate does not implement: written by a caller's
+    /// [`BlockCompressor`](crate::compression::compressor::BlockCompressor),
+    /// or read from an archive.
+    Custom {
+        coder_id: Vec<u8>,
+        properties: Vec<u8>,
+    },
+}
+
+impl FolderCodec {
+    /// Coder ID written in the folder's coder record.
+    pub fn coder_id(&self) -> &[u8] {
+        match self {
+            FolderCodec::Lzma2 { .. } => &[LZMA2_CODER_ID],
+            FolderCodec::Lzma1 { .. } => &LZMA_CODER_ID,
+            FolderCodec::Bzip2 => &BZIP2_CODER_ID,
+            FolderCodec::Copy => &[COPY_CODER_ID],
+            FolderCodec::Custom { coder_id, .. } => coder_id,
+        }
+    }
+
+    /// Coder properties (empty for coders without any).
+    pub fn properties(&self) -> Vec<u8> {
+        match self {
+            FolderCodec::Lzma2 { properties_byte } => vec![*properties_byte],
+            FolderCodec::Lzma1 { properties } => properties.to_vec(),
+            FolderCodec::Bzip2 | FolderCodec::Copy => Vec::new(),
+            FolderCodec::Custom { properties, .. } => properties.clone(),
+        }
+    }
+
+    /// Dictionary size of LZMA and LZMA2 folders; `None` for other coders.
+    pub fn dict_size(&self) -> Option<u32> {
+        match *self {
+            FolderCodec::Lzma2 { properties_byte } => Some(decode_dict_size(properties_byte)),
+            FolderCodec::Lzma1 {
+                properties: [_, dict @ ..],
+            } => Some(u32::from_le_bytes(dict)),
+            FolderCodec::Bzip2 | FolderCodec::Copy | FolderCodec::Custom { .. } => None,
+        }
+    }
+
+    /// Oldest format version that defines this coder.
+    pub fn required_version(&self) -> FormatVersion {
+        match self {
+            FolderCodec::Lzma2 { .. }
+            | FolderCodec::Lzma1 { .. }
+            | FolderCodec::Bzip2
+            | FolderCodec::Copy
+            | FolderCodec::Custom { .. } => FormatVersion::V0_4,
+        }
+    }
+}
+
+/// AES-256 coder applied to a folder's codec output.
+pub struct FolderEncryption {
+    /// AES coder properties (NumCyclesPower, salt and IV).
+    pub properties: Vec<u8>,
+    /// Size of the codec's stream before encryption padding.
+    pub coded_size: u64,
+}
+
+/// One coder of a folder, as written in its coder record.
+pub struct Coder {
+    pub id: Vec<u8>,
+    /// Coder properties (empty for coders without any).
+    pub properties: Vec<u8>,
+    pub num_in_streams: u64,
+    /// Size of each output stream; its length is the number of output
+    /// streams.
+    pub unpack_sizes: Vec<u64>,
+}
+
+impl Coder {
+    /// A coder with one input and one output stream.
+    fn simple(id: &[u8], properties: Vec<u8>, unpack_size: u64) -> Self {
+        Self {
+            id: id.to_vec(),
+            properties,
+            num_in_streams: 1,
+            unpack_sizes: vec![unpack_size],
+        }
+    }
+
+    fn is_complex(&self) -> bool {
+        self.num_in_streams != 1 || self.unpack_sizes.len() != 1
+    }
+}
+
+/// Connects a coder input stream to the output stream feeding it. Streams
+/// are numbered across the whole folder, in coder order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindPair {
+    pub in_index: u64,
+    pub out_index: u64,
+}
+
+/// A folder's coders and how their streams are wired: every output stream
+/// but the folder's final output is bound to an input stream, and the
+/// remaining input streams read packed streams.
+pub struct CoderChain {
+    pub coders: Vec<Coder>,
+    pub bind_pairs: Vec<BindPair>,
+    /// Input stream index of each packed stream, in PackInfo order.
+    pub packed_streams: Vec<u64>,
+}
+
 /// Metadata for a file entry in the archive.
 pub struct FileEntry {
     pub name: String,
     pub uncompressed_size: u64,
     pub compressed_size: u64,
-    pub crc: u32,
+    /// `None` for entries without data, and for files written without
+    /// CRCs (see `SevenZipWriter::set_store_crc`).
+    pub crc: Option<u32>,
     pub has_data: bool,
+    pub is_directory: bool,
     pub modified_time: Option<u64>, // Windows FILETIME
+    /// 7z attribute word (Windows attributes, optionally with Unix mode
+    /// bits in the high 16 bits).
+    pub attributes: Option<u32>,
 }
 
-/// Metadata for a folder (one per file-with-data in non-solid mode).
+/// Size and CRC of one file's data inside a folder's output stream.
+pub struct SubStreamInfo {
+    pub size: u64,
+    pub crc: Option<u32>,
+}
+
+/// Metadata for a folder (one per file-with-data in non-solid mode, or one
+/// shared by several files in solid mode).
 pub struct FolderInfo {
     pub compressed_size: u64,
+    /// CRC32 of the packed stream as written (after encryption), recorded
+    /// in PackInfo when `set_pack_crc` is enabled.
+    pub packed_crc: Option<u32>,
     pub uncompressed_size: u64,
-    pub uncompressed_crc: u32,
-    pub lzma2_properties_byte: u8,
+    pub uncompressed_crc: Option<u32>,
+    pub codec: FolderCodec,
+    /// Filters applied to the data before the codec, in order.
+    pub filters: Vec<Filter>,
+    /// When set, the codec output is encrypted and `compressed_size` is the
+    /// encrypted (padded) size.
+    pub encryption: Option<FolderEncryption>,
+    /// Files stored in this folder, in order. Exactly one in non-solid mode.
+    pub substreams: Vec<SubStreamInfo>,
+}
+
+impl FolderInfo {
+    /// The folder's coders, first to last: its filters, its codec, then AES
+    /// when encrypted. Coder 0 produces the folder's output, each coder's
+    /// input is the next coder's output, and the packed stream is the input
+    /// of the last coder.
+    pub fn coder_chain(&self) -> CoderChain {
+        let mut coders: Vec<Coder> = self
+            .filters
+            .iter()
+            .map(|f| Coder::simple(f.coder_id(), f.properties(), self.uncompressed_size))
+            .collect();
+        // Filters keep the data size, so the codec output matches them
+        coders.push(Coder::simple(
+            self.codec.coder_id(),
+            self.codec.properties(),
+            self.uncompressed_size,
+        ));
+        if let Some(encryption) = &self.encryption {
+            coders.push(Coder::simple(
+                &AES_CODER_ID,
+                encryption.properties.clone(),
+                encryption.coded_size,
+            ));
+        }
+        let last = coders.len() as u64 - 1;
+        CoderChain {
+            coders,
+            bind_pairs: (0..last)
+                .map(|i| BindPair {
+                    in_index: i,
+                    out_index: i + 1,
+                })
+                .collect(),
+            packed_streams: vec![last],
+        }
+    }
 }
 
 /// The archive header, built after all compressed data is written.
@@ -56,6 +253,15 @@ pub struct ArchiveHeader {
 }
 
 impl ArchiveHeader {
+    /// Format version to write in the SignatureHeader: 0.4, raised to the
+    /// newest version required by any folder's coders.
+    pub fn required_version(&self) -> FormatVersion {
+        self.folders
+            .iter()
+            .map(|f| f.codec.required_version())
+            .fold(FormatVersion::V0_4, FormatVersion::max)
+    }
+
     /// Serializes the complete header to bytes.
     pub fn serialize(&self) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
@@ -86,7 +292,7 @@ impl ArchiveHeader {
             .map_err(|e| SevenZipError::HeaderError(e.to_string()))?;
 
         self.write_pack_info(w)?;
-        self.write_coders_info(w)?;
+        self.write_coders_info(w, false)?;
         self.write_sub_streams_info(w)?;
 
         w.write_all(&[K_END])
@@ -113,13 +319,37 @@ impl ArchiveHeader {
             write_number(w, folder.compressed_size).map_err(map_err)?;
         }
 
+        // kCRC of the packed streams, if any were recorded (folders kept
+        // from an appended archive may have none)
+        let packed_crcs: Vec<Option<u32>> = self.folders.iter().map(|f| f.packed_crc).collect();
+        write_digests(w, &packed_crcs).map_err(map_err)?;
+
         // kEnd (PackInfo)
         w.write_all(&[K_END]).map_err(map_err)?;
 
         Ok(())
     }
 
-    fn write_coders_info(&self, w: &mut Vec<u8>) -> Result<()> {
+    /// Serializes a `kEncodedHeader` record: streams info describing the
+    /// packed header as this header's single folder, with its CRC stored at
+    /// folder level since there is no SubStreamsInfo.
+    pub fn serialize_encoded(&self) -> Result<Vec<u8>> {
+        let map_err = |e: std::io::Error| SevenZipError::HeaderError(e.to_string());
+        if self.folders.len() != 1 || !self.files.is_empty() {
+            return Err(SevenZipError::HeaderError(
+                "an encoded header describes exactly one folder".to_string(),
+            ));
+        }
+
+        let mut buf = vec![K_ENCODED_HEADER];
+        self.write_pack_info(&mut buf)?;
+        self.write_coders_info(&mut buf, true)?;
+        buf.write_all(&[K_END]).map_err(map_err)?;
+
+        Ok(buf)
+    }
+
+    fn write_coders_info(&self, w: &mut Vec<u8>, folder_crcs: bool) -> Result<()> {
         let map_err = |e: std::io::Error| SevenZipError::HeaderError(e.to_string());
 
         // kUnPackInfo
@@ -132,40 +362,26 @@ impl ArchiveHeader {
         // External = 0 (not external)
         w.write_all(&[0x00]).map_err(map_err)?;
 
-        // For each folder: write the coder info
-        for folder in &self.folders {
-            // NumCoders (NUMBER) = 1
-            write_number(w, 1).map_err(map_err)?;
-
-            // Coder record:
-            //   Flag byte: bits 0-3 = CodecIdSize, bit 4 = IsComplexCoder, bit 5 = HasAttributes
-            //   CodecId bytes
-            //   NumInStreams, NumOutStreams (if complex, omitted for simple)
-            //   PropertiesSize (if has attributes)
-            //   Properties bytes
-
-            // Flag: id_size=1 (bits 0-3), not complex (bit 4=0), has attributes (bit 5=1)
-            // = 0b0010_0001 = 0x21
-            let flag: u8 = (1 & 0x0F) | (1 << 5); // id_size=1, has_attributes=true
-            w.write_all(&[flag]).map_err(map_err)?;
-
-            // CodecId: LZMA2 = 0x21
-            w.write_all(&[LZMA2_CODER_ID]).map_err(map_err)?;
-
-            // PropertiesSize (NUMBER)
-            write_number(w, 1).map_err(map_err)?;
-
-            // Properties: LZMA2 dict size byte
-            w.write_all(&[folder.lzma2_properties_byte]).map_err(map_err)?;
+        let chains: Vec<CoderChain> = self.folders.iter().map(FolderInfo::coder_chain).collect();
+        for chain in &chains {
+            write_folder(w, chain)?;
         }
 
-        // kCodersUnPackSize: uncompressed sizes for each folder's output stream
+        // kCodersUnPackSize: size of every coder output stream, in coder order
         w.write_all(&[K_CODERS_UNPACK_SIZE]).map_err(map_err)?;
-        for folder in &self.folders {
-            write_number(w, folder.uncompressed_size).map_err(map_err)?;
+        for coder in chains.iter().flat_map(|c| &c.coders) {
+            for &size in &coder.unpack_sizes {
+                write_number(w, size).map_err(map_err)?;
+            }
         }
 
-        // kEnd (UnPackInfo) -- CRC is in SubStreamsInfo instead
+        // Folder CRCs, only when there is no SubStreamsInfo to carry them
+        if folder_crcs {
+            let crcs: Vec<Option<u32>> = self.folders.iter().map(|f| f.uncompressed_crc).collect();
+            write_digests(w, &crcs).map_err(map_err)?;
+        }
+
+        // kEnd (UnPackInfo)
         w.write_all(&[K_END]).map_err(map_err)?;
 
         Ok(())
@@ -177,19 +393,35 @@ impl ArchiveHeader {
         // kSubStreamsInfo
         w.write_all(&[K_SUB_STREAMS_INFO]).map_err(map_err)?;
 
-        // NumUnPackStream per folder: default is 1, so we omit it.
-
-        // kCRC for each stream
-        w.write_all(&[K_CRC]).map_err(map_err)?;
-
-        // AllAreDefined = 1 (all streams have CRC)
-        w.write_all(&[0x01]).map_err(map_err)?;
+        // NumUnPackStream per folder: default is 1, so it is only written when
+        // some folder holds several files (solid mode).
+        let all_single = self.folders.iter().all(|f| f.substreams.len() == 1);
+        if !all_single {
+            w.write_all(&[K_NUM_UNPACK_STREAM]).map_err(map_err)?;
+            for folder in &self.folders {
+                write_number(w, folder.substreams.len() as u64).map_err(map_err)?;
+            }
 
-        // CRC32 values (u32 LE, NOT u64)
-        for folder in &self.folders {
-            write_u32_le(w, folder.uncompressed_crc).map_err(map_err)?;
+            // kSize: every substream size except the last of each folder,
+            // which is implied by the folder's unpack size.
+            w.write_all(&[K_SIZE]).map_err(map_err)?;
+            for folder in &self.folders {
+                if let Some((_, leading)) = folder.substreams.split_last() {
+                    for substream in leading {
+                        write_number(w, substream.size).map_err(map_err)?;
+                    }
+                }
+            }
         }
 
+        // kCRC for each stream, left out when none was stored
+        let crcs: Vec<Option<u32>> = self
+            .folders
+            .iter()
+            .flat_map(|f| f.substreams.iter().map(|s| s.crc))
+            .collect();
+        write_digests(w, &crcs).map_err(map_err)?;
+
         // kEnd (SubStreamsInfo)
         w.write_all(&[K_END]).map_err(map_err)?;
 
@@ -205,24 +437,26 @@ impl ArchiveHeader {
         // NumFiles
         write_number(w, self.files.len() as u64).map_err(map_err)?;
 
-        // --- Property: Names ---
-        self.write_names_property(w)?;
+        // Properties are written in the order 7-Zip itself uses (7zOut.cpp):
+        // EmptyStream, EmptyFile, Name, MTime, Attributes.
 
         // --- Property: EmptyStream (if any files have no data) ---
         let empty_stream: Vec<bool> = self.files.iter().map(|f| !f.has_data).collect();
-        if empty_stream.iter().any(|&b| b) {
-            self.write_empty_stream_property(w, &empty_stream)?;
-
-            // EmptyFile: among empty-stream entries, which are files (vs directories)
-            // For now, mark all empty-stream entries as empty files
-            let empty_file: Vec<bool> = self
-                .files
-                .iter()
-                .filter(|f| !f.has_data)
-                .map(|_| true)
-                .collect();
-            self.write_empty_file_property(w, &empty_file)?;
-        }
+        write_bool_vector_property(w, K_EMPTY_STREAM, &empty_stream)?;
+
+        // EmptyFile: among empty-stream entries, which are files (vs directories).
+        // An empty-stream entry without the EmptyFile bit is a directory, so the
+        // property is only needed when at least one empty file is present.
+        let empty_file: Vec<bool> = self
+            .files
+            .iter()
+            .filter(|f| !f.has_data)
+            .map(|f| !f.is_directory)
+            .collect();
+        write_bool_vector_property(w, K_EMPTY_FILE, &empty_file)?;
+
+        // --- Property: Names ---
+        self.write_names_property(w)?;
 
         // --- Property: MTime (if any files have modification times) ---
         let has_any_mtime = self.files.iter().any(|f| f.modified_time.is_some());
@@ -230,6 +464,11 @@ impl ArchiveHeader {
             self.write_mtime_property(w)?;
         }
 
+        // --- Property: Attributes (if any files have attributes) ---
+        if self.files.iter().any(|f| f.attributes.is_some()) {
+            self.write_attributes_property(w)?;
+        }
+
         // kEnd (FilesInfo)
         w.write_all(&[K_END]).map_err(map_err)?;
 
@@ -242,13 +481,14 @@ impl ArchiveHeader {
         w.write_all(&[K_NAME]).map_err(map_err)?;
 
         // Compute the size of the names data: External byte + UTF-16LE names with null terminators
-        let mut names_buf = Vec::new();
+        // (at most as many UTF-16 units as UTF-8 bytes, so the buffer never regrows)
+        let name_units: usize = self.files.iter().map(|f| f.name.len() + 1).sum();
+        let mut names_buf = Vec::with_capacity(1 + 2 * name_units);
         // External = 0
         names_buf.write_all(&[0x00]).map_err(map_err)?;
         for file in &self.files {
-            // Use forward slashes in archive paths
-            let name = file.name.replace('\\', "/");
-            write_utf16le_string(&mut names_buf, &name).map_err(map_err)?;
+            // Names are normalized to forward slashes when queued
+            write_utf16le_string(&mut names_buf, &file.name).map_err(map_err)?;
         }
 
         // PropertySize
@@ -258,27 +498,35 @@ impl ArchiveHeader {
         Ok(())
     }
 
-    fn write_empty_stream_property(&self, w: &mut Vec<u8>, empty_stream: &[bool]) -> Result<()> {
+    fn write_mtime_property(&self, w: &mut Vec<u8>) -> Result<()> {
         let map_err = |e: std::io::Error| SevenZipError::HeaderError(e.to_string());
 
-        w.write_all(&[K_EMPTY_STREAM]).map_err(map_err)?;
+        w.write_all(&[K_M_TIME]).map_err(map_err)?;
 
         let mut data = Vec::new();
-        write_bool_vector(&mut data, empty_stream).map_err(map_err)?;
 
-        write_number(w, data.len() as u64).map_err(map_err)?;
-        w.write_all(&data).map_err(map_err)?;
-
-        Ok(())
-    }
+        // Defined vector: which files have mtime defined
+        let defined: Vec<bool> = self.files.iter().map(|f| f.modified_time.is_some()).collect();
+        let all_defined = defined.iter().all(|&b| b);
 
-    fn write_empty_file_property(&self, w: &mut Vec<u8>, empty_file: &[bool]) -> Result<()> {
-        let map_err = |e: std::io::Error| SevenZipError::HeaderError(e.to_string());
+        if all_defined {
+            // AllAreDefined = 1
+            data.write_all(&[0x01]).map_err(map_err)?;
+        } else {
+            // AllAreDefined = 0, then write defined vector
+            data.write_all(&[0x00]).map_err(map_err)?;
+            write_bool_vector(&mut data, &defined).map_err(map_err)?;
+        }
 
-        w.write_all(&[K_EMPTY_FILE]).map_err(map_err)?;
+        // External = 0
+        data.write_all(&[0x00]).map_err(map_err)?;
 
-        let mut data = Vec::new();
-        write_bool_vector(&mut data, empty_file).map_err(map_err)?;
+        // Write FILETIME values for defined entries
+        for file in &self.files {
+            if let Some(ft) = file.modified_time {
+                write_u64_le(&mut data, ft).map_err(map_err)?;
+            }
+        }
 
         write_number(w, data.len() as u64).map_err(map_err)?;
         w.write_all(&data).map_err(map_err)?;
@@ -286,22 +534,18 @@ impl ArchiveHeader {
         Ok(())
     }
 
-    fn write_mtime_property(&self, w: &mut Vec<u8>) -> Result<()> {
+    fn write_attributes_property(&self, w: &mut Vec<u8>) -> Result<()> {
         let map_err = |e: std::io::Error| SevenZipError::HeaderError(e.to_string());
 
-        w.write_all(&[K_M_TIME]).map_err(map_err)?;
+        w.write_all(&[K_ATTRIBUTES]).map_err(map_err)?;
 
         let mut data = Vec::new();
 
-        // Defined vector: which files have mtime defined
-        let defined: Vec<bool> = self.files.iter().map(|f| f.modified_time.is_some()).collect();
-        let all_defined = defined.iter().all(|&b| b);
-
-        if all_defined {
-            // AllAreDefined = 1
+        // Defined vector, as for MTime
+        let defined: Vec<bool> = self.files.iter().map(|f| f.attributes.is_some()).collect();
+        if defined.iter().all(|&b| b) {
             data.write_all(&[0x01]).map_err(map_err)?;
         } else {
-            // AllAreDefined = 0, then write defined vector
             data.write_all(&[0x00]).map_err(map_err)?;
             write_bool_vector(&mut data, &defined).map_err(map_err)?;
         }
@@ -309,11 +553,8 @@ impl ArchiveHeader {
         // External = 0
         data.write_all(&[0x00]).map_err(map_err)?;
 
-        // Write FILETIME values for defined entries
-        for file in &self.files {
-            if let Some(ft) = file.modified_time {
-                write_u64_le(&mut data, ft).map_err(map_err)?;
-            }
+        for attributes in self.files.iter().filter_map(|f| f.attributes) {
+            write_u32_le(&mut data, attributes).map_err(map_err)?;
         }
 
         write_number(w, data.len() as u64).map_err(map_err)?;
@@ -323,22 +564,236 @@ impl ArchiveHeader {
     }
 }
 
-/// Converts a Unix timestamp (seconds since epoch) to a Windows FILETIME.
-pub fn unix_to_filetime(unix_secs: u64) -> u64 {
-    (unix_secs + 11_644_473_600) * 10_000_000
+/// Writes a bit-vector file property such as EmptyStream, or nothing when no
+/// bit is set: readers take a missing property as all-false, and 7-Zip
+/// omits it in that case.
+fn write_bool_vector_property(w: &mut Vec<u8>, property_id: u8, bits: &[bool]) -> Result<()> {
+    if !bits.contains(&true) {
+        return Ok(());
+    }
+    let map_err = |e: std::io::Error| SevenZipError::HeaderError(e.to_string());
+
+    w.write_all(&[property_id]).map_err(map_err)?;
+
+    let mut data = Vec::new();
+    write_bool_vector(&mut data, bits).map_err(map_err)?;
+
+    write_number(w, data.len() as u64).map_err(map_err)?;
+    w.write_all(&data).map_err(map_err)?;
+
+    Ok(())
+}
+
+/// Writes one folder record: its coders, bind pairs and packed stream
+/// indices. The stream counts must agree: one bind pair per output stream
+/// but the folder's final output, and one packed stream per unbound input.
+fn write_folder(w: &mut Vec<u8>, chain: &CoderChain) -> Result<()> {
+    let map_err = |e: std::io::Error| SevenZipError::HeaderError(e.to_string());
+    let num_in: u64 = chain.coders.iter().map(|c| c.num_in_streams).sum();
+    let num_out: u64 = chain
+        .coders
+        .iter()
+        .map(|c| c.unpack_sizes.len() as u64)
+        .sum();
+    if chain.bind_pairs.len() as u64 + 1 != num_out
+        || chain.packed_streams.len() as u64 + chain.bind_pairs.len() as u64 != num_in
+    {
+        return Err(SevenZipError::HeaderError(format!(
+            "coder chain with {num_in} input and {num_out} output streams has {} bind pairs \
+             and {} packed streams",
+            chain.bind_pairs.len(),
+            chain.packed_streams.len()
+        )));
+    }
+
+    // NumCoders (NUMBER)
+    write_number(w, chain.coders.len() as u64).map_err(map_err)?;
+    for coder in &chain.coders {
+        // Coder record:
+        //   Flag byte: bits 0-3 = CodecIdSize, bit 4 = IsComplexCoder, bit 5 = HasAttributes
+        //   CodecId bytes
+        //   NumInStreams, NumOutStreams (if complex, omitted for simple)
+        //   PropertiesSize (if has attributes)
+        //   Properties bytes
+        let is_complex = if coder.is_complex() { 1 << 4 } else { 0 };
+        let has_attributes = if coder.properties.is_empty() {
+            0
+        } else {
+            1 << 5
+        };
+        w.write_all(&[coder.id.len() as u8 | is_complex | has_attributes])
+            .map_err(map_err)?;
+        w.write_all(&coder.id).map_err(map_err)?;
+        if coder.is_complex() {
+            write_number(w, coder.num_in_streams).map_err(map_err)?;
+            write_number(w, coder.unpack_sizes.len() as u64).map_err(map_err)?;
+        }
+        if !coder.properties.is_empty() {
+            write_number(w, coder.properties.len() as u64).map_err(map_err)?;
+            w.write_all(&coder.properties).map_err(map_err)?;
+        }
+    }
+
+    for pair in &chain.bind_pairs {
+        write_number(w, pair.in_index).map_err(map_err)?;
+        write_number(w, pair.out_index).map_err(map_err)?;
+    }
+    // A single packed stream is the one unbound input, so its index is
+    // implied
+    if chain.packed_streams.len() > 1 {
+        for &index in &chain.packed_streams {
+            write_number(w, index).map_err(map_err)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes a kCRC record for `crcs`, with a defined vector unless all are
+/// known, or nothing when none is.
+fn write_digests(w: &mut Vec<u8>, crcs: &[Option<u32>]) -> std::io::Result<()> {
+    let defined: Vec<bool> = crcs.iter().map(Option::is_some).collect();
+    if !defined.contains(&true) {
+        return Ok(());
+    }
+    w.write_all(&[K_CRC])?;
+    if defined.iter().all(|&b| b) {
+        w.write_all(&[0x01])?; // AllAreDefined
+    } else {
+        w.write_all(&[0x00])?;
+        write_bool_vector(w, &defined)?;
+    }
+    for &crc in crcs.iter().flatten() {
+        write_u32_le(w, crc)?;
+    }
+    Ok(())
+}
+
+/// Read-only Windows attribute.
+pub const FILE_ATTRIBUTE_READONLY: u32 = 0x01;
+/// Hidden Windows attribute.
+pub const FILE_ATTRIBUTE_HIDDEN: u32 = 0x02;
+/// System Windows attribute.
+pub const FILE_ATTRIBUTE_SYSTEM: u32 = 0x04;
+/// Directory Windows attribute.
+pub const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+/// Archive Windows attribute, set on regular files.
+pub const FILE_ATTRIBUTE_ARCHIVE: u32 = 0x20;
+/// 7-Zip extension flag: the high 16 bits hold a Unix `st_mode`.
+pub const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+/// Unix `st_mode` file type bits of a symbolic link.
+pub const S_IFLNK: u32 = 0o120000;
+
+/// Packs a Unix `st_mode` into a 7z attribute word, following 7-Zip: the
+/// mode goes in the high 16 bits behind `FILE_ATTRIBUTE_UNIX_EXTENSION`,
+/// with matching Windows attributes in the low bits for other platforms.
+pub fn unix_mode_to_attributes(mode: u32, is_directory: bool) -> u32 {
+    let mut attributes = if is_directory {
+        FILE_ATTRIBUTE_DIRECTORY
+    } else {
+        FILE_ATTRIBUTE_ARCHIVE
+    };
+    if mode & 0o222 == 0 {
+        attributes |= FILE_ATTRIBUTE_READONLY;
+    }
+    attributes | FILE_ATTRIBUTE_UNIX_EXTENSION | ((mode & 0xFFFF) << 16)
+}
+
+/// Keeps the Windows attributes worth restoring on extraction (read-only,
+/// hidden, system, directory and archive) from a raw `file_attributes()`
+/// word. Bits describing on-disk storage, such as compressed, sparse or
+/// reparse point, are dropped.
+pub fn windows_attributes(raw: u32) -> u32 {
+    raw & (FILE_ATTRIBUTE_READONLY
+        | FILE_ATTRIBUTE_HIDDEN
+        | FILE_ATTRIBUTE_SYSTEM
+        | FILE_ATTRIBUTE_DIRECTORY
+        | FILE_ATTRIBUTE_ARCHIVE)
+}
+
+/// Converts a Unix timestamp (seconds since epoch, plus nanoseconds) to a
+/// Windows FILETIME, keeping its 100 ns resolution. `None` past the last
+/// FILETIME, in the year 60056.
+pub fn unix_to_filetime(unix_secs: u64, nanos: u32) -> Option<u64> {
+    unix_secs
+        .checked_add(11_644_473_600)?
+        .checked_mul(10_000_000)?
+        .checked_add(u64::from(nanos / 100))
+}
+
+/// Converts a point in time to a Windows FILETIME, or `None` if it is
+/// before the Unix epoch or past the last FILETIME.
+pub fn system_time_to_filetime(time: std::time::SystemTime) -> Option<u64> {
+    let since_epoch = time.duration_since(std::time::UNIX_EPOCH).ok()?;
+    unix_to_filetime(since_epoch.as_secs(), since_epoch.subsec_nanos())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::io::reader::read_number;
+
+    #[test]
+    fn test_unix_mode_to_attributes() {
+        // Regular executable file: S_IFREG | 0755
+        assert_eq!(unix_mode_to_attributes(0o100755, false), 0x81ED_8020);
+        // Directory: S_IFDIR | 0755
+        assert_eq!(unix_mode_to_attributes(0o040755, true), 0x41ED_8010);
+        // Read-only file: S_IFREG | 0444
+        assert_eq!(unix_mode_to_attributes(0o100444, false), 0x8124_8021);
+    }
+
+    #[test]
+    fn test_windows_attributes() {
+        // Hidden, read-only, sparse (0x200) and reparse point (0x400)
+        assert_eq!(
+            windows_attributes(0x02 | 0x01 | 0x200 | 0x400),
+            FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_READONLY
+        );
+        assert_eq!(windows_attributes(0x10 | 0x800), FILE_ATTRIBUTE_DIRECTORY);
+        // Never claims the Unix extension
+        assert_eq!(
+            windows_attributes(u32::MAX) & FILE_ATTRIBUTE_UNIX_EXTENSION,
+            0
+        );
+    }
 
     #[test]
     fn test_unix_to_filetime() {
         // Unix epoch = Jan 1 1970 -> FILETIME for that
-        let ft = unix_to_filetime(0);
+        let ft = unix_to_filetime(0, 0).unwrap();
         assert_eq!(ft, 116_444_736_000_000_000);
     }
 
+    #[test]
+    fn test_unix_to_filetime_sub_second() {
+        let whole = unix_to_filetime(1_700_000_000, 0).unwrap();
+        assert_eq!(
+            unix_to_filetime(1_700_000_000, 123_456_789),
+            Some(whole + 1_234_567)
+        );
+        // Below FILETIME's 100 ns resolution
+        assert_eq!(unix_to_filetime(1_700_000_000, 99), Some(whole));
+        assert_eq!(
+            unix_to_filetime(1_700_000_000, 999_999_999),
+            Some(whole + 9_999_999)
+        );
+    }
+
+    #[test]
+    fn test_filetime_range() {
+        let max_secs = u64::MAX / 10_000_000 - 11_644_473_600;
+        assert!(unix_to_filetime(max_secs, 0).is_some());
+        assert_eq!(unix_to_filetime(max_secs + 1, 0), None);
+        assert_eq!(unix_to_filetime(1 << 62, 0), None);
+        assert_eq!(unix_to_filetime(u64::MAX, 0), None);
+
+        let far = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1 << 62);
+        assert_eq!(system_time_to_filetime(far), None);
+        let before = std::time::UNIX_EPOCH - std::time::Duration::from_secs(1);
+        assert_eq!(system_time_to_filetime(before), None);
+    }
+
     #[test]
     fn test_serialize_empty_archive() {
         let header = ArchiveHeader {
@@ -356,17 +811,28 @@ mod tests {
         let header = ArchiveHeader {
             folders: vec![FolderInfo {
                 compressed_size: 100,
+                packed_crc: None,
                 uncompressed_size: 200,
-                uncompressed_crc: 0x12345678,
-                lzma2_properties_byte: 23,
+                uncompressed_crc: Some(0x12345678),
+                codec: FolderCodec::Lzma2 {
+                    properties_byte: 23,
+                },
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 200,
+                    crc: Some(0x12345678),
+                }],
             }],
             files: vec![FileEntry {
                 name: "test.txt".to_string(),
                 uncompressed_size: 200,
                 compressed_size: 100,
-                crc: 0x12345678,
+                crc: Some(0x12345678),
                 has_data: true,
+                is_directory: false,
                 modified_time: None,
+                attributes: None,
             }],
             pack_position: 0,
         };
@@ -377,4 +843,730 @@ mod tests {
         // Should end with kEnd
         assert_eq!(*data.last().unwrap(), K_END);
     }
+
+    #[test]
+    fn test_sub_streams_info_solid_folder() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 50,
+                packed_crc: None,
+                uncompressed_size: 30,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Lzma2 {
+                    properties_byte: 16,
+                },
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![
+                    SubStreamInfo {
+                        size: 10,
+                        crc: Some(1),
+                    },
+                    SubStreamInfo {
+                        size: 20,
+                        crc: Some(2),
+                    },
+                ],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_sub_streams_info(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                K_SUB_STREAMS_INFO,
+                K_NUM_UNPACK_STREAM,
+                2,
+                K_SIZE,
+                10, // only the first size; the last is implied
+                K_CRC,
+                0x01,
+                1,
+                0,
+                0,
+                0,
+                2,
+                0,
+                0,
+                0,
+                K_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sub_streams_info_single_stream_folders() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 5,
+                packed_crc: None,
+                uncompressed_size: 10,
+                uncompressed_crc: Some(7),
+                codec: FolderCodec::Lzma2 {
+                    properties_byte: 16,
+                },
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 10,
+                    crc: Some(7),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_sub_streams_info(&mut buf).unwrap();
+        // No kNumUnpackStream / kSize when every folder holds one file
+        assert_eq!(
+            buf,
+            vec![K_SUB_STREAMS_INFO, K_CRC, 0x01, 7, 0, 0, 0, K_END]
+        );
+    }
+
+    #[test]
+    fn test_sub_streams_info_without_crcs() {
+        let folder = |crc| FolderInfo {
+            compressed_size: 5,
+            packed_crc: None,
+            uncompressed_size: 10,
+            uncompressed_crc: crc,
+            codec: FolderCodec::Copy,
+            filters: Vec::new(),
+            encryption: None,
+            substreams: vec![SubStreamInfo { size: 10, crc }],
+        };
+        let mut header = ArchiveHeader {
+            folders: vec![folder(None), folder(None)],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_sub_streams_info(&mut buf).unwrap();
+        // No kCRC record at all
+        assert_eq!(buf, vec![K_SUB_STREAMS_INFO, K_END]);
+
+        // An appended folder with a CRC gets a defined vector
+        header.folders[1] = folder(Some(7));
+        buf.clear();
+        header.write_sub_streams_info(&mut buf).unwrap();
+        assert_eq!(
+            buf,
+            vec![K_SUB_STREAMS_INFO, K_CRC, 0x00, 0x40, 7, 0, 0, 0, K_END]
+        );
+    }
+
+    #[test]
+    fn test_pack_info_with_packed_crcs() {
+        let folder = |packed_crc| FolderInfo {
+            compressed_size: 3,
+            packed_crc,
+            uncompressed_size: 3,
+            uncompressed_crc: Some(0),
+            codec: FolderCodec::Copy,
+            filters: Vec::new(),
+            encryption: None,
+            substreams: vec![SubStreamInfo {
+                size: 3,
+                crc: Some(0),
+            }],
+        };
+        let mut header = ArchiveHeader {
+            folders: vec![folder(Some(0x0403_0201)), folder(None)],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_pack_info(&mut buf).unwrap();
+        // Only the first folder's CRC is defined
+        assert_eq!(buf[..6], [K_PACK_INFO, 0, 2, K_SIZE, 3, 3]);
+        assert_eq!(buf[6..], [K_CRC, 0x00, 0x80, 1, 2, 3, 4, K_END]);
+
+        header.folders[1].packed_crc = Some(5);
+        buf.clear();
+        header.write_pack_info(&mut buf).unwrap();
+        assert_eq!(buf[6..], [K_CRC, 0x01, 1, 2, 3, 4, 5, 0, 0, 0, K_END]);
+
+        header.folders.iter_mut().for_each(|f| f.packed_crc = None);
+        buf.clear();
+        header.write_pack_info(&mut buf).unwrap();
+        assert_eq!(buf, vec![K_PACK_INFO, 0, 2, K_SIZE, 3, 3, K_END]);
+    }
+
+    #[test]
+    fn test_coders_info_copy_folder() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 3,
+                packed_crc: None,
+                uncompressed_size: 3,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Copy,
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 3,
+                    crc: Some(0),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_coders_info(&mut buf, false).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                K_UNPACK_INFO,
+                K_FOLDER,
+                1,    // NumFolders
+                0x00, // External
+                1,    // NumCoders
+                0x01, // flag: id_size=1, simple, no attributes
+                COPY_CODER_ID,
+                K_CODERS_UNPACK_SIZE,
+                3,
+                K_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coders_info_lzma1_folder() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 40,
+                packed_crc: None,
+                uncompressed_size: 100,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Lzma1 {
+                    properties: [0x5D, 0x00, 0x00, 0x80, 0x00],
+                },
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 100,
+                    crc: Some(0),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_coders_info(&mut buf, false).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                K_UNPACK_INFO,
+                K_FOLDER,
+                1,    // NumFolders
+                0x00, // External
+                1,    // NumCoders
+                0x23, // flag: id_size=3, simple, has attributes
+                0x03,
+                0x01,
+                0x01,
+                5, // PropertiesSize
+                0x5D,
+                0x00,
+                0x00,
+                0x80,
+                0x00,
+                K_CODERS_UNPACK_SIZE,
+                100,
+                K_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coders_info_bzip2_folder() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 40,
+                packed_crc: None,
+                uncompressed_size: 100,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Bzip2,
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 100,
+                    crc: Some(0),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_coders_info(&mut buf, false).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                K_UNPACK_INFO,
+                K_FOLDER,
+                1,    // NumFolders
+                0x00, // External
+                1,    // NumCoders
+                0x03, // flag: id_size=3, simple, no attributes
+                0x04,
+                0x02,
+                0x02,
+                K_CODERS_UNPACK_SIZE,
+                100,
+                K_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coders_info_encrypted_folder() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 32,
+                packed_crc: None,
+                uncompressed_size: 100,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Lzma2 {
+                    properties_byte: 16,
+                },
+                filters: Vec::new(),
+                encryption: Some(FolderEncryption {
+                    properties: vec![0xAA, 0xBB],
+                    coded_size: 20,
+                }),
+                substreams: vec![SubStreamInfo {
+                    size: 100,
+                    crc: Some(0),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_coders_info(&mut buf, false).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                K_UNPACK_INFO,
+                K_FOLDER,
+                1,    // NumFolders
+                0x00, // External
+                2,    // NumCoders
+                0x21, // LZMA2: id_size=1, has attributes
+                LZMA2_CODER_ID,
+                1,
+                16,
+                0x24, // AES: id_size=4, has attributes
+                0x06,
+                0xF1,
+                0x07,
+                0x01,
+                2,
+                0xAA,
+                0xBB,
+                0, // BindPair in index
+                1, // BindPair out index
+                K_CODERS_UNPACK_SIZE,
+                100,
+                20,
+                K_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coders_info_two_coder_folder() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 10,
+                packed_crc: None,
+                uncompressed_size: 200,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Lzma2 {
+                    properties_byte: 16,
+                },
+                filters: vec![Filter::Delta { distance: 4 }],
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 200,
+                    crc: Some(0),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let chain = header.folders[0].coder_chain();
+        assert_eq!(
+            chain.bind_pairs,
+            [BindPair {
+                in_index: 0,
+                out_index: 1
+            }]
+        );
+        assert_eq!(chain.packed_streams, [1]);
+
+        let mut buf = Vec::new();
+        header.write_coders_info(&mut buf, false).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                K_UNPACK_INFO,
+                K_FOLDER,
+                1,    // NumFolders
+                0x00, // External
+                2,    // NumCoders
+                0x21, // Delta: id_size=1, has attributes
+                0x03,
+                1,
+                3,    // distance - 1
+                0x21, // LZMA2: id_size=1, has attributes
+                LZMA2_CODER_ID,
+                1,
+                16,
+                0, // Delta input <- LZMA2 output
+                1,
+                K_CODERS_UNPACK_SIZE,
+                0x80, // Delta: 200, as a two-byte NUMBER
+                200,
+                0x80, // LZMA2
+                200,
+                K_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_folder_complex_coder() {
+        // A coder reading two packed streams, as BCJ2 does with its call
+        // and jump streams
+        let chain = CoderChain {
+            coders: vec![Coder {
+                id: vec![0x0A],
+                properties: Vec::new(),
+                num_in_streams: 2,
+                unpack_sizes: vec![50],
+            }],
+            bind_pairs: Vec::new(),
+            packed_streams: vec![1, 0],
+        };
+        let mut buf = Vec::new();
+        write_folder(&mut buf, &chain).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                1,    // NumCoders
+                0x11, // flag: id_size=1, complex, no attributes
+                0x0A, // coder ID
+                2,    // NumInStreams
+                1,    // NumOutStreams
+                1,    // packed stream 0 <- input 1
+                0,    // packed stream 1 <- input 0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_folder_rejects_unwired_streams() {
+        // Two simple coders need one bind pair
+        let chain = CoderChain {
+            coders: vec![
+                Coder::simple(&[0x03], vec![3], 8),
+                Coder::simple(&[COPY_CODER_ID], Vec::new(), 8),
+            ],
+            bind_pairs: Vec::new(),
+            packed_streams: vec![1],
+        };
+        assert!(matches!(
+            write_folder(&mut Vec::new(), &chain),
+            Err(SevenZipError::HeaderError(_))
+        ));
+    }
+
+    #[test]
+    fn test_coders_info_filter_chain() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 32,
+                packed_crc: None,
+                uncompressed_size: 100,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Lzma2 {
+                    properties_byte: 16,
+                },
+                filters: vec![Filter::BcjX86],
+                encryption: Some(FolderEncryption {
+                    properties: vec![0xAA],
+                    coded_size: 20,
+                }),
+                substreams: vec![SubStreamInfo {
+                    size: 100,
+                    crc: Some(0),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_coders_info(&mut buf, false).unwrap();
+        assert_eq!(
+            buf,
+            vec![
+                K_UNPACK_INFO,
+                K_FOLDER,
+                1,    // NumFolders
+                0x00, // External
+                3,    // NumCoders
+                0x04, // BCJ: id_size=4, no attributes
+                0x03,
+                0x03,
+                0x01,
+                0x03,
+                0x21, // LZMA2: id_size=1, has attributes
+                LZMA2_CODER_ID,
+                1,
+                16,
+                0x24, // AES: id_size=4, has attributes
+                0x06,
+                0xF1,
+                0x07,
+                0x01,
+                1,
+                0xAA,
+                0, // BCJ input <- LZMA2 output
+                1,
+                1, // LZMA2 input <- AES output
+                2,
+                K_CODERS_UNPACK_SIZE,
+                100, // BCJ
+                100, // LZMA2
+                20,  // AES
+                K_END,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_files_info_property_order_matches_7zip() {
+        let entry = |name: &str, has_data: bool, is_directory: bool| FileEntry {
+            name: name.to_string(),
+            uncompressed_size: 0,
+            compressed_size: 0,
+            crc: None,
+            has_data,
+            is_directory,
+            modified_time: unix_to_filetime(1_700_000_000, 0),
+            attributes: Some(0x20),
+        };
+        let header = ArchiveHeader {
+            folders: vec![],
+            files: vec![
+                entry("data.bin", true, false),
+                entry("empty.txt", false, false),
+                entry("dir", false, true),
+            ],
+            pack_position: 0,
+        };
+        let mut buf = Vec::new();
+        header.write_files_info(&mut buf).unwrap();
+
+        let mut r = &buf[..];
+        assert_eq!(read_number(&mut r).unwrap(), K_FILES_INFO as u64);
+        assert_eq!(read_number(&mut r).unwrap(), 3);
+        let mut ids = Vec::new();
+        loop {
+            let id = read_number(&mut r).unwrap() as u8;
+            if id == K_END {
+                break;
+            }
+            let size = read_number(&mut r).unwrap() as usize;
+            r = &r[size..];
+            ids.push(id);
+        }
+        assert!(r.is_empty());
+        assert_eq!(
+            ids,
+            [K_EMPTY_STREAM, K_EMPTY_FILE, K_NAME, K_M_TIME, K_ATTRIBUTES]
+        );
+    }
+
+    #[test]
+    fn test_attributes_property_defined_vector() {
+        let attributes_property = |attributes: &[Option<u32>]| {
+            let header = ArchiveHeader {
+                folders: vec![],
+                files: attributes
+                    .iter()
+                    .map(|&attributes| FileEntry {
+                        name: "f".to_string(),
+                        uncompressed_size: 0,
+                        compressed_size: 0,
+                        crc: None,
+                        has_data: false,
+                        is_directory: false,
+                        modified_time: None,
+                        attributes,
+                    })
+                    .collect(),
+                pack_position: 0,
+            };
+            let mut buf = Vec::new();
+            header.write_attributes_property(&mut buf).unwrap();
+            buf
+        };
+
+        // All defined: AllAreDefined = 1 and no bit vector
+        assert_eq!(
+            attributes_property(&[Some(0x20), Some(0x10)]),
+            [K_ATTRIBUTES, 10, 1, 0, 0x20, 0, 0, 0, 0x10, 0, 0, 0]
+        );
+        // Partly defined: AllAreDefined = 0, the bit vector, then only the
+        // defined values
+        let mut expected = vec![K_ATTRIBUTES, 11, 0, 0b1010_0000, 0];
+        expected.extend(0x20u32.to_le_bytes());
+        expected.extend(0x81A4_8020u32.to_le_bytes());
+        assert_eq!(
+            attributes_property(&[Some(0x20), None, Some(0x81A4_8020)]),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_bool_vector_property_omitted_when_all_false() {
+        let mut buf = Vec::new();
+        write_bool_vector_property(&mut buf, K_EMPTY_STREAM, &[false; 20]).unwrap();
+        write_bool_vector_property(&mut buf, K_EMPTY_FILE, &[]).unwrap();
+        assert!(buf.is_empty());
+
+        write_bool_vector_property(&mut buf, K_EMPTY_STREAM, &[false, true]).unwrap();
+        assert_eq!(buf, [K_EMPTY_STREAM, 1, 0b0100_0000]);
+    }
+
+    #[test]
+    fn test_files_info_empty_properties() {
+        let property_ids = |files: Vec<FileEntry>| {
+            let header = ArchiveHeader {
+                folders: vec![],
+                files,
+                pack_position: 0,
+            };
+            let mut buf = Vec::new();
+            header.write_files_info(&mut buf).unwrap();
+            let mut r = &buf[2..];
+            let mut ids = Vec::new();
+            loop {
+                let id = read_number(&mut r).unwrap() as u8;
+                if id == K_END {
+                    break ids;
+                }
+                let size = read_number(&mut r).unwrap() as usize;
+                r = &r[size..];
+                ids.push(id);
+            }
+        };
+        let entry = |has_data: bool, is_directory: bool| FileEntry {
+            name: "x".to_string(),
+            uncompressed_size: 0,
+            compressed_size: 0,
+            crc: None,
+            has_data,
+            is_directory,
+            modified_time: None,
+            attributes: None,
+        };
+
+        // Every entry has data: neither property
+        let files = (0..16).map(|_| entry(true, false)).collect();
+        assert_eq!(property_ids(files), [K_NAME]);
+        // Directories only: EmptyStream without EmptyFile
+        let files = vec![entry(true, false), entry(false, true)];
+        assert_eq!(property_ids(files), [K_EMPTY_STREAM, K_NAME]);
+    }
+
+    #[test]
+    fn test_required_version_defaults_to_0_4() {
+        let empty = ArchiveHeader {
+            folders: vec![],
+            files: vec![],
+            pack_position: 0,
+        };
+        assert_eq!(empty.required_version(), FormatVersion::V0_4);
+
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 3,
+                packed_crc: None,
+                uncompressed_size: 3,
+                uncompressed_crc: Some(0),
+                codec: FolderCodec::Copy,
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 3,
+                    crc: Some(0),
+                }],
+            }],
+            files: vec![],
+            pack_position: 0,
+        };
+        assert_eq!(header.required_version(), FormatVersion::V0_4);
+    }
+
+    #[test]
+    fn test_serialize_encoded_header() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: 40,
+                packed_crc: None,
+                uncompressed_size: 300,
+                uncompressed_crc: Some(0x04030201),
+                codec: FolderCodec::Lzma2 {
+                    properties_byte: 16,
+                },
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo {
+                    size: 300,
+                    crc: Some(0x04030201),
+                }],
+            }],
+            files: vec![],
+            pack_position: 100,
+        };
+        let data = header.serialize_encoded().unwrap();
+        assert_eq!(
+            data,
+            vec![
+                K_ENCODED_HEADER,
+                K_PACK_INFO,
+                100, // PackPos
+                1,   // NumPackStreams
+                K_SIZE,
+                40,
+                K_END,
+                K_UNPACK_INFO,
+                K_FOLDER,
+                1,
+                0x00,
+                1,
+                0x21,
+                LZMA2_CODER_ID,
+                1,
+                16,
+                K_CODERS_UNPACK_SIZE,
+                0x81, // 300 as a two-byte NUMBER
+                0x2C,
+                K_CRC,
+                0x01,
+                0x01,
+                0x02,
+                0x03,
+                0x04,
+                K_END,
+                K_END,
+            ]
+        );
+    }
 }
@@ -1,3 +1,11 @@
 pub mod builder;
+pub mod disk;
 pub mod header;
+pub mod multi;
+pub mod options;
+pub mod path;
+pub mod pattern;
+pub mod progress;
+pub mod reader;
+pub mod scan;
 pub mod writer;
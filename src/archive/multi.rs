@@ -0,0 +1,159 @@
+use crate::archive::builder::SevenZipWriter;
+use crate::error::{Result, SevenZipError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Magic bytes closing a multi-archive index.
+pub const MULTI_ARCHIVE_MAGIC: [u8; 8] = *b"7zMULTI\x01";
+
+/// Size of the fixed index trailer: archive count (u64 LE) + magic.
+const TRAILER_SIZE: u64 = 16;
+
+/// Writes several independent 7z archives back-to-back into one output,
+/// followed by an index of their start offsets.
+///
+/// Each sub-archive is self-contained: its header offsets are relative to
+/// its own SignatureHeader, so the bytes starting at a recorded offset form
+/// a valid 7z archive.
+///
+/// Index layout (appended by `finish()`):
+///   [offset_0 .. offset_{n-1}]  u64 LE each
+///   [n]                         u64 LE
+///   [MULTI_ARCHIVE_MAGIC]       8 bytes
+///
+/// # Example
+/// ```no_run
+/// use sevenzip_mt::MultiArchiveWriter;
+///
+/// let file = std::fs::File::create("bundle.bin").unwrap();
+/// let mut bundle = MultiArchiveWriter::new(file);
+///
+/// let mut first = bundle.begin_archive().unwrap();
+/// first.add_bytes("a.txt", b"first").unwrap();
+/// first.finish().unwrap();
+///
+/// let mut second = bundle.begin_archive().unwrap();
+/// second.add_bytes("b.txt", b"second").unwrap();
+/// second.finish().unwrap();
+///
+/// bundle.finish().unwrap();
+/// ```
+pub struct MultiArchiveWriter<W: Write + Seek> {
+    writer: W,
+    offsets: Vec<u64>,
+}
+
+impl<W: Write + Seek> MultiArchiveWriter<W> {
+    /// Creates a new multi-archive writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offsets: Vec::new(),
+        }
+    }
+
+    /// Starts a new sub-archive at the end of the output.
+    ///
+    /// The returned writer must be finished before the next sub-archive is
+    /// started; an unfinished sub-archive leaves a zeroed SignatureHeader.
+    pub fn begin_archive(&mut self) -> Result<SevenZipWriter<&mut W>> {
+        let offset = self.writer.seek(SeekFrom::End(0))?;
+        self.offsets.push(offset);
+        SevenZipWriter::new(&mut self.writer)
+    }
+
+    /// Returns the start offsets of the sub-archives begun so far.
+    pub fn offsets(&self) -> &[u64] {
+        &self.offsets
+    }
+
+    /// Writes the trailing index and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.writer.seek(SeekFrom::End(0))?;
+        for &offset in &self.offsets {
+            self.writer.write_u64::<LittleEndian>(offset)?;
+        }
+        self.writer
+            .write_u64::<LittleEndian>(self.offsets.len() as u64)?;
+        self.writer.write_all(&MULTI_ARCHIVE_MAGIC)?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads the sub-archive start offsets from a multi-archive output.
+pub fn read_multi_archive_index<R: Read + Seek>(reader: &mut R) -> Result<Vec<u64>> {
+    let total_len = reader.seek(SeekFrom::End(0))?;
+    if total_len < TRAILER_SIZE {
+        return Err(SevenZipError::HeaderError(
+            "multi-archive index: output too short".to_string(),
+        ));
+    }
+
+    reader.seek(SeekFrom::Start(total_len - TRAILER_SIZE))?;
+    let count = reader.read_u64::<LittleEndian>()?;
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != MULTI_ARCHIVE_MAGIC {
+        return Err(SevenZipError::HeaderError(
+            "multi-archive index: missing magic".to_string(),
+        ));
+    }
+
+    let index_len = count
+        .checked_mul(8)
+        .filter(|&len| len <= total_len - TRAILER_SIZE)
+        .ok_or_else(|| {
+            SevenZipError::HeaderError(format!(
+                "multi-archive index: archive count {count} exceeds output size"
+            ))
+        })?;
+
+    reader.seek(SeekFrom::Start(total_len - TRAILER_SIZE - index_len))?;
+    let mut offsets = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        offsets.push(reader.read_u64::<LittleEndian>()?);
+    }
+    Ok(offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::header::SIGNATURE;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_index_roundtrip() {
+        let mut bundle = MultiArchiveWriter::new(Cursor::new(Vec::new()));
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let mut archive = bundle.begin_archive().unwrap();
+            archive.add_bytes(name, name.as_bytes()).unwrap();
+            archive.finish().unwrap();
+        }
+        let expected = bundle.offsets().to_vec();
+        let mut cursor = bundle.finish().unwrap();
+
+        let offsets = read_multi_archive_index(&mut cursor).unwrap();
+        assert_eq!(offsets, expected);
+        assert_eq!(offsets[0], 0);
+
+        let data = cursor.into_inner();
+        for &offset in &offsets {
+            let start = offset as usize;
+            assert_eq!(&data[start..start + 6], &SIGNATURE);
+        }
+    }
+
+    #[test]
+    fn test_index_empty_bundle() {
+        let bundle = MultiArchiveWriter::new(Cursor::new(Vec::new()));
+        let mut cursor = bundle.finish().unwrap();
+        assert!(read_multi_archive_index(&mut cursor).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_index_missing_magic() {
+        let mut cursor = Cursor::new(vec![0u8; 32]);
+        assert!(read_multi_archive_index(&mut cursor).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use crate::compression::filter::Filter;
+use crate::compression::lzma2::{Codec, Lzma2Config};
+
+/// Per-entry overrides of the archive-wide [`Lzma2Config`], passed to
+/// [`SevenZipWriter::add_file_with_options`](crate::SevenZipWriter::add_file_with_options)
+/// or [`add_bytes_with_options`](crate::SevenZipWriter::add_bytes_with_options).
+///
+/// Each entry is its own folder with its own coder chain; in solid mode,
+/// consecutive entries with equal options share a folder.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryOptions {
+    /// Compression method, or `None` for the archive's `Lzma2Config::codec`
+    /// (or its `compressor`, which a codec set here replaces).
+    pub codec: Option<Codec>,
+    /// Filters applied before compression, replacing the archive's
+    /// `Lzma2Config::filters`.
+    pub filters: Vec<Filter>,
+}
+
+impl EntryOptions {
+    /// The archive configuration with these overrides applied.
+    pub(crate) fn apply(&self, config: &Lzma2Config) -> Lzma2Config {
+        Lzma2Config {
+            codec: self.codec.unwrap_or(config.codec),
+            // An explicit codec replaces the archive's custom compressor
+            compressor: match self.codec {
+                Some(_) => None,
+                None => config.compressor.clone(),
+            },
+            filters: self.filters.clone(),
+            ..config.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_overrides() {
+        let archive = Lzma2Config {
+            preset: 9,
+            filters: vec![Filter::BcjX86],
+            ..Default::default()
+        };
+
+        let stored = EntryOptions {
+            codec: Some(Codec::Copy),
+            ..Default::default()
+        }
+        .apply(&archive);
+        assert_eq!(stored.codec, Codec::Copy);
+        assert!(stored.filters.is_empty());
+        assert_eq!(stored.preset, 9);
+
+        let delta = EntryOptions {
+            codec: None,
+            filters: vec![Filter::Delta { distance: 4 }],
+        }
+        .apply(&archive);
+        assert_eq!(delta.codec, Codec::Lzma2);
+        assert_eq!(delta.filters, [Filter::Delta { distance: 4 }]);
+    }
+}
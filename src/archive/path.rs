@@ -0,0 +1,334 @@
+use crate::error::{Result, SevenZipError};
+use std::ffi::OsStr;
+use std::fmt::Write;
+
+/// How archive names are normalized when entries are queued, set with
+/// [`SevenZipWriter::set_path_normalization`](crate::SevenZipWriter::set_path_normalization).
+///
+/// Backslashes always become `/`, the 7z separator. The default strips
+/// drive letters, resolves `.` and `..` segments and removes the leading
+/// `/` of absolute names, much like 7-Zip does for paths given to `7z a`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathNormalization {
+    /// Removes a leading drive letter, e.g. `C:` (default: true).
+    pub strip_drive_letter: bool,
+    /// Drops empty and `.` segments and resolves `..` against the preceding
+    /// segment (default: true). A `..` climbing above the archive root is
+    /// rejected.
+    pub collapse_dot_segments: bool,
+    /// Rejects names that are still absolute (a leading `/`, or a drive
+    /// letter that is kept) instead of removing their leading `/`
+    /// (default: false).
+    pub reject_absolute: bool,
+    /// Number of leading path components removed, as with tar's
+    /// `--strip-components` (default: 0).
+    pub strip_components: usize,
+    /// What to do with file names found on disk that are not valid UTF-8
+    /// (default: [`NonUtf8Names::Reject`]).
+    pub non_utf8_names: NonUtf8Names,
+}
+
+/// How file names that are not valid UTF-8, which Unix filesystems allow,
+/// become archive names. 7z stores names as UTF-16, so such a name cannot
+/// be stored as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonUtf8Names {
+    /// Fails with `SevenZipError::InvalidState`.
+    #[default]
+    Reject,
+    /// Replaces each invalid sequence with U+FFFD, as
+    /// [`OsStr::to_string_lossy`] does. This is not reversible: the
+    /// original bytes are lost, and names differing only in their invalid
+    /// bytes collide.
+    Lossy,
+    /// Writes each invalid byte, and each `%` of the name, as `%XX`, so the
+    /// original bytes can be restored by percent-decoding. Valid names are
+    /// kept as they are, so a valid name that looks percent-encoded cannot
+    /// be told apart from an encoded one.
+    PercentEncode,
+}
+
+impl std::str::FromStr for NonUtf8Names {
+    type Err = SevenZipError;
+
+    /// Parses a policy name, case-insensitively: `reject`, `lossy` or
+    /// `percent-encode`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "reject" => Ok(NonUtf8Names::Reject),
+            "lossy" => Ok(NonUtf8Names::Lossy),
+            "percent-encode" => Ok(NonUtf8Names::PercentEncode),
+            _ => Err(SevenZipError::InvalidState(format!(
+                "unknown non-UTF-8 name policy '{s}' (expected reject, lossy or percent-encode)"
+            ))),
+        }
+    }
+}
+
+impl Default for PathNormalization {
+    fn default() -> Self {
+        Self {
+            strip_drive_letter: true,
+            collapse_dot_segments: true,
+            reject_absolute: false,
+            strip_components: 0,
+            non_utf8_names: NonUtf8Names::Reject,
+        }
+    }
+}
+
+impl PathNormalization {
+    /// Normalizes an archive name, returning `SevenZipError::InvalidState`
+    /// for names that are rejected or end up empty, and
+    /// `SevenZipError::StrippedAway` for names with no more than
+    /// `strip_components` components.
+    pub fn normalize(&self, name: &str) -> Result<String> {
+        let invalid = |reason: &str| {
+            SevenZipError::InvalidState(format!("invalid archive name '{name}': {reason}"))
+        };
+
+        let mut path = name.replace('\\', "/");
+        let has_drive =
+            matches!(path.as_bytes(), [letter, b':', ..] if letter.is_ascii_alphabetic());
+        if has_drive && self.strip_drive_letter {
+            path.drain(..2);
+        }
+        let is_absolute = path.starts_with('/') || (has_drive && !self.strip_drive_letter);
+        if is_absolute {
+            if self.reject_absolute {
+                return Err(invalid("absolute path"));
+            }
+            path = path.trim_start_matches('/').to_string();
+        }
+
+        if self.collapse_dot_segments {
+            let mut segments: Vec<&str> = Vec::new();
+            for segment in path.split('/') {
+                match segment {
+                    "" | "." => {}
+                    ".." => {
+                        segments
+                            .pop()
+                            .ok_or_else(|| invalid("'..' climbs above the archive root"))?;
+                    }
+                    _ => segments.push(segment),
+                }
+            }
+            path = segments.join("/");
+        }
+
+        if self.strip_components > 0 {
+            let stripped: Vec<&str> = path
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .skip(self.strip_components)
+                .collect();
+            if stripped.is_empty() {
+                return Err(SevenZipError::StrippedAway(name.to_string()));
+            }
+            path = stripped.join("/");
+        }
+
+        if path.is_empty() {
+            return Err(invalid("empty name"));
+        }
+        Ok(path)
+    }
+
+    /// Converts a file name or path from disk into a string to build an
+    /// archive name from, applying `non_utf8_names` if it is not valid
+    /// UTF-8.
+    pub fn name_from_os(&self, name: &OsStr) -> Result<String> {
+        if let Some(name) = name.to_str() {
+            return Ok(name.to_string());
+        }
+        match self.non_utf8_names {
+            NonUtf8Names::Reject => Err(SevenZipError::InvalidState(format!(
+                "non-UTF-8 file name: {}",
+                name.to_string_lossy()
+            ))),
+            NonUtf8Names::Lossy => Ok(name.to_string_lossy().into_owned()),
+            NonUtf8Names::PercentEncode => {
+                let mut encoded = String::new();
+                for chunk in name.as_encoded_bytes().utf8_chunks() {
+                    encoded.push_str(&chunk.valid().replace('%', "%25"));
+                    for byte in chunk.invalid() {
+                        let _ = write!(encoded, "%{byte:02X}");
+                    }
+                }
+                Ok(encoded)
+            }
+        }
+    }
+}
+
+/// Device names Windows reserves in every directory, with or without an
+/// extension.
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks a normalized archive name for what breaks extraction.
+///
+/// Control characters (NUL, newlines, ...) are rejected with
+/// `SevenZipError::InvalidState`. Names that are otherwise valid but use a
+/// reserved Windows device name as a component, like `CON` or `aux.txt`,
+/// return that component so the caller can warn about or reject it. Lone
+/// UTF-16 surrogates, which 7z names could not encode, cannot occur in a
+/// `&str`.
+pub(crate) fn check_name(name: &str) -> Result<Option<&str>> {
+    if let Some(c) = name.chars().find(|c| c.is_control()) {
+        return Err(SevenZipError::InvalidState(format!(
+            "invalid archive name {name:?}: control character U+{:04X}",
+            c as u32
+        )));
+    }
+    Ok(name.split('/').find(|component| {
+        // Windows ignores the extension and trailing spaces
+        let stem = component.split('.').next().unwrap_or_default().trim_end();
+        RESERVED_WINDOWS_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_path() {
+        let normalization = PathNormalization::default();
+        assert_eq!(normalization.normalize(r"C:\a\b.txt").unwrap(), "a/b.txt");
+        assert_eq!(
+            normalization.normalize(r"dir\file.txt").unwrap(),
+            "dir/file.txt"
+        );
+
+        let keep_drive = PathNormalization {
+            strip_drive_letter: false,
+            ..Default::default()
+        };
+        assert_eq!(keep_drive.normalize(r"C:\a\b.txt").unwrap(), "C:/a/b.txt");
+        let reject = PathNormalization {
+            strip_drive_letter: false,
+            reject_absolute: true,
+            ..Default::default()
+        };
+        assert!(reject.normalize(r"C:\a\b.txt").is_err());
+    }
+
+    #[test]
+    fn test_dot_segments() {
+        let normalization = PathNormalization::default();
+        assert_eq!(normalization.normalize("./a/../b.txt").unwrap(), "b.txt");
+        assert_eq!(normalization.normalize("a//./b/").unwrap(), "a/b");
+        assert!(normalization.normalize("a/../../b.txt").is_err());
+        assert!(normalization.normalize("./").is_err());
+
+        let keep = PathNormalization {
+            collapse_dot_segments: false,
+            ..Default::default()
+        };
+        assert_eq!(keep.normalize("./a/../b.txt").unwrap(), "./a/../b.txt");
+    }
+
+    #[test]
+    fn test_strip_components() {
+        let strip = |n| PathNormalization {
+            strip_components: n,
+            ..Default::default()
+        };
+        assert_eq!(
+            strip(0).normalize("/home/me/src/x.rs").unwrap(),
+            "home/me/src/x.rs"
+        );
+        assert_eq!(strip(2).normalize("/home/me/src/x.rs").unwrap(), "src/x.rs");
+        assert_eq!(
+            strip(2).normalize("./home//me/../you/x.rs").unwrap(),
+            "x.rs"
+        );
+
+        let err = strip(2).normalize("home/me").unwrap_err();
+        assert!(matches!(err, SevenZipError::StrippedAway(name) if name == "home/me"));
+        assert!(strip(5).normalize("/home/me/src/x.rs").is_err());
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert_eq!(check_name("dir/file.txt").unwrap(), None);
+        assert_eq!(check_name("console/com10.txt").unwrap(), None);
+        for name in ["a\0b", "line\nbreak", "tab\there", "del\u{7f}"] {
+            let err = check_name(name).unwrap_err();
+            assert!(matches!(err, SevenZipError::InvalidState(_)));
+            assert!(err.to_string().contains("control character"), "{err}");
+        }
+
+        assert_eq!(check_name("CON").unwrap(), Some("CON"));
+        assert_eq!(check_name("dir/aux.txt").unwrap(), Some("aux.txt"));
+        assert_eq!(check_name("Lpt9 .tar.gz/x").unwrap(), Some("Lpt9 .tar.gz"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xE9 100%.txt");
+        let policy = |non_utf8_names| PathNormalization {
+            non_utf8_names,
+            ..Default::default()
+        };
+
+        let err = policy(NonUtf8Names::Reject).name_from_os(name).unwrap_err();
+        assert!(matches!(err, SevenZipError::InvalidState(_)));
+        assert!(err.to_string().contains("non-UTF-8"), "{err}");
+        assert_eq!(
+            policy(NonUtf8Names::Lossy).name_from_os(name).unwrap(),
+            "caf\u{FFFD} 100%.txt"
+        );
+        assert_eq!(
+            policy(NonUtf8Names::PercentEncode)
+                .name_from_os(name)
+                .unwrap(),
+            "caf%E9 100%25.txt"
+        );
+
+        // Valid names are never changed
+        for non_utf8_names in [
+            NonUtf8Names::Reject,
+            NonUtf8Names::Lossy,
+            NonUtf8Names::PercentEncode,
+        ] {
+            assert_eq!(
+                policy(non_utf8_names)
+                    .name_from_os(OsStr::new("100%.txt"))
+                    .unwrap(),
+                "100%.txt"
+            );
+        }
+        assert_eq!(
+            "Percent-Encode".parse::<NonUtf8Names>().unwrap(),
+            NonUtf8Names::PercentEncode
+        );
+    }
+
+    #[test]
+    fn test_absolute_path() {
+        let normalization = PathNormalization::default();
+        assert_eq!(
+            normalization.normalize("/etc/passwd").unwrap(),
+            "etc/passwd"
+        );
+
+        let reject = PathNormalization {
+            reject_absolute: true,
+            ..Default::default()
+        };
+        let err = reject.normalize("/etc/passwd").unwrap_err();
+        assert!(matches!(err, SevenZipError::InvalidState(_)));
+        assert!(err.to_string().contains("absolute path"));
+        assert_eq!(reject.normalize("etc/passwd").unwrap(), "etc/passwd");
+    }
+}
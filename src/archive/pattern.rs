@@ -0,0 +1,169 @@
+/// A gitignore-style exclusion pattern, matched against paths relative to
+/// the directory being added (components separated by `/`).
+///
+/// - A trailing `/` only matches directories.
+/// - A pattern without any other `/` matches a file or directory name at
+///   any depth, e.g. `*.tmp` or `target/`.
+/// - Otherwise the pattern is anchored at the root (a leading `/` is
+///   optional), and a `**` component matches any number of directories,
+///   e.g. `docs/**/*.pdf`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExcludePattern {
+    components: Vec<String>,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl ExcludePattern {
+    /// Parses a pattern; every string is a valid pattern.
+    pub fn new(pattern: &str) -> Self {
+        let dir_only = pattern.ends_with('/');
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+        Self {
+            components: trimmed
+                .split('/')
+                .filter(|c| !c.is_empty())
+                .map(str::to_string)
+                .collect(),
+            anchored,
+            dir_only,
+        }
+    }
+
+    /// Whether the entry at `relative_path` is excluded.
+    pub fn matches(&self, relative_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path: Vec<&str> = relative_path.split('/').filter(|c| !c.is_empty()).collect();
+        if self.anchored {
+            match_components(&self.components, &path)
+        } else {
+            match (self.components.first(), path.last()) {
+                (Some(pattern), Some(name)) => wildcard_match(pattern, name),
+                _ => false,
+            }
+        }
+    }
+}
+
+fn match_components(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|i| match_components(rest, &path[i..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                wildcard_match(first, name) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Matches one path component against a pattern with `*`, `?` and `[...]`.
+///
+/// `*` matches any run of characters, `?` any single character and `[...]`
+/// a character class (ranges like `a-z`, negated by a leading `!` or `^`).
+/// Matching is on bytes, so classes only hold ASCII characters reliably.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match_bytes(pattern.as_bytes(), name.as_bytes())
+}
+
+fn match_bytes(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| match_bytes(rest, &name[i..])),
+        Some((b'?', rest)) => match name.split_first() {
+            Some((_, name_rest)) => match_bytes(rest, name_rest),
+            None => false,
+        },
+        Some((b'[', class)) => match (name.split_first(), parse_class(class)) {
+            (Some((&c, name_rest)), Some((matches, rest))) => {
+                matches(c) && match_bytes(rest, name_rest)
+            }
+            // An unterminated `[` is a literal character
+            (Some((b'[', name_rest)), None) => match_bytes(class, name_rest),
+            _ => false,
+        },
+        Some((&p, rest)) => name.first() == Some(&p) && match_bytes(rest, &name[1..]),
+    }
+}
+
+/// Parses a character class after its opening `[`. Returns a predicate for
+/// the class and the pattern after the closing `]`, or `None` if the class
+/// is not terminated.
+fn parse_class(class: &[u8]) -> Option<(impl Fn(u8) -> bool + '_, &[u8])> {
+    let (negated, body) = match class.first() {
+        Some(b'!' | b'^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    // A `]` right after the opening bracket is part of the class
+    let end = 1 + body.get(1..)?.iter().position(|&b| b == b']')?;
+    let (items, rest) = (&body[..end], &body[end + 1..]);
+
+    let matches = move |c: u8| {
+        let mut i = 0;
+        let mut found = false;
+        while i < items.len() {
+            if i + 2 < items.len() && items[i + 1] == b'-' {
+                found |= (items[i]..=items[i + 2]).contains(&c);
+                i += 3;
+            } else {
+                found |= items[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matches, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        let matches = wildcard_match;
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("file[0-9].txt", "file7.txt"));
+        assert!(!matches("file[!0-9].txt", "file7.txt"));
+        assert!(matches("file[!0-9].txt", "fileA.txt"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("a[", "a["));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b*", "xxbxxaxx"));
+    }
+
+    #[test]
+    fn test_exclude_pattern_unanchored() {
+        let tmp = ExcludePattern::new("*.tmp");
+        assert!(tmp.matches("a.tmp", false));
+        assert!(tmp.matches("deep/dir/b.tmp", false));
+        assert!(!tmp.matches("a.tmpx", false));
+
+        let target = ExcludePattern::new("target/");
+        assert!(target.matches("target", true));
+        assert!(target.matches("crates/x/target", true));
+        assert!(!target.matches("target", false));
+    }
+
+    #[test]
+    fn test_exclude_pattern_anchored() {
+        let build = ExcludePattern::new("/build");
+        assert!(build.matches("build", true));
+        assert!(!build.matches("src/build", true));
+
+        let pdfs = ExcludePattern::new("docs/**/*.pdf");
+        assert!(pdfs.matches("docs/a.pdf", false));
+        assert!(pdfs.matches("docs/x/y/a.pdf", false));
+        assert!(!pdfs.matches("other/docs/a.pdf", false));
+    }
+}
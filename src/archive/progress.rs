@@ -0,0 +1,231 @@
+use crate::archive::header::{ArchiveHeader, FolderCodec};
+use crate::compression::filter::Filter;
+use crate::error::SevenZipError;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Progress of `finish()`, passed to the progress callback.
+///
+/// `bytes_done` counts uncompressed bytes whose compression has finished;
+/// `files_done` counts files whose data has been written to the archive.
+/// Only files with data are counted: empty files and directories are not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub files_done: usize,
+    pub total_files: usize,
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+}
+
+/// Totals of a completed archive, returned by
+/// [`finish_with_stats`](crate::SevenZipWriter::finish_with_stats).
+///
+/// As for [`Progress`], only files with data are counted in `num_files`.
+/// `total_compressed` is the packed file data, without headers or the
+/// recovery record.
+#[derive(Debug)]
+pub struct FinishStats {
+    pub total_uncompressed: u64,
+    pub total_compressed: u64,
+    pub num_files: usize,
+    pub num_folders: usize,
+    /// Length of the whole archive in bytes, recovery record included,
+    /// from the position it started at to where the writer is left.
+    pub archive_size: u64,
+    /// Time spent reading, compressing and writing the archive.
+    pub wall_time: Duration,
+    /// Compression time of each file with data, slowest first. This is the
+    /// time its folder's blocks spent on worker threads, summed, so it can
+    /// exceed `wall_time`; a solid folder's time is split between its files
+    /// by size.
+    pub slowest_files: Vec<(String, Duration)>,
+    /// SHA-256 of each file's data, empty files included, in archive order;
+    /// only filled in with
+    /// [`set_compute_sha256`](crate::SevenZipWriter::set_compute_sha256).
+    pub sha256: Vec<(String, [u8; 32])>,
+    /// Files left out because they could not be read, with the error; only
+    /// filled in with
+    /// [`set_skip_errors`](crate::SevenZipWriter::set_skip_errors).
+    pub skipped: Vec<(PathBuf, SevenZipError)>,
+}
+
+/// The folders and entries of a completed archive, as written in its
+/// header, returned by
+/// [`finish_with_report`](crate::SevenZipWriter::finish_with_report).
+///
+/// When appending, the archive's existing folders and entries come first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveReport {
+    pub folders: Vec<FolderReport>,
+    /// Every entry, in archive order.
+    pub files: Vec<FileReport>,
+}
+
+/// One folder of an [`ArchiveReport`]: a packed stream and how it was coded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderReport {
+    pub codec: FolderCodec,
+    /// Dictionary size of LZMA and LZMA2 folders.
+    pub dict_size: Option<u32>,
+    /// Filters applied before the codec, in order.
+    pub filters: Vec<Filter>,
+    /// Size of the packed stream, after encryption if any.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    /// CRC32 of the uncompressed data; `None` when written without CRCs.
+    pub crc: Option<u32>,
+}
+
+/// One entry of an [`ArchiveReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    pub name: String,
+    pub size: u64,
+    /// CRC32 of the data; `None` for empty files and directories, and when
+    /// written without CRCs.
+    pub crc: Option<u32>,
+    pub is_directory: bool,
+    /// Index of the folder holding the data; `None` for empty files and
+    /// directories.
+    pub folder: Option<usize>,
+}
+
+impl ArchiveReport {
+    pub(crate) fn new(header: &ArchiveHeader) -> Self {
+        let folders = header
+            .folders
+            .iter()
+            .map(|folder| FolderReport {
+                codec: folder.codec.clone(),
+                dict_size: folder.codec.dict_size(),
+                filters: folder.filters.clone(),
+                compressed_size: folder.compressed_size,
+                uncompressed_size: folder.uncompressed_size,
+                crc: folder.uncompressed_crc,
+            })
+            .collect();
+        // Files with data take the folders' substreams in order
+        let mut substream_folders = header
+            .folders
+            .iter()
+            .enumerate()
+            .flat_map(|(index, folder)| std::iter::repeat_n(index, folder.substreams.len()));
+        let files = header
+            .files
+            .iter()
+            .map(|file| FileReport {
+                name: file.name.clone(),
+                size: file.uncompressed_size,
+                crc: file.crc,
+                is_directory: file.is_directory,
+                folder: if file.has_data {
+                    substream_folders.next()
+                } else {
+                    None
+                },
+            })
+            .collect();
+        Self { folders, files }
+    }
+}
+
+/// Callback receiving [`Progress`] updates, possibly from worker threads.
+pub type ProgressCallback = Box<dyn Fn(Progress) + Send + Sync>;
+
+/// Progress counters shared with the compression workers.
+///
+/// Counters are lock-free; only the callback invocation is serialized, and
+/// the counters are read under that lock, so reported values never go
+/// backwards even when workers finish out of order.
+pub(crate) struct ProgressTracker<'a> {
+    callback: Option<&'a ProgressCallback>,
+    total_files: usize,
+    total_bytes: u64,
+    files_done: AtomicUsize,
+    bytes_done: AtomicU64,
+    report_lock: Mutex<()>,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub fn new(
+        callback: Option<&'a ProgressCallback>,
+        total_files: usize,
+        total_bytes: u64,
+    ) -> Self {
+        Self {
+            callback,
+            total_files,
+            total_bytes,
+            files_done: AtomicUsize::new(0),
+            bytes_done: AtomicU64::new(0),
+            report_lock: Mutex::new(()),
+        }
+    }
+
+    /// Records `bytes` more uncompressed bytes as compressed.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+        self.report();
+    }
+
+    /// Records `files` more files as written.
+    pub fn add_files(&self, files: usize) {
+        self.files_done.fetch_add(files, Ordering::Relaxed);
+        self.report();
+    }
+
+    fn report(&self) {
+        let Some(callback) = self.callback else {
+            return;
+        };
+        let _guard = self.report_lock.lock().unwrap_or_else(|e| e.into_inner());
+        callback(Progress {
+            files_done: self.files_done.load(Ordering::Relaxed),
+            total_files: self.total_files,
+            bytes_done: self.bytes_done.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reports_are_monotonic_across_threads() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        let callback: ProgressCallback = Box::new(move |p: Progress| {
+            sink.lock().unwrap().push(p.bytes_done);
+        });
+        let tracker = ProgressTracker::new(Some(&callback), 0, 8 * 100 * 3);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        tracker.add_bytes(3);
+                    }
+                });
+            }
+        });
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 800);
+        assert!(seen.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(*seen.last().unwrap(), 2400);
+    }
+
+    #[test]
+    fn test_without_callback_only_counts() {
+        let tracker = ProgressTracker::new(None, 2, 10);
+        tracker.add_bytes(10);
+        tracker.add_files(2);
+        assert_eq!(tracker.bytes_done.load(Ordering::Relaxed), 10);
+        assert_eq!(tracker.files_done.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,1479 @@
+use crate::archive::header::{
+    ArchiveHeader, FileEntry, FolderCodec, FolderInfo, SubStreamInfo, BZIP2_CODER_ID,
+    COPY_CODER_ID, K_ATTRIBUTES, K_CODERS_UNPACK_SIZE, K_CRC, K_EMPTY_FILE, K_EMPTY_STREAM,
+    K_ENCODED_HEADER, K_END, K_FILES_INFO, K_FOLDER, K_HEADER, K_MAIN_STREAMS_INFO, K_M_TIME,
+    K_NAME, K_NUM_UNPACK_STREAM, K_PACK_INFO, K_SIZE, K_SUB_STREAMS_INFO, K_UNPACK_INFO,
+    LZMA2_CODER_ID, LZMA_CODER_ID, SIGNATURE,
+};
+use crate::archive::writer::{FormatVersion, SIGNATURE_HEADER_SIZE};
+use crate::compression::block::crc32_combine;
+use crate::compression::lzma2::decode_dict_size;
+use crate::error::{Result, SevenZipError};
+use crate::io::reader::{read_bool_vector, read_number, read_u32_le, read_u64_le};
+use crate::io::seek::CrcWriter;
+use lzma_rust2::{Lzma2Reader, LzmaReader};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Reads archives produced by [`SevenZipWriter`](crate::SevenZipWriter).
+///
+/// Only the subset of the 7z format that this crate writes is supported:
+/// plain or LZMA2-encoded headers, and single-coder LZMA2 or Copy folders.
+pub struct SevenZipReader<R: Read + Seek> {
+    reader: R,
+    version: FormatVersion,
+    header: ArchiveHeader,
+    /// Absolute position of each folder's packed stream.
+    pack_offsets: Vec<u64>,
+    /// For each entry with data: its folder and offset within the folder's output.
+    locations: Vec<Option<(usize, u64)>>,
+}
+
+impl<R: Read + Seek> SevenZipReader<R> {
+    /// Opens the archive that starts at the reader's current position and
+    /// parses its header.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let base_offset = reader.stream_position()?;
+
+        // 1. SignatureHeader
+        let mut signature_header = [0u8; SIGNATURE_HEADER_SIZE as usize];
+        reader
+            .read_exact(&mut signature_header)
+            .map_err(|e| SevenZipError::HeaderError(format!("read signature header: {e}")))?;
+        if signature_header[0..6] != SIGNATURE {
+            return Err(SevenZipError::HeaderError(
+                "not a 7z archive: bad signature".to_string(),
+            ));
+        }
+        if signature_header[6] != 0 {
+            return Err(SevenZipError::HeaderError(format!(
+                "unsupported archive version {}.{}",
+                signature_header[6], signature_header[7]
+            )));
+        }
+
+        let version = FormatVersion {
+            major: signature_header[6],
+            minor: signature_header[7],
+        };
+
+        let mut start_header = &signature_header[8..];
+        let start_header_crc = read_u32_le(&mut start_header)?;
+        if crc32fast::hash(start_header) != start_header_crc {
+            return Err(SevenZipError::HeaderError(
+                "start header CRC mismatch".to_string(),
+            ));
+        }
+        let next_header_offset = read_u64_le(&mut start_header)?;
+        let next_header_size = read_u64_le(&mut start_header)?;
+        let next_header_crc = read_u32_le(&mut start_header)?;
+
+        // 2. Next header, verified against its CRC before parsing
+        let header_position = base_offset
+            .checked_add(SIGNATURE_HEADER_SIZE)
+            .and_then(|p| p.checked_add(next_header_offset))
+            .ok_or_else(|| SevenZipError::HeaderError("header offset overflow".to_string()))?;
+        reader.seek(SeekFrom::Start(header_position))?;
+        let mut header_bytes = Vec::new();
+        (&mut reader)
+            .take(next_header_size)
+            .read_to_end(&mut header_bytes)?;
+        if header_bytes.len() as u64 != next_header_size {
+            return Err(SevenZipError::HeaderError(
+                "archive truncated before end of header".to_string(),
+            ));
+        }
+        if crc32fast::hash(&header_bytes) != next_header_crc {
+            return Err(SevenZipError::HeaderError(
+                "header CRC mismatch".to_string(),
+            ));
+        }
+
+        // An encoded header points at a packed folder holding the real header.
+        if header_bytes.first() == Some(&K_ENCODED_HEADER) {
+            let pack_base = header_position - next_header_offset;
+            header_bytes = read_encoded_header(&mut reader, pack_base, &header_bytes[1..])?;
+        }
+
+        // 7-Zip writes an archive without entries as a bare SignatureHeader
+        // with an empty next header.
+        let header = if header_bytes.is_empty() {
+            ArchiveHeader {
+                folders: Vec::new(),
+                files: Vec::new(),
+                pack_position: 0,
+            }
+        } else {
+            parse_header(&header_bytes)?
+        };
+
+        // 3. Locate every folder's packed stream and every file inside its folder.
+        let overflow = |what: &str| SevenZipError::HeaderError(format!("{what} overflow"));
+        let mut pack_offsets = Vec::with_capacity(header.folders.len());
+        let mut offset = (header_position - next_header_offset)
+            .checked_add(header.pack_position)
+            .ok_or_else(|| overflow("pack stream offset"))?;
+        for folder in &header.folders {
+            pack_offsets.push(offset);
+            offset = offset
+                .checked_add(folder.compressed_size)
+                .ok_or_else(|| overflow("pack stream offset"))?;
+        }
+
+        let mut substream_starts = Vec::new();
+        for (i, folder) in header.folders.iter().enumerate() {
+            let mut position = 0u64;
+            for substream in &folder.substreams {
+                substream_starts.push((i, position));
+                position = position
+                    .checked_add(substream.size)
+                    .ok_or_else(|| overflow("substream offset"))?;
+            }
+        }
+        let mut locations = Vec::with_capacity(header.files.len());
+        let mut substreams = substream_starts.into_iter();
+        for file in &header.files {
+            locations.push(if file.has_data {
+                substreams.next()
+            } else {
+                None
+            });
+        }
+
+        Ok(Self {
+            reader,
+            version,
+            header,
+            pack_offsets,
+            locations,
+        })
+    }
+
+    /// Returns the format version from the SignatureHeader.
+    pub fn version(&self) -> FormatVersion {
+        self.version
+    }
+
+    /// Returns the archive entries, in header order.
+    pub fn entries(&self) -> &[FileEntry] {
+        &self.header.files
+    }
+
+    /// Consumes the reader, returning the parsed header.
+    pub(crate) fn into_header(self) -> ArchiveHeader {
+        self.header
+    }
+
+    /// Decompresses the entry at `index` and verifies its CRC.
+    ///
+    /// Directories and empty files return an empty vector. In a solid folder,
+    /// the data of all preceding files in that folder is decompressed and
+    /// discarded first.
+    pub fn read_file(&mut self, index: usize) -> Result<Vec<u8>> {
+        let entry = self
+            .header
+            .files
+            .get(index)
+            .ok_or_else(|| SevenZipError::InvalidState(format!("no entry at index {index}")))?;
+        let Some((folder_index, offset)) = self.locations[index] else {
+            return Ok(Vec::new());
+        };
+        let folder = &self.header.folders[folder_index];
+
+        self.reader
+            .seek(SeekFrom::Start(self.pack_offsets[folder_index]))?;
+        let packed = (&mut self.reader).take(folder.compressed_size);
+        let decompress_err = |e: std::io::Error| {
+            SevenZipError::Compression(format!("decompress '{}': {e}", entry.name))
+        };
+        let mut unpacked = unpack_folder(packed, &folder.codec, folder.uncompressed_size)
+            .map_err(decompress_err)?;
+        let skipped = std::io::copy(&mut (&mut unpacked).take(offset), &mut std::io::sink())
+            .map_err(decompress_err)?;
+        let mut data = Vec::new();
+        unpacked
+            .take(entry.uncompressed_size)
+            .read_to_end(&mut data)
+            .map_err(decompress_err)?;
+        if skipped != offset || data.len() as u64 != entry.uncompressed_size {
+            return Err(SevenZipError::Compression(format!(
+                "decompress '{}': folder data ends early",
+                entry.name
+            )));
+        }
+
+        if let Some(expected) = entry.crc {
+            let actual = crc32fast::hash(&data);
+            if actual != expected {
+                return Err(SevenZipError::CrcMismatch {
+                    name: entry.name.clone(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Returns a reader over the data of the entry at `index`, decompressed
+    /// as it is read, so that entries of any size can be extracted in
+    /// bounded memory.
+    ///
+    /// In a solid folder, the data of all preceding files in that folder is
+    /// decompressed and discarded before this returns. The entry's CRC is
+    /// checked once its last byte has been read: a mismatch is reported by
+    /// that read as an `InvalidData` I/O error wrapping
+    /// `SevenZipError::CrcMismatch`. Directories and empty files read as
+    /// empty.
+    pub fn stream_file(&mut self, index: usize) -> Result<impl Read + '_> {
+        let entry = self
+            .header
+            .files
+            .get(index)
+            .ok_or_else(|| SevenZipError::InvalidState(format!("no entry at index {index}")))?;
+        let Some((folder_index, offset)) = self.locations[index] else {
+            return Ok(EntryStream::new(Box::new(std::io::empty()), entry, 0));
+        };
+        let folder = &self.header.folders[folder_index];
+
+        self.reader
+            .seek(SeekFrom::Start(self.pack_offsets[folder_index]))?;
+        let packed = (&mut self.reader).take(folder.compressed_size);
+        let decompress_err = |e: std::io::Error| {
+            SevenZipError::Compression(format!("decompress '{}': {e}", entry.name))
+        };
+        let mut unpacked = unpack_folder(packed, &folder.codec, folder.uncompressed_size)
+            .map_err(decompress_err)?;
+        let skipped = std::io::copy(&mut (&mut unpacked).take(offset), &mut std::io::sink())
+            .map_err(decompress_err)?;
+        if skipped != offset {
+            return Err(SevenZipError::Compression(format!(
+                "decompress '{}': folder data ends early",
+                entry.name
+            )));
+        }
+        Ok(EntryStream::new(unpacked, entry, entry.uncompressed_size))
+    }
+
+    /// Decompresses every folder once and checks the CRC of every entry with
+    /// data, without keeping file contents in memory. Returns the number of
+    /// entries checked, which leaves out entries stored without a CRC.
+    pub fn verify(&mut self) -> Result<usize> {
+        let mut checked = 0;
+        for (folder_index, folder) in self.header.folders.iter().enumerate() {
+            self.reader
+                .seek(SeekFrom::Start(self.pack_offsets[folder_index]))?;
+            let packed = (&mut self.reader).take(folder.compressed_size);
+            let mut unpacked = unpack_folder(packed, &folder.codec, folder.uncompressed_size)
+                .map_err(|e| {
+                    SevenZipError::Compression(format!("decompress folder {folder_index}: {e}"))
+                })?;
+
+            // Entries of a folder appear in header order, at increasing offsets
+            let entries = self
+                .header
+                .files
+                .iter()
+                .zip(&self.locations)
+                .filter(|(_, location)| matches!(location, Some((i, _)) if *i == folder_index));
+            for (entry, _) in entries {
+                let mut hasher = crc32fast::Hasher::new();
+                let mut chunk = vec![0u8; 64 * 1024];
+                let mut remaining = entry.uncompressed_size;
+                while remaining > 0 {
+                    let len = (chunk.len() as u64).min(remaining) as usize;
+                    unpacked.read_exact(&mut chunk[..len]).map_err(|e| {
+                        SevenZipError::Compression(format!("decompress '{}': {e}", entry.name))
+                    })?;
+                    hasher.update(&chunk[..len]);
+                    remaining -= len as u64;
+                }
+
+                let Some(expected) = entry.crc else {
+                    continue;
+                };
+                let actual = hasher.finalize();
+                if actual != expected {
+                    return Err(SevenZipError::CrcMismatch {
+                        name: entry.name.clone(),
+                        expected,
+                        actual,
+                    });
+                }
+                checked += 1;
+            }
+        }
+        Ok(checked)
+    }
+
+    /// Decompresses every folder once and checks its CRC as a whole, the
+    /// way 7-Zip checks a folder's unpack stream. Returns the number of
+    /// folders checked: a folder is only decompressed to check that it
+    /// decodes when its files were stored without CRCs.
+    ///
+    /// A mismatch is reported as `SevenZipError::VerificationFailed`; data
+    /// too damaged to decode is a `SevenZipError::Compression` error.
+    pub fn verify_folders(&mut self) -> Result<usize> {
+        let mut checked = 0;
+        for (folder_index, folder) in self.header.folders.iter().enumerate() {
+            self.reader
+                .seek(SeekFrom::Start(self.pack_offsets[folder_index]))?;
+            let packed = (&mut self.reader).take(folder.compressed_size);
+            let mut unpacked = unpack_folder(packed, &folder.codec, folder.uncompressed_size)
+                .map_err(|e| {
+                    SevenZipError::Compression(format!("decompress folder {folder_index}: {e}"))
+                })?
+                .take(folder.uncompressed_size);
+
+            let mut hasher = crc32fast::Hasher::new();
+            let mut chunk = vec![0u8; 64 * 1024];
+            let mut size = 0u64;
+            loop {
+                let n = unpacked.read(&mut chunk).map_err(|e| {
+                    SevenZipError::Compression(format!("decompress folder {folder_index}: {e}"))
+                })?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&chunk[..n]);
+                size += n as u64;
+            }
+            if size != folder.uncompressed_size {
+                return Err(SevenZipError::Compression(format!(
+                    "decompress folder {folder_index}: data ends early"
+                )));
+            }
+
+            let Some(expected) = folder.uncompressed_crc else {
+                continue;
+            };
+            let actual = hasher.finalize();
+            if actual != expected {
+                return Err(SevenZipError::VerificationFailed {
+                    folder: folder_index,
+                    expected,
+                    actual,
+                });
+            }
+            checked += 1;
+        }
+        Ok(checked)
+    }
+
+    /// Checks the CRC of every packed stream that has one recorded (see
+    /// [`set_pack_crc`](crate::SevenZipWriter::set_pack_crc)) against the
+    /// bytes on disk, without decompressing anything. Returns the number of
+    /// folders checked.
+    ///
+    /// A mismatch is reported as `SevenZipError::VerificationFailed`.
+    pub fn verify_pack_crcs(&mut self) -> Result<usize> {
+        let mut checked = 0;
+        for (folder_index, folder) in self.header.folders.iter().enumerate() {
+            let Some(expected) = folder.packed_crc else {
+                continue;
+            };
+            self.reader
+                .seek(SeekFrom::Start(self.pack_offsets[folder_index]))?;
+            let mut packed = CrcWriter::new(std::io::sink());
+            let size = std::io::copy(
+                &mut (&mut self.reader).take(folder.compressed_size),
+                &mut packed,
+            )?;
+            if size != folder.compressed_size {
+                return Err(SevenZipError::HeaderError(format!(
+                    "packed stream of folder {folder_index} ends early"
+                )));
+            }
+            let actual = packed.crc();
+            if actual != expected {
+                return Err(SevenZipError::VerificationFailed {
+                    folder: folder_index,
+                    expected,
+                    actual,
+                });
+            }
+            checked += 1;
+        }
+        Ok(checked)
+    }
+}
+
+/// One entry's data, read from its decompressed folder, with the CRC, if
+/// stored, checked after the last byte.
+struct EntryStream<'a> {
+    unpacked: Box<dyn Read + 'a>,
+    name: String,
+    remaining: u64,
+    hasher: crc32fast::Hasher,
+    expected_crc: Option<u32>,
+}
+
+impl<'a> EntryStream<'a> {
+    fn new(unpacked: Box<dyn Read + 'a>, entry: &FileEntry, size: u64) -> Self {
+        Self {
+            unpacked,
+            name: entry.name.clone(),
+            remaining: size,
+            hasher: crc32fast::Hasher::new(),
+            expected_crc: entry.crc,
+        }
+    }
+}
+
+impl Read for EntryStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.unpacked.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("decompress '{}': folder data ends early", self.name),
+            ));
+        }
+        self.hasher.update(&buf[..n]);
+        self.remaining -= n as u64;
+
+        if let Some(expected) = self.expected_crc.filter(|_| self.remaining == 0) {
+            let actual = self.hasher.clone().finalize();
+            if actual != expected {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    SevenZipError::CrcMismatch {
+                        name: self.name.clone(),
+                        expected,
+                        actual,
+                    },
+                ));
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// Wraps a folder's packed stream in the decoder for its codec.
+/// `unpack_size` is the folder's unpacked size, which LZMA needs to know
+/// where its stream ends.
+fn unpack_folder<'a>(
+    packed: impl Read + 'a,
+    codec: &FolderCodec,
+    unpack_size: u64,
+) -> std::io::Result<Box<dyn Read + 'a>> {
+    Ok(match *codec {
+        FolderCodec::Lzma2 { properties_byte } => Box::new(Lzma2Reader::new(
+            packed,
+            decode_dict_size(properties_byte),
+            None,
+        )),
+        FolderCodec::Lzma1 { properties } => {
+            let [props, dict @ ..] = properties;
+            Box::new(LzmaReader::new_with_props(
+                packed,
+                unpack_size,
+                props,
+                u32::from_le_bytes(dict),
+                None,
+            )?)
+        }
+        #[cfg(feature = "bzip2")]
+        FolderCodec::Bzip2 => Box::new(bzip2::read::BzDecoder::new(packed)),
+        #[cfg(not(feature = "bzip2"))]
+        FolderCodec::Bzip2 => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "BZip2 support is not enabled (feature \"bzip2\")",
+            ))
+        }
+        FolderCodec::Copy => Box::new(packed),
+        FolderCodec::Custom { ref coder_id, .. } => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("unsupported coder {coder_id:02x?}"),
+            ))
+        }
+    })
+}
+
+/// Decodes the header described by a `kEncodedHeader` record (without its
+/// leading ID). `pack_base` is the position right after the SignatureHeader.
+fn read_encoded_header<R: Read + Seek>(
+    reader: &mut R,
+    pack_base: u64,
+    mut record: &[u8],
+) -> Result<Vec<u8>> {
+    let streams = read_streams_info(&mut record)?;
+    if streams.codecs.len() != 1 {
+        return Err(SevenZipError::HeaderError(format!(
+            "encoded header spans {} folders",
+            streams.codecs.len()
+        )));
+    }
+
+    let position = pack_base
+        .checked_add(streams.pack_position)
+        .ok_or_else(|| SevenZipError::HeaderError("header offset overflow".to_string()))?;
+    reader.seek(SeekFrom::Start(position))?;
+    let packed = reader.take(streams.pack_sizes[0]);
+    let mut header_bytes = Vec::new();
+    unpack_folder(packed, &streams.codecs[0], streams.unpack_sizes[0])
+        .and_then(|unpacked| {
+            unpacked
+                .take(streams.unpack_sizes[0])
+                .read_to_end(&mut header_bytes)
+        })
+        .map_err(|e| SevenZipError::HeaderError(format!("decode encoded header: {e}")))?;
+
+    if header_bytes.len() as u64 != streams.unpack_sizes[0] {
+        return Err(SevenZipError::HeaderError(
+            "encoded header ends early".to_string(),
+        ));
+    }
+    if let Some(crc) = streams.folder_crcs[0] {
+        if crc32fast::hash(&header_bytes) != crc {
+            return Err(SevenZipError::HeaderError(
+                "encoded header CRC mismatch".to_string(),
+            ));
+        }
+    }
+
+    Ok(header_bytes)
+}
+
+/// Folder and substream layout from MainStreamsInfo.
+#[derive(Default)]
+struct StreamsInfo {
+    pack_position: u64,
+    pack_sizes: Vec<u64>,
+    pack_crcs: Vec<Option<u32>>,
+    codecs: Vec<FolderCodec>,
+    unpack_sizes: Vec<u64>,
+    folder_crcs: Vec<Option<u32>>,
+    num_substreams: Vec<u64>,
+    substream_sizes: Vec<u64>,
+    substream_crcs: Vec<Option<u32>>,
+}
+
+fn truncated(e: std::io::Error) -> SevenZipError {
+    SevenZipError::HeaderError(format!("truncated header: {e}"))
+}
+
+fn read_u8(r: &mut &[u8]) -> Result<u8> {
+    let mut byte = [0u8];
+    r.read_exact(&mut byte).map_err(truncated)?;
+    Ok(byte[0])
+}
+
+fn expect_id(r: &mut &[u8], expected: u8) -> Result<()> {
+    let id = read_u8(r)?;
+    if id != expected {
+        return Err(SevenZipError::HeaderError(format!(
+            "expected property {expected:#04x}, found {id:#04x}"
+        )));
+    }
+    Ok(())
+}
+
+/// Reads a NUMBER used as a count, rejecting values larger than the bytes
+/// left in the header (every counted item takes at least one bit).
+fn read_count(r: &mut &[u8]) -> Result<usize> {
+    let count = read_number(r).map_err(truncated)?;
+    if count > (r.len() as u64 + 1) * 8 {
+        return Err(SevenZipError::HeaderError(format!(
+            "implausible count {count}"
+        )));
+    }
+    Ok(count as usize)
+}
+
+fn read_digests(r: &mut &[u8], count: usize) -> Result<Vec<Option<u32>>> {
+    let all_defined = read_u8(r)? != 0;
+    let defined = if all_defined {
+        vec![true; count]
+    } else {
+        read_bool_vector(r, count).map_err(truncated)?
+    };
+    defined
+        .into_iter()
+        .map(|d| d.then(|| read_u32_le(r).map_err(truncated)).transpose())
+        .collect()
+}
+
+/// Parses a plain `kHeader` block into an [`ArchiveHeader`].
+fn parse_header(buf: &[u8]) -> Result<ArchiveHeader> {
+    let mut r = buf;
+    match read_u8(&mut r)? {
+        K_HEADER => {}
+        K_ENCODED_HEADER => {
+            return Err(SevenZipError::HeaderError(
+                "encoded headers are not supported".to_string(),
+            ))
+        }
+        id => {
+            return Err(SevenZipError::HeaderError(format!(
+                "expected header, found property {id:#04x}"
+            )))
+        }
+    }
+
+    let mut id = read_u8(&mut r)?;
+    let mut streams = StreamsInfo::default();
+    if id == K_MAIN_STREAMS_INFO {
+        streams = read_streams_info(&mut r)?;
+        id = read_u8(&mut r)?;
+    }
+
+    let mut files = Vec::new();
+    if id == K_FILES_INFO {
+        files = read_files_info(&mut r, &streams)?;
+        id = read_u8(&mut r)?;
+    }
+    if id != K_END {
+        return Err(SevenZipError::HeaderError(format!(
+            "unsupported header property {id:#04x}"
+        )));
+    }
+
+    // read_streams_info checked that every list matches the folder count
+    let mut sizes = streams.substream_sizes.iter();
+    let mut crcs = streams.substream_crcs.iter();
+    let mut folders = Vec::with_capacity(streams.codecs.len());
+    for (i, codec) in streams.codecs.iter().enumerate() {
+        let mut substreams = Vec::new();
+        for _ in 0..streams.num_substreams[i] {
+            let (Some(&size), Some(&crc)) = (sizes.next(), crcs.next()) else {
+                return Err(SevenZipError::HeaderError(format!(
+                    "missing substream size or CRC in folder {i}"
+                )));
+            };
+            substreams.push(SubStreamInfo { size, crc });
+        }
+        let uncompressed_crc = streams.folder_crcs[i].or_else(|| {
+            substreams
+                .iter()
+                .try_fold(0, |crc, s| Some(crc32_combine(crc, s.crc?, s.size)))
+        });
+        folders.push(FolderInfo {
+            compressed_size: streams.pack_sizes[i],
+            packed_crc: streams.pack_crcs.get(i).copied().flatten(),
+            uncompressed_size: streams.unpack_sizes[i],
+            uncompressed_crc,
+            codec: codec.clone(),
+            filters: Vec::new(),
+            encryption: None,
+            substreams,
+        });
+    }
+
+    // Files with data map onto the substreams in order.
+    let mut substreams = folders
+        .iter()
+        .flat_map(|f| f.substreams.iter().map(move |s| (f, s)));
+    for file in files.iter_mut().filter(|f| f.has_data) {
+        let (folder, substream) = substreams.next().ok_or_else(|| {
+            SevenZipError::HeaderError("more files with data than substreams".to_string())
+        })?;
+        file.uncompressed_size = substream.size;
+        file.crc = substream.crc;
+        if folder.substreams.len() == 1 {
+            file.compressed_size = folder.compressed_size;
+        }
+    }
+    if substreams.next().is_some() {
+        return Err(SevenZipError::HeaderError(
+            "fewer files with data than substreams".to_string(),
+        ));
+    }
+
+    Ok(ArchiveHeader {
+        folders,
+        files,
+        pack_position: streams.pack_position,
+    })
+}
+
+fn read_streams_info(r: &mut &[u8]) -> Result<StreamsInfo> {
+    let mut info = StreamsInfo::default();
+    let mut has_substreams_info = false;
+
+    // PackInfo, UnpackInfo and SubStreamsInfo each appear at most once, in
+    // that order: SubStreamsInfo is read against the folders before it
+    let mut previous = 0;
+    loop {
+        let id = read_u8(r)?;
+        if id != K_END {
+            if id <= previous {
+                return Err(SevenZipError::HeaderError(format!(
+                    "streams property {id:#04x} out of order"
+                )));
+            }
+            previous = id;
+        }
+        match id {
+            K_PACK_INFO => read_pack_info(r, &mut info)?,
+            K_UNPACK_INFO => read_unpack_info(r, &mut info)?,
+            K_SUB_STREAMS_INFO => {
+                read_sub_streams_info(r, &mut info)?;
+                has_substreams_info = true;
+            }
+            K_END => break,
+            id => {
+                return Err(SevenZipError::HeaderError(format!(
+                    "unsupported streams property {id:#04x}"
+                )))
+            }
+        }
+    }
+
+    if info.pack_sizes.len() != info.codecs.len() {
+        return Err(SevenZipError::HeaderError(format!(
+            "{} pack streams for {} folders",
+            info.pack_sizes.len(),
+            info.codecs.len()
+        )));
+    }
+    if !has_substreams_info {
+        // One substream per folder, covering the whole folder output.
+        info.num_substreams = vec![1; info.codecs.len()];
+        info.substream_sizes = info.unpack_sizes.clone();
+        info.substream_crcs = info.folder_crcs.clone();
+    }
+    let num_folders = info.codecs.len();
+    let num_substreams: u64 = info.num_substreams.iter().sum();
+    if info.unpack_sizes.len() != num_folders
+        || info.folder_crcs.len() != num_folders
+        || info.num_substreams.len() != num_folders
+        || info.substream_sizes.len() as u64 != num_substreams
+        || info.substream_crcs.len() as u64 != num_substreams
+    {
+        return Err(SevenZipError::HeaderError(format!(
+            "streams info does not match its {num_folders} folders"
+        )));
+    }
+
+    Ok(info)
+}
+
+fn read_pack_info(r: &mut &[u8], info: &mut StreamsInfo) -> Result<()> {
+    info.pack_position = read_number(r).map_err(truncated)?;
+    let num_pack_streams = read_count(r)?;
+
+    loop {
+        match read_u8(r)? {
+            K_SIZE => {
+                info.pack_sizes = (0..num_pack_streams)
+                    .map(|_| read_number(r).map_err(truncated))
+                    .collect::<Result<_>>()?;
+            }
+            K_CRC => info.pack_crcs = read_digests(r, num_pack_streams)?,
+            K_END => return Ok(()),
+            id => {
+                return Err(SevenZipError::HeaderError(format!(
+                    "unsupported pack info property {id:#04x}"
+                )))
+            }
+        }
+    }
+}
+
+fn read_unpack_info(r: &mut &[u8], info: &mut StreamsInfo) -> Result<()> {
+    expect_id(r, K_FOLDER)?;
+    let num_folders = read_count(r)?;
+    if read_u8(r)? != 0 {
+        return Err(SevenZipError::HeaderError(
+            "external folder data is not supported".to_string(),
+        ));
+    }
+    info.codecs = (0..num_folders)
+        .map(|_| read_folder(r))
+        .collect::<Result<_>>()?;
+
+    expect_id(r, K_CODERS_UNPACK_SIZE)?;
+    info.unpack_sizes = (0..num_folders)
+        .map(|_| read_number(r).map_err(truncated))
+        .collect::<Result<_>>()?;
+
+    info.folder_crcs = vec![None; num_folders];
+    loop {
+        match read_u8(r)? {
+            K_CRC => info.folder_crcs = read_digests(r, num_folders)?,
+            K_END => return Ok(()),
+            id => {
+                return Err(SevenZipError::HeaderError(format!(
+                    "unsupported unpack info property {id:#04x}"
+                )))
+            }
+        }
+    }
+}
+
+/// Reads one folder record. Only single-coder LZMA2 and Copy folders are supported.
+fn read_folder(r: &mut &[u8]) -> Result<FolderCodec> {
+    let num_coders = read_number(r).map_err(truncated)?;
+    if num_coders != 1 {
+        return Err(SevenZipError::HeaderError(format!(
+            "folders with {num_coders} coders are not supported"
+        )));
+    }
+
+    let flag = read_u8(r)?;
+    let id_size = (flag & 0x0F) as usize;
+    let is_complex = flag & 0x10 != 0;
+    let has_attributes = flag & 0x20 != 0;
+    if flag & 0x80 != 0 {
+        return Err(SevenZipError::HeaderError(
+            "alternative coder methods are not supported".to_string(),
+        ));
+    }
+
+    let mut id = vec![0u8; id_size];
+    r.read_exact(&mut id).map_err(truncated)?;
+    if is_complex {
+        let num_in_streams = read_number(r).map_err(truncated)?;
+        let num_out_streams = read_number(r).map_err(truncated)?;
+        if num_in_streams != 1 || num_out_streams != 1 {
+            return Err(SevenZipError::HeaderError(
+                "coders with several streams are not supported".to_string(),
+            ));
+        }
+    }
+    let mut properties = Vec::new();
+    if has_attributes {
+        let size = read_count(r)?;
+        properties = vec![0u8; size];
+        r.read_exact(&mut properties).map_err(truncated)?;
+    }
+
+    match (id.as_slice(), properties.as_slice()) {
+        ([LZMA2_CODER_ID], &[properties_byte]) => Ok(FolderCodec::Lzma2 { properties_byte }),
+        (id, &[props, dict_0, dict_1, dict_2, dict_3]) if id == LZMA_CODER_ID => {
+            if props >= 9 * 5 * 5 {
+                return Err(SevenZipError::HeaderError(format!(
+                    "invalid LZMA properties byte {props:#04x}"
+                )));
+            }
+            Ok(FolderCodec::Lzma1 {
+                properties: [props, dict_0, dict_1, dict_2, dict_3],
+            })
+        }
+        (id, []) if id == BZIP2_CODER_ID => Ok(FolderCodec::Bzip2),
+        ([COPY_CODER_ID], []) => Ok(FolderCodec::Copy),
+        // Listed, but not decoded
+        _ => Ok(FolderCodec::Custom {
+            coder_id: id,
+            properties,
+        }),
+    }
+}
+
+fn read_sub_streams_info(r: &mut &[u8], info: &mut StreamsInfo) -> Result<()> {
+    let num_folders = info.codecs.len();
+    info.num_substreams = vec![1; num_folders];
+
+    let mut id = read_u8(r)?;
+    if id == K_NUM_UNPACK_STREAM {
+        info.num_substreams = (0..num_folders)
+            .map(|_| read_count(r).map(|n| n as u64))
+            .collect::<Result<_>>()?;
+        id = read_u8(r)?;
+    }
+
+    // Sizes: all but the last substream of each folder are stored explicitly.
+    let has_sizes = id == K_SIZE;
+    info.substream_sizes.clear();
+    for (i, &count) in info.num_substreams.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let mut remaining = info.unpack_sizes[i];
+        if has_sizes {
+            for _ in 1..count {
+                let size = read_number(r).map_err(truncated)?;
+                remaining = remaining.checked_sub(size).ok_or_else(|| {
+                    SevenZipError::HeaderError(format!(
+                        "substream sizes exceed folder {i} unpack size"
+                    ))
+                })?;
+                info.substream_sizes.push(size);
+            }
+        } else if count > 1 {
+            return Err(SevenZipError::HeaderError(format!(
+                "folder {i} has {count} substreams but no sizes"
+            )));
+        }
+        info.substream_sizes.push(remaining);
+    }
+    if has_sizes {
+        id = read_u8(r)?;
+    }
+
+    // CRCs: folders with a single substream and a folder-level CRC reuse it.
+    let inherits_crc = |i: usize| {
+        info.num_substreams[i] == 1 && info.folder_crcs.get(i).copied().flatten().is_some()
+    };
+    let num_digests: u64 = (0..num_folders)
+        .filter(|&i| !inherits_crc(i))
+        .map(|i| info.num_substreams[i])
+        .sum();
+    let mut digests = Vec::new();
+    if id == K_CRC {
+        digests = read_digests(r, num_digests as usize)?;
+        id = read_u8(r)?;
+    }
+    let mut digests = digests.into_iter();
+    info.substream_crcs.clear();
+    for i in 0..num_folders {
+        if inherits_crc(i) {
+            info.substream_crcs.push(info.folder_crcs[i]);
+        } else {
+            for _ in 0..info.num_substreams[i] {
+                info.substream_crcs.push(digests.next().flatten());
+            }
+        }
+    }
+
+    if id != K_END {
+        return Err(SevenZipError::HeaderError(format!(
+            "unsupported substreams property {id:#04x}"
+        )));
+    }
+    Ok(())
+}
+
+fn read_files_info(r: &mut &[u8], streams: &StreamsInfo) -> Result<Vec<FileEntry>> {
+    let num_files = read_count(r)?;
+    let mut names = Vec::new();
+    let mut empty_stream = vec![false; num_files];
+    let mut empty_file = Vec::new();
+    let mut mtimes = vec![None; num_files];
+    let mut attributes = vec![None; num_files];
+
+    loop {
+        let property = read_number(r).map_err(truncated)?;
+        if property == K_END as u64 {
+            break;
+        }
+        let size = read_count(r)?;
+        if size > r.len() {
+            return Err(SevenZipError::HeaderError(format!(
+                "property {property:#04x} overruns header"
+            )));
+        }
+        let (mut data, rest) = r.split_at(size);
+        *r = rest;
+
+        match u8::try_from(property) {
+            Ok(K_NAME) => names = read_names(&mut data, num_files)?,
+            Ok(K_EMPTY_STREAM) => {
+                empty_stream = read_bool_vector(&mut data, num_files).map_err(truncated)?;
+            }
+            Ok(K_EMPTY_FILE) => {
+                let num_empty = empty_stream.iter().filter(|&&b| b).count();
+                empty_file = read_bool_vector(&mut data, num_empty).map_err(truncated)?;
+            }
+            Ok(K_M_TIME) => {
+                let all_defined = read_u8(&mut data)? != 0;
+                let defined = if all_defined {
+                    vec![true; num_files]
+                } else {
+                    read_bool_vector(&mut data, num_files).map_err(truncated)?
+                };
+                if read_u8(&mut data)? != 0 {
+                    return Err(SevenZipError::HeaderError(
+                        "external times are not supported".to_string(),
+                    ));
+                }
+                for (mtime, defined) in mtimes.iter_mut().zip(defined) {
+                    if defined {
+                        *mtime = Some(read_u64_le(&mut data).map_err(truncated)?);
+                    }
+                }
+            }
+            Ok(K_ATTRIBUTES) => {
+                let all_defined = read_u8(&mut data)? != 0;
+                let defined = if all_defined {
+                    vec![true; num_files]
+                } else {
+                    read_bool_vector(&mut data, num_files).map_err(truncated)?
+                };
+                if read_u8(&mut data)? != 0 {
+                    return Err(SevenZipError::HeaderError(
+                        "external attributes are not supported".to_string(),
+                    ));
+                }
+                for (attributes, defined) in attributes.iter_mut().zip(defined) {
+                    if defined {
+                        *attributes = Some(read_u32_le(&mut data).map_err(truncated)?);
+                    }
+                }
+            }
+            // Any other properties are not needed to extract data.
+            _ => {}
+        }
+    }
+
+    if names.len() != num_files {
+        return Err(SevenZipError::HeaderError("missing file names".to_string()));
+    }
+    let num_with_data = empty_stream.iter().filter(|&&b| !b).count() as u64;
+    if num_with_data != streams.num_substreams.iter().sum::<u64>() {
+        return Err(SevenZipError::HeaderError(format!(
+            "{num_with_data} files with data do not match the folder substreams"
+        )));
+    }
+
+    let mut empty_index = 0;
+    let files = names
+        .into_iter()
+        .zip(empty_stream)
+        .zip(mtimes)
+        .zip(attributes)
+        .map(|(((name, is_empty), modified_time), attributes)| {
+            let is_directory = is_empty && !empty_file.get(empty_index).copied().unwrap_or(false);
+            if is_empty {
+                empty_index += 1;
+            }
+            FileEntry {
+                name,
+                uncompressed_size: 0,
+                compressed_size: 0,
+                crc: None,
+                has_data: !is_empty,
+                is_directory,
+                modified_time,
+                attributes,
+            }
+        })
+        .collect();
+
+    Ok(files)
+}
+
+fn read_names(data: &mut &[u8], num_files: usize) -> Result<Vec<String>> {
+    if read_u8(data)? != 0 {
+        return Err(SevenZipError::HeaderError(
+            "external names are not supported".to_string(),
+        ));
+    }
+    if !data.len().is_multiple_of(2) {
+        return Err(SevenZipError::HeaderError(
+            "odd-length UTF-16 names".to_string(),
+        ));
+    }
+
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let mut names = Vec::with_capacity(num_files);
+    let mut rest = units.as_slice();
+    while let Some(end) = rest.iter().position(|&u| u == 0) {
+        let name = String::from_utf16(&rest[..end])
+            .map_err(|e| SevenZipError::HeaderError(format!("invalid file name: {e}")))?;
+        names.push(name);
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() || names.len() != num_files {
+        return Err(SevenZipError::HeaderError(format!(
+            "expected {num_files} file names, found {}",
+            names.len()
+        )));
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::builder::SevenZipWriter;
+    use crate::compression::lzma2::Lzma2Config;
+    use std::io::Cursor;
+
+    fn sample_files() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            ("hello.txt", b"Hello, world!".to_vec()),
+            ("empty.txt", Vec::new()),
+            ("dir/repeated.bin", b"abcdefgh".repeat(50_000)),
+            ("dir/small.txt", b"x".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn test_roundtrip_encoded_header() {
+        let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_compress_header(true);
+        for i in 0..100 {
+            archive
+                .add_bytes(&format!("dir/file_{i:03}.txt"), format!("{i}").as_bytes())
+                .unwrap();
+        }
+        let bytes = archive.finish().unwrap().into_inner();
+
+        let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.entries().len(), 100);
+        assert_eq!(reader.entries()[42].name, "dir/file_042.txt");
+        assert_eq!(reader.read_file(42).unwrap(), b"42");
+    }
+
+    fn write_archive(solid: bool) -> Vec<u8> {
+        let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_solid(solid);
+        archive.set_config(Lzma2Config {
+            block_size: Some(100_000),
+            ..Default::default()
+        });
+        archive.add_directory("dir").unwrap();
+        for (name, data) in sample_files() {
+            archive.add_bytes(name, &data).unwrap();
+        }
+        archive.finish().unwrap().into_inner()
+    }
+
+    fn assert_roundtrip(bytes: Vec<u8>) {
+        let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.version(), FormatVersion::V0_4);
+        let expected = sample_files();
+        assert_eq!(reader.entries().len(), expected.len() + 1);
+
+        for (name, data) in expected {
+            let index = reader
+                .entries()
+                .iter()
+                .position(|e| e.name == name)
+                .unwrap();
+            let entry = &reader.entries()[index];
+            assert_eq!(entry.uncompressed_size, data.len() as u64, "{name}");
+            let crc = (!data.is_empty()).then(|| crc32fast::hash(&data));
+            assert_eq!(entry.crc, crc, "{name}");
+            assert!(!entry.is_directory);
+            assert_eq!(reader.read_file(index).unwrap(), data, "{name}");
+        }
+
+        let dir = reader
+            .entries()
+            .iter()
+            .position(|e| e.name == "dir")
+            .unwrap();
+        assert!(reader.entries()[dir].is_directory);
+        assert!(reader.read_file(dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_roundtrip_per_file_folders() {
+        assert_roundtrip(write_archive(false));
+    }
+
+    #[test]
+    fn test_roundtrip_solid_folder() {
+        assert_roundtrip(write_archive(true));
+    }
+
+    #[test]
+    fn test_roundtrip_at_nonzero_offset() {
+        let mut bytes = b"prefix".to_vec();
+        bytes.extend(write_archive(false));
+        let mut cursor = Cursor::new(bytes);
+        cursor.set_position(6);
+
+        let mut reader = SevenZipReader::new(cursor).unwrap();
+        let index = reader
+            .entries()
+            .iter()
+            .position(|e| e.name == "hello.txt")
+            .unwrap();
+        assert_eq!(reader.read_file(index).unwrap(), b"Hello, world!");
+    }
+
+    #[test]
+    fn test_empty_archives() {
+        let bytes = SevenZipWriter::new(Cursor::new(Vec::new()))
+            .unwrap()
+            .finish()
+            .unwrap()
+            .into_inner();
+        let reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        assert!(reader.entries().is_empty());
+
+        // As written by 7-Zip: no header at all
+        let mut bytes = Vec::new();
+        crate::archive::writer::write_signature_header(
+            &mut bytes,
+            FormatVersion::V0_4,
+            0,
+            0,
+            crc32fast::hash(&[]),
+        )
+        .unwrap();
+        let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        assert!(reader.entries().is_empty());
+        reader.verify().unwrap();
+    }
+
+    #[test]
+    fn test_rejects_corrupt_signature_header() {
+        let mut bytes = write_archive(false);
+        bytes[12] ^= 0xFF;
+        assert!(matches!(
+            SevenZipReader::new(Cursor::new(bytes)),
+            Err(SevenZipError::HeaderError(_))
+        ));
+
+        assert!(matches!(
+            SevenZipReader::new(Cursor::new(b"not an archive at all, really!!!".to_vec())),
+            Err(SevenZipError::HeaderError(_))
+        ));
+    }
+
+    #[test]
+    fn test_detects_corrupt_file_data() {
+        let mut bytes = write_archive(false);
+        // First folder holds "hello.txt", stored right after the signature header.
+        bytes[SIGNATURE_HEADER_SIZE as usize + 2] ^= 0x01;
+        let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        let index = reader
+            .entries()
+            .iter()
+            .position(|e| e.name == "hello.txt")
+            .unwrap();
+        assert!(reader.read_file(index).is_err());
+    }
+
+    #[test]
+    fn test_verify_checks_every_entry() {
+        for solid in [false, true] {
+            let mut reader = SevenZipReader::new(Cursor::new(write_archive(solid))).unwrap();
+            let with_data = reader.entries().iter().filter(|e| e.has_data).count();
+            assert_eq!(reader.verify().unwrap(), with_data);
+        }
+
+        let mut bytes = write_archive(false);
+        bytes[SIGNATURE_HEADER_SIZE as usize + 2] ^= 0x01;
+        let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        assert!(reader.verify().is_err());
+    }
+
+    #[test]
+    fn test_stream_file_matches_read_file() {
+        for solid in [false, true] {
+            let mut reader = SevenZipReader::new(Cursor::new(write_archive(solid))).unwrap();
+            for index in 0..reader.entries().len() {
+                let mut streamed = Vec::new();
+                reader
+                    .stream_file(index)
+                    .unwrap()
+                    .read_to_end(&mut streamed)
+                    .unwrap();
+                assert_eq!(streamed, reader.read_file(index).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_large_entry_in_chunks() {
+        let large: Vec<u8> = (0..6 << 20)
+            .map(|i: u32| ((i / 3) ^ (i % 251)) as u8)
+            .collect();
+        let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_solid(true);
+        archive.set_config(Lzma2Config {
+            preset: 0,
+            block_size: Some(1 << 20),
+            ..Default::default()
+        });
+        archive.add_bytes("before.txt", b"skipped first").unwrap();
+        archive.add_bytes("large.bin", &large).unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+
+        let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        let index = reader
+            .entries()
+            .iter()
+            .position(|e| e.name == "large.bin")
+            .unwrap();
+        let expected_crc = reader.entries()[index].crc;
+        let mut stream = reader.stream_file(index).unwrap();
+        let mut chunk = vec![0u8; 64 * 1024];
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size = 0;
+        loop {
+            let n = stream.read(&mut chunk).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(
+                chunk[..n] == large[size..size + n],
+                "mismatch at offset {size}"
+            );
+            hasher.update(&chunk[..n]);
+            size += n;
+        }
+        assert_eq!(size, large.len());
+        assert_eq!(Some(hasher.finalize()), expected_crc);
+    }
+
+    #[test]
+    fn test_stream_file_reports_corrupt_data() {
+        let mut bytes = write_archive(false);
+        bytes[SIGNATURE_HEADER_SIZE as usize + 2] ^= 0x01;
+        let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        let index = reader
+            .entries()
+            .iter()
+            .position(|e| e.name == "hello.txt")
+            .unwrap();
+        let mut data = Vec::new();
+        let read = reader
+            .stream_file(index)
+            .and_then(|mut stream| Ok(stream.read_to_end(&mut data)?));
+        assert!(read.is_err());
+    }
+
+    /// Sizes past 4 GiB go through the header writer and parser intact,
+    /// without writing the data they describe.
+    #[test]
+    fn test_header_sizes_over_4_gib() {
+        const GIB: u64 = 1 << 30;
+        let file = |name: &str| FileEntry {
+            name: name.to_string(),
+            uncompressed_size: 0,
+            compressed_size: 0,
+            crc: None,
+            has_data: true,
+            is_directory: false,
+            modified_time: None,
+            attributes: None,
+        };
+        let header = ArchiveHeader {
+            folders: vec![
+                // One 6 GiB file
+                FolderInfo {
+                    compressed_size: 5 * GIB,
+                    packed_crc: None,
+                    uncompressed_size: 6 * GIB,
+                    uncompressed_crc: Some(0x1111_1111),
+                    codec: FolderCodec::Lzma2 {
+                        properties_byte: 16,
+                    },
+                    filters: Vec::new(),
+                    encryption: None,
+                    substreams: vec![SubStreamInfo {
+                        size: 6 * GIB,
+                        crc: Some(0x1111_1111),
+                    }],
+                },
+                // A solid folder: 4 GiB + 1 byte, then 1 byte
+                FolderInfo {
+                    compressed_size: 4 * GIB + 7,
+                    packed_crc: None,
+                    uncompressed_size: 4 * GIB + 2,
+                    uncompressed_crc: None,
+                    codec: FolderCodec::Copy,
+                    filters: Vec::new(),
+                    encryption: None,
+                    substreams: vec![
+                        SubStreamInfo {
+                            size: 4 * GIB + 1,
+                            crc: Some(0x2222_2222),
+                        },
+                        SubStreamInfo {
+                            size: 1,
+                            crc: Some(0x3333_3333),
+                        },
+                    ],
+                },
+            ],
+            files: vec![file("big.bin"), file("solid/a.bin"), file("solid/b.bin")],
+            pack_position: 0,
+        };
+
+        let parsed = parse_header(&header.serialize().unwrap()).unwrap();
+        let folders: Vec<(u64, u64)> = parsed
+            .folders
+            .iter()
+            .map(|f| (f.compressed_size, f.uncompressed_size))
+            .collect();
+        assert_eq!(folders, [(5 * GIB, 6 * GIB), (4 * GIB + 7, 4 * GIB + 2)]);
+        let files: Vec<(&str, u64, Option<u32>)> = parsed
+            .files
+            .iter()
+            .map(|f| (f.name.as_str(), f.uncompressed_size, f.crc))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("big.bin", 6 * GIB, Some(0x1111_1111)),
+                ("solid/a.bin", 4 * GIB + 1, Some(0x2222_2222)),
+                ("solid/b.bin", 1, Some(0x3333_3333)),
+            ]
+        );
+        assert_eq!(parsed.files[0].compressed_size, 5 * GIB);
+        // Combined from the substreams across the 4 GiB boundary
+        assert_eq!(
+            parsed.folders[1].uncompressed_crc,
+            Some(crc32_combine(0x2222_2222, 0x3333_3333, 1))
+        );
+    }
+
+    /// An archive whose next header is `header`, with valid CRCs.
+    fn archive_with_header(header: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        crate::archive::writer::write_signature_header(
+            &mut bytes,
+            FormatVersion::V0_4,
+            0,
+            header.len() as u64,
+            crc32fast::hash(header),
+        )
+        .unwrap();
+        bytes.extend_from_slice(header);
+        bytes
+    }
+
+    #[test]
+    fn test_rejects_substreams_info_before_unpack_info() {
+        let header = [
+            0x01, 0x04, 0x08, 0x00, 0x06, 0x00, 0x01, 0x09, 0x01, 0x00, 0x07, 0x0B, 0x01, 0x00,
+            0x01, 0x01, 0x00, 0x0C, 0x01, 0x00, 0x00, 0x00,
+        ];
+        assert!(matches!(
+            SevenZipReader::new(Cursor::new(archive_with_header(&header))),
+            Err(SevenZipError::HeaderError(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_pack_offset_overflow() {
+        let header = ArchiveHeader {
+            folders: vec![FolderInfo {
+                compressed_size: u64::MAX - 8,
+                packed_crc: None,
+                uncompressed_size: 1,
+                uncompressed_crc: None,
+                codec: FolderCodec::Copy,
+                filters: Vec::new(),
+                encryption: None,
+                substreams: vec![SubStreamInfo { size: 1, crc: None }],
+            }],
+            files: vec![FileEntry {
+                name: "a".to_string(),
+                uncompressed_size: 1,
+                compressed_size: 0,
+                crc: None,
+                has_data: true,
+                is_directory: false,
+                modified_time: None,
+                attributes: None,
+            }],
+            pack_position: 16,
+        };
+        let bytes = archive_with_header(&header.serialize().unwrap());
+        match SevenZipReader::new(Cursor::new(bytes)) {
+            Err(SevenZipError::HeaderError(msg)) => assert!(msg.contains("overflow"), "{msg}"),
+            other => panic!("expected a header error, got {:?}", other.err()),
+        }
+    }
+}
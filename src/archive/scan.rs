@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+
+/// One entry as it would be stored in the archive.
+#[derive(Debug, Clone)]
+pub struct ScanEntry {
+    /// Name inside the archive.
+    pub archive_name: String,
+    /// Source file on disk, if the entry comes from the filesystem.
+    pub disk_path: Option<PathBuf>,
+    /// Size in bytes, or `None` for streams whose length is unknown until read.
+    pub size: Option<u64>,
+    pub is_directory: bool,
+    /// The source file has holes: it occupies less disk space than its size.
+    /// 7z cannot record holes, so they are stored (and extracted) as zeros.
+    pub is_sparse: bool,
+}
+
+/// Manifest of queued entries, produced without reading or compressing data.
+#[derive(Debug, Clone, Default)]
+pub struct ScanReport {
+    pub entries: Vec<ScanEntry>,
+    /// Number of non-directory entries (including empty files and streams).
+    pub file_count: usize,
+    pub directory_count: usize,
+    /// Sum of all known entry sizes.
+    pub total_bytes: u64,
+    /// Number of entries whose size is unknown (streaming readers).
+    pub unknown_size_count: usize,
+    /// Number of sparse source files, which extract as dense files.
+    pub sparse_count: usize,
+}
+
+impl ScanReport {
+    pub(crate) fn push(&mut self, entry: ScanEntry) {
+        if entry.is_directory {
+            self.directory_count += 1;
+        } else {
+            self.file_count += 1;
+        }
+        match entry.size {
+            Some(size) => self.total_bytes += size,
+            None => self.unknown_size_count += 1,
+        }
+        if entry.is_sparse {
+            self.sparse_count += 1;
+        }
+        self.entries.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_totals() {
+        let mut report = ScanReport::default();
+        report.push(ScanEntry {
+            archive_name: "dir".to_string(),
+            disk_path: None,
+            size: Some(0),
+            is_directory: true,
+            is_sparse: false,
+        });
+        report.push(ScanEntry {
+            archive_name: "dir/a.bin".to_string(),
+            disk_path: None,
+            size: Some(10),
+            is_directory: false,
+            is_sparse: false,
+        });
+        report.push(ScanEntry {
+            archive_name: "stream".to_string(),
+            disk_path: None,
+            size: None,
+            is_directory: false,
+            is_sparse: false,
+        });
+
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(report.file_count, 2);
+        assert_eq!(report.directory_count, 1);
+        assert_eq!(report.total_bytes, 10);
+        assert_eq!(report.unknown_size_count, 1);
+        assert_eq!(report.sparse_count, 0);
+    }
+}
@@ -2,9 +2,20 @@ use crate::archive::header::SIGNATURE;
 use byteorder::{LittleEndian, WriteBytesExt};
 use std::io::Write;
 
-/// 7z format version: major 0, minor 4.
-const VERSION_MAJOR: u8 = 0;
-const VERSION_MINOR: u8 = 4;
+/// 7z format version stored in the SignatureHeader.
+///
+/// Readers reject archives whose major version they do not know; the minor
+/// version records the newest format features the archive relies on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FormatVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl FormatVersion {
+    /// Version 0.4, as written by current 7-Zip releases.
+    pub const V0_4: FormatVersion = FormatVersion { major: 0, minor: 4 };
+}
 
 /// Size of the SignatureHeader in bytes.
 pub const SIGNATURE_HEADER_SIZE: u64 = 32;
@@ -21,6 +32,7 @@ pub const SIGNATURE_HEADER_SIZE: u64 = 32;
 ///   [28..32] NextHeaderCRC (u32 LE)
 pub fn write_signature_header<W: Write>(
     w: &mut W,
+    version: FormatVersion,
     next_header_offset: u64,
     next_header_size: u64,
     next_header_crc: u32,
@@ -35,8 +47,8 @@ pub fn write_signature_header<W: Write>(
 
     // Write the full 32-byte header
     w.write_all(&SIGNATURE)?;
-    w.write_u8(VERSION_MAJOR)?;
-    w.write_u8(VERSION_MINOR)?;
+    w.write_u8(version.major)?;
+    w.write_u8(version.minor)?;
     w.write_u32::<LittleEndian>(start_header_crc)?;
     w.write_all(&start_header_data)?;
 
@@ -50,14 +62,14 @@ mod tests {
     #[test]
     fn test_signature_header_size() {
         let mut buf = Vec::new();
-        write_signature_header(&mut buf, 0, 0, 0).unwrap();
+        write_signature_header(&mut buf, FormatVersion::V0_4, 0, 0, 0).unwrap();
         assert_eq!(buf.len(), 32);
     }
 
     #[test]
     fn test_signature_header_starts_with_signature() {
         let mut buf = Vec::new();
-        write_signature_header(&mut buf, 100, 50, 0xDEADBEEF).unwrap();
+        write_signature_header(&mut buf, FormatVersion::V0_4, 100, 50, 0xDEADBEEF).unwrap();
         assert_eq!(&buf[0..6], &SIGNATURE);
         assert_eq!(buf[6], 0); // major
         assert_eq!(buf[7], 4); // minor
@@ -66,11 +78,19 @@ mod tests {
     #[test]
     fn test_signature_header_crc_covers_20_bytes() {
         let mut buf = Vec::new();
-        write_signature_header(&mut buf, 100, 50, 0xAABBCCDD).unwrap();
+        write_signature_header(&mut buf, FormatVersion::V0_4, 100, 50, 0xAABBCCDD).unwrap();
 
         // Verify StartHeaderCRC (bytes 8..12) matches CRC of bytes 12..32
         let start_header_crc = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
         let computed_crc = crc32fast::hash(&buf[12..32]);
         assert_eq!(start_header_crc, computed_crc);
     }
+
+    #[test]
+    fn test_signature_header_writes_given_version() {
+        let mut buf = Vec::new();
+        let version = FormatVersion { major: 0, minor: 7 };
+        write_signature_header(&mut buf, version, 0, 0, 0).unwrap();
+        assert_eq!(&buf[6..8], &[0, 7]);
+    }
 }
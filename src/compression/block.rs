@@ -1,3 +1,8 @@
+use crate::error::{Result, SevenZipError};
+#[cfg(test)]
+use std::cell::Cell;
+use std::time::Duration;
+
 /// A raw (uncompressed) block of data with its index.
 pub struct RawBlock {
     pub data: Vec<u8>,
@@ -6,11 +11,20 @@ pub struct RawBlock {
 
 /// A compressed block with metadata.
 pub struct CompressedBlock {
+    /// Empty when the block is kept in `raw_fallback` instead.
     pub compressed_data: Vec<u8>,
     pub uncompressed_size: u64,
     pub compressed_size: u64,
-    pub uncompressed_crc: u32,
+    /// `None` when the block was not hashed (`Lzma2Config::block_crc`).
+    pub uncompressed_crc: Option<u32>,
     pub block_index: usize,
+    /// The original data, kept when LZMA2 did not shrink the block so the
+    /// folder can fall back to the Copy coder. Otherwise the block is
+    /// written as LZMA2 uncompressed chunks, or compressed again for
+    /// BZip2.
+    pub raw_fallback: Option<Vec<u8>>,
+    /// Time the worker spent on this block (CRC and compression).
+    pub compress_time: Duration,
 }
 
 /// Splits data into blocks of at most `block_size` bytes.
@@ -24,6 +38,126 @@ pub fn split_into_blocks(data: &[u8], block_size: usize) -> Vec<RawBlock> {
         .collect()
 }
 
+/// Splits owned data into blocks of at most `block_size` bytes, numbered
+/// from `first_index`, without holding a second copy of it.
+///
+/// Blocks are cut from the end of the buffer, which is shrunk after each
+/// cut, so at most about one block is allocated beyond the input at any
+/// time; the first block reuses the input's allocation.
+pub fn split_owned_into_blocks(
+    mut data: Vec<u8>,
+    block_size: usize,
+    first_index: usize,
+) -> Vec<RawBlock> {
+    let count = data.len().div_ceil(block_size);
+    let mut blocks = Vec::with_capacity(count);
+    for i in (1..count).rev() {
+        let tail = data.split_off(i * block_size);
+        data.shrink_to_fit();
+        blocks.push(RawBlock {
+            data: tail,
+            block_index: first_index + i,
+        });
+    }
+    if !data.is_empty() {
+        blocks.push(RawBlock {
+            data,
+            block_index: first_index,
+        });
+    }
+    blocks.reverse();
+    blocks
+}
+
+/// Re-chunks consecutive blocks into blocks of exactly `block_size` bytes
+/// (the last one may be shorter), numbering them from `first_index`.
+///
+/// Used in solid mode so that small files share blocks — and therefore an
+/// LZMA2 dictionary — instead of each starting a fresh stream. Input blocks
+/// are consumed one at a time, so only one extra block is held at once.
+pub fn repack_blocks(
+    blocks: impl IntoIterator<Item = RawBlock>,
+    block_size: usize,
+    first_index: usize,
+) -> Vec<RawBlock> {
+    let mut result = Vec::new();
+    let mut current: Vec<u8> = Vec::new();
+
+    for block in blocks {
+        let mut data = block.data.as_slice();
+        while !data.is_empty() {
+            let take = (block_size - current.len()).min(data.len());
+            current.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if current.len() == block_size {
+                result.push(RawBlock {
+                    data: std::mem::take(&mut current),
+                    block_index: first_index + result.len(),
+                });
+            }
+        }
+    }
+
+    if !current.is_empty() {
+        result.push(RawBlock {
+            data: current,
+            block_index: first_index + result.len(),
+        });
+    }
+
+    result
+}
+
+/// Computes the CRC32 of the concatenation `a || b` from `crc(a)`, `crc(b)`
+/// and the length of `b`, without touching the data again.
+pub fn crc32_combine(crc_a: u32, crc_b: u32, len_b: u64) -> u32 {
+    let mut hasher = crc32fast::Hasher::new_with_initial(crc_a);
+    hasher.combine(&crc32fast::Hasher::new_with_initial_len(crc_b, len_b));
+    hasher.finalize()
+}
+
+/// The CRC32 of the uncompressed data of `blocks`, in order, combined from
+/// the per-block CRCs computed by the compression threads; `None` if a
+/// block was not hashed.
+pub fn combined_crc(blocks: &[CompressedBlock]) -> Option<u32> {
+    blocks.iter().try_fold(0, |crc, block| {
+        let block_crc = block.uncompressed_crc?;
+        Some(crc32_combine(crc, block_crc, block.uncompressed_size))
+    })
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Bytes hashed with CRC32 on this thread, so tests can check that no
+    /// data is hashed when no CRCs are stored.
+    pub(crate) static CRC_BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Records that `len` bytes of entry data were hashed (test builds only).
+pub(crate) fn count_crc_bytes(_len: usize) {
+    #[cfg(test)]
+    CRC_BYTES.with(|bytes| bytes.set(bytes.get() + _len as u64));
+}
+
+/// Checks that the per-block CRCs of `blocks`, combined in order, match
+/// `expected`, the CRC computed over the same data as it was read.
+///
+/// Catches blocks that were reordered, dropped or duplicated between
+/// reading and writing; returns `SevenZipError::InvalidState` if so.
+/// Blocks compressed without CRCs are not checked.
+pub fn check_blocks_crc(blocks: &[CompressedBlock], expected: u32) -> Result<()> {
+    let Some(combined) = combined_crc(blocks) else {
+        return Ok(());
+    };
+    if combined != expected {
+        return Err(SevenZipError::InvalidState(format!(
+            "CRC of {} compressed blocks is {combined:08x}, data read had {expected:08x}",
+            blocks.len()
+        )));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,4 +195,123 @@ mod tests {
         let blocks = split_into_blocks(&data, 10);
         assert_eq!(blocks.len(), 0);
     }
+
+    #[test]
+    fn test_split_owned_matches_split() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        for block_size in [1, 7, 100, 999, 1000, 4096] {
+            let owned = split_owned_into_blocks(data.clone(), block_size, 5);
+            let borrowed = split_into_blocks(&data, block_size);
+            assert_eq!(owned.len(), borrowed.len());
+            for (i, (a, b)) in owned.iter().zip(&borrowed).enumerate() {
+                assert_eq!(a.data, b.data);
+                assert_eq!(a.block_index, 5 + i);
+            }
+        }
+        assert!(split_owned_into_blocks(Vec::new(), 10, 0).is_empty());
+    }
+
+    #[test]
+    fn test_repack_blocks_merges_small_blocks() {
+        let blocks = vec![
+            RawBlock {
+                data: vec![1, 2],
+                block_index: 0,
+            },
+            RawBlock {
+                data: vec![3, 4, 5],
+                block_index: 1,
+            },
+            RawBlock {
+                data: vec![6],
+                block_index: 2,
+            },
+        ];
+        let repacked = repack_blocks(blocks, 4, 7);
+        assert_eq!(repacked.len(), 2);
+        assert_eq!(repacked[0].data, vec![1, 2, 3, 4]);
+        assert_eq!(repacked[0].block_index, 7);
+        assert_eq!(repacked[1].data, vec![5, 6]);
+        assert_eq!(repacked[1].block_index, 8);
+    }
+
+    #[test]
+    fn test_repack_blocks_splits_large_block() {
+        let blocks = vec![RawBlock {
+            data: (0..10).collect(),
+            block_index: 0,
+        }];
+        let repacked = repack_blocks(blocks, 3, 0);
+        let sizes: Vec<usize> = repacked.iter().map(|b| b.data.len()).collect();
+        assert_eq!(sizes, vec![3, 3, 3, 1]);
+    }
+
+    #[test]
+    fn test_check_blocks_crc_detects_reordering() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut blocks: Vec<CompressedBlock> = split_into_blocks(&data, 300)
+            .into_iter()
+            .map(|block| CompressedBlock {
+                compressed_data: Vec::new(),
+                uncompressed_size: block.data.len() as u64,
+                compressed_size: 0,
+                uncompressed_crc: Some(crc32fast::hash(&block.data)),
+                block_index: block.block_index,
+                raw_fallback: None,
+                compress_time: Duration::ZERO,
+            })
+            .collect();
+        let expected = crc32fast::hash(&data);
+        check_blocks_crc(&blocks, expected).unwrap();
+
+        blocks.swap(0, 2);
+        assert!(matches!(
+            check_blocks_crc(&blocks, expected),
+            Err(SevenZipError::InvalidState(_))
+        ));
+        blocks.swap(0, 2);
+        blocks.pop();
+        assert!(check_blocks_crc(&blocks, expected).is_err());
+        assert!(check_blocks_crc(&[], 0).is_ok());
+    }
+
+    #[test]
+    fn test_combined_crc_matches_whole_data() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i * 31 / 7) as u8).collect();
+        let config = crate::compression::lzma2::Lzma2Config {
+            preset: 0,
+            ..Default::default()
+        };
+        for block_size in [1 << 10, 4099, 65_536, 299_999, 1 << 20] {
+            let blocks: Vec<CompressedBlock> = split_into_blocks(&data, block_size)
+                .into_iter()
+                .map(|block| crate::threading::worker::compress_raw_block(block, &config).unwrap())
+                .collect();
+            assert_eq!(
+                combined_crc(&blocks),
+                Some(crc32fast::hash(&data)),
+                "block size {block_size}"
+            );
+        }
+        assert_eq!(combined_crc(&[]), Some(crc32fast::hash(&[])));
+
+        let config = crate::compression::lzma2::Lzma2Config {
+            block_crc: false,
+            ..config
+        };
+        let blocks: Vec<CompressedBlock> = split_into_blocks(&data, 65_536)
+            .into_iter()
+            .map(|block| crate::threading::worker::compress_raw_block(block, &config).unwrap())
+            .collect();
+        assert_eq!(combined_crc(&blocks), None);
+        check_blocks_crc(&blocks, 0).unwrap();
+    }
+
+    #[test]
+    fn test_crc32_combine() {
+        let a = b"hello ";
+        let b = b"world";
+        let combined = crc32_combine(crc32fast::hash(a), crc32fast::hash(b), b.len() as u64);
+        assert_eq!(combined, crc32fast::hash(b"hello world"));
+    }
 }
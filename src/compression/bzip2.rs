@@ -0,0 +1,254 @@
+use crate::error::{Result, SevenZipError};
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+use std::io::Write;
+
+/// 48-bit magic opening every compressed block of a bzip2 stream.
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+
+/// 48-bit magic of the end-of-stream record, followed by the stream CRC.
+const END_MAGIC: u64 = 0x1772_4538_5090;
+
+/// Checks a bzip2 level, returning `SevenZipError::InvalidState` outside 1-9.
+pub fn validate_level(level: u32) -> Result<()> {
+    if !(1..=9).contains(&level) {
+        return Err(SevenZipError::InvalidState(format!(
+            "bzip2 level must be between 1 and 9, got {level}"
+        )));
+    }
+    Ok(())
+}
+
+/// Largest input guaranteed to fit in one bzip2 block at `level`.
+///
+/// A block holds `level × 100 000 - 19` bytes after the initial run-length
+/// encoding, which grows the input by at most 5/4 (runs of exactly four
+/// bytes gain a count byte).
+fn chunk_size(level: u32) -> usize {
+    (level as usize * 100_000 - 19) / 5 * 4
+}
+
+/// Number of bzip2 blocks in the stream `compress_block` produces for
+/// `len` input bytes.
+fn block_count(len: u64, level: u32) -> u32 {
+    len.div_ceil(chunk_size(level) as u64) as u32
+}
+
+/// Compresses a data block into a single bzip2 stream.
+///
+/// The data is cut into chunks that each fit in one bzip2 block, so the
+/// number of blocks depends only on the input size. That lets
+/// [`StreamJoiner`] merge the streams of consecutive data blocks into one
+/// stream, which every bzip2 decoder reads, rather than relying on
+/// multi-stream support.
+pub fn compress_block(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    validate_level(level)?;
+    let mut joiner = StreamJoiner::new();
+    joiner.start(level)?;
+    for chunk in data.chunks(chunk_size(level)) {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::new(level));
+        encoder
+            .write_all(chunk)
+            .map_err(|e| SevenZipError::Compression(format!("bzip2 write failed: {e}")))?;
+        let stream = encoder
+            .finish()
+            .map_err(|e| SevenZipError::Compression(format!("bzip2 finish failed: {e}")))?;
+        joiner.push_stream(&stream, 1)?;
+    }
+    Ok(joiner.finish())
+}
+
+/// Merges bzip2 streams into a single stream by splicing their blocks,
+/// which are bit-aligned, and recomputing the combined stream CRC.
+#[derive(Default)]
+pub struct StreamJoiner {
+    /// Block size digit of the first stream; all streams must match.
+    level: Option<u32>,
+    out: Vec<u8>,
+    /// Bits not yet forming a whole byte, in the low `pending_bits`.
+    pending: u64,
+    pending_bits: u32,
+    combined_crc: u32,
+}
+
+impl StreamJoiner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the blocks of a stream produced by [`compress_block`] from
+    /// `uncompressed_size` bytes.
+    pub fn push(&mut self, stream: &[u8], uncompressed_size: u64) -> Result<()> {
+        let level = stream_level(stream)?;
+        self.push_stream(stream, block_count(uncompressed_size, level))
+    }
+
+    /// Takes the merged bytes produced so far, leaving a partial byte pending.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    /// Writes the end-of-stream record and returns the remaining output.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.level.is_none() {
+            self.out.extend_from_slice(b"BZh9");
+        }
+        self.put_bits(END_MAGIC, 48);
+        self.put_bits(self.combined_crc as u64, 32);
+        if self.pending_bits > 0 {
+            self.put_bits(0, 8 - self.pending_bits);
+        }
+        self.out
+    }
+
+    /// Writes the stream header for `level`, or checks that it matches the
+    /// header already written.
+    fn start(&mut self, level: u32) -> Result<()> {
+        match self.level {
+            None => {
+                self.level = Some(level);
+                self.out.extend_from_slice(b"BZh");
+                self.out.push(b'0' + level as u8);
+                Ok(())
+            }
+            Some(current) if current == level => Ok(()),
+            Some(_) => Err(SevenZipError::Compression(
+                "cannot join bzip2 streams of different levels".to_string(),
+            )),
+        }
+    }
+
+    /// Appends the blocks of `stream`, which holds `num_blocks` blocks.
+    fn push_stream(&mut self, stream: &[u8], num_blocks: u32) -> Result<()> {
+        self.start(stream_level(stream)?)?;
+
+        let end = find_end_record(stream)?;
+        let stream_crc = read_bits(stream, end + 48, 32) as u32;
+        // The stream CRC of a single block is that block's CRC
+        if num_blocks == 1
+            && (read_bits(stream, 32, 48) != BLOCK_MAGIC
+                || read_bits(stream, 32 + 48, 32) as u32 != stream_crc)
+        {
+            return Err(SevenZipError::Compression(
+                "bzip2 chunk did not fit in a single block".to_string(),
+            ));
+        }
+        self.combined_crc = self.combined_crc.rotate_left(num_blocks) ^ stream_crc;
+
+        // Blocks start right after the 4-byte header, so whole bytes can be
+        // copied up to the byte holding the end record.
+        let whole_bytes = end / 8;
+        for &byte in &stream[4..whole_bytes] {
+            self.put_bits(byte as u64, 8);
+        }
+        let tail_bits = (end % 8) as u32;
+        if tail_bits > 0 {
+            self.put_bits((stream[whole_bytes] >> (8 - tail_bits)) as u64, tail_bits);
+        }
+        Ok(())
+    }
+
+    /// Appends the low `count` (at most 56) bits of `value`, most
+    /// significant first.
+    fn put_bits(&mut self, value: u64, count: u32) {
+        self.pending = (self.pending << count) | (value & ((1 << count) - 1));
+        self.pending_bits += count;
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.out.push((self.pending >> self.pending_bits) as u8);
+        }
+        self.pending &= (1 << self.pending_bits) - 1;
+    }
+}
+
+/// Block size digit from a stream's `BZh1`..`BZh9` header.
+fn stream_level(stream: &[u8]) -> Result<u32> {
+    match stream {
+        [b'B', b'Z', b'h', digit @ b'1'..=b'9', ..] => Ok((digit - b'0') as u32),
+        _ => Err(SevenZipError::Compression(
+            "invalid bzip2 stream: bad header".to_string(),
+        )),
+    }
+}
+
+/// Bit position of the end-of-stream record, which is followed by the
+/// 32-bit stream CRC and up to 7 zero padding bits.
+fn find_end_record(stream: &[u8]) -> Result<usize> {
+    let total_bits = stream.len() * 8;
+    (0..8)
+        .filter_map(|padding| total_bits.checked_sub(padding + 80))
+        .find(|&pos| pos >= 32 && read_bits(stream, pos, 48) == END_MAGIC)
+        .ok_or_else(|| {
+            SevenZipError::Compression("invalid bzip2 stream: missing end record".to_string())
+        })
+}
+
+/// Reads `count` (at most 64) bits starting at bit `pos`, most significant
+/// bit first.
+fn read_bits(data: &[u8], pos: usize, count: usize) -> u64 {
+    (pos..pos + count).fold(0, |value, bit| {
+        (value << 1) | ((data[bit / 8] >> (7 - bit % 8)) & 1) as u64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2::read::BzDecoder;
+    use std::io::Read;
+
+    fn decompress(stream: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        BzDecoder::new(stream).read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_validate_level() {
+        assert!(validate_level(1).is_ok());
+        assert!(validate_level(9).is_ok());
+        assert!(validate_level(0).is_err());
+        assert!(validate_level(10).is_err());
+    }
+
+    #[test]
+    fn test_compress_block_roundtrip() {
+        // Several chunks at level 1, with runs to exercise RLE growth
+        let data: Vec<u8> = (0..300_000u32)
+            .map(|i| if i % 9 < 4 { b'x' } else { (i % 251) as u8 })
+            .collect();
+        let stream = compress_block(&data, 1).unwrap();
+        assert_eq!(&stream[..4], b"BZh1");
+        assert_eq!(decompress(&stream), data);
+    }
+
+    #[test]
+    fn test_compress_block_empty() {
+        let stream = compress_block(b"", 9).unwrap();
+        assert!(decompress(&stream).is_empty());
+    }
+
+    #[test]
+    fn test_joined_streams_form_one_stream() {
+        let first = b"first block of text, ".repeat(20_000);
+        let second = b"and a second one".repeat(3);
+        let mut joiner = StreamJoiner::new();
+        let mut joined = Vec::new();
+        for data in [&first, &second] {
+            let stream = compress_block(data, 2).unwrap();
+            joiner.push(&stream, data.len() as u64).unwrap();
+            joined.extend(joiner.take_output());
+        }
+        joined.extend(joiner.finish());
+
+        // A single-stream decoder reads everything
+        assert_eq!(decompress(&joined), [first, second].concat());
+    }
+
+    #[test]
+    fn test_join_rejects_mixed_levels() {
+        let mut joiner = StreamJoiner::new();
+        joiner.push(&compress_block(b"a", 1).unwrap(), 1).unwrap();
+        assert!(joiner.push(&compress_block(b"b", 2).unwrap(), 1).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Content-defined chunking: block boundaries placed where a rolling hash
+//! of the last few bytes matches a pattern, instead of every N bytes.
+//!
+//! Inserting or deleting bytes then only moves the boundaries next to the
+//! edit; once the hash window is past it, the same content is cut at the
+//! same places again. Each chunk is still compressed as an independent
+//! block, so the archive format does not change.
+
+use crate::compression::block::RawBlock;
+
+/// Bytes of context the rolling hash covers.
+const WINDOW: usize = 48;
+
+/// Random values for each byte, generated with splitmix64 so the table,
+/// and therefore every boundary, is the same on every build.
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = (z ^ (z >> 31)) as u32;
+        i += 1;
+    }
+    table
+};
+
+/// Chunk size limits for content-defined chunking, as given by
+/// `BlockPolicy::ContentDefined`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLimits {
+    /// Target average chunk size.
+    pub avg_size: usize,
+    /// No boundary is placed before this many bytes.
+    pub min: usize,
+    /// A boundary is forced after this many bytes.
+    pub max: usize,
+}
+
+impl ChunkLimits {
+    /// Hash bits that must be zero at a boundary: past `min`, a boundary
+    /// then comes about every `avg_size - min` bytes.
+    fn mask(&self) -> u32 {
+        let spacing = self.avg_size.saturating_sub(self.min).max(1);
+        let bits = (usize::BITS - 1 - spacing.leading_zeros()).min(31);
+        (1u32 << bits) - 1
+    }
+}
+
+/// Re-cuts consecutive blocks at content-defined boundaries, numbering the
+/// chunks from `first_index`.
+///
+/// The hash restarts at each boundary, so where a chunk ends depends only
+/// on its own content. Input blocks are consumed one at a time, so only
+/// one extra chunk is held at once.
+pub fn split_content_defined(
+    blocks: impl IntoIterator<Item = RawBlock>,
+    limits: ChunkLimits,
+    first_index: usize,
+) -> Vec<RawBlock> {
+    let mask = limits.mask();
+    let mut result = Vec::new();
+    let mut current: Vec<u8> = Vec::new();
+    let mut hash = 0u32;
+
+    for block in blocks {
+        let mut data = block.data.as_slice();
+        while !data.is_empty() {
+            let (take, boundary) = scan(&current, data, &mut hash, limits, mask);
+            current.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if boundary {
+                result.push(RawBlock {
+                    data: std::mem::take(&mut current),
+                    block_index: first_index + result.len(),
+                });
+                hash = 0;
+            }
+        }
+    }
+
+    if !current.is_empty() {
+        result.push(RawBlock {
+            data: current,
+            block_index: first_index + result.len(),
+        });
+    }
+    result
+}
+
+/// Rolls the hash over `data`, which continues the chunk `current`, until
+/// a boundary. Returns how many bytes of `data` belong to the chunk and
+/// whether it ends there.
+fn scan(
+    current: &[u8],
+    data: &[u8],
+    hash: &mut u32,
+    limits: ChunkLimits,
+    mask: u32,
+) -> (usize, bool) {
+    for (i, &byte) in data.iter().enumerate() {
+        let len = current.len() + i + 1;
+        *hash = hash.rotate_left(1) ^ TABLE[byte as usize];
+        if len > WINDOW {
+            let out = len - 1 - WINDOW;
+            let out_byte = match current.get(out) {
+                Some(&b) => b,
+                None => data[out - current.len()],
+            };
+            *hash ^= TABLE[out_byte as usize].rotate_left(WINDOW as u32);
+        }
+        if len >= limits.max || (len >= limits.min && *hash & mask == 0) {
+            return (i + 1, true);
+        }
+    }
+    (data.len(), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn chunk_sizes(data: &[u8], limits: ChunkLimits, input_block: usize) -> Vec<usize> {
+        let blocks = data.chunks(input_block).map(|chunk| RawBlock {
+            data: chunk.to_vec(),
+            block_index: 0,
+        });
+        split_content_defined(blocks, limits, 0)
+            .iter()
+            .map(|b| b.data.len())
+            .collect()
+    }
+
+    const LIMITS: ChunkLimits = ChunkLimits {
+        avg_size: 8192,
+        min: 2048,
+        max: 32768,
+    };
+
+    #[test]
+    fn test_chunks_respect_limits() {
+        let data = pseudo_random(1 << 20, 1);
+        let blocks = split_content_defined(
+            data.chunks(100_000).map(|chunk| RawBlock {
+                data: chunk.to_vec(),
+                block_index: 0,
+            }),
+            LIMITS,
+            5,
+        );
+        let (last, rest) = blocks.split_last().unwrap();
+        assert!(rest
+            .iter()
+            .all(|b| (LIMITS.min..=LIMITS.max).contains(&b.data.len())));
+        assert!(last.data.len() <= LIMITS.max);
+        let indices: Vec<usize> = blocks.iter().map(|b| b.block_index).collect();
+        assert_eq!(indices, (5..5 + blocks.len()).collect::<Vec<_>>());
+        let joined: Vec<u8> = blocks.iter().flat_map(|b| b.data.clone()).collect();
+        assert_eq!(joined, data);
+        // Roughly the requested average
+        let average = data.len() / blocks.len();
+        assert!((4096..=16384).contains(&average), "average {average}");
+    }
+
+    #[test]
+    fn test_boundaries_independent_of_input_blocks() {
+        let data = pseudo_random(300_000, 2);
+        assert_eq!(
+            chunk_sizes(&data, LIMITS, 1000),
+            chunk_sizes(&data, LIMITS, 65536)
+        );
+    }
+
+    #[test]
+    fn test_insertion_only_moves_nearby_boundaries() {
+        let data = pseudo_random(1 << 20, 3);
+        let mut edited = data[..1000].to_vec();
+        edited.extend_from_slice(b"a few inserted bytes");
+        edited.extend_from_slice(&data[1000..]);
+
+        let ends = |sizes: Vec<usize>| -> Vec<usize> {
+            sizes
+                .iter()
+                .scan(0, |end, size| {
+                    *end += size;
+                    Some(*end)
+                })
+                .collect()
+        };
+        let original = ends(chunk_sizes(&data, LIMITS, 65536));
+        // Shift the edited boundaries back onto the original content
+        let shifted: Vec<usize> = ends(chunk_sizes(&edited, LIMITS, 65536))
+            .into_iter()
+            .map(|end| end - 20)
+            .collect();
+        let common = original.iter().filter(|end| shifted.contains(end)).count();
+        assert!(
+            common + 2 >= original.len(),
+            "{common} of {} boundaries kept",
+            original.len()
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use crate::archive::header::LZMA2_CODER_ID;
+use crate::compression::lzma2::{compress_block, encode_properties_byte, Codec, Lzma2Config};
+use crate::error::Result;
+use std::fmt;
+
+/// A compression method supplied by the caller, set with
+/// `Lzma2Config::compressor` in place of one of the built-in codecs.
+///
+/// Each block is compressed on its own and the output written as the
+/// folder's packed stream, so the method's streams do not need to be
+/// concatenable: like LZMA, folders using a custom compressor are
+/// compressed as a single block. The archive can only be extracted by a
+/// decoder that knows the method's [`coder_id`](Self::coder_id).
+pub trait BlockCompressor: fmt::Debug + Send + Sync {
+    /// Compresses `data` into one complete stream.
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// 7z method ID written in the folder's coder record (at most 15 bytes).
+    fn coder_id(&self) -> &[u8];
+
+    /// Coder properties written after the ID (empty for none).
+    fn properties(&self) -> Vec<u8>;
+}
+
+/// The built-in LZMA2 encoder (`lzma_rust2`) as a [`BlockCompressor`], e.g.
+/// to wrap it with instrumentation.
+#[derive(Debug, Clone)]
+pub struct Lzma2Compressor {
+    config: Lzma2Config,
+}
+
+impl Lzma2Compressor {
+    /// Compresses with `config`'s preset, dictionary size and encoder
+    /// options; its `codec` and `compressor` are ignored.
+    pub fn new(config: Lzma2Config) -> Self {
+        Self {
+            config: Lzma2Config {
+                codec: Codec::Lzma2,
+                compressor: None,
+                ..config
+            },
+        }
+    }
+}
+
+impl BlockCompressor for Lzma2Compressor {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        compress_block(data, &self.config)
+    }
+
+    fn coder_id(&self) -> &[u8] {
+        &[LZMA2_CODER_ID]
+    }
+
+    fn properties(&self) -> Vec<u8> {
+        vec![encode_properties_byte(self.config.effective_dict_size())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lzma_rust2::Lzma2Reader;
+    use std::io::Read;
+
+    #[test]
+    fn test_lzma2_compressor() {
+        let config = Lzma2Config {
+            preset: 1,
+            dict_size: Some(1 << 20),
+            ..Default::default()
+        };
+        let compressor = Lzma2Compressor::new(config);
+        assert_eq!(compressor.coder_id(), [0x21]);
+        assert_eq!(compressor.properties(), [encode_properties_byte(1 << 20)]);
+
+        let data = b"compressor ".repeat(1000);
+        let compressed = compressor.compress(&data).unwrap();
+        assert!(compressed.len() < data.len());
+        let mut decompressed = Vec::new();
+        Lzma2Reader::new(compressed.as_slice(), 1 << 20, None)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::compression::block::RawBlock;
+use crate::error::{Result, SevenZipError};
+
+/// BCJ x86 coder ID in 7z format.
+pub const BCJ_X86_CODER_ID: [u8; 4] = [0x03, 0x03, 0x01, 0x03];
+
+/// Delta coder ID in 7z format.
+pub const DELTA_CODER_ID: [u8; 1] = [0x03];
+
+/// Reversible transform applied to folder data before compression, to make
+/// it more compressible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// x86 branch/call/jump converter: rewrites the relative addresses of
+    /// `CALL`/`JMP` instructions as absolute ones, so repeated calls to the
+    /// same function produce identical bytes. Helps native x86/x86-64 code.
+    BcjX86,
+    /// Stores each byte as its difference from the byte `distance` bytes
+    /// earlier (1..=256). Helps fixed-width samples such as PCM audio or
+    /// tables of numbers; use the sample or record width as distance.
+    Delta { distance: u16 },
+}
+
+impl Filter {
+    /// Coder ID written in the folder's coder chain.
+    pub fn coder_id(&self) -> &'static [u8] {
+        match self {
+            Filter::BcjX86 => &BCJ_X86_CODER_ID,
+            Filter::Delta { .. } => &DELTA_CODER_ID,
+        }
+    }
+
+    /// Coder properties (empty for filters using their defaults).
+    pub fn properties(&self) -> Vec<u8> {
+        match self {
+            Filter::BcjX86 => Vec::new(),
+            // Stored as distance - 1, so that 256 fits in one byte
+            Filter::Delta { distance } => vec![(distance - 1) as u8],
+        }
+    }
+}
+
+/// Checks filter parameters, returning `SevenZipError::InvalidState` for
+/// values 7z cannot represent.
+pub fn validate_filters(filters: &[Filter]) -> Result<()> {
+    for filter in filters {
+        if let Filter::Delta { distance } = filter {
+            if !(1..=256).contains(distance) {
+                return Err(SevenZipError::InvalidState(format!(
+                    "delta distance must be between 1 and 256, got {distance}"
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies `filters`, in order, to the data of one folder split into
+/// consecutive blocks.
+///
+/// Decoders undo the filters over the folder's whole output stream, so the
+/// filter state is carried across block boundaries rather than reset per
+/// block. The pass is sequential but cheap next to compression.
+pub fn apply_filters(filters: &[Filter], blocks: &mut [RawBlock]) {
+    for filter in filters {
+        match filter {
+            Filter::BcjX86 => {
+                let mut bcj = BcjX86::new(true);
+                run_across_blocks(blocks, |buf| bcj.code(buf));
+            }
+            Filter::Delta { distance } => {
+                let mut delta = DeltaEncoder::new(*distance as usize);
+                for block in blocks.iter_mut() {
+                    delta.encode(&mut block.data);
+                }
+            }
+        }
+    }
+}
+
+/// Feeds consecutive blocks to a streaming filter that may leave a tail of
+/// each buffer unprocessed until more data is available. That tail is
+/// presented again in front of the next block; whatever remains after the
+/// last block is left as-is, exactly as a decoder leaves it.
+fn run_across_blocks(blocks: &mut [RawBlock], mut code: impl FnMut(&mut [u8]) -> usize) {
+    // Unprocessed bytes as (block index, offset) positions
+    let mut carry: Vec<(usize, usize)> = Vec::new();
+    let mut buf = Vec::new();
+
+    for i in 0..blocks.len() {
+        buf.clear();
+        buf.extend(carry.iter().map(|&(b, o)| blocks[b].data[o]));
+        buf.extend_from_slice(&blocks[i].data);
+
+        let processed = code(&mut buf);
+
+        for (&(b, o), &byte) in carry.iter().zip(&buf) {
+            blocks[b].data[o] = byte;
+        }
+        let carried = carry.len();
+        blocks[i].data.copy_from_slice(&buf[carried..]);
+
+        carry = (processed..buf.len())
+            .map(|pos| {
+                if pos < carried {
+                    carry[pos]
+                } else {
+                    (i, pos - carried)
+                }
+            })
+            .collect();
+    }
+}
+
+/// Streaming delta encoder: the history starts as zeros, as in decoders.
+struct DeltaEncoder {
+    /// The last `distance` input bytes, as a ring buffer.
+    history: Vec<u8>,
+    next: usize,
+}
+
+impl DeltaEncoder {
+    fn new(distance: usize) -> Self {
+        Self {
+            history: vec![0; distance],
+            next: 0,
+        }
+    }
+
+    fn encode(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            let previous = std::mem::replace(&mut self.history[self.next], *byte);
+            *byte = byte.wrapping_sub(previous);
+            self.next = (self.next + 1) % self.history.len();
+        }
+    }
+}
+
+/// Streaming BCJ x86 converter, compatible with the 7-Zip and xz decoders.
+struct BcjX86 {
+    encoding: bool,
+    /// Stream position of the next unprocessed byte, plus 5.
+    pos: u32,
+    prev_mask: u32,
+}
+
+const MASK_TO_ALLOWED: [bool; 8] = [true, true, true, false, true, false, false, false];
+const MASK_TO_BIT_NUMBER: [u32; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+
+fn test_ms_byte(b: u8) -> bool {
+    b == 0x00 || b == 0xFF
+}
+
+impl BcjX86 {
+    fn new(encoding: bool) -> Self {
+        Self {
+            encoding,
+            pos: 5,
+            prev_mask: 0,
+        }
+    }
+
+    /// Converts `buf` in place and returns how many leading bytes are final.
+    /// The rest (at most 4 bytes) must be passed again with more data.
+    fn code(&mut self, buf: &mut [u8]) -> usize {
+        if buf.len() < 5 {
+            return 0;
+        }
+        let end = buf.len() - 5;
+        let mut prev_pos: isize = -1;
+        let mut prev_mask = self.prev_mask;
+        let mut i = 0;
+
+        while i <= end {
+            if buf[i] & 0xFE != 0xE8 {
+                i += 1;
+                continue;
+            }
+
+            let distance = i as isize - prev_pos;
+            if distance & !3 != 0 {
+                prev_mask = 0;
+            } else {
+                prev_mask = (prev_mask << (distance - 1)) & 7;
+                if prev_mask != 0
+                    && (!MASK_TO_ALLOWED[prev_mask as usize]
+                        || test_ms_byte(
+                            buf[i + 4 - MASK_TO_BIT_NUMBER[prev_mask as usize] as usize],
+                        ))
+                {
+                    prev_pos = i as isize;
+                    prev_mask = (prev_mask << 1) | 1;
+                    i += 1;
+                    continue;
+                }
+            }
+
+            prev_pos = i as isize;
+            if test_ms_byte(buf[i + 4]) {
+                let mut src = u32::from_le_bytes([buf[i + 1], buf[i + 2], buf[i + 3], buf[i + 4]]);
+                let cur = self.pos.wrapping_add(i as u32);
+                let dest = loop {
+                    let dest = if self.encoding {
+                        src.wrapping_add(cur)
+                    } else {
+                        src.wrapping_sub(cur)
+                    };
+                    if prev_mask == 0 {
+                        break dest;
+                    }
+                    let index = MASK_TO_BIT_NUMBER[prev_mask as usize] * 8;
+                    if !test_ms_byte((dest >> (24 - index)) as u8) {
+                        break dest;
+                    }
+                    src = dest ^ ((1u32 << (32 - index)) - 1);
+                };
+
+                // The top byte is the sign extension of bit 24
+                let dest = (dest & 0x00FF_FFFF)
+                    | if dest & 0x0100_0000 != 0 {
+                        0xFF00_0000
+                    } else {
+                        0
+                    };
+                buf[i + 1..i + 5].copy_from_slice(&dest.to_le_bytes());
+                i += 5;
+            } else {
+                prev_mask = (prev_mask << 1) | 1;
+                i += 1;
+            }
+        }
+
+        let distance = i as isize - prev_pos;
+        self.prev_mask = if distance & !3 != 0 {
+            0
+        } else {
+            prev_mask << (distance - 1)
+        };
+        self.pos = self.pos.wrapping_add(i as u32);
+        i
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks_of(data: &[u8], block_size: usize) -> Vec<RawBlock> {
+        data.chunks(block_size)
+            .enumerate()
+            .map(|(block_index, chunk)| RawBlock {
+                data: chunk.to_vec(),
+                block_index,
+            })
+            .collect()
+    }
+
+    fn concat(blocks: &[RawBlock]) -> Vec<u8> {
+        blocks.iter().flat_map(|b| b.data.iter().copied()).collect()
+    }
+
+    /// Pseudo-code with plenty of CALL/JMP opcodes and near addresses.
+    fn sample_code() -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        let mut data = Vec::new();
+        for i in 0..20_000u32 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            match state >> 29 {
+                0 | 1 => {
+                    data.push(0xE8);
+                    data.extend_from_slice(
+                        &(i.wrapping_mul(3) % 4096).wrapping_sub(2048).to_le_bytes(),
+                    );
+                }
+                2 => data.push(0xE9),
+                _ => data.push((state >> 16) as u8),
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_bcj_roundtrip() {
+        let original = sample_code();
+        let mut data = original.clone();
+        let n = BcjX86::new(true).code(&mut data);
+        assert!(n >= original.len() - 4);
+        assert_ne!(data, original);
+
+        BcjX86::new(false).code(&mut data);
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn test_bcj_converts_call_to_absolute() {
+        // CALL rel32 = 0x10 at stream offset 0x100 targets 0x115
+        let mut data = vec![0x90; 0x100];
+        data.extend_from_slice(&[0xE8, 0x10, 0x00, 0x00, 0x00]);
+        data.extend_from_slice(&[0x90; 8]);
+        BcjX86::new(true).code(&mut data);
+        assert_eq!(&data[0x101..0x105], &[0x15, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_block_boundaries_do_not_change_output() {
+        let original = sample_code();
+        let mut whole = blocks_of(&original, original.len());
+        apply_filters(&[Filter::BcjX86], &mut whole);
+
+        for block_size in [1, 3, 5, 7, 64, 1000] {
+            let mut split = blocks_of(&original, block_size);
+            apply_filters(&[Filter::BcjX86], &mut split);
+            assert_eq!(concat(&split), concat(&whole), "block size {block_size}");
+            assert!(split.iter().all(|b| b.data.len() <= block_size));
+        }
+    }
+
+    #[test]
+    fn test_delta_encodes_differences_across_blocks() {
+        let original: Vec<u8> = (0..1000u32).map(|i| (i * 7 + i / 3) as u8).collect();
+        let mut blocks = blocks_of(&original, 33);
+        apply_filters(&[Filter::Delta { distance: 2 }], &mut blocks);
+        let encoded = concat(&blocks);
+
+        assert_eq!(&encoded[..2], &original[..2]);
+        for i in 2..original.len() {
+            assert_eq!(encoded[i], original[i].wrapping_sub(original[i - 2]));
+        }
+    }
+
+    #[test]
+    fn test_delta_properties_and_validation() {
+        assert_eq!(Filter::Delta { distance: 1 }.properties(), [0]);
+        assert_eq!(Filter::Delta { distance: 256 }.properties(), [255]);
+        assert!(validate_filters(&[Filter::BcjX86, Filter::Delta { distance: 256 }]).is_ok());
+        for distance in [0, 257] {
+            assert!(matches!(
+                validate_filters(&[Filter::Delta { distance }]),
+                Err(SevenZipError::InvalidState(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_no_filters_is_identity() {
+        let original = sample_code();
+        let mut blocks = blocks_of(&original, 100);
+        apply_filters(&[], &mut blocks);
+        assert_eq!(concat(&blocks), original);
+    }
+}
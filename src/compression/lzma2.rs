@@ -1,6 +1,150 @@
+use crate::compression::cdc::ChunkLimits;
+use crate::compression::compressor::BlockCompressor;
+use crate::compression::filter::Filter;
 use crate::error::{Result, SevenZipError};
-use lzma_rust2::{Lzma2Options, Lzma2Writer};
+use lzma_rust2::{EncodeMode, Lzma2Options, Lzma2Writer, LzmaOptions, LzmaWriter, MfType};
 use std::io::Write;
+use std::num::NonZeroU64;
+use std::sync::Arc;
+
+/// Largest dictionary size supported by LZMA2 encoders (1.5 GiB).
+pub const MAX_DICT_SIZE: u32 = 1536 << 20;
+
+/// Smallest LZMA2 dictionary size (4 KiB).
+pub const MIN_DICT_SIZE: u32 = 4096;
+
+/// Compression method written to the archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// LZMA2 (the default): large inputs are split into blocks compressed
+    /// in parallel.
+    #[default]
+    Lzma2,
+    /// Plain LZMA, for decoders that predate LZMA2. LZMA streams cannot be
+    /// concatenated, so every folder is compressed as a single block on one
+    /// thread.
+    Lzma1,
+    /// BZip2 at the given level (1-9, the block size in units of 100 kB).
+    /// Blocks are still compressed in parallel; `preset` and `dict_size`
+    /// only apply to the LZMA2-compressed archive header.
+    #[cfg(feature = "bzip2")]
+    Bzip2 { level: u32 },
+    /// No compression (the 7z Copy coder), e.g. for data that is already
+    /// compressed. `preset` and `dict_size` only apply to the archive
+    /// header.
+    Copy,
+}
+
+/// LZMA encoder mode for [`Lzma2Config::mode`], overriding the one the
+/// preset picks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lzma2Mode {
+    /// Fast mode with the hash-chain match finder, as presets 0-3 use:
+    /// several times faster than `Normal`, at the cost of a lower ratio.
+    Fast,
+    /// Normal mode with the binary-tree match finder, as presets 4-9 use.
+    Normal,
+}
+
+/// Named speed/ratio tradeoffs, as an alternative to the numeric preset;
+/// see [`Lzma2Config::from_profile`]. Each sets:
+///
+/// | Profile    | preset | dictionary | `nice_len` | `depth_limit` |
+/// |------------|--------|------------|------------|---------------|
+/// | `Fast`     | 1      | 1 MiB      | 32         | auto          |
+/// | `Balanced` | 6      | 8 MiB      | 64         | auto          |
+/// | `Max`      | 9      | 32 MiB     | 128        | auto          |
+/// | `Ultra`    | 9      | 64 MiB     | 273        | 512           |
+///
+/// `Fast` uses the encoder's fast mode and hash-chain match finder (presets
+/// 0-3); the others use its normal mode and binary-tree match finder.
+/// `Balanced` is the default configuration. Larger dictionaries cost memory
+/// on every thread (see [`Lzma2Config::estimated_memory_per_thread`]): about
+/// 750 MiB each with `Ultra`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Fast,
+    Balanced,
+    Max,
+    Ultra,
+}
+
+impl std::str::FromStr for Profile {
+    type Err = SevenZipError;
+
+    /// Parses a profile name, case-insensitively: `fast`, `balanced`, `max`
+    /// or `ultra`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "fast" => Ok(Profile::Fast),
+            "balanced" => Ok(Profile::Balanced),
+            "max" => Ok(Profile::Max),
+            "ultra" => Ok(Profile::Ultra),
+            _ => Err(SevenZipError::InvalidState(format!(
+                "unknown profile '{s}' (expected fast, balanced, max or ultra)"
+            ))),
+        }
+    }
+}
+
+/// How files are split into blocks for parallel compression, set with
+/// `Lzma2Config::block_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockPolicy {
+    /// Blocks of this many bytes.
+    Fixed(usize),
+    /// A block size chosen in `finish()` from the archive's total input
+    /// size and thread count.
+    Adaptive(AdaptiveBlockPolicy),
+    /// Blocks cut where a rolling hash of the content says so (see
+    /// [`cdc`](crate::compression::cdc)), about `avg_size` bytes long and
+    /// within `min..=max`. Inserting bytes into a file then only moves the
+    /// block boundaries near the edit, which keeps similar versions of a
+    /// file dedup-friendly. Entries up to the intra-file solid limit stay
+    /// in one block.
+    ContentDefined {
+        avg_size: usize,
+        min: usize,
+        max: usize,
+    },
+}
+
+/// Chooses a block size aiming for about `4 × num_threads` blocks of equal
+/// size across the archive's input, so threads finishing early still find
+/// work, within `min_block_size..=max_block_size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveBlockPolicy {
+    /// Smallest block size (default: 1 MiB); smaller blocks compress worse.
+    pub min_block_size: usize,
+    /// Largest block size (default: 64 MiB), bounding each thread's buffers.
+    pub max_block_size: usize,
+}
+
+impl Default for AdaptiveBlockPolicy {
+    fn default() -> Self {
+        Self {
+            min_block_size: 1 << 20,
+            max_block_size: 64 << 20,
+        }
+    }
+}
+
+impl AdaptiveBlockPolicy {
+    const BLOCKS_PER_THREAD: u64 = 4;
+
+    /// Block size for `total_size` bytes of input compressed on
+    /// `num_threads` threads.
+    ///
+    /// The size is then adjusted so the input divides into blocks of nearly
+    /// equal length, rather than leaving a short last block.
+    pub fn block_size(&self, total_size: u64, num_threads: usize) -> usize {
+        let (min, max) = (self.min_block_size as u64, self.max_block_size as u64);
+        let target_blocks = Self::BLOCKS_PER_THREAD * num_threads.max(1) as u64;
+        let size = total_size.div_ceil(target_blocks).min(max).max(min);
+        let blocks = (total_size.saturating_add(size / 2) / size).max(1);
+        total_size.div_ceil(blocks).min(max).max(min) as usize
+    }
+}
 
 /// Configuration for LZMA2 compression.
 #[derive(Debug, Clone)]
@@ -13,6 +157,71 @@ pub struct Lzma2Config {
     /// Files larger than this are split into blocks compressed in parallel.
     /// If `None`, defaults to `2 × dict_size` (minimum 1 MiB).
     pub block_size: Option<usize>,
+    /// How block sizes are chosen; takes precedence over `block_size` when
+    /// set (default: `None`).
+    pub block_policy: Option<BlockPolicy>,
+    /// Literal context bits (0-4). If `None`, uses the preset default (3).
+    pub lc: Option<u32>,
+    /// Literal position bits (0-4). If `None`, uses the preset default (0).
+    /// `lc + lp` must not exceed 4.
+    pub lp: Option<u32>,
+    /// Position bits (0-4). If `None`, uses the preset default (2).
+    pub pb: Option<u32>,
+    /// Match length at which the match finder stops looking for a longer
+    /// match (8-273). Higher values can improve the ratio at the cost of
+    /// speed. If `None`, uses the preset default.
+    pub nice_len: Option<u32>,
+    /// Maximum match finder search depth; 0 lets the encoder pick one from
+    /// `nice_len`. If `None`, uses the preset default.
+    pub depth_limit: Option<u32>,
+    /// Encoder mode and match finder. If `None`, uses the preset's:
+    /// `Lzma2Mode::Fast` for presets 0-3, `Lzma2Mode::Normal` above. Fast
+    /// mode trades ratio for speed, and stays fast at high presets, whose
+    /// dictionary size and `nice_len` it keeps.
+    pub mode: Option<Lzma2Mode>,
+    /// When `false` (the default), folders whose blocks all fail to shrink
+    /// under LZMA2 are stored with the Copy coder instead. Set to `true` to
+    /// always emit LZMA2.
+    pub disable_store_fallback: bool,
+    /// Filters applied, in order, to each folder's data before compression
+    /// (default: none), e.g. `Filter::BcjX86` for native executables.
+    pub filters: Vec<Filter>,
+    /// Compression method (default: `Codec::Lzma2`). LZMA ignores
+    /// `block_size`; see `Codec::Bzip2` for the options BZip2 uses.
+    pub codec: Codec,
+    /// Caller-supplied compression method used instead of `codec` (default:
+    /// `None`). Folders are compressed as a single block, like with LZMA.
+    pub compressor: Option<Arc<dyn BlockCompressor>>,
+    /// Starts an independent LZMA2 chunk, resetting the dictionary and
+    /// encoder state, once `chunk_size` uncompressed bytes of a block have
+    /// been encoded since the last reset (default: `None`, one reset per
+    /// block). Raised to at least the dictionary size; `0` is rejected by
+    /// `validate`.
+    ///
+    /// Resets land on LZMA2 packet boundaries, which the format fixes at
+    /// most 2 MiB uncompressed or 64 KiB compressed apart, so highly
+    /// compressible data sees few of them. Each reset costs some ratio, as
+    /// matches cannot reach back across it. Ignored by other codecs.
+    pub chunk_size: Option<usize>,
+    /// Computes the CRC32 of each block as it is compressed (default:
+    /// `true`), from which the CRCs of files not hashed while reading are
+    /// combined. `SevenZipWriter` sets it from
+    /// [`set_store_crc`](crate::SevenZipWriter::set_store_crc), so it only
+    /// matters when calling the threading functions directly.
+    pub block_crc: bool,
+}
+
+/// Values derived from an [`Lzma2Config`], as returned by
+/// [`Lzma2Config::resolved`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedConfig {
+    pub preset: u32,
+    /// Dictionary size in bytes.
+    pub dict_size: u32,
+    /// Block size for intra-file splitting, in bytes.
+    pub block_size: usize,
+    /// LZMA2 properties byte written to the header for `dict_size`.
+    pub properties_byte: u8,
 }
 
 impl Default for Lzma2Config {
@@ -21,30 +230,241 @@ impl Default for Lzma2Config {
             preset: 6,
             dict_size: None,
             block_size: None,
+            block_policy: None,
+            lc: None,
+            lp: None,
+            pb: None,
+            nice_len: None,
+            depth_limit: None,
+            mode: None,
+            disable_store_fallback: false,
+            filters: Vec::new(),
+            codec: Codec::Lzma2,
+            compressor: None,
+            chunk_size: None,
+            block_crc: true,
         }
     }
 }
 
 impl Lzma2Config {
+    /// The configuration of a named [`Profile`], with every other field at
+    /// its default.
+    pub fn from_profile(profile: Profile) -> Self {
+        let (preset, dict_size, nice_len, depth_limit) = match profile {
+            Profile::Fast => (1, 1 << 20, 32, 0),
+            Profile::Balanced => (6, 8 << 20, 64, 0),
+            Profile::Max => (9, 32 << 20, 128, 0),
+            Profile::Ultra => (9, 64 << 20, 273, 512),
+        };
+        Self {
+            preset,
+            dict_size: Some(dict_size),
+            nice_len: Some(nice_len),
+            depth_limit: Some(depth_limit),
+            ..Default::default()
+        }
+    }
+
     fn to_lzma2_options(&self) -> Lzma2Options {
         let mut opts = Lzma2Options::with_preset(self.preset);
         if let Some(ds) = self.dict_size {
             opts.lzma_options.dict_size = ds;
         }
+        if let Some(lc) = self.lc {
+            opts.lzma_options.lc = lc;
+        }
+        if let Some(lp) = self.lp {
+            opts.lzma_options.lp = lp;
+        }
+        if let Some(pb) = self.pb {
+            opts.lzma_options.pb = pb;
+        }
+        if let Some(nice_len) = self.nice_len {
+            opts.lzma_options.nice_len = nice_len;
+        }
+        if let Some(depth_limit) = self.depth_limit {
+            opts.lzma_options.depth_limit = depth_limit.try_into().unwrap_or(i32::MAX);
+        }
+        match self.mode {
+            Some(Lzma2Mode::Fast) => {
+                opts.lzma_options.mode = EncodeMode::Fast;
+                opts.lzma_options.mf = MfType::Hc4;
+            }
+            Some(Lzma2Mode::Normal) => {
+                opts.lzma_options.mode = EncodeMode::Normal;
+                opts.lzma_options.mf = MfType::Bt4;
+            }
+            None => {}
+        }
+        opts.chunk_size = self
+            .chunk_size
+            .and_then(|size| NonZeroU64::new(size as u64));
         opts
     }
 
+    /// Checks the literal context parameters, `nice_len`, `block_policy`
+    /// and `chunk_size`, returning `SevenZipError::InvalidState` for values
+    /// LZMA2 cannot encode or the encoder does not support.
+    ///
+    /// With `Codec::Lzma2`, the dictionary size must also be stored exactly
+    /// by the LZMA2 properties byte (2^n or 3 × 2^n bytes, 4 KiB to
+    /// 1.5 GiB); other sizes are `SevenZipError::UnsupportedDictSize`.
+    pub fn validate(&self) -> Result<()> {
+        let opts = self.to_lzma2_options().lzma_options;
+        if self.codec == Codec::Lzma2 {
+            let dict_size = opts.dict_size;
+            if dict_size > MAX_DICT_SIZE
+                || decode_dict_size(encode_properties_byte(dict_size)) != dict_size
+            {
+                return Err(SevenZipError::UnsupportedDictSize(dict_size));
+            }
+        }
+        if opts.pb > 4 {
+            return Err(SevenZipError::InvalidState(format!(
+                "pb must be between 0 and 4, got {}",
+                opts.pb
+            )));
+        }
+        let nice_lens = LzmaOptions::NICE_LEN_MIN..=LzmaOptions::NICE_LEN_MAX;
+        if !nice_lens.contains(&opts.nice_len) {
+            return Err(SevenZipError::InvalidState(format!(
+                "nice_len must be between {} and {}, got {}",
+                nice_lens.start(),
+                nice_lens.end(),
+                opts.nice_len
+            )));
+        }
+        if opts.lc + opts.lp > 4 {
+            return Err(SevenZipError::InvalidState(format!(
+                "lc + lp must not exceed 4, got lc={} lp={}",
+                opts.lc, opts.lp
+            )));
+        }
+        match self.block_policy {
+            Some(BlockPolicy::Fixed(0)) => {
+                return Err(SevenZipError::InvalidState(
+                    "block size must not be 0".to_string(),
+                ));
+            }
+            Some(BlockPolicy::Adaptive(policy))
+                if policy.min_block_size == 0 || policy.min_block_size > policy.max_block_size =>
+            {
+                return Err(SevenZipError::InvalidState(format!(
+                    "adaptive block sizes must satisfy 0 < min <= max, got min={} max={}",
+                    policy.min_block_size, policy.max_block_size
+                )));
+            }
+            Some(BlockPolicy::ContentDefined { avg_size, min, max })
+                if min == 0 || min > avg_size || avg_size > max =>
+            {
+                return Err(SevenZipError::InvalidState(format!(
+                    "content-defined block sizes must satisfy 0 < min <= avg_size <= max, \
+                     got min={min} avg_size={avg_size} max={max}"
+                )));
+            }
+            _ => {}
+        }
+        if self.chunk_size == Some(0) {
+            return Err(SevenZipError::InvalidState(
+                "chunk size must not be 0".to_string(),
+            ));
+        }
+        if let Some(compressor) = &self.compressor {
+            let id_len = compressor.coder_id().len();
+            if !(1..=15).contains(&id_len) {
+                return Err(SevenZipError::InvalidState(format!(
+                    "coder IDs must be 1 to 15 bytes long, got {id_len}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
     /// Returns the effective dictionary size for this config.
     pub fn effective_dict_size(&self) -> u32 {
         let opts = self.to_lzma2_options();
         opts.lzma_options.dict_size
     }
 
+    /// The dictionary size worth using for inputs of at most `input_size`
+    /// bytes: the smallest the LZMA2 properties byte stores that covers
+    /// them, but never below [`MIN_DICT_SIZE`] or above this config's own.
+    pub fn dict_size_for(&self, input_size: u64) -> u32 {
+        let dict_size = self.effective_dict_size();
+        if input_size >= dict_size as u64 {
+            return dict_size;
+        }
+        decode_dict_size(encode_properties_byte(input_size as u32))
+            .max(MIN_DICT_SIZE)
+            .min(dict_size)
+    }
+
+    /// Resolves the dictionary size, block size and properties byte this
+    /// config compresses with.
+    pub fn resolved(&self) -> ResolvedConfig {
+        let dict_size = self.effective_dict_size();
+        ResolvedConfig {
+            preset: self.preset,
+            dict_size,
+            block_size: self.effective_block_size(),
+            properties_byte: encode_properties_byte(dict_size),
+        }
+    }
+
     /// Returns the effective block size for intra-file splitting.
-    /// Defaults to `2 × dict_size`, minimum 1 MiB.
+    /// Defaults to `2 × dict_size`, minimum 1 MiB. With
+    /// `BlockPolicy::Adaptive` or `BlockPolicy::ContentDefined`, this is
+    /// the largest size it can choose.
     pub fn effective_block_size(&self) -> usize {
-        self.block_size
-            .unwrap_or_else(|| (2 * self.effective_dict_size() as usize).max(1 << 20))
+        match self.block_policy {
+            Some(BlockPolicy::Fixed(size)) => size,
+            Some(BlockPolicy::Adaptive(policy)) => policy.max_block_size,
+            Some(BlockPolicy::ContentDefined { max, .. }) => max,
+            None => self
+                .block_size
+                .unwrap_or_else(|| (2 * self.effective_dict_size() as usize).max(1 << 20)),
+        }
+    }
+
+    /// Returns the block size for an archive of `total_size` input bytes
+    /// compressed on `num_threads` threads.
+    pub fn block_size_for(&self, total_size: u64, num_threads: usize) -> usize {
+        match self.block_policy {
+            Some(BlockPolicy::Adaptive(policy)) => policy.block_size(total_size, num_threads),
+            _ => self.effective_block_size(),
+        }
+    }
+
+    /// Chunk size limits when blocks are cut with
+    /// `BlockPolicy::ContentDefined`.
+    pub fn chunk_limits(&self) -> Option<ChunkLimits> {
+        match self.block_policy {
+            Some(BlockPolicy::ContentDefined { avg_size, min, max }) => {
+                Some(ChunkLimits { avg_size, min, max })
+            }
+            _ => None,
+        }
+    }
+
+    /// The 5-byte LZMA coder properties stored in the 7z header: the
+    /// lc/lp/pb byte followed by the little-endian dictionary size.
+    pub fn lzma1_properties(&self) -> [u8; 5] {
+        let options = self.to_lzma2_options().lzma_options;
+        let mut properties = [0u8; 5];
+        properties[0] = options.get_props();
+        properties[1..].copy_from_slice(&options.dict_size.to_le_bytes());
+        properties
+    }
+
+    /// Approximate memory used by one compression thread, in bytes.
+    ///
+    /// Counts about `11.5 × dict_size` for the encoder's binary-tree match
+    /// finder, the figure xz documents for its presets, plus one block for
+    /// the compressed output buffer. Fixed-size encoder tables (well under
+    /// 1 MiB) are ignored, so this is an estimate rather than a bound.
+    pub fn estimated_memory_per_thread(&self) -> u64 {
+        self.effective_dict_size() as u64 * 23 / 2 + self.effective_block_size() as u64
     }
 }
 
@@ -77,7 +497,8 @@ pub fn encode_properties_byte(dict_size: u32) -> u8 {
     40
 }
 
-fn decode_dict_size(prop: u8) -> u32 {
+/// Decodes an LZMA2 properties byte back into the dictionary size it encodes.
+pub fn decode_dict_size(prop: u8) -> u32 {
     if prop > 40 {
         return u32::MAX;
     }
@@ -91,9 +512,46 @@ fn decode_dict_size(prop: u8) -> u32 {
     }
 }
 
+/// Describes the dictionary sizes closest to `dict_size` that the LZMA2
+/// properties byte stores exactly, for error messages.
+pub(crate) fn nearest_dict_sizes(dict_size: u32) -> String {
+    let valid = (0..=40u8)
+        .map(decode_dict_size)
+        .filter(|&size| size <= MAX_DICT_SIZE);
+    let below = valid.clone().rev().find(|&size| size <= dict_size);
+    let above = valid.clone().find(|&size| size >= dict_size);
+    match (below, above) {
+        (Some(below), Some(above)) => {
+            format!("nearest valid sizes are {below} and {above} bytes")
+        }
+        (Some(size), None) | (None, Some(size)) => format!("nearest valid size is {size} bytes"),
+        (None, None) => "no dictionary size is valid".to_string(),
+    }
+}
+
 /// LZMA2 end-of-stream marker byte.
 pub const LZMA2_END_MARKER: u8 = 0x00;
 
+/// Largest amount of data one LZMA2 uncompressed chunk holds.
+const UNCOMPRESSED_CHUNK_SIZE: usize = 1 << 16;
+
+/// Writes `data` as LZMA2 uncompressed chunks, the first of which resets
+/// the dictionary, without an end marker: the stream of a block kept raw
+/// because LZMA2 did not shrink it. Returns the number of bytes written.
+pub fn write_uncompressed_chunks(writer: &mut impl Write, data: &[u8]) -> std::io::Result<u64> {
+    let mut written = 0;
+    for (i, chunk) in data.chunks(UNCOMPRESSED_CHUNK_SIZE).enumerate() {
+        // 0x01 resets the dictionary, 0x02 keeps it
+        let control = if i == 0 { 0x01 } else { 0x02 };
+        let size = (chunk.len() - 1) as u16;
+        writer.write_all(&[control])?;
+        writer.write_all(&size.to_be_bytes())?;
+        writer.write_all(chunk)?;
+        written += 3 + chunk.len() as u64;
+    }
+    Ok(written)
+}
+
 /// Concatenates multiple independently-compressed LZMA2 streams into a single
 /// valid LZMA2 stream by stripping intermediate end-of-stream markers.
 ///
@@ -135,9 +593,22 @@ pub fn concatenate_lzma2_streams(streams: Vec<Vec<u8>>) -> Result<Vec<u8>> {
     Ok(result)
 }
 
-/// Compresses a data block using LZMA2.
+/// Compresses a data block using LZMA, or LZMA2 for any other codec.
+///
+/// LZMA2 output ends with an end-of-stream marker; LZMA output has none,
+/// as the 7z header records the unpacked size.
 pub fn compress_block(data: &[u8], config: &Lzma2Config) -> Result<Vec<u8>> {
     let options = config.to_lzma2_options();
+    if config.codec == Codec::Lzma1 {
+        let mut writer = LzmaWriter::new_no_header(Vec::new(), &options.lzma_options, false)
+            .map_err(|e| SevenZipError::Compression(format!("LZMA init failed: {e}")))?;
+        writer
+            .write_all(data)
+            .map_err(|e| SevenZipError::Compression(format!("LZMA write failed: {e}")))?;
+        return writer
+            .finish()
+            .map_err(|e| SevenZipError::Compression(format!("LZMA finish failed: {e}")));
+    }
     let output = Vec::new();
     let mut writer = Lzma2Writer::new(output, options);
     writer
@@ -200,6 +671,190 @@ mod tests {
         assert!(!compressed.is_empty()); // LZMA2 stream end marker
     }
 
+    #[test]
+    fn test_resolved_matches_accessors() {
+        for preset in [0, 3, 6, 9] {
+            let config = Lzma2Config {
+                preset,
+                ..Default::default()
+            };
+            let resolved = config.resolved();
+            assert_eq!(resolved.preset, preset);
+            assert_eq!(resolved.dict_size, config.effective_dict_size());
+            assert_eq!(resolved.block_size, config.effective_block_size());
+            assert_eq!(
+                resolved.properties_byte,
+                encode_properties_byte(config.effective_dict_size())
+            );
+        }
+
+        let config = Lzma2Config {
+            dict_size: Some(1 << 20),
+            block_size: Some(3 << 20),
+            ..Default::default()
+        };
+        let resolved = config.resolved();
+        assert_eq!(resolved.dict_size, 1 << 20);
+        assert_eq!(resolved.block_size, 3 << 20);
+        assert_eq!(resolved.properties_byte, 16);
+    }
+
+    #[test]
+    fn test_validate_literal_params() {
+        let config = |lc, lp, pb| Lzma2Config {
+            lc,
+            lp,
+            pb,
+            ..Default::default()
+        };
+        assert!(config(None, None, None).validate().is_ok());
+        assert!(config(Some(4), Some(0), Some(0)).validate().is_ok());
+        assert!(config(Some(0), Some(4), Some(4)).validate().is_ok());
+        assert!(config(Some(2), Some(2), None).validate().is_ok());
+
+        for invalid in [
+            config(Some(3), Some(2), None),
+            config(Some(5), None, None),
+            config(None, Some(2), None),
+            config(None, None, Some(5)),
+        ] {
+            assert!(matches!(
+                invalid.validate(),
+                Err(SevenZipError::InvalidState(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_validate_dict_size() {
+        let config = |dict_size| Lzma2Config {
+            dict_size: Some(dict_size),
+            ..Default::default()
+        };
+        assert!(config(1 << 20).validate().is_ok());
+        assert!(config(3 << 20).validate().is_ok());
+        assert!(config(MIN_DICT_SIZE).validate().is_ok());
+        assert!(config(MAX_DICT_SIZE).validate().is_ok());
+
+        // Above the LZMA2 maximum, even though the byte could encode it
+        let err = config(2 << 30).validate().unwrap_err();
+        assert!(matches!(err, SevenZipError::UnsupportedDictSize(size) if size == 2 << 30));
+        assert!(err
+            .to_string()
+            .contains("nearest valid size is 1610612736 bytes"));
+
+        // Between the 4 MiB and 6 MiB steps
+        let err = config(5 << 20).validate().unwrap_err();
+        assert!(matches!(err, SevenZipError::UnsupportedDictSize(size) if size == 5 << 20));
+        assert!(err
+            .to_string()
+            .contains("nearest valid sizes are 4194304 and 6291456 bytes"));
+
+        assert!(config(1000).validate().is_err());
+
+        // LZMA stores the exact size, so any size is fine there
+        let lzma1 = Lzma2Config {
+            codec: Codec::Lzma1,
+            ..config(5 << 20)
+        };
+        assert!(lzma1.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_nice_len() {
+        let config = |nice_len| Lzma2Config {
+            nice_len: Some(nice_len),
+            ..Default::default()
+        };
+        assert!(config(8).validate().is_ok());
+        assert!(config(273).validate().is_ok());
+        assert!(config(7).validate().is_err());
+        assert!(config(274).validate().is_err());
+    }
+
+    #[test]
+    fn test_compress_block_with_literal_params() {
+        let data = b"tuned literal context bits ".repeat(100);
+        let config = Lzma2Config {
+            lc: Some(0),
+            lp: Some(2),
+            pb: Some(0),
+            ..Default::default()
+        };
+        let compressed = compress_block(&data, &config).unwrap();
+        let mut decompressed = Vec::new();
+        let mut reader =
+            lzma_rust2::Lzma2Reader::new(compressed.as_slice(), config.effective_dict_size(), None);
+        std::io::Read::read_to_end(&mut reader, &mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+        // lc/lp/pb are carried in the LZMA properties byte
+        assert_eq!(config.lzma1_properties()[0], 2 * 9);
+    }
+
+    #[test]
+    fn test_compress_block_with_chunk_size() {
+        // Sixteen symbols of noise: resets land on packet boundaries, and
+        // packets only fill up before 2 MiB on data that compresses poorly
+        let mut seed = 1u32;
+        let data: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                b'a' + (seed >> 28) as u8
+            })
+            .collect();
+        let config = |chunk_size| Lzma2Config {
+            preset: 1,
+            dict_size: Some(1 << 16),
+            chunk_size,
+            ..Default::default()
+        };
+        let whole = compress_block(&data, &config(None)).unwrap();
+        for chunk_size in [1 << 16, 100_000] {
+            let config = config(Some(chunk_size));
+            assert!(config.validate().is_ok());
+            let compressed = compress_block(&data, &config).unwrap();
+            // Each reset starts the dictionary from scratch
+            assert!(compressed.len() > whole.len());
+            let mut decompressed = Vec::new();
+            let mut reader = lzma_rust2::Lzma2Reader::new(compressed.as_slice(), 1 << 16, None);
+            std::io::Read::read_to_end(&mut reader, &mut decompressed).unwrap();
+            assert_eq!(decompressed, data);
+        }
+        assert!(config(Some(0)).validate().is_err());
+    }
+
+    #[test]
+    fn test_lzma1_properties() {
+        // lc=3, lp=0, pb=2 and an 8 MiB dictionary, as in `.lzma` files
+        let config = Lzma2Config::default();
+        assert_eq!(config.lzma1_properties(), [0x5D, 0x00, 0x00, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn test_compress_block_lzma1_roundtrip() {
+        let data = b"LZMA1 round trip, LZMA1 round trip, LZMA1 round trip".repeat(20);
+        let config = Lzma2Config {
+            codec: Codec::Lzma1,
+            ..Default::default()
+        };
+        let compressed = compress_block(&data, &config).unwrap();
+        assert!(compressed.len() < data.len());
+
+        let properties = config.lzma1_properties();
+        let dict_size = u32::from_le_bytes(properties[1..].try_into().unwrap());
+        let mut reader = lzma_rust2::LzmaReader::new_with_props(
+            compressed.as_slice(),
+            data.len() as u64,
+            properties[0],
+            dict_size,
+            None,
+        )
+        .unwrap();
+        let mut decompressed = Vec::new();
+        std::io::Read::read_to_end(&mut reader, &mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
     #[test]
     fn test_concatenate_single_stream() {
         let config = Lzma2Config::default();
@@ -239,6 +894,34 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_uncompressed_chunks_between_compressed_blocks() {
+        let config = Lzma2Config::default();
+        let text = b"compressible text ".repeat(1000);
+        let mut state = 0x2545_F491_u32;
+        let noise: Vec<u8> = (0..150_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+
+        let mut stream = compress_block(&text, &config).unwrap();
+        stream.pop();
+        let written = write_uncompressed_chunks(&mut stream, &noise).unwrap();
+        // Three chunks of at most 64 KiB, with a 3-byte header each
+        assert_eq!(written, noise.len() as u64 + 9);
+        stream.extend(compress_block(&text, &config).unwrap());
+
+        let mut decompressed = Vec::new();
+        let mut reader =
+            lzma_rust2::Lzma2Reader::new(stream.as_slice(), config.effective_dict_size(), None);
+        std::io::Read::read_to_end(&mut reader, &mut decompressed).unwrap();
+        assert_eq!(decompressed, [&text[..], &noise, &text].concat());
+    }
+
     #[test]
     fn test_effective_block_size_default() {
         let config = Lzma2Config::default();
@@ -246,12 +929,211 @@ mod tests {
         assert_eq!(config.effective_block_size(), 2 * dict);
     }
 
+    #[test]
+    fn test_memory_estimate_scales_with_dict_size() {
+        let estimate = |dict_size: u32| {
+            Lzma2Config {
+                dict_size: Some(dict_size),
+                block_size: Some(1 << 20),
+                ..Default::default()
+            }
+            .estimated_memory_per_thread()
+        };
+        let small = estimate(8 << 20);
+        let large = estimate(64 << 20);
+        // 11.5 bytes per dictionary byte, plus the fixed block buffer
+        assert_eq!(small, (8 << 20) * 23 / 2 + (1 << 20));
+        assert_eq!(large - small, (56 << 20) * 23 / 2);
+        let ratio = large as f64 / small as f64;
+        assert!((7.0..8.0).contains(&ratio), "ratio {ratio}");
+    }
+
+    #[test]
+    fn test_adaptive_block_size() {
+        let policy = AdaptiveBlockPolicy::default();
+        let block_count = |total: u64, threads: usize| {
+            let size = policy.block_size(total, threads);
+            assert!((policy.min_block_size..=policy.max_block_size).contains(&size));
+            total.div_ceil(size as u64)
+        };
+
+        // Tiny inputs stay in one block, without a short second one
+        assert_eq!(block_count(100 << 10, 8), 1);
+        assert_eq!(block_count((1 << 20) + (100 << 10), 8), 1);
+        assert_eq!(block_count(0, 8), 0);
+
+        // Medium inputs get 4 blocks per thread, of near-equal size
+        assert_eq!(block_count(500 << 20, 8), 32);
+        assert_eq!(block_count(500 << 20, 1), 8);
+        let size = policy.block_size((500 << 20) + 7, 8) as u64;
+        assert!((500 << 20) + 7 - 31 * size <= size);
+        assert!(31 * size < (500 << 20) + 7);
+
+        // Huge inputs are capped at the maximum block size
+        assert_eq!(policy.block_size(100 << 30, 8), policy.max_block_size);
+        assert_eq!(block_count(100 << 30, 8), 1600);
+    }
+
+    #[test]
+    fn test_mode_overrides_preset() {
+        let options = |preset, mode| {
+            Lzma2Config {
+                preset,
+                mode,
+                ..Default::default()
+            }
+            .to_lzma2_options()
+            .lzma_options
+        };
+        let fast = options(9, Some(Lzma2Mode::Fast));
+        assert_eq!((fast.mode, fast.mf), (EncodeMode::Fast, MfType::Hc4));
+        // The preset's other settings are kept
+        assert_eq!(fast.dict_size, options(9, None).dict_size);
+        let normal = options(1, Some(Lzma2Mode::Normal));
+        assert_eq!((normal.mode, normal.mf), (EncodeMode::Normal, MfType::Bt4));
+        assert_eq!(options(1, None).mode, EncodeMode::Fast);
+        assert_eq!(options(9, None).mode, EncodeMode::Normal);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let profiles = [
+            Profile::Fast,
+            Profile::Balanced,
+            Profile::Max,
+            Profile::Ultra,
+        ];
+        for profile in profiles {
+            let config = Lzma2Config::from_profile(profile);
+            config.validate().unwrap();
+            let name = format!("{profile:?}").to_uppercase();
+            assert_eq!(name.parse::<Profile>().unwrap(), profile);
+        }
+        assert!("extreme".parse::<Profile>().is_err());
+
+        // Balanced spells out the default configuration
+        let balanced = Lzma2Config::from_profile(Profile::Balanced).to_lzma2_options();
+        let default = Lzma2Config::default().to_lzma2_options();
+        assert_eq!(
+            balanced.lzma_options.dict_size,
+            default.lzma_options.dict_size
+        );
+        assert_eq!(
+            balanced.lzma_options.nice_len,
+            default.lzma_options.nice_len
+        );
+
+        let text: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| format!("line {} of {}\n", i % 997, i % 13).into_bytes())
+            .collect();
+        let sizes: Vec<usize> = profiles
+            .iter()
+            .map(|&p| {
+                compress_block(&text, &Lzma2Config::from_profile(p))
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert!(
+            sizes[3] <= sizes[0],
+            "Ultra {} > Fast {}",
+            sizes[3],
+            sizes[0]
+        );
+    }
+
+    #[test]
+    fn test_dict_size_for() {
+        let config = Lzma2Config::default();
+        assert_eq!(config.effective_dict_size(), 8 << 20);
+        assert_eq!(config.dict_size_for(0), MIN_DICT_SIZE);
+        assert_eq!(config.dict_size_for(100), MIN_DICT_SIZE);
+        assert_eq!(config.dict_size_for(10 << 10), 12 << 10);
+        assert_eq!(config.dict_size_for(1 << 20), 1 << 20);
+        assert_eq!(config.dict_size_for((1 << 20) + 1), 3 << 19);
+        assert_eq!(config.dict_size_for(100 << 20), 8 << 20);
+        for size in [1u64, 4097, 50_000, 3 << 20, (8 << 20) - 1] {
+            let dict_size = config.dict_size_for(size);
+            assert!(dict_size as u64 >= size);
+            assert_eq!(
+                decode_dict_size(encode_properties_byte(dict_size)),
+                dict_size
+            );
+        }
+
+        // Never larger than the configured size, even if that is not a step
+        let config = Lzma2Config {
+            dict_size: Some(5000),
+            ..Default::default()
+        };
+        assert_eq!(config.dict_size_for(4500), 5000);
+    }
+
+    #[test]
+    fn test_block_policy() {
+        let config = Lzma2Config {
+            block_size: Some(4096),
+            block_policy: Some(BlockPolicy::Fixed(8192)),
+            ..Default::default()
+        };
+        assert_eq!(config.effective_block_size(), 8192);
+        assert_eq!(config.block_size_for(500 << 20, 8), 8192);
+
+        let config = Lzma2Config {
+            block_policy: Some(BlockPolicy::Adaptive(AdaptiveBlockPolicy::default())),
+            ..Default::default()
+        };
+        assert_eq!(config.effective_block_size(), 64 << 20);
+        assert_eq!(config.block_size_for(64 << 20, 4), 4 << 20);
+        assert!(config.validate().is_ok());
+
+        let config = Lzma2Config {
+            block_policy: Some(BlockPolicy::ContentDefined {
+                avg_size: 1 << 20,
+                min: 256 << 10,
+                max: 4 << 20,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(config.effective_block_size(), 4 << 20);
+        assert_eq!(config.chunk_limits().unwrap().avg_size, 1 << 20);
+        assert!(config.validate().is_ok());
+
+        for policy in [
+            BlockPolicy::Fixed(0),
+            BlockPolicy::Adaptive(AdaptiveBlockPolicy {
+                min_block_size: 2 << 20,
+                max_block_size: 1 << 20,
+            }),
+            BlockPolicy::ContentDefined {
+                avg_size: 1 << 20,
+                min: 0,
+                max: 4 << 20,
+            },
+            BlockPolicy::ContentDefined {
+                avg_size: 8 << 20,
+                min: 1 << 20,
+                max: 4 << 20,
+            },
+        ] {
+            let config = Lzma2Config {
+                block_policy: Some(policy),
+                ..Default::default()
+            };
+            assert!(matches!(
+                config.validate(),
+                Err(SevenZipError::InvalidState(_))
+            ));
+        }
+    }
+
     #[test]
     fn test_effective_block_size_custom() {
         let config = Lzma2Config {
             preset: 6,
             dict_size: None,
             block_size: Some(4096),
+            ..Default::default()
         };
         assert_eq!(config.effective_block_size(), 4096);
     }
@@ -263,6 +1145,7 @@ mod tests {
             preset: 0,
             dict_size: Some(4096),
             block_size: None,
+            ..Default::default()
         };
         assert!(config.effective_block_size() >= 1 << 20);
     }
@@ -1,2 +1,7 @@
 pub mod block;
+#[cfg(feature = "bzip2")]
+pub mod bzip2;
+pub mod cdc;
+pub mod compressor;
+pub mod filter;
 pub mod lzma2;
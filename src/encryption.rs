@@ -0,0 +1,288 @@
+use crate::error::{Result, SevenZipError};
+use aes::Aes256;
+use cbc::cipher::{BlockEncryptMut, KeyIvInit};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+
+/// 7z AES-256 + SHA-256 coder ID.
+pub const AES_CODER_ID: [u8; 4] = [0x06, 0xF1, 0x07, 0x01];
+
+/// Largest `iterations_log2` accepted by 7-Zip's decoder.
+pub const MAX_ITERATIONS_LOG2: u8 = 24;
+
+const SALT_SIZE: usize = 16;
+const IV_SIZE: usize = 16;
+const BLOCK_SIZE: usize = 16;
+
+/// Password-based AES-256 encryption settings.
+#[derive(Debug, Clone)]
+pub struct AesEncryptConfig {
+    pub password: String,
+    /// Base-2 logarithm of the number of SHA-256 rounds used to derive the
+    /// key from the password (7-Zip uses 19). At most [`MAX_ITERATIONS_LOG2`].
+    pub iterations_log2: u8,
+}
+
+impl AesEncryptConfig {
+    /// Creates a config with 7-Zip's default key derivation cost.
+    pub fn new(password: impl Into<String>) -> Self {
+        Self {
+            password: password.into(),
+            iterations_log2: 19,
+        }
+    }
+}
+
+/// Encrypts folders with a key derived once per archive.
+///
+/// The salt (and therefore the key) is random per archive; every folder gets
+/// its own random IV. See [`AesEncryptor::new_deterministic`] for the
+/// reproducible variant.
+pub struct AesEncryptor {
+    key: [u8; 32],
+    /// Empty in deterministic mode.
+    salt: Vec<u8>,
+    iterations_log2: u8,
+    /// IVs are derived from the folder content rather than random.
+    deterministic: bool,
+}
+
+impl AesEncryptor {
+    /// Generates a random salt and derives the key from the password.
+    pub fn new(config: &AesEncryptConfig) -> Result<Self> {
+        let mut salt = vec![0u8; SALT_SIZE];
+        fill_random(&mut salt)?;
+        Self::with_salt(config, salt, false)
+    }
+
+    /// Creates an encryptor producing identical output for identical input,
+    /// for reproducible archives.
+    ///
+    /// No salt is stored (as in 7-Zip's own archives), so the key depends on
+    /// the password alone. Each folder's IV is derived from the key and a
+    /// digest of the folder's content, as in SIV mode: a key and IV pair
+    /// only repeats for identical folders, and IVs are unpredictable
+    /// without the password.
+    pub fn new_deterministic(config: &AesEncryptConfig) -> Result<Self> {
+        Self::with_salt(config, Vec::new(), true)
+    }
+
+    fn with_salt(config: &AesEncryptConfig, salt: Vec<u8>, deterministic: bool) -> Result<Self> {
+        if config.iterations_log2 > MAX_ITERATIONS_LOG2 {
+            return Err(SevenZipError::InvalidState(format!(
+                "iterations_log2 must be at most {MAX_ITERATIONS_LOG2}, got {}",
+                config.iterations_log2
+            )));
+        }
+
+        let key = derive_key(&config.password, &salt, config.iterations_log2);
+
+        Ok(Self {
+            key,
+            salt,
+            iterations_log2: config.iterations_log2,
+            deterministic,
+        })
+    }
+
+    /// Starts encrypting one folder's packed stream into `inner`.
+    ///
+    /// Returns the encrypting writer and the AES coder properties to store in
+    /// the folder's header record. `folder_digest` (a digest of the data the
+    /// folder holds) is only called by deterministic encryptors.
+    pub fn encrypt_folder<W: Write>(
+        &self,
+        inner: W,
+        folder_digest: impl FnOnce() -> [u8; 32],
+    ) -> Result<(AesCbcWriter<W>, Vec<u8>)> {
+        let mut iv = [0u8; IV_SIZE];
+        if self.deterministic {
+            let mut sha = Sha256::new();
+            sha.update(self.key);
+            sha.update(folder_digest());
+            iv.copy_from_slice(&sha.finalize()[..IV_SIZE]);
+        } else {
+            fill_random(&mut iv)?;
+        }
+
+        // Properties: NumCyclesPower with salt/IV presence bits, then the
+        // salt and IV sizes minus one as nibbles, then salt and IV bytes.
+        let salt_bit = if self.salt.is_empty() { 0 } else { 0x80 };
+        let mut properties = Vec::with_capacity(2 + self.salt.len() + IV_SIZE);
+        properties.push(self.iterations_log2 | salt_bit | 0x40);
+        properties.push(((self.salt.len().saturating_sub(1) << 4) | (IV_SIZE - 1)) as u8);
+        properties.extend_from_slice(&self.salt);
+        properties.extend_from_slice(&iv);
+
+        let writer = AesCbcWriter {
+            inner,
+            cipher: cbc::Encryptor::new(&self.key.into(), &iv.into()),
+            pending: [0; BLOCK_SIZE],
+            pending_len: 0,
+        };
+        Ok((writer, properties))
+    }
+}
+
+/// Derives the AES key: SHA-256 over `2^iterations_log2` rounds of
+/// salt, UTF-16LE password and the little-endian round counter.
+fn derive_key(password: &str, salt: &[u8], iterations_log2: u8) -> [u8; 32] {
+    let password: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let mut sha = Sha256::new();
+    for round in 0..1u64 << iterations_log2 {
+        sha.update(salt);
+        sha.update(&password);
+        sha.update(round.to_le_bytes());
+    }
+    sha.finalize().into()
+}
+
+fn fill_random(buf: &mut [u8]) -> Result<()> {
+    getrandom::getrandom(buf)
+        .map_err(|e| SevenZipError::InvalidState(format!("random number generator failed: {e}")))
+}
+
+/// AES-256-CBC encrypting writer. Input is buffered to whole 16-byte blocks;
+/// [`finish`](Self::finish) zero-pads the final block.
+pub struct AesCbcWriter<W: Write> {
+    inner: W,
+    cipher: cbc::Encryptor<Aes256>,
+    /// The start of the next block; only `pending_len` bytes are filled.
+    pending: [u8; BLOCK_SIZE],
+    pending_len: usize,
+}
+
+impl<W: Write> AesCbcWriter<W> {
+    /// Pads and writes the final block, returning the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.pending_len > 0 {
+            self.pending[self.pending_len..].fill(0);
+            self.encrypt_pending()?;
+        }
+        Ok(self.inner)
+    }
+
+    fn encrypt_pending(&mut self) -> io::Result<()> {
+        self.cipher.encrypt_block_mut((&mut self.pending).into());
+        self.inner.write_all(&self.pending)?;
+        self.pending_len = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for AesCbcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let take = (BLOCK_SIZE - self.pending_len).min(rest.len());
+            self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&rest[..take]);
+            self.pending_len += take;
+            rest = &rest[take..];
+            if self.pending_len == BLOCK_SIZE {
+                self.encrypt_pending()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Size of an encrypted stream: the input rounded up to whole AES blocks.
+pub fn encrypted_size(size: u64) -> u64 {
+    size.next_multiple_of(BLOCK_SIZE as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cbc::cipher::BlockDecryptMut;
+
+    #[test]
+    fn test_derive_key_depends_on_inputs() {
+        let key = derive_key("secret", &[1; 16], 4);
+        assert_eq!(key, derive_key("secret", &[1; 16], 4));
+        assert_ne!(key, derive_key("secret", &[2; 16], 4));
+        assert_ne!(key, derive_key("Secret", &[1; 16], 4));
+        assert_ne!(key, derive_key("secret", &[1; 16], 5));
+    }
+
+    #[test]
+    fn test_rejects_excessive_iterations() {
+        let config = AesEncryptConfig {
+            password: "pw".to_string(),
+            iterations_log2: MAX_ITERATIONS_LOG2 + 1,
+        };
+        assert!(matches!(
+            AesEncryptor::new(&config),
+            Err(SevenZipError::InvalidState(_))
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_folder_roundtrip() {
+        let config = AesEncryptConfig {
+            password: "pässword".to_string(),
+            iterations_log2: 6,
+        };
+        let encryptor = AesEncryptor::new(&config).unwrap();
+        let data: Vec<u8> = (0..100u8).collect();
+
+        let (mut writer, properties) = encryptor.encrypt_folder(Vec::new(), || [0; 32]).unwrap();
+        writer.write_all(&data[..7]).unwrap();
+        writer.write_all(&data[7..]).unwrap();
+        let mut encrypted = writer.finish().unwrap();
+        assert_eq!(encrypted.len() as u64, encrypted_size(data.len() as u64));
+
+        assert_eq!(properties.len(), 2 + SALT_SIZE + IV_SIZE);
+        assert_eq!(properties[0], 6 | 0xC0);
+        let salt = &properties[2..2 + SALT_SIZE];
+        let iv: [u8; IV_SIZE] = properties[2 + SALT_SIZE..].try_into().unwrap();
+        let key = derive_key(&config.password, salt, 6);
+
+        let mut decryptor = cbc::Decryptor::<Aes256>::new(&key.into(), &iv.into());
+        for block in encrypted.chunks_exact_mut(BLOCK_SIZE) {
+            let block: &mut [u8; BLOCK_SIZE] = block.try_into().unwrap();
+            decryptor.decrypt_block_mut(block.into());
+        }
+        assert_eq!(&encrypted[..data.len()], data.as_slice());
+        assert!(encrypted[data.len()..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_folders_get_distinct_ivs() {
+        let encryptor = AesEncryptor::new(&AesEncryptConfig {
+            password: "pw".to_string(),
+            iterations_log2: 0,
+        })
+        .unwrap();
+        let (_, a) = encryptor.encrypt_folder(Vec::new(), || [0; 32]).unwrap();
+        let (_, b) = encryptor.encrypt_folder(Vec::new(), || [0; 32]).unwrap();
+        assert_eq!(a[2..2 + SALT_SIZE], b[2..2 + SALT_SIZE]);
+        assert_ne!(a[2 + SALT_SIZE..], b[2 + SALT_SIZE..]);
+    }
+
+    #[test]
+    fn test_deterministic_encryptor_is_repeatable() {
+        let config = AesEncryptConfig {
+            password: "pw".to_string(),
+            iterations_log2: 0,
+        };
+        let first = AesEncryptor::new_deterministic(&config).unwrap();
+        let second = AesEncryptor::new_deterministic(&config).unwrap();
+
+        let (_, a0) = first.encrypt_folder(Vec::new(), || [1; 32]).unwrap();
+        let (_, a1) = first.encrypt_folder(Vec::new(), || [2; 32]).unwrap();
+        let (_, b0) = second.encrypt_folder(Vec::new(), || [1; 32]).unwrap();
+        // No salt: only the presence bit and size nibble of the IV are set
+        assert_eq!(a0[..2], [0x40, (IV_SIZE - 1) as u8]);
+        assert_eq!(a0.len(), 2 + IV_SIZE);
+        // The IV follows the folder content, not the folder's position
+        assert_eq!(a0, b0);
+        assert_ne!(a0, a1);
+        let (_, a2) = first.encrypt_folder(Vec::new(), || [1; 32]).unwrap();
+        assert_eq!(a0, a2);
+    }
+}
@@ -8,6 +8,11 @@ pub enum SevenZipError {
     #[error("file not found: {0}")]
     FileNotFound(String),
 
+    /// A file queued with `add_file` or found by `add_path_recursive` no
+    /// longer existed when `finish()` came to read it.
+    #[error("file vanished before it was read: {0}")]
+    FileVanished(String),
+
     #[error("compression error: {0}")]
     Compression(String),
 
@@ -22,6 +27,38 @@ pub enum SevenZipError {
 
     #[error("threading error: {0}")]
     Threading(String),
+
+    #[error("recovery error: {0}")]
+    Recovery(String),
+
+    #[error(
+        "unsupported dictionary size {0} bytes: {nearest}",
+        nearest = crate::compression::lzma2::nearest_dict_sizes(*.0)
+    )]
+    UnsupportedDictSize(u32),
+
+    #[error("duplicate archive name: {0}")]
+    DuplicateEntry(String),
+
+    #[error("no path components left in '{0}' after stripping leading ones")]
+    StrippedAway(String),
+
+    #[error("operation cancelled")]
+    Cancelled,
+
+    #[error("CRC mismatch for '{name}': expected {expected:#010x}, got {actual:#010x}")]
+    CrcMismatch {
+        name: String,
+        expected: u32,
+        actual: u32,
+    },
+
+    #[error("verification failed for folder {folder}: expected CRC {expected:#010x}, got {actual:#010x}")]
+    VerificationFailed {
+        folder: usize,
+        expected: u32,
+        actual: u32,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, SevenZipError>;
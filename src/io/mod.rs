@@ -1,2 +1,5 @@
+pub mod reader;
 pub mod seek;
+pub mod spool;
+pub mod volume;
 pub mod writer;
@@ -0,0 +1,226 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::Read;
+
+/// Reads a 7z variable-length encoded integer (NUMBER).
+///
+/// Mirror of [`write_number`](crate::io::writer::write_number): the count of
+/// leading 1-bits in the first byte gives the number of little-endian bytes
+/// that follow, and the remaining bits of the first byte are the most
+/// significant bits of the value.
+pub fn read_number<R: Read>(r: &mut R) -> std::io::Result<u64> {
+    let first_byte = r.read_u8()?;
+    let byte_count = first_byte.leading_ones();
+
+    if byte_count >= 8 {
+        return r.read_u64::<LittleEndian>();
+    }
+
+    let mut value = 0u64;
+    for i in 0..byte_count {
+        value |= (r.read_u8()? as u64) << (i * 8);
+    }
+
+    // Bits of the first byte below the length prefix (and its 0 terminator)
+    let high_bits = first_byte & (0x7F >> byte_count);
+    value |= (high_bits as u64) << (byte_count * 8);
+
+    Ok(value)
+}
+
+pub fn read_u32_le<R: Read>(r: &mut R) -> std::io::Result<u32> {
+    r.read_u32::<LittleEndian>()
+}
+
+pub fn read_u64_le<R: Read>(r: &mut R) -> std::io::Result<u64> {
+    r.read_u64::<LittleEndian>()
+}
+
+/// Reads a bit vector of `count` entries, packed into bytes MSB-first.
+pub fn read_bool_vector<R: Read>(r: &mut R, count: usize) -> std::io::Result<Vec<bool>> {
+    let mut bools = Vec::with_capacity(count);
+    let mut current_byte = 0u8;
+
+    for i in 0..count {
+        if i % 8 == 0 {
+            current_byte = r.read_u8()?;
+        }
+        bools.push(current_byte & (1 << (7 - i % 8)) != 0);
+    }
+
+    Ok(bools)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::writer::{write_bool_vector, write_number};
+
+    fn roundtrip_number(value: u64) -> u64 {
+        let mut buf = Vec::new();
+        write_number(&mut buf, value).unwrap();
+        let mut slice = buf.as_slice();
+        let decoded = read_number(&mut slice).unwrap();
+        assert!(slice.is_empty(), "trailing bytes for {value}");
+        decoded
+    }
+
+    #[test]
+    fn test_read_number_roundtrip() {
+        for value in [
+            0,
+            1,
+            0x7F,
+            0x80,
+            0x3FFF,
+            0x4000,
+            0x1F_FFFF,
+            1 << 40,
+            u64::MAX,
+        ] {
+            assert_eq!(roundtrip_number(value), value);
+        }
+    }
+
+    #[test]
+    fn test_read_number_length_boundaries() {
+        for value in [0, 127, 128, 16383, 16384, u32::MAX as u64, u64::MAX] {
+            assert_eq!(roundtrip_number(value), value);
+        }
+        // Up to 2^56, each extra byte holds 7 more bits (beyond, all values
+        // take 9 bytes): check both sides of every length change
+        for bits in (7..=56).step_by(7) {
+            let last = (1u64 << bits) - 1;
+            for value in [last, last + 1] {
+                assert_eq!(roundtrip_number(value), value);
+            }
+            let mut short = Vec::new();
+            write_number(&mut short, last).unwrap();
+            let mut long = Vec::new();
+            write_number(&mut long, last + 1).unwrap();
+            assert_eq!(long.len(), short.len() + 1, "at 2^{bits}");
+        }
+
+        let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+        for _ in 0..10_000 {
+            // Random magnitudes, so short encodings are exercised too
+            let value = rng.next() >> (rng.next() % 64);
+            assert_eq!(roundtrip_number(value), value);
+        }
+    }
+
+    /// Length of a NUMBER by the format: `n` bytes hold `7 * n` bits up to
+    /// 8 bytes (2^56), and anything larger takes the 9-byte form.
+    fn expected_number_len(value: u64) -> usize {
+        (1..=8).find(|&len| value >> (7 * len) == 0).unwrap_or(9)
+    }
+
+    #[test]
+    fn test_number_every_power_of_two() {
+        let mut values = vec![0, u64::MAX];
+        for bit in 0..64 {
+            let power = 1u64 << bit;
+            values.extend([power - 1, power, power + 1]);
+        }
+        for value in values {
+            let mut buf = Vec::new();
+            write_number(&mut buf, value).unwrap();
+            assert_eq!(
+                buf.len(),
+                expected_number_len(value),
+                "length of {value:#x}"
+            );
+            // The length prefix is one 1-bit per byte following the first
+            assert_eq!(
+                (buf[0].leading_ones() as usize).min(8),
+                buf.len() - 1,
+                "prefix of {value:#x}"
+            );
+            assert_eq!(roundtrip_number(value), value, "value {value:#x}");
+        }
+        assert_eq!(expected_number_len((1 << 56) - 1), 8);
+        assert_eq!(expected_number_len(1 << 56), 9);
+    }
+
+    #[test]
+    fn test_read_number_truncated() {
+        let mut buf = Vec::new();
+        write_number(&mut buf, u32::MAX as u64).unwrap();
+        buf.pop();
+        assert!(read_number(&mut buf.as_slice()).is_err());
+        assert!(read_number(&mut [].as_slice()).is_err());
+    }
+
+    /// Minimal xorshift generator, so the property tests below are
+    /// reproducible without a dependency.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// A bool vector of length `len`, with a random density of set bits.
+        fn bools(&mut self, len: usize) -> Vec<bool> {
+            let density = self.next() % 101;
+            (0..len).map(|_| self.next() % 100 < density).collect()
+        }
+    }
+
+    fn roundtrip_bools(bools: &[bool]) {
+        let mut buf = Vec::new();
+        write_bool_vector(&mut buf, bools).unwrap();
+        assert_eq!(buf.len(), bools.len().div_ceil(8), "length {}", bools.len());
+        // Padding bits of the last byte are zero
+        if !bools.len().is_multiple_of(8) {
+            let padding = 8 - bools.len() % 8;
+            assert_eq!(buf.last().unwrap() & ((1 << padding) - 1), 0);
+        }
+
+        let mut slice = buf.as_slice();
+        assert_eq!(read_bool_vector(&mut slice, bools.len()).unwrap(), bools);
+        assert!(
+            slice.is_empty(),
+            "trailing bytes for length {}",
+            bools.len()
+        );
+    }
+
+    #[test]
+    fn test_bool_vector_roundtrip_arbitrary() {
+        let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..500 {
+            let len = (rng.next() % 300) as usize;
+            roundtrip_bools(&rng.bools(len));
+        }
+        // Exact multiples of 8 and large vectors, around byte boundaries
+        for len in [8, 16, 64, 8 * 1250, 10_000, 10_001, 10_007, 65_536, 65_543] {
+            roundtrip_bools(&rng.bools(len));
+            roundtrip_bools(&vec![true; len]);
+            roundtrip_bools(&vec![false; len]);
+        }
+    }
+
+    #[test]
+    fn test_read_bool_vector_truncated() {
+        let bools = vec![true; 17];
+        let mut buf = Vec::new();
+        write_bool_vector(&mut buf, &bools).unwrap();
+        buf.pop();
+        assert!(read_bool_vector(&mut buf.as_slice(), bools.len()).is_err());
+    }
+
+    #[test]
+    fn test_read_bool_vector_roundtrip() {
+        let bools = [true, false, true, true, false, false, false, true, true];
+        let mut buf = Vec::new();
+        write_bool_vector(&mut buf, &bools).unwrap();
+        assert_eq!(buf.len(), 2);
+        assert_eq!(
+            read_bool_vector(&mut buf.as_slice(), bools.len()).unwrap(),
+            bools
+        );
+    }
+}
@@ -35,6 +35,37 @@ impl<W: Write> Write for CountingWriter<W> {
     }
 }
 
+/// Wraps a writer and computes the CRC32 of the bytes written.
+pub struct CrcWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> CrcWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -48,4 +79,14 @@ mod tests {
         assert_eq!(cw.bytes_written(), 11);
         assert_eq!(cw.into_inner(), b"hello world");
     }
+
+    #[test]
+    fn test_crc_writer() {
+        let mut out = Vec::new();
+        let mut cw = CrcWriter::new(&mut out);
+        cw.write_all(b"hello").unwrap();
+        cw.write_all(b" world").unwrap();
+        assert_eq!(cw.crc(), crc32fast::hash(b"hello world"));
+        assert_eq!(out, b"hello world");
+    }
 }
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+/// Default amount of archive data [`SpoolWriter`] keeps in memory before
+/// spilling to a temporary file (64 MiB).
+pub const DEFAULT_MEMORY_LIMIT: usize = 64 << 20;
+
+/// Gives a forward-only output the `Write + Seek` interface
+/// [`SevenZipWriter`](crate::SevenZipWriter) needs, by buffering the whole
+/// archive until [`into_inner`](Self::into_inner) copies it out in one
+/// forward pass.
+///
+/// The buffer lives in memory up to a limit, then moves to an anonymous
+/// temporary file, so writing an archive costs as much temporary storage
+/// as the archive itself. The buffer is also readable, so strict
+/// validation works on it.
+pub struct SpoolWriter<W: Write> {
+    output: W,
+    memory_limit: usize,
+    spool: Spool,
+}
+
+enum Spool {
+    Memory(Cursor<Vec<u8>>),
+    File(File),
+}
+
+impl<W: Write> SpoolWriter<W> {
+    /// Buffers up to [`DEFAULT_MEMORY_LIMIT`] bytes in memory.
+    pub fn new(output: W) -> Self {
+        Self::with_memory_limit(output, DEFAULT_MEMORY_LIMIT)
+    }
+
+    /// Buffers up to `memory_limit` bytes in memory before spilling to a
+    /// temporary file; `0` always uses a file.
+    pub fn with_memory_limit(output: W, memory_limit: usize) -> Self {
+        Self {
+            output,
+            memory_limit,
+            spool: Spool::Memory(Cursor::new(Vec::new())),
+        }
+    }
+
+    /// Whether the buffer has moved to a temporary file.
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.spool, Spool::File(_))
+    }
+
+    /// Writes everything buffered to the output, from the start, and
+    /// returns the output.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        match &mut self.spool {
+            Spool::Memory(cursor) => self.output.write_all(cursor.get_ref())?,
+            Spool::File(file) => {
+                file.seek(SeekFrom::Start(0))?;
+                io::copy(file, &mut self.output)?;
+            }
+        }
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    /// Moves the in-memory buffer to a temporary file, keeping the position.
+    fn spill(&mut self) -> io::Result<()> {
+        if let Spool::Memory(cursor) = &self.spool {
+            let mut file = tempfile::tempfile()?;
+            file.write_all(cursor.get_ref())?;
+            file.seek(SeekFrom::Start(cursor.position()))?;
+            self.spool = Spool::File(file);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SpoolWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Spool::Memory(cursor) = &self.spool {
+            let end = cursor.position().saturating_add(buf.len() as u64);
+            if end > self.memory_limit as u64 {
+                self.spill()?;
+            }
+        }
+        match &mut self.spool {
+            Spool::Memory(cursor) => cursor.write(buf),
+            Spool::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.spool {
+            Spool::Memory(_) => Ok(()),
+            Spool::File(file) => file.flush(),
+        }
+    }
+}
+
+impl<W: Write> Seek for SpoolWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.spool {
+            Spool::Memory(cursor) => cursor.seek(pos),
+            Spool::File(file) => file.seek(pos),
+        }
+    }
+}
+
+impl<W: Write> Read for SpoolWriter<W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.spool {
+            Spool::Memory(cursor) => cursor.read(buf),
+            Spool::File(file) => file.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backpatch_in_memory() {
+        let mut writer = SpoolWriter::new(Vec::new());
+        writer.write_all(&[0; 4]).unwrap();
+        writer.write_all(b"data").unwrap();
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        writer.write_all(b"head").unwrap();
+        assert!(!writer.is_spilled());
+        assert_eq!(writer.into_inner().unwrap(), b"headdata");
+    }
+
+    #[test]
+    fn test_spills_past_memory_limit() {
+        let mut writer = SpoolWriter::with_memory_limit(Vec::new(), 8);
+        writer.write_all(&[0; 4]).unwrap();
+        assert!(!writer.is_spilled());
+        writer.write_all(b"more than the limit").unwrap();
+        assert!(writer.is_spilled());
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        writer.write_all(b"head").unwrap();
+        assert_eq!(writer.seek(SeekFrom::End(0)).unwrap(), 23);
+
+        let mut read_back = Vec::new();
+        writer.seek(SeekFrom::Start(0)).unwrap();
+        writer.read_to_end(&mut read_back).unwrap();
+        assert_eq!(read_back, b"headmore than the limit");
+        assert_eq!(writer.into_inner().unwrap(), b"headmore than the limit");
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Splits one logical output stream over numbered volume files
+/// (`<base>.001`, `<base>.002`, ...), each at most `volume_size` bytes.
+///
+/// Writing past the end of a volume rolls over to the next one. The stream
+/// is fully seekable, so the SignatureHeader written last by
+/// [`SevenZipWriter::finish`](crate::SevenZipWriter::finish) lands back in
+/// the first volume; it is also readable, so strict validation works on it.
+///
+/// Only one volume file is open at a time.
+pub struct VolumeWriter {
+    base: PathBuf,
+    volume_size: u64,
+    /// Number of volume files created so far.
+    volumes: usize,
+    /// Currently open volume index and file.
+    current: Option<(usize, File)>,
+    pos: u64,
+    len: u64,
+}
+
+impl VolumeWriter {
+    /// Creates the first volume, `<base>.001`. `volume_size` must not be 0.
+    pub fn create(base: impl AsRef<Path>, volume_size: u64) -> io::Result<Self> {
+        if volume_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "volume size must not be 0",
+            ));
+        }
+        let mut writer = Self {
+            base: base.as_ref().to_path_buf(),
+            volume_size,
+            volumes: 0,
+            current: None,
+            pos: 0,
+            len: 0,
+        };
+        writer.open_volume(0)?;
+        Ok(writer)
+    }
+
+    /// Path of the volume with the given zero-based index.
+    pub fn volume_path(&self, index: usize) -> PathBuf {
+        let mut name = self.base.clone().into_os_string();
+        name.push(format!(".{:03}", index + 1));
+        PathBuf::from(name)
+    }
+
+    /// Paths of all volumes written so far, in order.
+    pub fn volume_paths(&self) -> Vec<PathBuf> {
+        (0..self.volumes).map(|i| self.volume_path(i)).collect()
+    }
+
+    /// Makes volume `index` the open file, creating it (and any volume
+    /// before it) if needed.
+    fn open_volume(&mut self, index: usize) -> io::Result<&mut File> {
+        let file = match self.current.take() {
+            Some((i, file)) if i == index => file,
+            _ => {
+                while self.volumes <= index {
+                    // Earlier volumes must be full-size for offsets to line up
+                    if self.volumes > 0 {
+                        let previous = OpenOptions::new()
+                            .write(true)
+                            .open(self.volume_path(self.volumes - 1))?;
+                        if previous.metadata()?.len() < self.volume_size {
+                            previous.set_len(self.volume_size)?;
+                        }
+                    }
+                    File::create(self.volume_path(self.volumes))?;
+                    self.volumes += 1;
+                }
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(self.volume_path(index))?
+            }
+        };
+        Ok(&mut self.current.insert((index, file)).1)
+    }
+
+    /// Opens the volume holding the current position and seeks into it.
+    /// Returns the open file and the bytes left in that volume.
+    fn position_in_volume(&mut self) -> io::Result<(&mut File, u64)> {
+        let index = (self.pos / self.volume_size) as usize;
+        let offset = self.pos % self.volume_size;
+        let remaining = self.volume_size - offset;
+        let file = self.open_volume(index)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok((file, remaining))
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (file, remaining) = self.position_in_volume()?;
+        let n = file.write(&buf[..(buf.len() as u64).min(remaining) as usize])?;
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((_, file)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for VolumeWriter {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.len.saturating_sub(self.pos);
+        if buf.is_empty() || available == 0 {
+            return Ok(0);
+        }
+        let (file, remaining) = self.position_in_volume()?;
+        let max = (buf.len() as u64).min(remaining).min(available) as usize;
+        let n = file.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for VolumeWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolls_over_and_backpatches() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("out.7z");
+        let mut writer = VolumeWriter::create(&base, 10).unwrap();
+
+        let data: Vec<u8> = (0..25u8).collect();
+        writer.write_all(&data).unwrap();
+        writer.seek(SeekFrom::Start(2)).unwrap();
+        writer.write_all(&[0xAA, 0xBB]).unwrap();
+        assert_eq!(writer.seek(SeekFrom::End(0)).unwrap(), 25);
+        writer.flush().unwrap();
+
+        let paths = writer.volume_paths();
+        assert_eq!(paths.len(), 3);
+        assert!(paths[0].to_str().unwrap().ends_with("out.7z.001"));
+        assert!(paths[2].to_str().unwrap().ends_with("out.7z.003"));
+
+        let mut expected = data.clone();
+        expected[2..4].copy_from_slice(&[0xAA, 0xBB]);
+        let joined: Vec<u8> = paths
+            .iter()
+            .flat_map(|p| std::fs::read(p).unwrap())
+            .collect();
+        assert_eq!(joined, expected);
+        assert_eq!(std::fs::read(&paths[1]).unwrap().len(), 10);
+
+        writer.seek(SeekFrom::Start(5)).unwrap();
+        let mut read_back = Vec::new();
+        writer.read_to_end(&mut read_back).unwrap();
+        assert_eq!(read_back, &expected[5..]);
+    }
+
+    #[test]
+    fn test_rejects_zero_volume_size() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(VolumeWriter::create(dir.path().join("out.7z"), 0).is_err());
+    }
+}
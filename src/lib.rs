@@ -2,10 +2,28 @@
 
 pub mod archive;
 pub mod compression;
+pub mod encryption;
 pub mod error;
 pub mod io;
+pub mod recovery;
 pub mod threading;
 
-pub use archive::builder::SevenZipWriter;
-pub use compression::lzma2::Lzma2Config;
+pub use archive::builder::{Clock, SevenZipWriter};
+pub use archive::multi::{read_multi_archive_index, MultiArchiveWriter};
+pub use archive::options::EntryOptions;
+pub use archive::path::{NonUtf8Names, PathNormalization};
+pub use archive::progress::{
+    ArchiveReport, FileReport, FinishStats, FolderReport, Progress, ProgressCallback,
+};
+pub use archive::reader::SevenZipReader;
+pub use archive::scan::{ScanEntry, ScanReport};
+pub use compression::compressor::{BlockCompressor, Lzma2Compressor};
+pub use compression::filter::Filter;
+pub use compression::lzma2::{
+    AdaptiveBlockPolicy, BlockPolicy, Codec, Lzma2Config, Lzma2Mode, Profile, ResolvedConfig,
+};
+pub use encryption::AesEncryptConfig;
 pub use error::SevenZipError;
+pub use io::spool::SpoolWriter;
+pub use io::volume::VolumeWriter;
+pub use recovery::repair;
@@ -1,8 +1,13 @@
 #![forbid(unsafe_code)]
 
 use clap::Parser;
-use sevenzip_mt::{Lzma2Config, SevenZipWriter};
-use std::path::PathBuf;
+use sevenzip_mt::archive::pattern::{wildcard_match, ExcludePattern};
+use sevenzip_mt::compression::lzma2::{MAX_DICT_SIZE, MIN_DICT_SIZE};
+use sevenzip_mt::{
+    Lzma2Config, NonUtf8Names, PathNormalization, Profile, SevenZipError, SevenZipWriter,
+};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
 /// Create 7z archives with LZMA2 multi-threaded compression
@@ -12,17 +17,83 @@ struct Cli {
     /// Path to the output .7z archive
     output: PathBuf,
 
-    /// Files to add to the archive
+    /// Files to add to the archive (glob patterns are expanded; '-' reads
+    /// stdin)
     #[arg(required = true)]
     files: Vec<PathBuf>,
 
+    /// Archive name for the data read from stdin
+    #[arg(long, value_name = "NAME", default_value = "stdin")]
+    stdin_name: String,
+
+    /// Archive directories given as FILES with everything below them,
+    /// stored under the directory's own name
+    #[arg(short, long)]
+    recursive: bool,
+
     /// Compression level 0-9
     #[arg(short, long, default_value_t = 6)]
     level: u32,
 
-    /// Number of threads (default: number of logical CPUs)
+    /// Named compression profile instead of a level: fast, balanced, max or
+    /// ultra (--dict and --block still apply)
+    #[arg(long, conflicts_with = "level")]
+    profile: Option<Profile>,
+
+    /// Number of threads (0 or omitted: number of logical CPUs)
     #[arg(short, long)]
     threads: Option<usize>,
+
+    /// Dictionary size, e.g. 64m or 48m: 2^n or 3 × 2^n bytes (suffixes k, m,
+    /// g; default: from the level)
+    #[arg(long, value_parser = parse_size)]
+    dict: Option<u64>,
+
+    /// Block size for parallel compression, e.g. 16m (default: 2 × dict, min 1m)
+    #[arg(long, value_parser = parse_size)]
+    block: Option<u64>,
+
+    /// Name entries by their path as given, minus N leading components (like
+    /// tar); files left with no components are skipped
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    strip_components: usize,
+
+    /// Skip entries matching a gitignore-style pattern, e.g. '*.tmp' or
+    /// 'target/' (repeatable)
+    #[arg(short = 'x', long = "exclude", value_name = "PATTERN")]
+    excludes: Vec<String>,
+
+    /// Leave out files that cannot be read instead of failing
+    #[arg(long)]
+    skip_errors: bool,
+
+    /// What to do with file names that are not valid UTF-8: reject, lossy
+    /// (replace invalid bytes with U+FFFD) or percent-encode (invalid bytes
+    /// and '%' as %XX)
+    #[arg(long, value_name = "POLICY", default_value = "reject")]
+    non_utf8: NonUtf8Names,
+
+    /// Print the compression time of the slowest files
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// Parses a byte count with an optional binary suffix: `k` (KiB), `m` (MiB)
+/// or `g` (GiB), case-insensitive.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size '{s}' (expected a number with optional k, m or g)"))?;
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{s}' is too large"))
 }
 
 fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
@@ -30,40 +101,262 @@ fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
         return Err(format!("compression level must be 0-9, got {}", cli.level).into());
     }
 
+    let dict_size = match cli.dict {
+        Some(dict) if !(MIN_DICT_SIZE as u64..=MAX_DICT_SIZE as u64).contains(&dict) => {
+            return Err(
+                format!("dictionary size must be between 4k and 1536m, got {dict} bytes").into(),
+            );
+        }
+        dict => dict.map(|d| d as u32),
+    };
+    let block_size = match cli.block {
+        Some(0) => return Err("block size must not be 0".into()),
+        block => block
+            .map(usize::try_from)
+            .transpose()
+            .map_err(|_| "block size is too large for this platform")?,
+    };
+
+    let mut inputs: Vec<(PathBuf, String)> = Vec::new();
+    let mut directories: Vec<(PathBuf, String)> = Vec::new();
+    let mut read_stdin = false;
     for path in &cli.files {
-        if !path.exists() {
-            return Err(format!("file not found: {}", path.display()).into());
+        if path == Path::new("-") {
+            if read_stdin {
+                return Err("stdin ('-') can only be given once".into());
+            }
+            read_stdin = true;
+            continue;
+        }
+        match path.to_str().filter(|p| is_glob(p)) {
+            Some(pattern) => {
+                let matches = expand_glob(pattern, cli.non_utf8)?;
+                if matches.is_empty() {
+                    return Err(format!("no files match pattern '{pattern}'").into());
+                }
+                inputs.extend(matches);
+            }
+            None => {
+                if !path.exists() {
+                    return Err(format!("file not found: {}", path.display()).into());
+                }
+                let archive_name = archive_name(path, cli.non_utf8)?;
+                if !path.is_dir() {
+                    inputs.push((path.clone(), archive_name));
+                } else if cli.recursive {
+                    directories.push((path.clone(), archive_name));
+                } else {
+                    return Err(format!(
+                        "{} is a directory (use --recursive to add its contents)",
+                        path.display()
+                    )
+                    .into());
+                }
+            }
         }
     }
 
+    if cli.strip_components > 0 {
+        for (path, name) in inputs.iter_mut().chain(&mut directories) {
+            *name = os_name(path.as_os_str(), cli.non_utf8)?;
+        }
+    }
+
+    let excludes: Vec<ExcludePattern> = cli
+        .excludes
+        .iter()
+        .map(|p| ExcludePattern::new(p))
+        .collect();
+    inputs.retain(|(_, name)| !is_excluded(&excludes, name));
+    directories.retain(|(_, name)| !excludes.iter().any(|p| p.matches(name, true)));
+    if inputs.is_empty() && directories.is_empty() && !read_stdin {
+        return Err("all files are excluded".into());
+    }
+
     let output_file = std::fs::File::create(&cli.output)?;
     let mut archive = SevenZipWriter::new(output_file)?;
 
+    let config = match cli.profile {
+        Some(profile) => Lzma2Config::from_profile(profile),
+        None => Lzma2Config {
+            preset: cli.level,
+            ..Default::default()
+        },
+    };
     archive.set_config(Lzma2Config {
-        preset: cli.level,
-        dict_size: None,
-        block_size: None,
+        dict_size: dict_size.or(config.dict_size),
+        block_size,
+        ..config
     });
-    archive.set_num_threads(cli.threads);
+    // `--threads 0` means auto; the library itself rejects an explicit zero.
+    archive.set_num_threads(cli.threads.filter(|&n| n > 0));
+    archive.set_path_strip_components(cli.strip_components);
+    archive.set_skip_errors(cli.skip_errors);
+    archive.set_non_utf8_names(cli.non_utf8);
 
-    for path in &cli.files {
-        let archive_name = path
-            .file_name()
-            .ok_or_else(|| format!("cannot determine file name for {}", path.display()))?
-            .to_str()
-            .ok_or_else(|| format!("non-UTF-8 file name: {}", path.display()))?;
+    for (path, archive_name) in &inputs {
+        match archive.add_file(path, archive_name) {
+            Err(e @ SevenZipError::StrippedAway(_)) => eprintln!("Skipped {}: {e}", path.display()),
+            result => result?,
+        }
+    }
+    // Streamed in during finish(), without knowing its size up front
+    if read_stdin {
+        archive.add_reader(&cli.stdin_name, Box::new(std::io::stdin().lock()))?;
+    }
+    // Inside directories, patterns match paths relative to the directory
+    archive.set_exclude_patterns(cli.excludes.clone());
+    for (path, archive_name) in &directories {
+        for skipped in archive.add_path_recursive(path, archive_name)? {
+            eprintln!("Skipped {}", skipped.display());
+        }
+    }
 
-        archive.add_file(&path.to_string_lossy(), archive_name)?;
+    for warning in archive.name_warnings() {
+        eprintln!("Warning: {warning}");
     }
 
-    archive.finish()?;
+    let file_count = archive.scan()?.file_count;
+    let (_, stats) = archive.finish_with_stats()?;
 
+    for (path, error) in &stats.skipped {
+        eprintln!("Skipped {}: {error}", path.display());
+    }
     eprintln!(
-        "Created {} with {} file(s)",
+        "Created {} with {} file(s), {} bytes",
         cli.output.display(),
-        cli.files.len()
+        file_count - stats.skipped.len(),
+        stats.archive_size
     );
+    if cli.verbose {
+        for (name, time) in stats.slowest_files.iter().take(10) {
+            eprintln!("{:>10.3}s  {name}", time.as_secs_f64());
+        }
+    }
+
+    Ok(())
+}
 
+/// The archive name of a file or directory given on the command line: its
+/// last path component, resolving paths such as `.` or `dir/..`.
+fn archive_name(path: &Path, non_utf8: NonUtf8Names) -> Result<String, String> {
+    let resolved;
+    let file_name = match path.file_name() {
+        Some(name) => name,
+        None => {
+            resolved = path
+                .canonicalize()
+                .map_err(|e| format!("cannot resolve {}: {e}", path.display()))?;
+            resolved
+                .file_name()
+                .ok_or_else(|| format!("cannot determine file name for {}", path.display()))?
+        }
+    };
+    os_name(file_name, non_utf8)
+}
+
+/// Converts a file name or path from disk for an archive name, as
+/// `--non-utf8` says if it is not valid UTF-8.
+fn os_name(name: &OsStr, non_utf8: NonUtf8Names) -> Result<String, String> {
+    let normalization = PathNormalization {
+        non_utf8_names: non_utf8,
+        ..Default::default()
+    };
+    normalization.name_from_os(name).map_err(|e| e.to_string())
+}
+
+/// Whether an archive name, or any directory leading to it, matches one of
+/// the exclude patterns.
+fn is_excluded(excludes: &[ExcludePattern], name: &str) -> bool {
+    let parent_dirs = name.match_indices('/').map(|(i, _)| &name[..i]);
+    excludes.iter().any(|pattern| {
+        pattern.matches(name, false) || parent_dirs.clone().any(|dir| pattern.matches(dir, true))
+    })
+}
+
+/// Whether a command-line argument is a glob pattern rather than a path.
+fn is_glob(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+/// Expands a glob pattern into the regular files it matches, as
+/// `(disk path, archive name)` pairs in name order.
+///
+/// Components are separated by `/`. Within a component, `*` matches any run
+/// of characters, `?` any single character and `[...]` a character class
+/// (ranges like `a-z`, negated by a leading `!` or `^`); a `**` component
+/// matches any number of directories. Archive names are the matched paths
+/// as written, so `src/**/*.rs` keeps the `src/` prefix; for absolute
+/// patterns, the leading directories without wildcards are dropped.
+fn expand_glob(pattern: &str, non_utf8: NonUtf8Names) -> Result<Vec<(PathBuf, String)>, String> {
+    let absolute = pattern.starts_with('/');
+    let components: Vec<&str> = pattern
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+    let literal = components.iter().take_while(|c| !is_glob(c)).count();
+
+    let mut base = PathBuf::from(if absolute { "/" } else { "." });
+    base.extend(&components[..literal]);
+    let name_prefix = if absolute {
+        String::new()
+    } else {
+        components[..literal].join("/")
+    };
+
+    let mut matches = Vec::new();
+    walk_glob(
+        &base,
+        &name_prefix,
+        &components[literal..],
+        non_utf8,
+        &mut matches,
+    )?;
+    matches.sort_by(|a, b| a.1.cmp(&b.1));
+    matches.dedup_by(|a, b| a.1 == b.1);
+    Ok(matches)
+}
+
+fn walk_glob(
+    dir: &Path,
+    name: &str,
+    components: &[&str],
+    non_utf8: NonUtf8Names,
+    matches: &mut Vec<(PathBuf, String)>,
+) -> Result<(), String> {
+    let Some((&component, rest)) = components.split_first() else {
+        if dir.is_file() {
+            matches.push((dir.to_path_buf(), name.to_string()));
+        }
+        return Ok(());
+    };
+    // A missing or unreadable directory simply matches nothing
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Ok(());
+    };
+
+    if component == "**" {
+        walk_glob(dir, name, rest, non_utf8, matches)?;
+    }
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("cannot read {}: {e}", dir.display()))?;
+        let file_name = os_name(&entry.file_name(), non_utf8)?;
+        let child_name = if name.is_empty() {
+            file_name.clone()
+        } else {
+            format!("{name}/{file_name}")
+        };
+        // Directories are entered without following symlinks
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+
+        if component == "**" {
+            if is_dir {
+                walk_glob(&entry.path(), &child_name, components, non_utf8, matches)?;
+            }
+        } else if (rest.is_empty() || is_dir) && wildcard_match(component, &file_name) {
+            walk_glob(&entry.path(), &child_name, rest, non_utf8, matches)?;
+        }
+    }
     Ok(())
 }
 
@@ -77,3 +370,83 @@ fn main() -> ExitCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512k"), Ok(512 * 1024));
+        assert_eq!(parse_size("4m"), Ok(4 << 20));
+        assert_eq!(parse_size("4M"), Ok(4 << 20));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert_eq!(parse_size("1000"), Ok(1000));
+        assert!(parse_size("10x").is_err());
+        assert!(parse_size("m").is_err());
+        assert!(parse_size("").is_err());
+        assert!(parse_size("99999999999g").is_err());
+    }
+
+    #[test]
+    fn test_archive_name() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().join("mydir");
+        std::fs::create_dir(&root).unwrap();
+        assert_eq!(
+            archive_name(&root.join(""), NonUtf8Names::Reject).unwrap(),
+            "mydir"
+        );
+        assert_eq!(
+            archive_name(&root.join("."), NonUtf8Names::Reject).unwrap(),
+            "mydir"
+        );
+        assert!(archive_name(&root.join("sub/.."), NonUtf8Names::Reject).is_err());
+        std::fs::create_dir(root.join("sub")).unwrap();
+        assert_eq!(
+            archive_name(&root.join("sub/.."), NonUtf8Names::Reject).unwrap(),
+            "mydir"
+        );
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let excludes = [ExcludePattern::new("*.log"), ExcludePattern::new("skip/")];
+        assert!(is_excluded(&excludes, "a.log"));
+        assert!(is_excluded(&excludes, "src/skip/deep/a.rs"));
+        assert!(!is_excluded(&excludes, "src/a.rs"));
+        assert!(!is_excluded(&excludes, "skip"));
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src/sub/deep")).unwrap();
+        for file in ["src/a.rs", "src/b.txt", "src/sub/c.rs", "src/sub/deep/d.rs"] {
+            std::fs::write(root.join(file), b"x").unwrap();
+        }
+        let pattern = format!("{}/src/**/*.rs", root.display());
+
+        let names: Vec<String> = expand_glob(&pattern, NonUtf8Names::Reject)
+            .unwrap()
+            .into_iter()
+            .map(|(path, name)| {
+                assert!(path.is_file());
+                name
+            })
+            .collect();
+        assert_eq!(names, ["a.rs", "sub/c.rs", "sub/deep/d.rs"]);
+
+        let pattern = format!("{}/src/*/*.rs", root.display());
+        assert_eq!(
+            expand_glob(&pattern, NonUtf8Names::Reject).unwrap().len(),
+            1
+        );
+        let pattern = format!("{}/missing/*.rs", root.display());
+        assert!(expand_glob(&pattern, NonUtf8Names::Reject)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,493 @@
+use crate::error::{Result, SevenZipError};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Magic bytes closing a recovery record.
+pub const RECOVERY_MAGIC: [u8; 8] = *b"7zRECOV\x01";
+
+/// Data shards per Reed-Solomon group.
+const DATA_SHARDS: usize = 64;
+
+/// Shard size for archives of at least one full group. Smaller archives use
+/// `ceil(len / DATA_SHARDS)` so the parity stays proportional.
+const MAX_SHARD_SIZE: usize = 4096;
+
+/// Footer layout: archive length (u64), shard size (u32), data shards (u32),
+/// parity shards (u32), record length (u64), footer CRC (u32), magic.
+const FOOTER_SIZE: usize = 8 + 4 + 4 + 4 + 8 + 4 + 8;
+
+/// Computes Reed-Solomon parity over an archive as it is written.
+///
+/// The archive is split into contiguous shards, 64 per group, and each
+/// group gets `ceil(64 * percent / 100)` parity shards, so a
+/// damaged run of up to `percent`% of a group can be rebuilt. The first group
+/// is only encoded in [`finish`](Self::finish), which lets the caller
+/// [`patch_start`](Self::patch_start) the SignatureHeader written last.
+pub struct RecoveryEncoder {
+    rs: ReedSolomon,
+    parity_shards: usize,
+    first_group: Vec<u8>,
+    group: Vec<u8>,
+    /// Parity and shard CRCs of every group after the first, in order.
+    parity: Vec<u8>,
+    crcs: Vec<u32>,
+    archive_length: u64,
+}
+
+impl RecoveryEncoder {
+    /// Creates an encoder for `percent` (1-100) of parity.
+    pub fn new(percent: u8) -> Result<Self> {
+        if !(1..=100).contains(&percent) {
+            return Err(SevenZipError::InvalidState(format!(
+                "recovery percent must be between 1 and 100, got {percent}"
+            )));
+        }
+        let parity_shards = (DATA_SHARDS * percent as usize).div_ceil(100);
+        let rs = ReedSolomon::new(DATA_SHARDS, parity_shards)
+            .map_err(|e| SevenZipError::Recovery(e.to_string()))?;
+
+        Ok(Self {
+            rs,
+            parity_shards,
+            first_group: Vec::new(),
+            group: Vec::new(),
+            parity: Vec::new(),
+            crcs: Vec::new(),
+            archive_length: 0,
+        })
+    }
+
+    /// Feeds the next archive bytes.
+    pub fn update(&mut self, mut data: &[u8]) -> Result<()> {
+        const GROUP_SIZE: usize = DATA_SHARDS * MAX_SHARD_SIZE;
+        self.archive_length += data.len() as u64;
+
+        let take = (GROUP_SIZE - self.first_group.len()).min(data.len());
+        self.first_group.extend_from_slice(&data[..take]);
+        data = &data[take..];
+
+        while !data.is_empty() {
+            let take = (GROUP_SIZE - self.group.len()).min(data.len());
+            self.group.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.group.len() == GROUP_SIZE {
+                let group = std::mem::take(&mut self.group);
+                let (parity, crcs) = self.encode_group(&group, MAX_SHARD_SIZE)?;
+                self.parity.extend_from_slice(&parity);
+                self.crcs.extend_from_slice(&crcs);
+            }
+        }
+        Ok(())
+    }
+
+    /// Overwrites the first `data.len()` bytes already passed to `update`.
+    pub fn patch_start(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > self.first_group.len() {
+            return Err(SevenZipError::Recovery(
+                "patch extends past the buffered start of the archive".to_string(),
+            ));
+        }
+        self.first_group[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Encodes the remaining groups and returns the recovery record to
+    /// append right after the archive.
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        let shard_size = shard_size_for(self.archive_length);
+
+        let first_group = std::mem::take(&mut self.first_group);
+        let (mut parity, mut crcs) = self.encode_group(&first_group, shard_size)?;
+        parity.append(&mut self.parity);
+        crcs.append(&mut self.crcs);
+        if !self.group.is_empty() {
+            let group = std::mem::take(&mut self.group);
+            let (last_parity, last_crcs) = self.encode_group(&group, shard_size)?;
+            parity.extend_from_slice(&last_parity);
+            crcs.extend_from_slice(&last_crcs);
+        }
+
+        let mut record = parity;
+        for crc in crcs {
+            record.write_u32::<LittleEndian>(crc)?;
+        }
+        let record_length = (record.len() + FOOTER_SIZE) as u64;
+        write_footer(
+            &mut record,
+            &Footer {
+                archive_length: self.archive_length,
+                shard_size,
+                parity_shards: self.parity_shards,
+                record_length,
+            },
+        )?;
+
+        Ok(record)
+    }
+
+    /// Encodes one group, zero-padding it to whole shards. Returns the parity
+    /// shards and the CRCs of all data and parity shards.
+    fn encode_group(&self, data: &[u8], shard_size: usize) -> Result<(Vec<u8>, Vec<u32>)> {
+        let mut shards = split_group(data, shard_size);
+        shards.resize(DATA_SHARDS + self.parity_shards, vec![0u8; shard_size]);
+        self.rs
+            .encode(&mut shards)
+            .map_err(|e| SevenZipError::Recovery(e.to_string()))?;
+
+        let crcs = shards.iter().map(|s| crc32fast::hash(s)).collect();
+        Ok((shards[DATA_SHARDS..].concat(), crcs))
+    }
+}
+
+/// Splits a group's data into `DATA_SHARDS` zero-padded shards.
+fn split_group(data: &[u8], shard_size: usize) -> Vec<Vec<u8>> {
+    (0..DATA_SHARDS)
+        .map(|i| {
+            let start = (i * shard_size).min(data.len());
+            let end = ((i + 1) * shard_size).min(data.len());
+            let mut shard = data[start..end].to_vec();
+            shard.resize(shard_size, 0);
+            shard
+        })
+        .collect()
+}
+
+fn shard_size_for(archive_length: u64) -> usize {
+    (archive_length.div_ceil(DATA_SHARDS as u64) as usize).clamp(1, MAX_SHARD_SIZE)
+}
+
+/// Write adapter that feeds everything written through it to an optional
+/// [`RecoveryEncoder`].
+pub struct RecoveryTee<'a, W: Write> {
+    inner: W,
+    encoder: Option<&'a mut RecoveryEncoder>,
+}
+
+impl<'a, W: Write> RecoveryTee<'a, W> {
+    pub fn new(inner: W, encoder: Option<&'a mut RecoveryEncoder>) -> Self {
+        Self { inner, encoder }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Write> Write for RecoveryTee<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(encoder) = self.encoder.as_mut() {
+            encoder.update(&buf[..n]).map_err(io::Error::other)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Footer {
+    archive_length: u64,
+    shard_size: usize,
+    parity_shards: usize,
+    record_length: u64,
+}
+
+fn write_footer(w: &mut Vec<u8>, footer: &Footer) -> io::Result<()> {
+    let mut fields = Vec::with_capacity(FOOTER_SIZE);
+    fields.write_u64::<LittleEndian>(footer.archive_length)?;
+    fields.write_u32::<LittleEndian>(footer.shard_size as u32)?;
+    fields.write_u32::<LittleEndian>(DATA_SHARDS as u32)?;
+    fields.write_u32::<LittleEndian>(footer.parity_shards as u32)?;
+    fields.write_u64::<LittleEndian>(footer.record_length)?;
+    let crc = crc32fast::hash(&fields);
+
+    w.write_all(&fields)?;
+    w.write_u32::<LittleEndian>(crc)?;
+    w.write_all(&RECOVERY_MAGIC)?;
+    Ok(())
+}
+
+fn read_footer(bytes: &[u8; FOOTER_SIZE]) -> Result<(Footer, usize)> {
+    if bytes[FOOTER_SIZE - 8..] != RECOVERY_MAGIC {
+        return Err(SevenZipError::Recovery(
+            "no recovery record found".to_string(),
+        ));
+    }
+    let mut r = &bytes[..];
+    let archive_length = r.read_u64::<LittleEndian>()?;
+    let shard_size = r.read_u32::<LittleEndian>()? as usize;
+    let data_shards = r.read_u32::<LittleEndian>()? as usize;
+    let parity_shards = r.read_u32::<LittleEndian>()? as usize;
+    let record_length = r.read_u64::<LittleEndian>()?;
+    let crc = r.read_u32::<LittleEndian>()?;
+    if crc32fast::hash(&bytes[..FOOTER_SIZE - 12]) != crc {
+        return Err(SevenZipError::Recovery(
+            "recovery record footer is damaged".to_string(),
+        ));
+    }
+
+    let footer = Footer {
+        archive_length,
+        shard_size,
+        parity_shards,
+        record_length,
+    };
+    Ok((footer, data_shards))
+}
+
+/// Repairs an archive followed by a recovery record.
+///
+/// Shards whose CRC no longer matches are rebuilt from the parity, and the
+/// repaired stream (any data before the archive, the archive, and its
+/// recovery record) is written to `writer`. Returns the number of shards
+/// that were rebuilt; `0` means the archive was intact.
+pub fn repair<R: Read + Seek, W: Write>(mut reader: R, mut writer: W) -> Result<usize> {
+    let total_length = reader.seek(SeekFrom::End(0))?;
+    if total_length < FOOTER_SIZE as u64 {
+        return Err(SevenZipError::Recovery(
+            "no recovery record found".to_string(),
+        ));
+    }
+    reader.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+    let mut footer_bytes = [0u8; FOOTER_SIZE];
+    reader.read_exact(&mut footer_bytes)?;
+    let (footer, data_shards) = read_footer(&footer_bytes)?;
+    if data_shards != DATA_SHARDS
+        || footer.shard_size == 0
+        || footer.shard_size > MAX_SHARD_SIZE
+        || footer.parity_shards == 0
+        || footer.parity_shards > DATA_SHARDS
+    {
+        return Err(SevenZipError::Recovery(
+            "unsupported recovery record layout".to_string(),
+        ));
+    }
+
+    let shard_size = footer.shard_size;
+    // At most MAX_SHARD_SIZE times DATA_SHARDS, so this cannot overflow
+    let group_size = (DATA_SHARDS * shard_size) as u64;
+    let group_count = footer.archive_length.div_ceil(group_size);
+    let shards_per_group = DATA_SHARDS + footer.parity_shards;
+    // The footer's lengths are untrusted until they match the stream
+    let layout = || {
+        let parity_length = group_count
+            .checked_mul(footer.parity_shards as u64)?
+            .checked_mul(shard_size as u64)?;
+        let crc_length = group_count.checked_mul(shards_per_group as u64 * 4)?;
+        let record_length = parity_length
+            .checked_add(crc_length)?
+            .checked_add(FOOTER_SIZE as u64)?;
+        if record_length != footer.record_length {
+            return None;
+        }
+        let archive_start = total_length
+            .checked_sub(footer.record_length)?
+            .checked_sub(footer.archive_length)?;
+        Some((
+            usize::try_from(group_count).ok()?,
+            usize::try_from(parity_length).ok()?,
+            usize::try_from(crc_length).ok()?,
+            archive_start,
+        ))
+    };
+    let Some((group_count, parity_length, crc_length, archive_start)) = layout() else {
+        return Err(SevenZipError::Recovery(
+            "recovery record does not match the stream length".to_string(),
+        ));
+    };
+    let parity_start = archive_start + footer.archive_length;
+    let crc_table_start = parity_start + parity_length as u64;
+    // Lengths now fit in the stream, so offsets within them cannot overflow
+    let group_parity_size = (footer.parity_shards * shard_size) as u64;
+    let group_crc_size = (shards_per_group * 4) as u64;
+
+    // Data before the archive is not covered by the record.
+    reader.seek(SeekFrom::Start(0))?;
+    io::copy(&mut (&mut reader).take(archive_start), &mut writer)?;
+
+    let rs = ReedSolomon::new(DATA_SHARDS, footer.parity_shards)
+        .map_err(|e| SevenZipError::Recovery(e.to_string()))?;
+    let mut repaired = 0;
+    let mut parity = Vec::with_capacity(parity_length);
+    let mut crc_table = Vec::with_capacity(crc_length);
+
+    for g in 0..group_count {
+        let group_start = g as u64 * group_size;
+        let group_length = (footer.archive_length - group_start).min(group_size) as usize;
+
+        reader.seek(SeekFrom::Start(archive_start + group_start))?;
+        let mut data = vec![0u8; group_length];
+        reader.read_exact(&mut data)?;
+        let mut shards = split_group(&data, shard_size);
+
+        reader.seek(SeekFrom::Start(parity_start + g as u64 * group_parity_size))?;
+        for _ in 0..footer.parity_shards {
+            let mut shard = vec![0u8; shard_size];
+            reader.read_exact(&mut shard)?;
+            shards.push(shard);
+        }
+
+        reader.seek(SeekFrom::Start(crc_table_start + g as u64 * group_crc_size))?;
+        let mut present = Vec::with_capacity(shards_per_group);
+        for shard in shards {
+            let expected = reader.read_u32::<LittleEndian>()?;
+            present.push((crc32fast::hash(&shard) == expected).then_some(shard));
+        }
+
+        let damaged = present.iter().filter(|s| s.is_none()).count();
+        if damaged > footer.parity_shards {
+            return Err(SevenZipError::Recovery(format!(
+                "group {g} has {damaged} damaged shards, only {} can be rebuilt",
+                footer.parity_shards
+            )));
+        }
+        if damaged > 0 {
+            rs.reconstruct(&mut present)
+                .map_err(|e| SevenZipError::Recovery(e.to_string()))?;
+            repaired += damaged;
+        }
+
+        let shards: Vec<Vec<u8>> = present.into_iter().flatten().collect();
+        writer.write_all(&shards[..DATA_SHARDS].concat()[..group_length])?;
+        for shard in &shards[DATA_SHARDS..] {
+            parity.extend_from_slice(shard);
+        }
+        for shard in &shards {
+            crc_table.write_u32::<LittleEndian>(crc32fast::hash(shard))?;
+        }
+    }
+
+    writer.write_all(&parity)?;
+    writer.write_all(&crc_table)?;
+    let mut footer_out = Vec::with_capacity(FOOTER_SIZE);
+    write_footer(&mut footer_out, &footer)?;
+    writer.write_all(&footer_out)?;
+
+    Ok(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protect(archive: &[u8], percent: u8) -> Vec<u8> {
+        let mut encoder = RecoveryEncoder::new(percent).unwrap();
+        for chunk in archive.chunks(1000) {
+            encoder.update(chunk).unwrap();
+        }
+        let mut stream = archive.to_vec();
+        stream.extend(encoder.finish().unwrap());
+        stream
+    }
+
+    fn repair_bytes(stream: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let mut out = Vec::new();
+        let repaired = repair(io::Cursor::new(stream), &mut out)?;
+        Ok((out, repaired))
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_rejects_invalid_percent() {
+        assert!(RecoveryEncoder::new(0).is_err());
+        assert!(RecoveryEncoder::new(101).is_err());
+    }
+
+    #[test]
+    fn test_intact_stream_is_unchanged() {
+        let stream = protect(&sample(10_000), 5);
+        let (out, repaired) = repair_bytes(&stream).unwrap();
+        assert_eq!(repaired, 0);
+        assert_eq!(out, stream);
+    }
+
+    #[test]
+    fn test_repairs_damage_across_groups() {
+        // Three groups, the last one partial
+        let archive = sample(2 * DATA_SHARDS * MAX_SHARD_SIZE + 12_345);
+        let stream = protect(&archive, 10);
+
+        let mut damaged = stream.clone();
+        damaged[3] ^= 0xFF;
+        damaged[DATA_SHARDS * MAX_SHARD_SIZE + 100] ^= 0x01;
+        damaged[archive.len() - 1] ^= 0x80;
+        // A damaged parity shard is rebuilt too
+        damaged[archive.len() + 10] ^= 0x01;
+
+        let (out, repaired) = repair_bytes(&damaged).unwrap();
+        assert_eq!(repaired, 4);
+        assert_eq!(out, stream);
+    }
+
+    #[test]
+    fn test_patch_start_is_covered() {
+        let mut archive = sample(5_000);
+        let mut encoder = RecoveryEncoder::new(10).unwrap();
+        encoder.update(&archive).unwrap();
+        encoder.patch_start(b"patched").unwrap();
+        archive[..7].copy_from_slice(b"patched");
+        archive.extend(encoder.finish().unwrap());
+
+        let (_, repaired) = repair_bytes(&archive).unwrap();
+        assert_eq!(repaired, 0);
+    }
+
+    #[test]
+    fn test_too_much_damage_is_reported() {
+        let archive = sample(6_400);
+        let mut stream = protect(&archive, 1);
+        // 1% of 64 shards is one parity shard; damage two data shards
+        stream[0] ^= 1;
+        stream[6_399] ^= 1;
+        assert!(matches!(
+            repair_bytes(&stream),
+            Err(SevenZipError::Recovery(_))
+        ));
+    }
+
+    #[test]
+    fn test_oversized_footer_lengths_are_rejected() {
+        // A well-formed footer whose sizes overflow when multiplied out
+        for (archive_length, parity_shards) in [(u64::MAX, DATA_SHARDS), (u64::MAX / 2, 1)] {
+            let mut stream = sample(100);
+            let footer = Footer {
+                archive_length,
+                shard_size: MAX_SHARD_SIZE,
+                parity_shards,
+                record_length: u64::MAX,
+            };
+            write_footer(&mut stream, &footer).unwrap();
+            assert!(matches!(
+                repair_bytes(&stream),
+                Err(SevenZipError::Recovery(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_missing_record_is_reported() {
+        assert!(matches!(
+            repair_bytes(&sample(100)),
+            Err(SevenZipError::Recovery(_))
+        ));
+    }
+
+    #[test]
+    fn test_preserves_leading_data() {
+        let archive = sample(3_000);
+        let mut stream = b"prefix".to_vec();
+        stream.extend(protect(&archive, 20));
+
+        let mut damaged = stream.clone();
+        damaged[6 + 1_000] ^= 0x42;
+        let (out, repaired) = repair_bytes(&damaged).unwrap();
+        assert_eq!(repaired, 1);
+        assert_eq!(out, stream);
+    }
+}
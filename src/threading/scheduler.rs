@@ -2,36 +2,325 @@ use crate::compression::block::{CompressedBlock, RawBlock};
 use crate::compression::lzma2::Lzma2Config;
 use crate::error::{Result, SevenZipError};
 use rayon::prelude::*;
-use rayon::ThreadPoolBuilder;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, TryRecvError};
+
+/// Checks an explicit thread count. `Some(0)` is rejected rather than being
+/// silently treated as "auto" the way rayon would; use `None` for auto.
+pub fn validate_num_threads(num_threads: Option<usize>) -> Result<()> {
+    if num_threads == Some(0) {
+        return Err(SevenZipError::InvalidState(
+            "num_threads must be at least 1 (use None for one thread per logical CPU)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a cap on blocks in flight; `Some(0)` would never start a block.
+pub fn validate_max_in_flight(max_in_flight: Option<usize>) -> Result<()> {
+    if max_in_flight == Some(0) {
+        return Err(SevenZipError::InvalidState(
+            "max_blocks_in_flight must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The number of threads `num_threads` stands for: one per logical CPU
+/// for `None`.
+pub fn effective_num_threads(num_threads: Option<usize>) -> usize {
+    num_threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+}
 
 /// Compresses multiple blocks in parallel using a dedicated rayon thread pool,
-/// returning them sorted by block_index.
+/// returning them sorted by block_index. `config_of_block` gives the
+/// configuration to compress each block with, by block_index.
 ///
 /// If `num_threads` is `None`, uses the number of available logical CPUs.
-pub fn compress_blocks_parallel(
+/// `Some(0)` is an error (see `validate_num_threads`). The pool never gets
+/// more threads than there are blocks, and a single block is compressed on
+/// the calling thread without building a pool at all.
+///
+/// `on_block_done` is called from the worker threads with the uncompressed
+/// size of each block as soon as it is compressed. Once `cancel` is set, no
+/// further block is started and `SevenZipError::Cancelled` is returned.
+///
+/// With `max_in_flight`, at most that many blocks are compressed at once:
+/// blocks are handed to the pool in batches of that size, bounding the
+/// encoder state and output buffers alive at any time. `None` leaves the
+/// pool's threads as the only limit; `Some(0)` is an error.
+pub fn compress_blocks_parallel<'c>(
     blocks: Vec<RawBlock>,
-    config: &Lzma2Config,
+    config_of_block: &(dyn Fn(usize) -> &'c Lzma2Config + Sync),
     num_threads: Option<usize>,
+    on_block_done: &(dyn Fn(u64) + Sync),
+    cancel: Option<&AtomicBool>,
+    max_in_flight: Option<usize>,
 ) -> Result<Vec<CompressedBlock>> {
-    let mut builder = ThreadPoolBuilder::new();
-    if let Some(n) = num_threads {
-        builder = builder.num_threads(n);
-    }
-    let pool = builder.build().map_err(|e| {
-        SevenZipError::Threading(format!("failed to build thread pool: {e}"))
-    })?;
-
-    let mut results: Vec<CompressedBlock> = pool.install(|| {
-        blocks
-            .into_par_iter()
-            .map(|block| crate::threading::worker::compress_raw_block(block, config))
-            .collect::<Result<Vec<_>>>()
-    })?;
-
-    results.sort_by_key(|b| b.block_index);
+    validate_num_threads(num_threads)?;
+    validate_max_in_flight(max_in_flight)?;
+    if blocks.len() <= 1 {
+        return compress_blocks_inline(blocks, config_of_block, on_block_done, cancel);
+    }
+    let pool = build_pool(num_threads, blocks.len(), max_in_flight)?;
+    compress_blocks_parallel_in(
+        &pool,
+        blocks,
+        config_of_block,
+        on_block_done,
+        cancel,
+        max_in_flight,
+    )
+}
+
+/// Like [`compress_blocks_parallel`], but runs on the caller's `pool`
+/// instead of building one.
+pub fn compress_blocks_parallel_in<'c>(
+    pool: &ThreadPool,
+    blocks: Vec<RawBlock>,
+    config_of_block: &(dyn Fn(usize) -> &'c Lzma2Config + Sync),
+    on_block_done: &(dyn Fn(u64) + Sync),
+    cancel: Option<&AtomicBool>,
+    max_in_flight: Option<usize>,
+) -> Result<Vec<CompressedBlock>> {
+    compress_blocks_parallel_results_in(
+        pool,
+        blocks,
+        config_of_block,
+        on_block_done,
+        cancel,
+        max_in_flight,
+    )?
+    .into_iter()
+    .collect()
+}
+
+/// Like [`compress_blocks_parallel_in`], but keeps the outcome of each
+/// block, in block order, so a caller can tell which block failed. No
+/// batch is started after one in which a block failed, so the results end
+/// with that batch.
+pub fn compress_blocks_parallel_results_in<'c>(
+    pool: &ThreadPool,
+    mut blocks: Vec<RawBlock>,
+    config_of_block: &(dyn Fn(usize) -> &'c Lzma2Config + Sync),
+    on_block_done: &(dyn Fn(u64) + Sync),
+    cancel: Option<&AtomicBool>,
+    max_in_flight: Option<usize>,
+) -> Result<Vec<Result<CompressedBlock>>> {
+    validate_max_in_flight(max_in_flight)?;
+    if blocks.len() <= 1 {
+        return Ok(
+            compress_blocks_sequential(blocks, config_of_block, on_block_done, cancel).collect(),
+        );
+    }
+
+    blocks.sort_by_key(|b| b.block_index);
+    let batch_size = max_in_flight.unwrap_or(blocks.len());
+    let mut blocks = blocks.into_iter();
+    let mut results: Vec<Result<CompressedBlock>> = Vec::with_capacity(blocks.len());
+    loop {
+        let batch: Vec<RawBlock> = blocks.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            break;
+        }
+        let compressed: Vec<Result<CompressedBlock>> = pool.install(|| {
+            batch
+                .into_par_iter()
+                .map(|block| {
+                    let config = config_of_block(block.block_index);
+                    compress_block(block, config, on_block_done, cancel)
+                })
+                .collect()
+        });
+        let failed = compressed.iter().any(Result::is_err);
+        results.extend(compressed);
+        if failed {
+            break;
+        }
+    }
     Ok(results)
 }
 
+/// Like [`compress_blocks_parallel`], but hands the compressed blocks to
+/// `consume` in block order while later ones are still being compressed,
+/// instead of collecting them all first.
+///
+/// At most two blocks per thread are compressed or waiting ahead of the one
+/// `consume` needs next, so finished blocks never pile up behind a slow
+/// one. `max_in_flight` lowers that limit further. `consume` runs on the
+/// calling thread; once it returns, blocks not yet started are dropped.
+pub fn compress_blocks_streaming<'c, T>(
+    blocks: Vec<RawBlock>,
+    config_of_block: &(dyn Fn(usize) -> &'c Lzma2Config + Sync),
+    num_threads: Option<usize>,
+    on_block_done: &(dyn Fn(u64) + Sync),
+    cancel: Option<&AtomicBool>,
+    max_in_flight: Option<usize>,
+    consume: impl FnOnce(&mut dyn Iterator<Item = Result<CompressedBlock>>) -> Result<T>,
+) -> Result<T> {
+    validate_num_threads(num_threads)?;
+    validate_max_in_flight(max_in_flight)?;
+    if blocks.len() <= 1 {
+        let compressed = compress_blocks_inline(blocks, config_of_block, on_block_done, cancel);
+        return consume(&mut compressed?.into_iter().map(Ok));
+    }
+    let pool = build_pool(num_threads, blocks.len(), max_in_flight)?;
+    compress_blocks_streaming_in(
+        &pool,
+        blocks,
+        config_of_block,
+        on_block_done,
+        cancel,
+        max_in_flight,
+        consume,
+    )
+}
+
+/// Like [`compress_blocks_streaming`], but runs on the caller's `pool`
+/// instead of building one. It may be called from one of the pool's own
+/// threads, which then helps compressing while it waits for a block.
+pub fn compress_blocks_streaming_in<'c, T>(
+    pool: &ThreadPool,
+    blocks: Vec<RawBlock>,
+    config_of_block: &(dyn Fn(usize) -> &'c Lzma2Config + Sync),
+    on_block_done: &(dyn Fn(u64) + Sync),
+    cancel: Option<&AtomicBool>,
+    max_in_flight: Option<usize>,
+    consume: impl FnOnce(&mut dyn Iterator<Item = Result<CompressedBlock>>) -> Result<T>,
+) -> Result<T> {
+    validate_max_in_flight(max_in_flight)?;
+    if blocks.len() <= 1 {
+        let compressed = compress_blocks_inline(blocks, config_of_block, on_block_done, cancel);
+        return consume(&mut compressed?.into_iter().map(Ok));
+    }
+
+    let compress = |block: RawBlock| {
+        let config = config_of_block(block.block_index);
+        compress_block(block, config, on_block_done, cancel)
+    };
+    let compress = &compress;
+    let ahead = (2 * pool.current_num_threads()).min(max_in_flight.unwrap_or(usize::MAX));
+    pool.in_place_scope(|scope| {
+        let mut blocks = blocks.into_iter();
+        let mut pending = VecDeque::with_capacity(ahead);
+        let mut ordered = std::iter::from_fn(|| {
+            while pending.len() < ahead {
+                let Some(block) = blocks.next() else { break };
+                let (sender, receiver) = sync_channel(1);
+                scope.spawn(move |_| {
+                    // The receiver is gone if `consume` already returned
+                    let _ = sender.send(compress(block));
+                });
+                pending.push_back(receiver);
+            }
+            let receiver = pending.pop_front()?;
+            let received = loop {
+                match receiver.try_recv() {
+                    Ok(result) => break Ok(result),
+                    // On a pool thread, run queued blocks rather than block
+                    // a thread they may need
+                    Err(TryRecvError::Empty)
+                        if rayon::yield_now() == Some(rayon::Yield::Executed) => {}
+                    Err(_) => break receiver.recv(),
+                }
+            };
+            Some(received.unwrap_or_else(|_| {
+                Err(SevenZipError::Threading(
+                    "compression worker panicked".to_string(),
+                ))
+            }))
+        });
+        consume(&mut ordered)
+    })
+}
+
+/// Compresses blocks one after another on the calling thread, for inputs
+/// too small to be worth a pool.
+fn compress_blocks_inline<'c>(
+    blocks: Vec<RawBlock>,
+    config_of_block: &(dyn Fn(usize) -> &'c Lzma2Config + Sync),
+    on_block_done: &(dyn Fn(u64) + Sync),
+    cancel: Option<&AtomicBool>,
+) -> Result<Vec<CompressedBlock>> {
+    compress_blocks_sequential(blocks, config_of_block, on_block_done, cancel).collect()
+}
+
+/// Compresses blocks lazily on the calling thread, each one as the
+/// iterator reaches it, in block order: the single-threaded counterpart of
+/// [`compress_blocks_streaming`], holding one block in flight.
+pub fn compress_blocks_sequential<'a, 'c: 'a, F>(
+    blocks: Vec<RawBlock>,
+    config_of_block: &'a F,
+    on_block_done: &'a (dyn Fn(u64) + Sync),
+    cancel: Option<&'a AtomicBool>,
+) -> impl Iterator<Item = Result<CompressedBlock>> + 'a
+where
+    F: Fn(usize) -> &'c Lzma2Config + Sync + ?Sized,
+{
+    blocks.into_iter().map(move |block| {
+        let config = config_of_block(block.block_index);
+        compress_block(block, config, on_block_done, cancel)
+    })
+}
+
+/// Compresses one block unless `cancel` is set, then reports its size.
+fn compress_block(
+    block: RawBlock,
+    config: &Lzma2Config,
+    on_block_done: &(dyn Fn(u64) + Sync),
+    cancel: Option<&AtomicBool>,
+) -> Result<CompressedBlock> {
+    if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+        return Err(SevenZipError::Cancelled);
+    }
+    let compressed = crate::threading::worker::compress_raw_block(block, config)?;
+    on_block_done(compressed.uncompressed_size);
+    Ok(compressed)
+}
+
+/// Builds a pool of `num_threads` threads (`None` = one per logical CPU),
+/// capped at `num_blocks` and at `max_in_flight`, since threads beyond
+/// either would have nothing to do.
+fn build_pool(
+    num_threads: Option<usize>,
+    num_blocks: usize,
+    max_in_flight: Option<usize>,
+) -> Result<ThreadPool> {
+    let threads = effective_num_threads(num_threads)
+        .min(num_blocks)
+        .min(max_in_flight.unwrap_or(usize::MAX));
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|e| SevenZipError::Threading(format!("failed to build thread pool: {e}")))
+}
+
+/// Builds a pool as [`compress_blocks_parallel`] would. With
+/// `allow_fallback`, a pool that cannot be built, e.g. in a sandbox that
+/// forbids spawning threads, gives `None` instead of an error, for the
+/// caller to compress with [`compress_blocks_sequential`].
+pub fn build_pool_or_fallback(
+    num_threads: Option<usize>,
+    num_blocks: usize,
+    max_in_flight: Option<usize>,
+    allow_fallback: bool,
+) -> Result<Option<ThreadPool>> {
+    fallback_on_error(
+        build_pool(num_threads, num_blocks, max_in_flight),
+        allow_fallback,
+    )
+}
+
+fn fallback_on_error(pool: Result<ThreadPool>, allow_fallback: bool) -> Result<Option<ThreadPool>> {
+    match pool {
+        Ok(pool) => Ok(Some(pool)),
+        Err(SevenZipError::Threading(_)) if allow_fallback => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -46,7 +335,8 @@ mod tests {
             .collect();
 
         let config = Lzma2Config::default();
-        let results = compress_blocks_parallel(blocks, &config, None).unwrap();
+        let results =
+            compress_blocks_parallel(blocks, &|_| &config, None, &|_| {}, None, None).unwrap();
 
         assert_eq!(results.len(), 4);
         for (i, block) in results.iter().enumerate() {
@@ -64,11 +354,384 @@ mod tests {
             .collect();
 
         let config = Lzma2Config::default();
-        let results = compress_blocks_parallel(blocks, &config, Some(2)).unwrap();
+        let results =
+            compress_blocks_parallel(blocks, &|_| &config, Some(2), &|_| {}, None, None).unwrap();
 
         assert_eq!(results.len(), 4);
         for (i, block) in results.iter().enumerate() {
             assert_eq!(block.block_index, i);
         }
     }
+
+    #[test]
+    fn test_compress_parallel_rejects_zero_threads() {
+        let blocks = vec![RawBlock {
+            data: b"data".to_vec(),
+            block_index: 0,
+        }];
+        let config = Lzma2Config::default();
+        let result = compress_blocks_parallel(blocks, &|_| &config, Some(0), &|_| {}, None, None);
+        assert!(matches!(result, Err(SevenZipError::InvalidState(_))));
+    }
+
+    #[test]
+    fn test_compress_parallel_stops_when_cancelled() {
+        let blocks: Vec<RawBlock> = (0..16)
+            .map(|i| RawBlock {
+                data: vec![i as u8; 1000],
+                block_index: i,
+            })
+            .collect();
+        let config = Lzma2Config::default();
+        let cancel = AtomicBool::new(false);
+        let compressed = std::sync::atomic::AtomicUsize::new(0);
+        let on_block_done = |_| {
+            compressed.fetch_add(1, Ordering::Relaxed);
+            cancel.store(true, Ordering::Relaxed);
+        };
+
+        let result = compress_blocks_parallel(
+            blocks,
+            &|_| &config,
+            Some(1),
+            &on_block_done,
+            Some(&cancel),
+            None,
+        );
+        assert!(matches!(result, Err(SevenZipError::Cancelled)));
+        assert_eq!(compressed.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_single_block_compressed_inline() {
+        // A single block skips pool construction. Building a one-thread
+        // rayon pool measured about 35 µs (release build), and the cost
+        // grows with the thread count, while compressing this block takes
+        // a few µs.
+        let data = b"a single small block, compressed on the calling thread".repeat(10);
+        let blocks = vec![RawBlock {
+            data: data.clone(),
+            block_index: 0,
+        }];
+        let caller = std::thread::current().id();
+        let on_block_done = |size| {
+            assert_eq!(size, data.len() as u64);
+            assert_eq!(std::thread::current().id(), caller);
+        };
+
+        let config = Lzma2Config::default();
+        let results =
+            compress_blocks_parallel(blocks, &|_| &config, None, &on_block_done, None, None)
+                .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].block_index, 0);
+        assert_eq!(results[0].uncompressed_size, data.len() as u64);
+        assert_eq!(results[0].uncompressed_crc, Some(crc32fast::hash(&data)));
+        assert!(results[0].compressed_size < data.len() as u64);
+    }
+
+    #[test]
+    fn test_compress_streaming_in_order() {
+        let blocks = || {
+            (0..12)
+                .map(|i| RawBlock {
+                    // Uneven sizes so blocks finish out of order
+                    data: format!("block {i} ")
+                        .repeat(1 + (11 - i) * 500)
+                        .into_bytes(),
+                    block_index: i,
+                })
+                .collect::<Vec<_>>()
+        };
+        let config = Lzma2Config::default();
+        let expected =
+            compress_blocks_parallel(blocks(), &|_| &config, Some(3), &|_| {}, None, None).unwrap();
+
+        let results = compress_blocks_streaming(
+            blocks(),
+            &|_| &config,
+            Some(3),
+            &|_| {},
+            None,
+            None,
+            |iter| iter.collect::<Result<Vec<_>>>(),
+        )
+        .unwrap();
+        assert_eq!(results.len(), expected.len());
+        for (result, expected) in results.iter().zip(&expected) {
+            assert_eq!(result.block_index, expected.block_index);
+            assert_eq!(result.compressed_data, expected.compressed_data);
+        }
+    }
+
+    #[test]
+    fn test_compress_streaming_stops_when_consumer_returns() {
+        let blocks: Vec<RawBlock> = (0..64)
+            .map(|i| RawBlock {
+                data: vec![i as u8; 1000],
+                block_index: i,
+            })
+            .collect();
+        let config = Lzma2Config::default();
+        let compressed = std::sync::atomic::AtomicUsize::new(0);
+        let on_block_done = |_| {
+            compressed.fetch_add(1, Ordering::Relaxed);
+        };
+
+        let first = compress_blocks_streaming(
+            blocks,
+            &|_| &config,
+            Some(2),
+            &on_block_done,
+            None,
+            None,
+            |iter| iter.next().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(first.block_index, 0);
+        // Only the blocks queued ahead of the first one were compressed
+        assert!(compressed.load(Ordering::Relaxed) <= 5);
+    }
+
+    #[test]
+    fn test_compress_on_caller_pool() {
+        let blocks: Vec<RawBlock> = (0..8)
+            .map(|i| RawBlock {
+                data: format!("block {i} content")
+                    .repeat(100 * (8 - i))
+                    .into_bytes(),
+                block_index: i,
+            })
+            .collect();
+        let pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let on_block_done = |_| assert!(pool.current_thread_index().is_some());
+
+        let config = Lzma2Config::default();
+        let results =
+            compress_blocks_parallel_in(&pool, blocks, &|_| &config, &on_block_done, None, None)
+                .unwrap();
+        assert_eq!(results.len(), 8);
+        for (i, block) in results.iter().enumerate() {
+            assert_eq!(block.block_index, i);
+            assert_eq!(
+                block.uncompressed_crc,
+                Some(crc32fast::hash(
+                    format!("block {i} content")
+                        .repeat(100 * (8 - i))
+                        .as_bytes()
+                ))
+            );
+        }
+    }
+
+    #[derive(Debug)]
+    struct FailingCompressor;
+
+    impl crate::BlockCompressor for FailingCompressor {
+        fn compress(&self, _data: &[u8]) -> Result<Vec<u8>> {
+            Err(SevenZipError::Compression("encoder failed".to_string()))
+        }
+
+        fn coder_id(&self) -> &[u8] {
+            &[0x7F]
+        }
+
+        fn properties(&self) -> Vec<u8> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_parallel_results_keep_failed_block() {
+        let blocks: Vec<RawBlock> = (0..6)
+            .map(|i| RawBlock {
+                data: vec![i as u8; 1000],
+                block_index: i,
+            })
+            .collect();
+        let pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let config = Lzma2Config::default();
+        let failing = Lzma2Config {
+            compressor: Some(std::sync::Arc::new(FailingCompressor)),
+            ..Default::default()
+        };
+        let config_of_block = |i| if i == 3 { &failing } else { &config };
+
+        let results = compress_blocks_parallel_results_in(
+            &pool,
+            blocks,
+            &config_of_block,
+            &|_| {},
+            None,
+            Some(2),
+        )
+        .unwrap();
+        // The batch holding block 3 is the last one started
+        assert_eq!(results.len(), 4);
+        for (i, result) in results.iter().enumerate().take(3) {
+            assert_eq!(result.as_ref().unwrap().block_index, i);
+        }
+        assert!(matches!(&results[3], Err(SevenZipError::Compression(_))));
+    }
+
+    #[test]
+    fn test_compress_streaming_from_inside_pool() {
+        // A single pool thread waiting for its own blocks must not deadlock
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let config = Lzma2Config::default();
+        let indices: Vec<usize> = pool.install(|| {
+            let blocks: Vec<RawBlock> = (0..6)
+                .map(|i| RawBlock {
+                    data: vec![i as u8; 1000],
+                    block_index: i,
+                })
+                .collect();
+            compress_blocks_streaming_in(&pool, blocks, &|_| &config, &|_| {}, None, None, |iter| {
+                iter.map(|block| block.map(|b| b.block_index)).collect()
+            })
+            .unwrap()
+        });
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_no_blocks() {
+        let config = Lzma2Config::default();
+        let results =
+            compress_blocks_parallel(Vec::new(), &|_| &config, Some(4), &|_| {}, None, None)
+                .unwrap();
+        assert!(results.is_empty());
+    }
+
+    /// Counts blocks between `config_of_block`, called as a block starts,
+    /// and `on_block_done`, and records the most seen at once.
+    #[derive(Default)]
+    struct Gauge {
+        current: std::sync::atomic::AtomicUsize,
+        peak: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Gauge {
+        fn enter(&self) {
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(current, Ordering::SeqCst);
+        }
+
+        fn leave(&self) {
+            self.current.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn gauge_blocks() -> Vec<RawBlock> {
+        (0..24)
+            .map(|i| RawBlock {
+                data: format!("block {i} ").repeat(2000).into_bytes(),
+                block_index: i,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compress_parallel_max_in_flight() {
+        let config = Lzma2Config::default();
+        let expected =
+            compress_blocks_parallel(gauge_blocks(), &|_| &config, Some(8), &|_| {}, None, None)
+                .unwrap();
+
+        let gauge = Gauge::default();
+        let config_of_block = |_| {
+            gauge.enter();
+            &config
+        };
+        let pool = ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        let results = compress_blocks_parallel_in(
+            &pool,
+            gauge_blocks(),
+            &config_of_block,
+            &|_| gauge.leave(),
+            None,
+            Some(2),
+        )
+        .unwrap();
+        assert!(gauge.peak.load(Ordering::SeqCst) <= 2);
+        assert_eq!(results.len(), expected.len());
+        for (result, expected) in results.iter().zip(&expected) {
+            assert_eq!(result.block_index, expected.block_index);
+            assert_eq!(result.compressed_data, expected.compressed_data);
+        }
+    }
+
+    #[test]
+    fn test_compress_streaming_max_in_flight() {
+        let config = Lzma2Config::default();
+        let gauge = Gauge::default();
+        let config_of_block = |_| {
+            gauge.enter();
+            &config
+        };
+        let pool = ThreadPoolBuilder::new().num_threads(8).build().unwrap();
+        let indices: Vec<usize> = compress_blocks_streaming_in(
+            &pool,
+            gauge_blocks(),
+            &config_of_block,
+            &|_| gauge.leave(),
+            None,
+            Some(3),
+            |iter| iter.map(|block| block.map(|b| b.block_index)).collect(),
+        )
+        .unwrap();
+        assert!(gauge.peak.load(Ordering::SeqCst) <= 3);
+        assert_eq!(indices, (0..24).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_compress_parallel_rejects_zero_in_flight() {
+        let config = Lzma2Config::default();
+        let result =
+            compress_blocks_parallel(gauge_blocks(), &|_| &config, None, &|_| {}, None, Some(0));
+        assert!(matches!(result, Err(SevenZipError::InvalidState(_))));
+    }
+
+    #[test]
+    fn test_pool_build_failure_falls_back() {
+        // No thread can get a stack this large
+        let unbuildable = || {
+            ThreadPoolBuilder::new()
+                .num_threads(2)
+                .stack_size(usize::MAX)
+                .build()
+                .map_err(|e| SevenZipError::Threading(e.to_string()))
+        };
+        assert!(matches!(
+            fallback_on_error(unbuildable(), false),
+            Err(SevenZipError::Threading(_))
+        ));
+        assert!(fallback_on_error(unbuildable(), true).unwrap().is_none());
+        let pool = build_pool_or_fallback(Some(2), 4, None, true).unwrap();
+        assert_eq!(pool.unwrap().current_num_threads(), 2);
+    }
+
+    #[test]
+    fn test_compress_sequential_in_order() {
+        let config = Lzma2Config::default();
+        let done = std::sync::atomic::AtomicU64::new(0);
+        let on_block_done = |bytes| {
+            done.fetch_add(bytes, Ordering::SeqCst);
+        };
+        let blocks: Vec<RawBlock> = (0..3)
+            .map(|i| RawBlock {
+                data: vec![i as u8; 1000],
+                block_index: i,
+            })
+            .collect();
+        let config_of_block = |_| &config;
+        let mut compressed =
+            compress_blocks_sequential(blocks, &config_of_block, &on_block_done, None);
+        assert_eq!(compressed.next().unwrap().unwrap().block_index, 0);
+        // Later blocks are only compressed once reached
+        assert_eq!(done.load(Ordering::SeqCst), 1000);
+        let rest: Vec<usize> = compressed.map(|b| b.unwrap().block_index).collect();
+        assert_eq!(rest, [1, 2]);
+        assert_eq!(done.load(Ordering::SeqCst), 3000);
+    }
 }
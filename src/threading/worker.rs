@@ -1,20 +1,67 @@
-use crate::compression::block::{CompressedBlock, RawBlock};
-use crate::compression::lzma2::{compress_block, Lzma2Config};
+use crate::compression::block::{count_crc_bytes, CompressedBlock, RawBlock};
+use crate::compression::lzma2::{compress_block, Codec, Lzma2Config};
 use crate::error::Result;
+use std::time::Instant;
 
-/// Compresses a single raw block with LZMA2 and computes its CRC32.
+/// Compresses a single raw block with the configured codec and computes
+/// its CRC32, unless `Lzma2Config::block_crc` is off.
+///
+/// If the compressed output is not smaller than the input (and the store
+/// fallback is enabled), the input is kept in `raw_fallback`. LZMA2 blocks
+/// then drop their compressed output, since the folder can hold the raw
+/// data as uncompressed chunks; other codecs keep both.
+///
+/// With `Codec::Copy`, nothing is compressed and the input always goes to
+/// `raw_fallback`. A `Lzma2Config::compressor` takes precedence over the
+/// codec.
 pub fn compress_raw_block(block: RawBlock, config: &Lzma2Config) -> Result<CompressedBlock> {
+    let started = Instant::now();
     let uncompressed_size = block.data.len() as u64;
-    let uncompressed_crc = crc32fast::hash(&block.data);
-    let compressed_data = compress_block(&block.data, config)?;
+    let uncompressed_crc = config.block_crc.then(|| {
+        count_crc_bytes(block.data.len());
+        crc32fast::hash(&block.data)
+    });
+    let compressed_data = if let Some(compressor) = &config.compressor {
+        compressor.compress(&block.data)?
+    } else {
+        match config.codec {
+            #[cfg(feature = "bzip2")]
+            Codec::Bzip2 { level } => {
+                crate::compression::bzip2::compress_block(&block.data, level)?
+            }
+            Codec::Lzma2 | Codec::Lzma1 => compress_block(&block.data, config)?,
+            Codec::Copy => {
+                return Ok(CompressedBlock {
+                    compressed_data: Vec::new(),
+                    uncompressed_size,
+                    compressed_size: uncompressed_size,
+                    uncompressed_crc,
+                    block_index: block.block_index,
+                    raw_fallback: Some(block.data),
+                    compress_time: started.elapsed(),
+                })
+            }
+        }
+    };
     let compressed_size = compressed_data.len() as u64;
 
+    let raw_fallback = (!config.disable_store_fallback && compressed_size >= uncompressed_size)
+        .then_some(block.data);
+    let (compressed_data, compressed_size) =
+        if raw_fallback.is_some() && config.compressor.is_none() && config.codec == Codec::Lzma2 {
+            (Vec::new(), uncompressed_size)
+        } else {
+            (compressed_data, compressed_size)
+        };
+
     Ok(CompressedBlock {
         compressed_data,
         uncompressed_size,
         compressed_size,
         uncompressed_crc,
         block_index: block.block_index,
+        raw_fallback,
+        compress_time: started.elapsed(),
     })
 }
 
@@ -32,7 +79,98 @@ mod tests {
         let result = compress_raw_block(block, &config).unwrap();
         assert_eq!(result.uncompressed_size, 13);
         assert_eq!(result.block_index, 0);
+        // Too short to shrink, so stored as is
+        assert_eq!(result.compressed_size, 13);
+        assert_eq!(result.raw_fallback.as_deref(), Some(&b"Hello, World!"[..]));
+        assert_eq!(
+            result.uncompressed_crc,
+            Some(crc32fast::hash(b"Hello, World!"))
+        );
+        assert!(!result.compress_time.is_zero());
+    }
+
+    #[test]
+    fn test_compress_raw_block_without_crc() {
+        let block = RawBlock {
+            data: b"Hello, World!".to_vec(),
+            block_index: 0,
+        };
+        let config = Lzma2Config {
+            block_crc: false,
+            ..Default::default()
+        };
+        let hashed = crate::compression::block::CRC_BYTES.get();
+        let result = compress_raw_block(block, &config).unwrap();
+        assert_eq!(result.uncompressed_crc, None);
+        assert_eq!(crate::compression::block::CRC_BYTES.get(), hashed);
+    }
+
+    #[test]
+    fn test_compress_raw_block_keeps_incompressible_data() {
+        // A few bytes always grow under LZMA2 framing
+        let block = RawBlock {
+            data: vec![0x5A; 4],
+            block_index: 0,
+        };
+        let result = compress_raw_block(block, &Lzma2Config::default()).unwrap();
+        assert_eq!(result.raw_fallback, Some(vec![0x5A; 4]));
+        // Held once: the LZMA2 output is dropped
+        assert!(result.compressed_data.is_empty());
+
+        let config = Lzma2Config {
+            disable_store_fallback: true,
+            ..Default::default()
+        };
+        let block = RawBlock {
+            data: vec![0x5A; 4],
+            block_index: 0,
+        };
+        let result = compress_raw_block(block, &config).unwrap();
+        assert!(result.raw_fallback.is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "bzip2")]
+    fn test_compress_raw_block_keeps_bzip2_output_of_incompressible_data() {
+        // BZip2 has no uncompressed chunks to fall back to inside a folder
+        let config = Lzma2Config {
+            codec: Codec::Bzip2 { level: 1 },
+            ..Default::default()
+        };
+        let block = RawBlock {
+            data: vec![0x5A; 4],
+            block_index: 0,
+        };
+        let result = compress_raw_block(block, &config).unwrap();
+        assert_eq!(result.raw_fallback, Some(vec![0x5A; 4]));
+        assert!(!result.compressed_data.is_empty());
         assert_eq!(result.compressed_size, result.compressed_data.len() as u64);
-        assert_eq!(result.uncompressed_crc, crc32fast::hash(b"Hello, World!"));
+    }
+
+    #[test]
+    fn test_compress_raw_block_drops_compressible_data() {
+        let block = RawBlock {
+            data: vec![0u8; 10_000],
+            block_index: 0,
+        };
+        let result = compress_raw_block(block, &Lzma2Config::default()).unwrap();
+        assert!(result.raw_fallback.is_none());
+    }
+
+    #[test]
+    fn test_copy_codec_stores_block() {
+        let block = RawBlock {
+            data: vec![0u8; 10_000],
+            block_index: 3,
+        };
+        let config = Lzma2Config {
+            codec: Codec::Copy,
+            disable_store_fallback: true,
+            ..Default::default()
+        };
+        let result = compress_raw_block(block, &config).unwrap();
+        assert_eq!(result.raw_fallback, Some(vec![0u8; 10_000]));
+        assert_eq!(result.compressed_size, 10_000);
+        assert_eq!(result.block_index, 3);
     }
 }
@@ -0,0 +1,34 @@
+//! Splitting owned data must not hold a second copy of it. Checked from
+//! the blocks' allocations: the first block keeps the input's buffer and
+//! no block holds spare capacity.
+
+use sevenzip_mt::compression::block::split_owned_into_blocks;
+
+#[test]
+fn test_split_owned_data_stays_bounded() {
+    const SIZE: usize = 32 << 20;
+    const BLOCK_SIZE: usize = 1 << 20;
+    let data: Vec<u8> = (0..SIZE).map(|i| (i % 251) as u8).collect();
+    let expected_crc = crc32fast::hash(&data);
+    let input_ptr = data.as_ptr();
+
+    let blocks = split_owned_into_blocks(data, BLOCK_SIZE, 0);
+
+    // The first block is the input's allocation, shrunk in place
+    assert_eq!(blocks[0].data.as_ptr(), input_ptr);
+    // Together the blocks hold exactly the input, never a second copy
+    for block in &blocks {
+        assert_eq!(block.data.capacity(), block.data.len());
+    }
+    let held: usize = blocks.iter().map(|b| b.data.capacity()).sum();
+    assert_eq!(held, SIZE);
+
+    assert_eq!(blocks.len(), SIZE / BLOCK_SIZE);
+    let mut hasher = crc32fast::Hasher::new();
+    for (i, block) in blocks.iter().enumerate() {
+        assert_eq!(block.block_index, i);
+        assert_eq!(block.data.len(), BLOCK_SIZE);
+        hasher.update(&block.data);
+    }
+    assert_eq!(hasher.finalize(), expected_crc);
+}
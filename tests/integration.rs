@@ -1,6 +1,11 @@
-use sevenzip_mt::Lzma2Config;
+use sevenzip_mt::{
+    AdaptiveBlockPolicy, AesEncryptConfig, BlockPolicy, Codec, Filter, Lzma2Config, Lzma2Mode,
+    PathNormalization, SevenZipError,
+};
 use sha2::{Digest, Sha256};
 use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::process::Command;
 use tempfile::TempDir;
 
@@ -10,6 +15,36 @@ fn sha256_hex(data: &[u8]) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// Runs `7z` with the given arguments and asserts it succeeds.
+fn run_7z(args: &[&str]) -> String {
+    let output = Command::new("7z")
+        .args(args)
+        .output()
+        .expect("failed to run 7z");
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "7z {args:?} failed:\nstdout: {stdout}\nstderr: {stderr}"
+    );
+    stdout
+}
+
+/// Verifies archive integrity with `7z t`.
+fn test_with_7z(archive_path: &Path) {
+    run_7z(&["t", archive_path.to_str().unwrap()]);
+}
+
+/// Extracts an archive into `extract_dir` with `7z x`.
+fn extract_with_7z(archive_path: &Path, extract_dir: &Path) {
+    run_7z(&[
+        "x",
+        archive_path.to_str().unwrap(),
+        &format!("-o{}", extract_dir.to_str().unwrap()),
+        "-y",
+    ]);
+}
+
 #[test]
 fn test_create_and_extract_single_file() {
     let dir = TempDir::new().unwrap();
@@ -178,6 +213,282 @@ fn test_add_file_from_disk() {
     assert_eq!(sha256_hex(&extracted), content_hash);
 }
 
+#[test]
+fn test_file_mtime_keeps_sub_second_precision() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("precise.txt");
+    fs::write(&path, b"timestamp").unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    archive
+        .add_file(path.to_str().unwrap(), "precise.txt")
+        .unwrap();
+    let bytes = archive.finish_to_vec().unwrap();
+
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    // 100 ns FILETIME ticks: the last two digits of the nanoseconds are lost
+    let expected = (1_700_000_000 + 11_644_473_600) * 10_000_000 + 1_234_567;
+    assert_eq!(reader.entries()[0].modified_time, Some(expected));
+}
+
+#[test]
+fn test_explicit_mtime_overrides_entry_time() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = TempDir::new().unwrap();
+    let disk_path = dir.path().join("on_disk.txt");
+    fs::write(&disk_path, b"file with a wrong mtime").unwrap();
+    let chosen = SystemTime::UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+    let filetime = (1_234_567_890 + 11_644_473_600) * 10_000_000;
+
+    let archive_path = dir.path().join("mtime.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive
+        .add_file_with_mtime(disk_path.to_str().unwrap(), "file.txt", chosen)
+        .unwrap();
+    archive
+        .add_bytes_with_mtime("bytes.txt", b"in memory", chosen)
+        .unwrap();
+    archive.add_bytes("plain.txt", b"no time").unwrap();
+    assert!(matches!(
+        archive.add_bytes_with_mtime(
+            "early.txt",
+            b"",
+            SystemTime::UNIX_EPOCH - Duration::from_secs(1)
+        ),
+        Err(SevenZipError::InvalidState(_))
+    ));
+    archive.finish().unwrap();
+
+    let reader = sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    let mtimes: Vec<(&str, Option<u64>)> = reader
+        .entries()
+        .iter()
+        .map(|e| (e.name.as_str(), e.modified_time))
+        .collect();
+    assert_eq!(
+        mtimes,
+        [
+            ("file.txt", Some(filetime)),
+            ("bytes.txt", Some(filetime)),
+            ("plain.txt", None)
+        ]
+    );
+
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(
+        fs::read(out_dir.join("file.txt")).unwrap(),
+        b"file with a wrong mtime"
+    );
+    assert_eq!(fs::read(out_dir.join("bytes.txt")).unwrap(), b"in memory");
+}
+
+#[test]
+fn test_clock_stamps_entries_without_mtime() {
+    use std::time::{Duration, SystemTime};
+
+    let now = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 500_000_000);
+    let chosen = SystemTime::UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    archive.set_clock(Box::new(move || now));
+    archive
+        .add_bytes_with_mtime("explicit.txt", b"in memory", chosen)
+        .unwrap();
+    archive.add_bytes("plain.txt", b"no time").unwrap();
+    archive.add_directory("dir").unwrap();
+    let bytes = archive.finish_to_vec().unwrap();
+
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    let mtimes: Vec<(&str, Option<u64>)> = reader
+        .entries()
+        .iter()
+        .map(|e| (e.name.as_str(), e.modified_time))
+        .collect();
+    let chosen_filetime = (1_234_567_890 + 11_644_473_600) * 10_000_000;
+    let now_filetime = (1_700_000_000 + 11_644_473_600) * 10_000_000 + 5_000_000;
+    assert_eq!(
+        mtimes,
+        [
+            ("explicit.txt", Some(chosen_filetime)),
+            ("plain.txt", Some(now_filetime)),
+            ("dir", Some(now_filetime)),
+        ]
+    );
+
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    archive.set_clock(Box::new(|| SystemTime::UNIX_EPOCH - Duration::from_secs(1)));
+    archive.add_bytes("plain.txt", b"no time").unwrap();
+    assert!(matches!(
+        archive.finish_to_vec(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn test_mtime_past_filetime_range() {
+    use std::time::{Duration, SystemTime};
+
+    let far = SystemTime::UNIX_EPOCH + Duration::from_secs(1 << 62);
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    assert!(matches!(
+        archive.add_bytes_with_mtime("a", b"x", far),
+        Err(SevenZipError::InvalidState(_))
+    ));
+
+    archive.set_clock(Box::new(move || far));
+    archive.add_bytes("plain.txt", b"no time").unwrap();
+    assert!(matches!(
+        archive.finish_to_vec(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn test_skip_errors_leaves_out_unreadable_files() {
+    let dir = TempDir::new().unwrap();
+    let kept = dir.path().join("kept.txt");
+    let vanished = dir.path().join("vanished.txt");
+    fs::write(&kept, b"still here\n").unwrap();
+
+    let build = |skip_errors: bool| {
+        fs::write(&vanished, b"about to go").unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_skip_errors(skip_errors);
+        archive
+            .add_file(vanished.to_str().unwrap(), "vanished.txt")
+            .unwrap();
+        archive
+            .add_file(kept.to_str().unwrap(), "kept.txt")
+            .unwrap();
+        fs::remove_file(&vanished).unwrap();
+        archive.finish_with_stats()
+    };
+
+    match build(false) {
+        Err(SevenZipError::FileVanished(path)) => assert_eq!(path, vanished.display().to_string()),
+        other => panic!("expected FileVanished, got {other:?}"),
+    }
+
+    let (cursor, stats) = build(true).unwrap();
+    assert_eq!(stats.num_files, 1);
+    assert_eq!(stats.skipped.len(), 1);
+    assert_eq!(stats.skipped[0].0, vanished);
+    assert!(matches!(
+        &stats.skipped[0].1,
+        SevenZipError::FileVanished(_)
+    ));
+
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    assert_eq!(reader.entries().len(), 1);
+    assert_eq!(reader.entries()[0].name, "kept.txt");
+    assert_eq!(reader.read_file(0).unwrap(), b"still here\n");
+}
+
+#[test]
+fn test_archive_comment() {
+    let dir = TempDir::new().unwrap();
+    let comment = "Nightly build 2024-05-01\nBuilt on ünïcödé host\n";
+
+    for name in [None, Some("docs/README-archive.txt")] {
+        let archive_path = dir.path().join("commented.7z");
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.add_bytes("data.txt", b"payload").unwrap();
+        archive.set_comment("replaced");
+        archive.set_comment(comment);
+        if let Some(name) = name {
+            archive.set_comment_name(name);
+        }
+        archive.finish().unwrap();
+
+        let out_dir = dir.path().join(format!("out{}", name.is_some()));
+        extract_with_7z(&archive_path, &out_dir);
+        let comment_name = name.unwrap_or(sevenzip_mt::archive::builder::DEFAULT_COMMENT_NAME);
+        assert_eq!(
+            fs::read_to_string(out_dir.join(comment_name)).unwrap(),
+            comment
+        );
+        assert_eq!(fs::read(out_dir.join("data.txt")).unwrap(), b"payload");
+    }
+
+    // The comment entry's name is claimed like any other
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.add_bytes("notes.txt", b"notes").unwrap();
+    archive.set_comment("comment");
+    archive.set_comment_name("notes.txt");
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::DuplicateEntry(_))
+    ));
+}
+
+#[test]
+fn test_empty_archive() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("nothing.7z");
+
+    let file = fs::File::create(&archive_path).unwrap();
+    sevenzip_mt::SevenZipWriter::new(file)
+        .unwrap()
+        .finish()
+        .unwrap();
+    test_with_7z(&archive_path);
+    let listing = run_7z(&["l", archive_path.to_str().unwrap()]);
+    assert!(listing.contains("0 files"), "{listing}");
+
+    // Entries can be appended to it later
+    let file = fs::File::options()
+        .read(true)
+        .write(true)
+        .open(&archive_path)
+        .unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::open_append(file).unwrap();
+    archive.add_bytes("later.txt", b"added later\n").unwrap();
+    archive.finish().unwrap();
+    let mut reader =
+        sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    assert_eq!(reader.entries().len(), 1);
+    assert_eq!(reader.read_file(0).unwrap(), b"added later\n");
+    test_with_7z(&archive_path);
+}
+
+#[test]
+fn test_error_on_empty() {
+    // Permissive by default
+    let bytes = sevenzip_mt::SevenZipWriter::in_memory()
+        .finish_to_vec()
+        .unwrap();
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    assert!(reader.entries().is_empty());
+
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    archive.set_error_on_empty(true);
+    assert!(matches!(
+        archive.finish_to_vec(),
+        Err(SevenZipError::InvalidState(message)) if message == "no entries"
+    ));
+
+    // An empty directory or file is still an entry
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    archive.set_error_on_empty(true);
+    archive.add_directory("dir").unwrap();
+    let bytes = archive.finish_to_vec().unwrap();
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.entries().len(), 1);
+}
+
 #[test]
 fn test_empty_file_in_archive() {
     let dir = TempDir::new().unwrap();
@@ -284,6 +595,122 @@ fn test_large_file_compression() {
     assert_eq!(extracted.len(), content.len());
 }
 
+#[test]
+fn test_finish_with_stats_reports_totals() {
+    let content: Vec<u8> = (0..1_048_576).map(|i| (i % 251) as u8).collect();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.add_bytes("large.bin", &content).unwrap();
+    archive.add_bytes("small.txt", b"hello\n").unwrap();
+    archive.add_bytes("empty.txt", b"").unwrap();
+    let (cursor, stats) = archive.finish_with_stats().unwrap();
+
+    assert_eq!(stats.total_uncompressed, content.len() as u64 + 6);
+    assert!(
+        stats.total_compressed < stats.total_uncompressed,
+        "compressed {} >= uncompressed {}",
+        stats.total_compressed,
+        stats.total_uncompressed
+    );
+    assert!(stats.total_compressed < cursor.get_ref().len() as u64);
+    assert_eq!(stats.num_files, 2);
+    assert_eq!(stats.num_folders, 2);
+    assert_eq!(stats.archive_size, cursor.get_ref().len() as u64);
+}
+
+#[test]
+fn test_finish_stats_report_archive_size() {
+    // After a prefix, with a recovery record, and when appending nothing
+    let mut output = Cursor::new(b"prefix".to_vec());
+    output.seek(SeekFrom::End(0)).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(output).unwrap();
+    archive.set_recovery_percent(10);
+    archive.add_bytes("a.txt", b"some text\n").unwrap();
+    let (output, stats) = archive.finish_with_stats().unwrap();
+    let len = output.get_ref().len() as u64;
+    assert_eq!(stats.archive_size, len - 6);
+
+    let mut output = Cursor::new(output.into_inner());
+    output.set_position(6);
+    let archive = sevenzip_mt::SevenZipWriter::open_append(output).unwrap();
+    let (_, stats) = archive.finish_with_stats().unwrap();
+    assert_eq!(stats.archive_size, len - 6);
+}
+
+#[test]
+fn test_finish_stats_report_file_times() {
+    for solid in [false, true] {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_solid(solid);
+        for i in 0..8 {
+            let data = format!("file {i} ").repeat(1000 * (i + 1));
+            archive
+                .add_bytes(&format!("file{i}.txt"), data.as_bytes())
+                .unwrap();
+        }
+        archive.add_bytes("empty.txt", b"").unwrap();
+        let (_, stats) = archive.finish_with_stats().unwrap();
+
+        assert_eq!(stats.slowest_files.len(), stats.num_files);
+        assert_eq!(stats.num_files, 8);
+        assert!(stats.slowest_files.iter().all(|(_, time)| !time.is_zero()));
+        assert!(stats
+            .slowest_files
+            .windows(2)
+            .all(|pair| pair[0].1 >= pair[1].1));
+    }
+}
+
+#[test]
+fn test_finish_stats_report_sha256() {
+    let dir = TempDir::new().unwrap();
+    let disk: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    let disk_path = dir.path().join("disk.bin");
+    fs::write(&disk_path, &disk).unwrap();
+    let bytes = b"in memory".repeat(5000);
+    let stream = b"streamed".repeat(3000);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        block_size: Some(16_384), // hashes span several blocks
+        ..Default::default()
+    });
+    archive.set_compute_sha256(true);
+    archive
+        .add_file(disk_path.to_str().unwrap(), "disk.bin")
+        .unwrap();
+    archive.add_bytes("bytes.bin", &bytes).unwrap();
+    archive
+        .add_reader("stream.bin", Box::new(Cursor::new(stream.clone())))
+        .unwrap();
+    archive.add_bytes("empty.txt", b"").unwrap();
+    archive.add_directory("dir").unwrap();
+    let (_, stats) = archive.finish_with_stats().unwrap();
+
+    let reported: Vec<(&str, [u8; 32])> = stats
+        .sha256
+        .iter()
+        .map(|(name, hash)| (name.as_str(), *hash))
+        .collect();
+    let expected: Vec<(&str, [u8; 32])> = [
+        ("disk.bin", &disk[..]),
+        ("bytes.bin", &bytes[..]),
+        ("stream.bin", &stream[..]),
+        ("empty.txt", &[][..]),
+    ]
+    .into_iter()
+    .map(|(name, data)| (name, Sha256::digest(data).into()))
+    .collect();
+    assert_eq!(reported, expected);
+
+    // Off by default
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.add_bytes("bytes.bin", &bytes).unwrap();
+    let (_, stats) = archive.finish_with_stats().unwrap();
+    assert!(stats.sha256.is_empty());
+}
+
 #[test]
 fn test_intra_file_block_splitting() {
     let dir = TempDir::new().unwrap();
@@ -302,6 +729,7 @@ fn test_intra_file_block_splitting() {
         preset: 1,
         dict_size: None,
         block_size: Some(16_384), // 16 KiB blocks
+        ..Default::default()
     });
     archive.add_bytes("split.bin", &content).unwrap();
     archive.finish().unwrap();
@@ -339,3 +767,3187 @@ fn test_intra_file_block_splitting() {
     assert_eq!(sha256_hex(&extracted), content_hash);
     assert_eq!(extracted.len(), content.len());
 }
+
+#[test]
+fn test_intra_file_solid_limit_improves_ratio() {
+    let dir = TempDir::new().unwrap();
+    // A 2 MiB file made of one 256 KiB random chunk repeated: each 256 KiB
+    // block alone is incompressible, the whole file is not.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let chunk: Vec<u8> = (0..256 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let content = chunk.repeat(8);
+    let disk_path = dir.path().join("repeated.bin");
+    fs::write(&disk_path, &content).unwrap();
+
+    let build = |limit: u64| {
+        let archive_path = dir.path().join(format!("solid{limit}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            block_size: Some(256 * 1024),
+            ..Default::default()
+        });
+        archive.set_intra_file_solid_limit(limit);
+        archive
+            .add_file(disk_path.to_str().unwrap(), "file.bin")
+            .unwrap();
+        archive.add_bytes("bytes.bin", &content).unwrap();
+        let (_, stats) = archive.finish_with_stats().unwrap();
+        test_with_7z(&archive_path);
+        stats.total_compressed
+    };
+
+    let split = build(0);
+    let whole = build(4 << 20);
+    assert!(split > 2 * content.len() as u64 * 9 / 10, "split: {split}");
+    assert!(whole < split / 4, "whole: {whole}, split: {split}");
+    // Files above the limit are still split
+    assert_eq!(build(1 << 20), split);
+}
+
+/// Reader yielding its data in short, irregularly sized reads.
+struct OddChunkReader {
+    data: Vec<u8>,
+    position: usize,
+    reads: usize,
+}
+
+impl Read for OddChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads += 1;
+        let chunk = [1, 7, 4093, 13, 65_537, 2][self.reads % 6];
+        let len = chunk.min(buf.len()).min(self.data.len() - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[test]
+fn test_add_bytes_owned_matches_add_bytes() {
+    let small = b"small single-block entry".to_vec();
+    let large: Vec<u8> = (0..500_000u32).map(|i| (i % 253) as u8).collect();
+
+    let build = |owned: bool| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            // Splits the large entry into several blocks
+            block_size: Some(128 * 1024),
+            ..Default::default()
+        });
+        for (name, data) in [("small.txt", &small), ("large.bin", &large)] {
+            if owned {
+                archive.add_bytes_owned(name, data.clone()).unwrap();
+            } else {
+                archive.add_bytes(name, data).unwrap();
+            }
+        }
+        archive.finish().unwrap().into_inner()
+    };
+
+    let owned = build(true);
+    assert_eq!(owned, build(false));
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("owned.7z");
+    fs::write(&archive_path, &owned).unwrap();
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("small.txt")).unwrap(), small);
+    assert_eq!(fs::read(out_dir.join("large.bin")).unwrap(), large);
+}
+
+#[test]
+fn test_add_entries_and_add_files() {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    let dir = TempDir::new().unwrap();
+    let disk_files: Vec<(PathBuf, String)> = (0..3)
+        .map(|i| {
+            let path = dir.path().join(format!("disk_{i}.txt"));
+            fs::write(&path, format!("disk file {i}")).unwrap();
+            (path, format!("files/{i}.txt"))
+        })
+        .collect();
+    let ordered: Vec<(String, Vec<u8>)> = (0..3)
+        .map(|i| (format!("ordered/{i}.txt"), vec![b'o'; i + 1]))
+        .collect();
+    let unordered: HashMap<String, Vec<u8>> = (0..50)
+        .map(|i| (format!("map/{i:02}.bin"), vec![i as u8; i]))
+        .collect();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    archive.add_entries(ordered.clone()).unwrap();
+    archive.add_entries(unordered.clone()).unwrap();
+    archive.add_files(disk_files).unwrap();
+    let bytes = archive.finish_to_vec().unwrap();
+
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.entries().len(), 3 + 50 + 3);
+    // A Vec keeps its order
+    let names: Vec<&str> = reader.entries()[..3]
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect();
+    assert_eq!(names, ["ordered/0.txt", "ordered/1.txt", "ordered/2.txt"]);
+    let mut contents = HashMap::new();
+    for index in 0..reader.entries().len() {
+        let name = reader.entries()[index].name.clone();
+        contents.insert(name, reader.read_file(index).unwrap());
+    }
+    let expected = ordered.iter().map(|(name, data)| (name, data));
+    for (name, data) in expected.chain(&unordered) {
+        assert_eq!(&contents[name], data, "{name}");
+    }
+    for i in 0..3 {
+        assert_eq!(
+            contents[&format!("files/{i}.txt")],
+            format!("disk file {i}").into_bytes()
+        );
+    }
+
+    // A name repeated within a batch is rejected like any duplicate
+    let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+    let repeated = vec![
+        ("a.txt".to_string(), b"first".to_vec()),
+        ("a.txt".to_string(), b"second".to_vec()),
+    ];
+    assert!(matches!(
+        archive.add_entries(repeated),
+        Err(SevenZipError::DuplicateEntry(name)) if name == "a.txt"
+    ));
+}
+
+#[test]
+fn test_archive_names_are_normalized_when_queued() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("names.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.add_bytes(r"C:\a\b.txt", b"drive").unwrap();
+    archive.add_bytes("./a/../c.txt", b"dots").unwrap();
+    archive.add_bytes("/etc/passwd", b"absolute").unwrap();
+    assert!(archive.add_bytes("../escape.txt", b"x").is_err());
+    archive.finish().unwrap();
+
+    let reader = sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["a/b.txt", "c.txt", "etc/passwd"]);
+
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("a/b.txt")).unwrap(), b"drive");
+    assert_eq!(fs::read(out_dir.join("c.txt")).unwrap(), b"dots");
+    assert_eq!(fs::read(out_dir.join("etc/passwd")).unwrap(), b"absolute");
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_path_normalization(PathNormalization {
+        reject_absolute: true,
+        ..Default::default()
+    });
+    assert!(matches!(
+        archive.add_bytes("/etc/passwd", b"absolute"),
+        Err(SevenZipError::InvalidState(_))
+    ));
+    // Still absolute once the drive letter is stripped
+    assert!(archive.add_bytes(r"C:\a\b.txt", b"drive").is_err());
+    archive.add_bytes(r"a\b.txt", b"relative").unwrap();
+}
+
+#[test]
+fn test_finish_and_sync() {
+    let dir = TempDir::new().unwrap();
+    let data: Vec<u8> = (0..500_000u32).map(|i| (i % 97) as u8).collect();
+    for explicit in [false, true] {
+        let archive_path = dir.path().join(format!("synced{explicit}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.add_bytes("data.bin", &data).unwrap();
+        let mut file = if explicit {
+            archive.set_sync_on_finish(true);
+            archive.finish().unwrap()
+        } else {
+            archive.finish_and_sync().unwrap()
+        };
+
+        // Everything written is on disk and the writer sits at the end
+        let on_disk = fs::read(&archive_path).unwrap();
+        assert_eq!(file.stream_position().unwrap(), on_disk.len() as u64);
+        let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(on_disk)).unwrap();
+        assert_eq!(reader.read_file(0).unwrap(), data);
+        test_with_7z(&archive_path);
+    }
+}
+
+/// Seekable in-memory output counting the write calls it receives.
+struct WriteCounter {
+    inner: Cursor<Vec<u8>>,
+    writes: usize,
+}
+
+impl Write for WriteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes += 1;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for WriteCounter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_write_buffer_size() {
+    let build = |buffer_size: usize| {
+        let output = WriteCounter {
+            inner: Cursor::new(Vec::new()),
+            writes: 0,
+        };
+        let mut archive = sevenzip_mt::SevenZipWriter::new(output).unwrap();
+        archive.set_write_buffer_size(buffer_size);
+        archive.set_recovery_percent(5);
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            block_size: Some(1 << 16),
+            ..Default::default()
+        });
+        for i in 0..300 {
+            archive
+                .add_bytes(
+                    &format!("file{i}.txt"),
+                    format!("small file {i}").as_bytes(),
+                )
+                .unwrap();
+        }
+        let large: Vec<u8> = (0..1_000_000u32).map(|i| (i * 31 / 7) as u8).collect();
+        archive.add_bytes("large.bin", &large).unwrap();
+        let output = archive.finish().unwrap();
+        (output.inner.into_inner(), output.writes)
+    };
+
+    let (unbuffered, unbuffered_writes) = build(0);
+    let (buffered, buffered_writes) =
+        build(sevenzip_mt::archive::builder::DEFAULT_WRITE_BUFFER_SIZE);
+    assert_eq!(buffered, unbuffered);
+    assert!(
+        buffered_writes * 10 < unbuffered_writes,
+        "{buffered_writes} buffered writes, {unbuffered_writes} unbuffered"
+    );
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(buffered)).unwrap();
+    assert_eq!(reader.verify().unwrap(), 301);
+}
+
+#[test]
+fn test_streaming_output_matches_batch() {
+    let build = |streaming: bool, solid: bool| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            block_size: Some(64 * 1024),
+            ..Default::default()
+        });
+        archive.set_num_threads(Some(4));
+        archive.set_streaming_output(streaming);
+        archive.set_solid(solid);
+        for i in 0..200 {
+            let data = format!("file {i}: {}\n", "payload ".repeat(i % 37)).repeat(1 + i % 5);
+            archive
+                .add_bytes(&format!("dir{}/file{i}.txt", i % 7), data.as_bytes())
+                .unwrap();
+        }
+        // Some multi-block files, and an empty one
+        for i in 0..3 {
+            let data: Vec<u8> = (0..300_000u32).map(|j| (j * (i + 3) / 7) as u8).collect();
+            archive.add_bytes(&format!("large{i}.bin"), &data).unwrap();
+        }
+        archive.add_bytes("empty.txt", b"").unwrap();
+        archive.finish().unwrap().into_inner()
+    };
+
+    for solid in [false, true] {
+        let batch = build(false, solid);
+        assert_eq!(build(true, solid), batch, "solid: {solid}");
+    }
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("streaming.7z");
+    fs::write(&archive_path, build(true, false)).unwrap();
+    test_with_7z(&archive_path);
+}
+
+/// A forward-only output, like a pipe: `Write` without `Seek`.
+struct NoSeek(Vec<u8>);
+
+impl Write for NoSeek {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_new_streaming_writes_to_non_seekable_output() {
+    let large: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+    let mut archive = sevenzip_mt::SevenZipWriter::new_streaming(NoSeek(Vec::new())).unwrap();
+    archive.add_bytes("hello.txt", b"Hello, pipe!").unwrap();
+    archive.add_bytes("large.bin", &large).unwrap();
+    let output = archive.finish_streaming().unwrap();
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("piped.7z");
+    fs::write(&archive_path, &output.0).unwrap();
+    test_with_7z(&archive_path);
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(
+        fs::read(out_dir.join("hello.txt")).unwrap(),
+        b"Hello, pipe!"
+    );
+    assert_eq!(fs::read(out_dir.join("large.bin")).unwrap(), large);
+
+    // Spilling to a temporary file produces the same bytes
+    let spool = sevenzip_mt::SpoolWriter::with_memory_limit(NoSeek(Vec::new()), 64);
+    let mut archive = sevenzip_mt::SevenZipWriter::new(spool).unwrap();
+    archive.add_bytes("hello.txt", b"Hello, pipe!").unwrap();
+    archive.add_bytes("large.bin", &large).unwrap();
+    let spool = archive.finish().unwrap();
+    assert!(spool.is_spilled());
+    assert_eq!(spool.into_inner().unwrap().0, output.0);
+}
+
+#[test]
+fn test_caller_thread_pool() {
+    let pool = std::sync::Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap(),
+    );
+    let large: Vec<u8> = (0..400_000u32).map(|i| (i * 7 / 13) as u8).collect();
+    let on_pool = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let build = |pool: Option<&std::sync::Arc<rayon::ThreadPool>>, streaming: bool| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            block_size: Some(64 * 1024),
+            ..Default::default()
+        });
+        if let Some(pool) = pool {
+            archive.set_thread_pool(pool);
+            // Blocks are compressed on the caller's pool
+            let on_pool = std::sync::Arc::clone(&on_pool);
+            let pool = std::sync::Arc::clone(pool);
+            archive.set_progress_callback(Box::new(move |_| {
+                if pool.current_thread_index().is_some() {
+                    on_pool.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+            }));
+        }
+        archive.set_streaming_output(streaming);
+        archive.add_bytes("large.bin", &large).unwrap();
+        for i in 0..10 {
+            archive
+                .add_bytes(
+                    &format!("small{i}.txt"),
+                    format!("small file {i}").as_bytes(),
+                )
+                .unwrap();
+        }
+        archive.finish().unwrap().into_inner()
+    };
+
+    let expected = build(None, false);
+    assert_eq!(build(Some(&pool), false), expected);
+    assert_eq!(build(Some(&pool), true), expected);
+    // Two runs of 17 blocks each (7 for the large file, 10 small files)
+    assert_eq!(on_pool.load(std::sync::atomic::Ordering::Relaxed), 34);
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("pool.7z");
+    fs::write(&archive_path, build(Some(&pool), false)).unwrap();
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("large.bin")).unwrap(), large);
+}
+
+#[test]
+fn test_adaptive_block_policy() {
+    let pool = std::sync::Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap(),
+    );
+    let content: Vec<u8> = (0..400_000u32).map(|i| (i * 7 / 13) as u8).collect();
+    let blocks = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("adaptive.7z");
+    let mut archive =
+        sevenzip_mt::SevenZipWriter::new(fs::File::create(&archive_path).unwrap()).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        block_policy: Some(BlockPolicy::Adaptive(AdaptiveBlockPolicy {
+            min_block_size: 16 * 1024,
+            max_block_size: 1 << 20,
+        })),
+        ..Default::default()
+    });
+    archive.set_thread_pool(&pool);
+    // Called once per block compressed on the pool
+    let counter = std::sync::Arc::clone(&blocks);
+    let callback_pool = std::sync::Arc::clone(&pool);
+    archive.set_progress_callback(Box::new(move |_| {
+        if callback_pool.current_thread_index().is_some() {
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }));
+    archive.add_bytes("data.bin", &content).unwrap();
+    archive.finish().unwrap();
+
+    // 4 blocks per thread of 50 000 bytes, rather than one 1 MiB block
+    assert_eq!(blocks.load(std::sync::atomic::Ordering::Relaxed), 8);
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("data.bin")).unwrap(), content);
+}
+
+#[test]
+fn test_duplicate_names_are_rejected() {
+    let dir = TempDir::new().unwrap();
+    let disk_file = dir.path().join("x.txt");
+    fs::write(&disk_file, b"disk").unwrap();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.add_bytes("x.txt", b"first").unwrap();
+    // Names are compared after normalization
+    assert!(matches!(
+        archive.add_bytes("./x.txt", b"second"),
+        Err(SevenZipError::DuplicateEntry(name)) if name == "x.txt"
+    ));
+    assert!(matches!(
+        archive.add_file(disk_file.to_str().unwrap(), "x.txt"),
+        Err(SevenZipError::DuplicateEntry(_))
+    ));
+    archive.add_directory("dir").unwrap();
+    assert!(matches!(
+        archive.add_directory("dir/"),
+        Err(SevenZipError::DuplicateEntry(_))
+    ));
+    assert_eq!(archive.scan().unwrap().entries.len(), 2);
+}
+
+#[test]
+fn test_empty_files_and_directories_keep_their_kind() {
+    let dir = TempDir::new().unwrap();
+    for solid in [false, true] {
+        let archive_path = dir.path().join(format!("empty_kinds_{solid}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.add_bytes("empty.txt", b"").unwrap();
+        archive.add_directory("empty_dir").unwrap();
+        archive.add_bytes("data.txt", b"some data").unwrap();
+        archive.add_directory("parent/empty_child").unwrap();
+        archive.add_bytes("parent/zero.bin", b"").unwrap();
+        archive.finish().unwrap();
+
+        let reader =
+            sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+        let kinds: Vec<(&str, bool, bool)> = reader
+            .entries()
+            .iter()
+            .map(|e| (e.name.as_str(), e.has_data, e.is_directory))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("data.txt", true, false),
+                ("empty.txt", false, false),
+                ("empty_dir", false, true),
+                ("parent/empty_child", false, true),
+                ("parent/zero.bin", false, false),
+            ]
+        );
+
+        let out_dir = dir.path().join(format!("out_{solid}"));
+        extract_with_7z(&archive_path, &out_dir);
+        for file in ["empty.txt", "parent/zero.bin"] {
+            let metadata = fs::metadata(out_dir.join(file)).unwrap();
+            assert!(metadata.is_file() && metadata.len() == 0, "{file}");
+        }
+        for directory in ["empty_dir", "parent/empty_child"] {
+            assert!(out_dir.join(directory).is_dir(), "{directory}");
+        }
+        assert_eq!(fs::read(out_dir.join("data.txt")).unwrap(), b"some data");
+    }
+}
+
+#[test]
+fn test_duplicate_names_allowed_when_opted_in() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_allow_duplicates(true);
+    archive.add_bytes("x.txt", b"first").unwrap();
+    archive.add_bytes("x.txt", b"second").unwrap();
+    let cursor = archive.finish().unwrap();
+
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["x.txt", "x.txt"]);
+}
+
+#[test]
+fn test_add_reader_streams_until_eof() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("streams.7z");
+    let extract_dir = dir.path().join("extracted");
+
+    let cursor_content = pseudo_random_bytes(40_000, 11);
+    let odd_content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        block_size: Some(30_000), // block boundaries fall mid-read
+        ..Default::default()
+    });
+    archive
+        .add_reader("cursor.bin", Box::new(Cursor::new(cursor_content.clone())))
+        .unwrap();
+    archive
+        .add_reader(
+            "odd.bin",
+            Box::new(OddChunkReader {
+                data: odd_content.clone(),
+                position: 0,
+                reads: 0,
+            }),
+        )
+        .unwrap();
+    archive
+        .add_reader("empty.bin", Box::new(std::io::empty()))
+        .unwrap();
+
+    let report = archive.scan().unwrap();
+    assert_eq!(report.unknown_size_count, 3);
+    archive.finish().unwrap();
+
+    test_with_7z(&archive_path);
+    extract_with_7z(&archive_path, &extract_dir);
+    assert_eq!(
+        fs::read(extract_dir.join("cursor.bin")).unwrap(),
+        cursor_content
+    );
+    assert_eq!(fs::read(extract_dir.join("odd.bin")).unwrap(), odd_content);
+    assert!(fs::read(extract_dir.join("empty.bin")).unwrap().is_empty());
+}
+
+#[test]
+fn test_add_reader_expecting_crc_match() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("reader.7z");
+    let extract_dir = dir.path().join("extracted");
+
+    let content: Vec<u8> = (0..50_000).map(|i| (i % 97) as u8).collect();
+    let expected_crc = crc32fast::hash(&content);
+
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        dict_size: None,
+        block_size: Some(16_384), // force several blocks
+        ..Default::default()
+    });
+    archive
+        .add_reader_expecting_crc(
+            "stream.bin",
+            Box::new(Cursor::new(content.clone())),
+            expected_crc,
+        )
+        .unwrap();
+    archive.finish().unwrap();
+
+    test_with_7z(&archive_path);
+    extract_with_7z(&archive_path, &extract_dir);
+
+    let extracted = fs::read(extract_dir.join("stream.bin")).unwrap();
+    assert_eq!(extracted, content);
+}
+
+#[test]
+fn test_add_reader_expecting_crc_mismatch() {
+    let content = b"bytes that were corrupted in transit".to_vec();
+    let declared_crc = crc32fast::hash(b"bytes that were expected");
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive
+        .add_reader_expecting_crc(
+            "stream.bin",
+            Box::new(Cursor::new(content.clone())),
+            declared_crc,
+        )
+        .unwrap();
+
+    match archive.finish() {
+        Err(SevenZipError::CrcMismatch {
+            name,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(name, "stream.bin");
+            assert_eq!(expected, declared_crc);
+            assert_eq!(actual, crc32fast::hash(&content));
+        }
+        Err(e) => panic!("expected CrcMismatch, got {e}"),
+        Ok(_) => panic!("expected CrcMismatch, archive was written"),
+    }
+}
+
+#[test]
+fn test_multi_archive_bundle() {
+    let dir = TempDir::new().unwrap();
+    let bundle_path = dir.path().join("bundle.bin");
+
+    let contents: [(&str, Vec<u8>); 2] = [
+        ("first.txt", b"First sub-archive content".to_vec()),
+        ("second.bin", (0..200_000).map(|i| (i % 13) as u8).collect()),
+    ];
+
+    let file = fs::File::create(&bundle_path).unwrap();
+    let mut bundle = sevenzip_mt::MultiArchiveWriter::new(file);
+    for (name, data) in &contents {
+        let mut archive = bundle.begin_archive().unwrap();
+        archive.add_bytes(name, data).unwrap();
+        archive.finish().unwrap();
+    }
+    bundle.finish().unwrap();
+
+    let mut file = fs::File::open(&bundle_path).unwrap();
+    let offsets = sevenzip_mt::read_multi_archive_index(&mut file).unwrap();
+    assert_eq!(offsets.len(), 2);
+
+    // Each sub-archive spans from its offset to the next one; the last ends
+    // where the index begins.
+    let bytes = fs::read(&bundle_path).unwrap();
+    let index_start = bytes.len() - (offsets.len() * 8 + 16);
+    for (i, (name, data)) in contents.iter().enumerate() {
+        let start = offsets[i] as usize;
+        let end = offsets.get(i + 1).map_or(index_start, |&o| o as usize);
+
+        let sub_path = dir.path().join(format!("sub{i}.7z"));
+        let extract_dir = dir.path().join(format!("extracted{i}"));
+        fs::write(&sub_path, &bytes[start..end]).unwrap();
+
+        test_with_7z(&sub_path);
+        extract_with_7z(&sub_path, &extract_dir);
+        assert_eq!(fs::read(extract_dir.join(name)).unwrap(), *data);
+    }
+}
+
+#[test]
+fn test_add_path_recursive() {
+    let dir = TempDir::new().unwrap();
+    let source = dir.path().join("source");
+    let extract_dir = dir.path().join("extracted");
+
+    fs::create_dir_all(source.join("a/b")).unwrap();
+    fs::create_dir_all(source.join("a/empty")).unwrap();
+    fs::write(source.join("a/b/c.txt"), b"nested file content").unwrap();
+    fs::write(source.join("a/top.txt"), b"top-level file").unwrap();
+    fs::write(source.join("a/zero.txt"), b"").unwrap();
+
+    let build = |archive_path: &Path| {
+        let file = fs::File::create(archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        let skipped = archive.add_path_recursive(&source, "root").unwrap();
+        assert!(skipped.is_empty(), "unexpected skipped paths: {skipped:?}");
+        archive.finish().unwrap();
+    };
+
+    let archive_path = dir.path().join("tree.7z");
+    build(&archive_path);
+
+    test_with_7z(&archive_path);
+    extract_with_7z(&archive_path, &extract_dir);
+
+    let root = extract_dir.join("root");
+    assert_eq!(
+        fs::read(root.join("a/b/c.txt")).unwrap(),
+        b"nested file content"
+    );
+    assert_eq!(fs::read(root.join("a/top.txt")).unwrap(), b"top-level file");
+    assert!(fs::read(root.join("a/zero.txt")).unwrap().is_empty());
+    assert!(root.join("a/empty").is_dir());
+
+    // Name-sorted traversal makes the layout stable across runs.
+    let second_path = dir.path().join("tree2.7z");
+    build(&second_path);
+    assert_eq!(
+        fs::read(&archive_path).unwrap(),
+        fs::read(&second_path).unwrap()
+    );
+}
+
+#[test]
+fn test_scan_reports_queued_entries() {
+    let dir = TempDir::new().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("one.txt"), vec![b'1'; 100]).unwrap();
+    fs::write(tree.join("sub/two.txt"), vec![b'2'; 250]).unwrap();
+    let loose = dir.path().join("loose.bin");
+    fs::write(&loose, vec![0u8; 4096]).unwrap();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive
+        .add_file(loose.to_str().unwrap(), "loose.bin")
+        .unwrap();
+    archive.add_bytes("memory.txt", b"in memory").unwrap();
+    archive.add_path_recursive(&tree, "tree").unwrap();
+
+    let report = archive.scan().unwrap();
+
+    let names: Vec<&str> = report
+        .entries
+        .iter()
+        .map(|e| e.archive_name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "loose.bin",
+            "memory.txt",
+            "tree",
+            "tree/one.txt",
+            "tree/sub",
+            "tree/sub/two.txt"
+        ]
+    );
+    assert_eq!(report.file_count, 4);
+    assert_eq!(report.directory_count, 2);
+    assert_eq!(report.total_bytes, 4096 + 9 + 100 + 250);
+    assert_eq!(report.unknown_size_count, 0);
+
+    // Scanning does not consume the queue.
+    archive.finish().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_scan_flags_sparse_files() {
+    let dir = TempDir::new().unwrap();
+    let sparse = dir.path().join("sparse.img");
+    let file = fs::File::create(&sparse).unwrap();
+    // 8 MiB hole followed by a single written byte
+    file.set_len(8 << 20).unwrap();
+    {
+        use std::io::{Seek, SeekFrom, Write};
+        let mut file = file;
+        file.seek(SeekFrom::End(-1)).unwrap();
+        file.write_all(b"x").unwrap();
+    }
+    let dense = dir.path().join("dense.bin");
+    fs::write(&dense, vec![7u8; 64 * 1024]).unwrap();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive
+        .add_file(sparse.to_str().unwrap(), "sparse.img")
+        .unwrap();
+    archive
+        .add_file(dense.to_str().unwrap(), "dense.bin")
+        .unwrap();
+    archive.add_bytes("memory.bin", &[0u8; 1024]).unwrap();
+
+    let report = archive.scan().unwrap();
+    let flags: Vec<bool> = report.entries.iter().map(|e| e.is_sparse).collect();
+    assert_eq!(flags, [true, false, false]);
+    assert_eq!(report.sparse_count, 1);
+
+    // Sparse files are still archived, as dense data
+    archive.finish().unwrap();
+}
+
+#[test]
+fn test_solid_mode_improves_ratio_for_small_files() {
+    let dir = TempDir::new().unwrap();
+    let extract_dir = dir.path().join("extracted");
+
+    // Many small, similar text files: each compresses poorly on its own but
+    // they share most of their vocabulary.
+    let files: Vec<(String, Vec<u8>)> = (0..200)
+        .map(|i| {
+            let text = format!(
+                "Record {i}: the quick brown fox jumps over the lazy dog. \
+                 Status=OK, owner=build-system, checksum-policy=strict.\n"
+            );
+            (format!("records/record_{i:03}.txt"), text.into_bytes())
+        })
+        .collect();
+
+    let build = |path: &Path, solid: bool| {
+        let file = fs::File::create(path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        for (name, data) in &files {
+            archive.add_bytes(name, data).unwrap();
+        }
+        archive.add_bytes("records/empty.txt", b"").unwrap();
+        archive.finish().unwrap();
+        fs::metadata(path).unwrap().len()
+    };
+
+    let solid_path = dir.path().join("solid.7z");
+    let solid_size = build(&solid_path, true);
+    let per_file_size = build(&dir.path().join("per_file.7z"), false);
+    assert!(
+        solid_size * 3 < per_file_size,
+        "solid archive ({solid_size}) should be much smaller than per-file ({per_file_size})"
+    );
+
+    test_with_7z(&solid_path);
+    extract_with_7z(&solid_path, &extract_dir);
+    for (name, data) in &files {
+        assert_eq!(fs::read(extract_dir.join(name)).unwrap(), *data, "{name}");
+    }
+    assert!(fs::read(extract_dir.join("records/empty.txt"))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_solid_mode_matches_across_files() {
+    let dir = TempDir::new().unwrap();
+    let extract_dir = dir.path().join("extracted");
+
+    // Two large, nearly-identical files of incompressible data: only a
+    // dictionary shared across the file boundary can remove the redundancy.
+    let first = pseudo_random_bytes(1 << 20, 0xD1C7);
+    let mut second = first.clone();
+    for i in (0..second.len()).step_by(65_536) {
+        second[i] ^= 0xFF;
+    }
+    let config = Lzma2Config {
+        preset: 1,
+        dict_size: Some(4 << 20),
+        ..Default::default()
+    };
+
+    let build = |path: &Path, entries: &[(&str, &[u8])], solid: bool| {
+        let file = fs::File::create(path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_config(config.clone());
+        archive.set_solid(solid);
+        for (name, data) in entries {
+            archive.add_bytes(name, data).unwrap();
+        }
+        archive.finish().unwrap();
+        fs::metadata(path).unwrap().len()
+    };
+
+    let solid_path = dir.path().join("solid.7z");
+    let entries: [(&str, &[u8]); 2] = [("first.bin", &first), ("second.bin", &second)];
+    let solid_size = build(&solid_path, &entries, true);
+    let independent_size = build(&dir.path().join("a.7z"), &entries[..1], false)
+        + build(&dir.path().join("b.7z"), &entries[1..], false);
+    assert!(
+        solid_size * 10 < independent_size * 6,
+        "solid archive ({solid_size}) should be much smaller than independent ({independent_size})"
+    );
+
+    test_with_7z(&solid_path);
+    extract_with_7z(&solid_path, &extract_dir);
+    assert_eq!(fs::read(extract_dir.join("first.bin")).unwrap(), first);
+    assert_eq!(fs::read(extract_dir.join("second.bin")).unwrap(), second);
+}
+
+#[test]
+fn test_zero_threads_is_rejected() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_num_threads(Some(0));
+    archive.add_bytes("a.txt", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+/// Deterministic pseudo-random bytes (xorshift64), effectively incompressible.
+fn pseudo_random_bytes(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed.max(1);
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_incompressible_data_is_stored() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("stored.7z");
+    let extract_dir = dir.path().join("extracted");
+
+    let content = pseudo_random_bytes(300_000, 0x5EED);
+
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_config(Lzma2Config {
+        block_size: Some(65_536),
+        ..Default::default()
+    });
+    archive.add_bytes("random.bin", &content).unwrap();
+    archive.add_bytes("text.txt", &[b'a'; 10_000]).unwrap();
+    archive.finish().unwrap();
+
+    // Copy coder: packed size equals the input, plus a small header
+    let archive_size = fs::metadata(&archive_path).unwrap().len();
+    let overhead = archive_size - content.len() as u64;
+    assert!(overhead < 512, "unexpected overhead: {overhead} bytes");
+
+    test_with_7z(&archive_path);
+    extract_with_7z(&archive_path, &extract_dir);
+    assert_eq!(fs::read(extract_dir.join("random.bin")).unwrap(), content);
+    assert_eq!(
+        fs::read(extract_dir.join("text.txt")).unwrap(),
+        [b'a'; 10_000]
+    );
+}
+
+#[test]
+fn test_store_fallback_can_be_disabled() {
+    let content = pseudo_random_bytes(100_000, 42);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        disable_store_fallback: true,
+        ..Default::default()
+    });
+    archive.add_bytes("random.bin", &content).unwrap();
+    let bytes = archive.finish().unwrap().into_inner();
+
+    // Pure LZMA2 framing makes random data slightly larger than the input
+    assert!(bytes.len() > content.len() + 32);
+}
+
+#[test]
+fn test_folder_mixing_stored_and_compressed_blocks_roundtrip() {
+    // The random blocks are kept raw, but the folder as a whole compresses,
+    // so they must be written inside the folder's own codec stream.
+    let random = pseudo_random_bytes(200_000, 7);
+    let text = "mixed blocks in one folder\n".repeat(10_000);
+
+    let codecs = [
+        Codec::Lzma2,
+        #[cfg(feature = "bzip2")]
+        Codec::Bzip2 { level: 1 },
+    ];
+
+    for codec in codecs {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_solid(true);
+        archive.set_config(Lzma2Config {
+            block_size: Some(65_536),
+            codec,
+            ..Default::default()
+        });
+        archive.add_bytes("random.bin", &random).unwrap();
+        archive.add_bytes("text.txt", text.as_bytes()).unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+        assert!(bytes.len() < random.len() + text.len() / 2);
+
+        let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.verify().unwrap(), 2);
+        assert_eq!(reader.read_file(0).unwrap(), random);
+        assert_eq!(reader.read_file(1).unwrap(), text.as_bytes());
+    }
+}
+
+#[test]
+fn test_encrypted_archive_roundtrip() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("encrypted.7z");
+    let extract_dir = dir.path().join("extracted");
+
+    let text = b"Top secret, compressible text. ".repeat(2_000);
+    let random = pseudo_random_bytes(50_000, 7);
+
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_encryption(AesEncryptConfig::new("correct horse"));
+    archive.add_bytes("secret.txt", &text).unwrap();
+    archive.add_bytes("random.bin", &random).unwrap();
+    archive.add_bytes("empty.txt", b"").unwrap();
+    archive.finish().unwrap();
+
+    // The plaintext must not appear in the archive
+    let archive_bytes = fs::read(&archive_path).unwrap();
+    assert!(!archive_bytes
+        .windows(10)
+        .any(|w| w == b"Top secret".as_slice()));
+
+    run_7z(&["t", archive_path.to_str().unwrap(), "-pcorrect horse"]);
+    run_7z(&[
+        "x",
+        archive_path.to_str().unwrap(),
+        &format!("-o{}", extract_dir.to_str().unwrap()),
+        "-pcorrect horse",
+        "-y",
+    ]);
+    assert_eq!(fs::read(extract_dir.join("secret.txt")).unwrap(), text);
+    assert_eq!(fs::read(extract_dir.join("random.bin")).unwrap(), random);
+    assert!(fs::read(extract_dir.join("empty.txt")).unwrap().is_empty());
+
+    let wrong_password = Command::new("7z")
+        .args(["t", archive_path.to_str().unwrap(), "-pwrong"])
+        .output()
+        .expect("failed to run 7z");
+    assert!(!wrong_password.status.success());
+}
+
+#[test]
+fn test_recovery_record_repairs_corruption() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("protected.7z");
+    let repaired_path = dir.path().join("repaired.7z");
+    let extract_dir = dir.path().join("extracted");
+
+    let content = pseudo_random_bytes(200_000, 11);
+
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_recovery_percent(10);
+    archive.add_bytes("data.bin", &content).unwrap();
+    archive
+        .add_bytes("notes.txt", b"recoverable notes")
+        .unwrap();
+    archive.finish().unwrap();
+
+    // The trailing record does not disturb 7z
+    test_with_7z(&archive_path);
+
+    let mut bytes = fs::read(&archive_path).unwrap();
+    bytes[1_000] ^= 0xFF;
+    fs::write(&archive_path, &bytes).unwrap();
+    let damaged = Command::new("7z")
+        .args(["t", archive_path.to_str().unwrap()])
+        .output()
+        .expect("failed to run 7z");
+    assert!(!damaged.status.success());
+
+    let repaired = sevenzip_mt::repair(
+        fs::File::open(&archive_path).unwrap(),
+        fs::File::create(&repaired_path).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(repaired, 1);
+
+    test_with_7z(&repaired_path);
+    extract_with_7z(&repaired_path, &extract_dir);
+    assert_eq!(fs::read(extract_dir.join("data.bin")).unwrap(), content);
+}
+
+#[test]
+fn test_recovery_percent_above_100_is_rejected() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_recovery_percent(101);
+    archive.add_bytes("a.txt", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn test_compressed_header_with_many_files() {
+    let dir = TempDir::new().unwrap();
+
+    let build = |path: &Path, compress_header: Option<bool>| {
+        let file = fs::File::create(path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(true);
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            ..Default::default()
+        });
+        if let Some(compress) = compress_header {
+            archive.set_compress_header(compress);
+        }
+        for i in 0..5_000 {
+            let name = format!("tree/{:02}/file_{i:05}.txt", i % 50);
+            archive
+                .add_bytes(&name, format!("{i}\n").as_bytes())
+                .unwrap();
+        }
+        archive.finish().unwrap();
+        fs::metadata(path).unwrap().len()
+    };
+
+    let encoded_path = dir.path().join("encoded.7z");
+    let encoded_size = build(&encoded_path, None);
+    let plain_size = build(&dir.path().join("plain.7z"), Some(false));
+    assert!(
+        encoded_size * 2 < plain_size,
+        "compressed header archive ({encoded_size}) should be much smaller than plain ({plain_size})"
+    );
+
+    let listing = run_7z(&["l", encoded_path.to_str().unwrap()]);
+    let listed: std::collections::HashSet<&str> = listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .collect();
+    for i in 0..5_000 {
+        let name = format!("tree/{:02}/file_{i:05}.txt", i % 50);
+        assert!(
+            listed.contains(name.as_str()),
+            "{name} missing from listing"
+        );
+    }
+    test_with_7z(&encoded_path);
+}
+
+#[test]
+fn test_bcj_x86_filter_roundtrip() {
+    // The test binary itself is a native executable (ELF/PE/Mach-O)
+    let exe = fs::read(std::env::current_exe().unwrap()).unwrap();
+    let sample = &exe[..exe.len().min(1 << 20)];
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("bcj.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        // Small blocks so the filter state crosses block boundaries
+        block_size: Some(64 * 1024 + 3),
+        filters: vec![Filter::BcjX86],
+        ..Default::default()
+    });
+    archive.add_bytes("program.bin", sample).unwrap();
+    archive.add_bytes("tiny.bin", &[0xE8, 0x01, 0x02]).unwrap();
+    archive.finish().unwrap();
+
+    test_with_7z(&archive_path);
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("program.bin")).unwrap(), sample);
+    assert_eq!(
+        fs::read(out_dir.join("tiny.bin")).unwrap(),
+        [0xE8, 0x01, 0x02]
+    );
+}
+
+#[test]
+fn test_literal_context_params_roundtrip() {
+    // Little-endian 32-bit records: lp=2 and pb=2 align contexts with them
+    let data: Vec<u8> = (0..200_000u32)
+        .flat_map(|i| (i * 7).to_le_bytes())
+        .collect();
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("lclppb.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_config(Lzma2Config {
+        lc: Some(0),
+        lp: Some(2),
+        pb: Some(2),
+        block_size: Some(256 * 1024),
+        ..Default::default()
+    });
+    archive.add_bytes("records.bin", &data).unwrap();
+    archive.finish().unwrap();
+
+    test_with_7z(&archive_path);
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("records.bin")).unwrap(), data);
+}
+
+#[test]
+fn test_literal_context_params_rejected() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        lc: Some(3),
+        lp: Some(2),
+        ..Default::default()
+    });
+    archive.add_bytes("a.bin", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn test_fast_mode_round_trip() {
+    // Repetitive text with some variation, over several blocks
+    let data: Vec<u8> = (0..50_000u32)
+        .flat_map(|i| format!("record {} value {}\n", i % 4099, i * 7 % 1013).into_bytes())
+        .collect();
+
+    let build = |mode| {
+        let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+        archive.set_config(Lzma2Config {
+            preset: 9,
+            block_size: Some(256 << 10),
+            mode: Some(mode),
+            ..Default::default()
+        });
+        archive.add_bytes("data.txt", &data).unwrap();
+        archive.finish_to_vec().unwrap()
+    };
+    let fast = build(Lzma2Mode::Fast);
+    let normal = build(Lzma2Mode::Normal);
+    assert!(
+        normal.len() <= fast.len(),
+        "normal: {} bytes, fast: {} bytes",
+        normal.len(),
+        fast.len()
+    );
+
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(fast.clone())).unwrap();
+    assert!(reader.read_file(0).unwrap() == data);
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("fast.7z");
+    fs::write(&archive_path, &fast).unwrap();
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert!(fs::read(out_dir.join("data.txt")).unwrap() == data);
+}
+
+#[test]
+fn test_higher_nice_len_is_no_larger() {
+    // 200-byte sentences drawn from a small set, each with one byte changed:
+    // matches run far beyond the default nice_len of 64
+    let mut seed = 1u32;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        seed >> 16
+    };
+    let sentences: Vec<Vec<u8>> = (0..64)
+        .map(|_| (0..200).map(|_| b'a' + (next() % 26) as u8).collect())
+        .collect();
+    let data: Vec<u8> = (0..3000)
+        .flat_map(|_| {
+            let mut sentence = sentences[(next() % 64) as usize].clone();
+            sentence[(next() % 200) as usize] = b'#';
+            sentence
+        })
+        .collect();
+
+    let build = |nice_len: Option<u32>, depth_limit: Option<u32>| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_config(Lzma2Config {
+            nice_len,
+            depth_limit,
+            ..Default::default()
+        });
+        archive.add_bytes("data.txt", &data).unwrap();
+        archive.finish().unwrap().into_inner()
+    };
+
+    let default = build(None, None);
+    let tuned = build(Some(273), Some(0));
+    assert!(
+        tuned.len() <= default.len(),
+        "nice_len 273: {} bytes, default: {} bytes",
+        tuned.len(),
+        default.len()
+    );
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("nice_len.7z");
+    fs::write(&archive_path, &tuned).unwrap();
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("data.txt")).unwrap(), data);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        nice_len: Some(300),
+        ..Default::default()
+    });
+    archive.add_bytes("data.txt", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn test_lzma1_codec_roundtrip() {
+    let large: Vec<u8> = (0..600_000u32)
+        .map(|i| (i % 251) as u8 ^ (i >> 12) as u8)
+        .collect();
+    let build = |name: &str, solid: bool| {
+        let dir = TempDir::new().unwrap();
+        let archive_path = dir.path().join(name);
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            // Ignored by LZMA: every file still goes through a single block
+            block_size: Some(64 * 1024),
+            codec: Codec::Lzma1,
+            ..Default::default()
+        });
+        archive.add_bytes("large.bin", &large).unwrap();
+        // Enough entries for an encoded (LZMA-compressed) header
+        for i in 0..100 {
+            let name = format!("dir/small_file_with_a_long_name_{i}.txt");
+            archive
+                .add_bytes(&name, format!("content {i}").as_bytes())
+                .unwrap();
+        }
+        archive.finish().unwrap();
+        (dir, archive_path)
+    };
+
+    for (name, solid) in [("lzma1.7z", false), ("lzma1_solid.7z", true)] {
+        let (dir, archive_path) = build(name, solid);
+        test_with_7z(&archive_path);
+        let out_dir = dir.path().join("out");
+        extract_with_7z(&archive_path, &out_dir);
+        assert_eq!(fs::read(out_dir.join("large.bin")).unwrap(), large);
+        assert_eq!(
+            fs::read_to_string(out_dir.join("dir/small_file_with_a_long_name_42.txt")).unwrap(),
+            "content 42"
+        );
+
+        let file = fs::File::open(&archive_path).unwrap();
+        let mut reader = sevenzip_mt::SevenZipReader::new(file).unwrap();
+        assert_eq!(reader.verify().unwrap(), 101);
+        assert_eq!(reader.read_file(0).unwrap(), large);
+    }
+}
+
+#[test]
+#[cfg(feature = "bzip2")]
+fn test_bzip2_codec_roundtrip() {
+    let text: String = (0..40_000)
+        .map(|i| format!("line {i}: the quick brown fox jumps over the lazy dog\n"))
+        .collect();
+
+    let dir = TempDir::new().unwrap();
+    let text_path = dir.path().join("corpus.txt");
+    fs::write(&text_path, &text).unwrap();
+
+    for solid in [false, true] {
+        let archive_path = dir.path().join(format!("bzip2_{solid}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.set_config(Lzma2Config {
+            // Several blocks per file, each holding several bzip2 blocks
+            block_size: Some(700_000),
+            codec: Codec::Bzip2 { level: 1 },
+            ..Default::default()
+        });
+        archive
+            .add_file(text_path.to_str().unwrap(), "corpus.txt")
+            .unwrap();
+        archive.add_bytes("small.txt", b"tiny").unwrap();
+        archive.finish().unwrap();
+
+        test_with_7z(&archive_path);
+        let out_dir = dir.path().join(format!("out_{solid}"));
+        extract_with_7z(&archive_path, &out_dir);
+        assert_eq!(
+            fs::read_to_string(out_dir.join("corpus.txt")).unwrap(),
+            text
+        );
+        assert_eq!(fs::read(out_dir.join("small.txt")).unwrap(), b"tiny");
+
+        let file = fs::File::open(&archive_path).unwrap();
+        let mut reader = sevenzip_mt::SevenZipReader::new(file).unwrap();
+        assert_eq!(reader.verify().unwrap(), 2);
+    }
+}
+
+#[test]
+#[cfg(feature = "bzip2")]
+fn test_bzip2_rejects_invalid_level() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        codec: Codec::Bzip2 { level: 0 },
+        ..Default::default()
+    });
+    archive.add_bytes("a.txt", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+/// Stores each block verbatim followed by a terminator byte, counting calls.
+#[derive(Debug, Default)]
+struct TerminatingCompressor {
+    calls: std::sync::atomic::AtomicUsize,
+}
+
+impl sevenzip_mt::BlockCompressor for TerminatingCompressor {
+    fn compress(&self, data: &[u8]) -> sevenzip_mt::error::Result<Vec<u8>> {
+        self.calls
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut out = data.to_vec();
+        out.push(0xEE);
+        Ok(out)
+    }
+
+    fn coder_id(&self) -> &[u8] {
+        &[0x7F, 0x01]
+    }
+
+    fn properties(&self) -> Vec<u8> {
+        vec![0x42]
+    }
+}
+
+#[test]
+fn test_custom_block_compressor() {
+    let compressor = std::sync::Arc::new(TerminatingCompressor::default());
+    let large: Vec<u8> = (0..3_000_000u32).map(|i| (i % 253) as u8).collect();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_compress_header(false);
+    archive.set_config(Lzma2Config {
+        block_size: Some(1 << 20),
+        disable_store_fallback: true,
+        compressor: Some(compressor.clone()),
+        ..Default::default()
+    });
+    archive.add_bytes("large.bin", &large).unwrap();
+    archive.add_bytes("small.txt", b"small").unwrap();
+    archive
+        .add_bytes_with_options(
+            "stored.txt",
+            b"stored",
+            sevenzip_mt::EntryOptions {
+                codec: Some(Codec::Copy),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    let bytes = archive.finish().unwrap().into_inner();
+
+    // One call per file, never split into blocks
+    assert_eq!(
+        compressor.calls.load(std::sync::atomic::Ordering::Relaxed),
+        2
+    );
+    // Packed streams follow the SignatureHeader in order
+    let packed = &bytes[32..];
+    assert_eq!(&packed[..large.len()], large);
+    assert_eq!(packed[large.len()], 0xEE);
+    assert_eq!(&packed[large.len() + 1..large.len() + 7], b"small\xEE");
+    assert_eq!(&packed[large.len() + 7..large.len() + 13], b"stored");
+    // Coder record: ID size 2 with properties, the ID, one property byte
+    let header = &bytes[32 + large.len() + 13..];
+    assert!(header
+        .windows(5)
+        .any(|w| w == [0x22, 0x7F, 0x01, 0x01, 0x42]));
+
+    // The header is listed, but the custom method cannot be decoded
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["large.bin", "small.txt", "stored.txt"]);
+    assert_eq!(reader.entries()[0].crc, Some(crc32fast::hash(&large)));
+    assert!(reader.read_file(0).is_err());
+    assert_eq!(reader.read_file(2).unwrap(), b"stored");
+}
+
+/// Fails on any block containing `bad`.
+#[derive(Debug)]
+struct FailingCompressor;
+
+impl sevenzip_mt::BlockCompressor for FailingCompressor {
+    fn compress(&self, data: &[u8]) -> sevenzip_mt::error::Result<Vec<u8>> {
+        if data.windows(3).any(|w| w == b"bad") {
+            return Err(SevenZipError::Compression("encoder failed".to_string()));
+        }
+        Ok(data.to_vec())
+    }
+
+    fn coder_id(&self) -> &[u8] {
+        &[0x7F, 0x02]
+    }
+
+    fn properties(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+#[test]
+fn test_compression_error_names_file() {
+    for streaming in [false, true] {
+        let mut archive = sevenzip_mt::SevenZipWriter::in_memory();
+        archive.set_streaming_output(streaming);
+        archive.set_config(Lzma2Config {
+            compressor: Some(std::sync::Arc::new(FailingCompressor)),
+            ..Default::default()
+        });
+        archive.add_bytes("good.txt", b"fine").unwrap();
+        archive.add_bytes("src/x.rs", b"a bad block").unwrap();
+        match archive.finish_to_vec() {
+            Err(SevenZipError::Compression(msg)) => {
+                assert_eq!(
+                    msg, "file 'src/x.rs': encoder failed",
+                    "streaming: {streaming}"
+                )
+            }
+            other => panic!("expected a compression error, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_lzma2_compressor_as_custom_compressor() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("custom.7z");
+    let text = "Lorem ipsum dolor sit amet. ".repeat(10_000);
+
+    let config = Lzma2Config {
+        preset: 1,
+        ..Default::default()
+    };
+    let compressor = sevenzip_mt::Lzma2Compressor::new(config.clone());
+    let mut archive =
+        sevenzip_mt::SevenZipWriter::new(fs::File::create(&archive_path).unwrap()).unwrap();
+    archive.set_config(Lzma2Config {
+        compressor: Some(std::sync::Arc::new(compressor)),
+        ..config
+    });
+    archive.add_bytes("notes.txt", text.as_bytes()).unwrap();
+    archive.finish().unwrap();
+
+    // Written with the LZMA2 coder ID, so any 7z decoder reads it
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read_to_string(out_dir.join("notes.txt")).unwrap(), text);
+}
+
+#[test]
+fn test_entry_options_override_codec_per_file() {
+    // A compressible "JPEG" makes storing it visible in the archive size
+    let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0];
+    jpeg.extend(std::iter::repeat_n(0u8, 200_000));
+    let text = "Lorem ipsum dolor sit amet. ".repeat(10_000);
+    let exe = fs::read(std::env::current_exe().unwrap()).unwrap();
+    let exe = &exe[..exe.len().min(200_000)];
+    let stored = sevenzip_mt::EntryOptions {
+        codec: Some(Codec::Copy),
+        ..Default::default()
+    };
+
+    for solid in [false, true] {
+        let dir = TempDir::new().unwrap();
+        let archive_path = dir.path().join("mixed.7z");
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            ..Default::default()
+        });
+        archive
+            .add_bytes_with_options("photo.jpg", &jpeg, stored.clone())
+            .unwrap();
+        archive
+            .add_bytes_with_options("thumb.jpg", &jpeg[..1000], stored.clone())
+            .unwrap();
+        archive.add_bytes("notes.txt", text.as_bytes()).unwrap();
+        archive
+            .add_bytes_with_options(
+                "tool.exe",
+                exe,
+                sevenzip_mt::EntryOptions {
+                    codec: None,
+                    filters: vec![Filter::BcjX86],
+                },
+            )
+            .unwrap();
+        let (_, stats) = archive.finish_with_stats().unwrap();
+        // In solid mode, the two stored files share a folder
+        assert_eq!(stats.num_folders, if solid { 3 } else { 4 });
+
+        let archive_size = fs::metadata(&archive_path).unwrap().len() as usize;
+        assert!(archive_size > jpeg.len() + 1000);
+        assert!(archive_size < jpeg.len() + 1000 + text.len() / 10 + exe.len());
+
+        test_with_7z(&archive_path);
+        let out_dir = dir.path().join("out");
+        extract_with_7z(&archive_path, &out_dir);
+        assert_eq!(fs::read(out_dir.join("photo.jpg")).unwrap(), jpeg);
+        assert_eq!(fs::read(out_dir.join("thumb.jpg")).unwrap(), &jpeg[..1000]);
+        assert_eq!(fs::read_to_string(out_dir.join("notes.txt")).unwrap(), text);
+        assert_eq!(fs::read(out_dir.join("tool.exe")).unwrap(), exe);
+    }
+}
+
+#[test]
+fn test_solid_block_limit_splits_folders() {
+    // 20 files of 512 KiB: 10 MiB in folders of at most 2 MiB
+    let files: Vec<Vec<u8>> = (0..20u32)
+        .map(|i| {
+            (0..512 * 1024u32)
+                .map(|j| ((j / 64) ^ (j % 7) ^ i) as u8)
+                .collect()
+        })
+        .collect();
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("solid_limit.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_solid(true);
+    archive.set_solid_block_limit(2 << 20);
+    archive.set_config(Lzma2Config {
+        preset: 0,
+        ..Default::default()
+    });
+    for (i, data) in files.iter().enumerate() {
+        archive
+            .add_bytes(&format!("file_{i:02}.bin"), data)
+            .unwrap();
+    }
+    let (_, stats) = archive.finish_with_stats().unwrap();
+    assert_eq!(stats.num_files, 20);
+    assert_eq!(stats.num_folders, 5);
+
+    test_with_7z(&archive_path);
+    let out_dir = dir.path().join("out");
+    run_7z(&[
+        "x",
+        archive_path.to_str().unwrap(),
+        &format!("-o{}", out_dir.to_str().unwrap()),
+        "-y",
+        "file_13.bin",
+    ]);
+    assert_eq!(fs::read(out_dir.join("file_13.bin")).unwrap(), files[13]);
+}
+
+#[test]
+fn test_bcj_x86_filter_solid_roundtrip() {
+    let exe = fs::read(std::env::current_exe().unwrap()).unwrap();
+    let first = &exe[..exe.len().min(300_000)];
+    let second = &exe[exe.len() / 2..(exe.len() / 2 + 300_000).min(exe.len())];
+
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("bcj_solid.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_solid(true);
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        block_size: Some(100_001),
+        filters: vec![Filter::BcjX86],
+        ..Default::default()
+    });
+    archive.add_bytes("first.bin", first).unwrap();
+    archive.add_bytes("second.bin", second).unwrap();
+    archive.finish().unwrap();
+
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("first.bin")).unwrap(), first);
+    assert_eq!(fs::read(out_dir.join("second.bin")).unwrap(), second);
+}
+
+#[test]
+fn test_delta_filter_improves_ratio_on_ramp_signal() {
+    // 16-bit stereo ramps with slightly different slopes: every sample
+    // differs from the one 4 bytes earlier by a small, nearly constant step.
+    let mut signal = Vec::new();
+    for i in 0..200_000u32 {
+        let left = (i.wrapping_mul(3) + i / 7) as u16;
+        let right = (i.wrapping_mul(5) + i / 11) as u16;
+        signal.extend_from_slice(&left.to_le_bytes());
+        signal.extend_from_slice(&right.to_le_bytes());
+    }
+
+    let dir = TempDir::new().unwrap();
+    let build = |name: &str, filters: Vec<Filter>, solid: bool| {
+        let path = dir.path().join(name);
+        let file = fs::File::create(&path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.set_config(Lzma2Config {
+            preset: 6,
+            block_size: Some(250_001),
+            filters,
+            ..Default::default()
+        });
+        archive.add_bytes("signal.pcm", &signal).unwrap();
+        archive.add_bytes("copy.pcm", &signal[..100_000]).unwrap();
+        archive.finish().unwrap();
+        path
+    };
+
+    let plain = build("plain.7z", vec![], false);
+    let delta = build("delta.7z", vec![Filter::Delta { distance: 4 }], false);
+    let solid = build("solid.7z", vec![Filter::Delta { distance: 4 }], true);
+    let plain_size = fs::metadata(&plain).unwrap().len();
+    let delta_size = fs::metadata(&delta).unwrap().len();
+    assert!(
+        delta_size < plain_size,
+        "delta {delta_size} should beat plain {plain_size}"
+    );
+
+    for archive_path in [&delta, &solid] {
+        let out_dir = archive_path.with_extension("out");
+        extract_with_7z(archive_path, &out_dir);
+        assert_eq!(fs::read(out_dir.join("signal.pcm")).unwrap(), signal);
+        assert_eq!(
+            fs::read(out_dir.join("copy.pcm")).unwrap(),
+            &signal[..100_000]
+        );
+    }
+}
+
+#[test]
+fn test_delta_distance_out_of_range_is_rejected() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        filters: vec![Filter::Delta { distance: 0 }],
+        ..Default::default()
+    });
+    archive.add_bytes("a.bin", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_permissions_preserved() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(&tree).unwrap();
+    let script = tree.join("run.sh");
+    fs::write(&script, "#!/bin/sh\necho hello\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let data = tree.join("data.txt");
+    fs::write(&data, "plain data\n").unwrap();
+    fs::set_permissions(&data, fs::Permissions::from_mode(0o640)).unwrap();
+    let empty = tree.join("empty.sh");
+    fs::write(&empty, "").unwrap();
+    fs::set_permissions(&empty, fs::Permissions::from_mode(0o700)).unwrap();
+
+    let archive_path = dir.path().join("perms.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.add_path_recursive(&tree, "tree").unwrap();
+    archive.add_bytes("memory.txt", b"no mode").unwrap();
+    archive.finish().unwrap();
+
+    test_with_7z(&archive_path);
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    let mode = |name: &str| {
+        fs::metadata(out_dir.join(name))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777
+    };
+    assert_eq!(mode("tree/run.sh"), 0o755);
+    assert_eq!(mode("tree/data.txt"), 0o640);
+    assert_eq!(mode("tree/empty.sh"), 0o700);
+    assert_eq!(
+        fs::read_to_string(out_dir.join("tree/run.sh")).unwrap(),
+        "#!/bin/sh\necho hello\n"
+    );
+}
+
+#[test]
+fn test_strict_validation_accepts_valid_archive() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("strict.7z");
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&archive_path)
+        .unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_strict_validation(true);
+    archive.set_recovery_percent(5);
+    archive.add_directory("dir").unwrap();
+    archive.add_bytes("dir/empty.txt", b"").unwrap();
+    archive
+        .add_bytes("dir/random.bin", &pseudo_random_bytes(300_000, 7))
+        .unwrap();
+    archive
+        .add_bytes("text.txt", "strict mode\n".repeat(1000).as_bytes())
+        .unwrap();
+    archive.finish().unwrap();
+    test_with_7z(&archive_path);
+
+    for solid in [false, true] {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(vec![0u8; 10])).unwrap();
+        archive.set_strict_validation(true);
+        archive.set_solid(solid);
+        archive.add_bytes("a.txt", b"first file").unwrap();
+        archive.add_bytes("b.txt", b"second file").unwrap();
+        let cursor = archive.finish().unwrap();
+        // The writer is left at the end of the archive
+        assert_eq!(cursor.position(), cursor.get_ref().len() as u64);
+    }
+}
+
+/// Cursor that flips one bit of whatever ends up at `corrupt_at`.
+struct CorruptingCursor {
+    inner: Cursor<Vec<u8>>,
+    corrupt_at: u64,
+}
+
+impl Write for CorruptingCursor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let start = self.inner.position();
+        let mut data = buf.to_vec();
+        if (start..start + buf.len() as u64).contains(&self.corrupt_at) {
+            data[(self.corrupt_at - start) as usize] ^= 0x01;
+        }
+        self.inner.write(&data)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Read for CorruptingCursor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for CorruptingCursor {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_strict_validation_catches_corruption() {
+    let build = |strict: bool| {
+        let writer = CorruptingCursor {
+            inner: Cursor::new(Vec::new()),
+            // Inside the stored data of the first (incompressible) file
+            corrupt_at: 32 + 1000,
+        };
+        let mut archive = sevenzip_mt::SevenZipWriter::new(writer).unwrap();
+        archive.set_strict_validation(strict);
+        archive
+            .add_bytes("random.bin", &pseudo_random_bytes(4096, 3))
+            .unwrap();
+        archive.finish()
+    };
+
+    assert!(build(false).is_ok());
+    assert!(matches!(
+        build(true),
+        Err(SevenZipError::CrcMismatch { ref name, .. }) if name == "random.bin"
+    ));
+}
+
+#[test]
+fn test_strict_validation_rejects_encrypted_archives() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_strict_validation(true);
+    archive.set_encryption(AesEncryptConfig::new("secret"));
+    archive.add_bytes("a.txt", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinks_archived_as_links() {
+    let dir = TempDir::new().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::write(tree.join("target.txt"), "link target\n").unwrap();
+    std::os::unix::fs::symlink("target.txt", tree.join("link")).unwrap();
+    std::os::unix::fs::symlink("../missing", tree.join("sub/dangling")).unwrap();
+    std::os::unix::fs::symlink("sub", tree.join("dir_link")).unwrap();
+
+    let archive_path = dir.path().join("links.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    let skipped = archive.add_path_recursive(&tree, "tree").unwrap();
+    assert!(skipped.is_empty());
+    archive.add_symlink("abs_link", "/etc/hostname").unwrap();
+    archive.finish().unwrap();
+
+    test_with_7z(&archive_path);
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+
+    let link_target = |name: &str| {
+        let path = out_dir.join(name);
+        assert!(fs::symlink_metadata(&path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        fs::read_link(path).unwrap()
+    };
+    assert_eq!(link_target("tree/link"), Path::new("target.txt"));
+    assert_eq!(link_target("tree/sub/dangling"), Path::new("../missing"));
+    assert_eq!(link_target("tree/dir_link"), Path::new("sub"));
+    assert_eq!(link_target("abs_link"), Path::new("/etc/hostname"));
+    assert_eq!(
+        fs::read_to_string(out_dir.join("tree/link")).unwrap(),
+        "link target\n"
+    );
+}
+
+#[test]
+fn test_symlink_with_empty_target_is_rejected() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    assert!(matches!(
+        archive.add_symlink("link", ""),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn test_progress_callback_reaches_totals() {
+    use sevenzip_mt::Progress;
+    use std::sync::{Arc, Mutex};
+
+    let updates: Arc<Mutex<Vec<Progress>>> = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&updates);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        block_size: Some(50_000),
+        ..Default::default()
+    });
+    archive.set_num_threads(Some(4));
+    archive.set_progress_callback(Box::new(move |p| sink.lock().unwrap().push(p)));
+    archive
+        .add_bytes("big.bin", &pseudo_random_bytes(400_000, 5))
+        .unwrap();
+    archive.add_bytes("small.txt", b"a small file\n").unwrap();
+    archive.add_bytes("empty.txt", b"").unwrap();
+    archive.add_directory("dir").unwrap();
+    archive.finish().unwrap();
+
+    let updates = updates.lock().unwrap();
+    let total_bytes = 400_000 + 13;
+    // 8 blocks of big.bin + 1 of small.txt, then one update per folder
+    assert_eq!(updates.len(), 9 + 2);
+    assert!(updates
+        .iter()
+        .all(|p| p.total_files == 2 && p.total_bytes == total_bytes));
+    assert!(updates
+        .windows(2)
+        .all(|w| w[0].bytes_done <= w[1].bytes_done && w[0].files_done <= w[1].files_done));
+    let last = updates.last().unwrap();
+    assert_eq!(last.bytes_done, total_bytes);
+    assert_eq!(last.files_done, 2);
+}
+
+#[test]
+fn test_cancel_flag_stops_finish() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&cancel);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        block_size: Some(50_000),
+        ..Default::default()
+    });
+    archive.set_num_threads(Some(1));
+    archive.set_cancel_flag(Arc::clone(&cancel));
+    // Cancel as soon as the first block is compressed
+    archive.set_progress_callback(Box::new(move |_| flag.store(true, Ordering::Relaxed)));
+    archive
+        .add_bytes("big.bin", &pseudo_random_bytes(400_000, 6))
+        .unwrap();
+
+    assert!(matches!(archive.finish(), Err(SevenZipError::Cancelled)));
+    assert!(cancel.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_cancel_flag_set_before_finish() {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_cancel_flag(Arc::new(AtomicBool::new(true)));
+    archive.add_bytes("a.txt", b"hello").unwrap();
+    assert!(matches!(archive.finish(), Err(SevenZipError::Cancelled)));
+}
+
+#[test]
+fn test_reproducible_output_is_byte_identical() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("a.txt"), b"alpha\n").unwrap();
+    fs::write(src.join("sub/b.bin"), pseudo_random_bytes(200_000, 7)).unwrap();
+    fs::write(src.join("sub/empty"), b"").unwrap();
+
+    let build = |encrypt: bool| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_reproducible(true);
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            block_size: Some(64 * 1024),
+            ..Default::default()
+        });
+        if encrypt {
+            archive.set_encryption(AesEncryptConfig {
+                password: "secret".to_string(),
+                iterations_log2: 4,
+            });
+        }
+        archive.add_path_recursive(&src, "src").unwrap();
+        archive.add_bytes("extra.txt", b"in memory\n").unwrap();
+        archive.finish().unwrap().into_inner()
+    };
+
+    let first = build(false);
+    let first_encrypted = build(true);
+    // Touching the inputs must not change the output
+    let touched = SystemTime::now() - Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(src.join("a.txt"))
+        .unwrap()
+        .set_modified(touched)
+        .unwrap();
+    assert_eq!(build(false), first);
+    assert_eq!(build(true), first_encrypted);
+
+    let archive_path = dir.path().join("encrypted.7z");
+    fs::write(&archive_path, &first_encrypted).unwrap();
+    run_7z(&["t", archive_path.to_str().unwrap(), "-psecret"]);
+
+    let archive = sevenzip_mt::SevenZipReader::new(Cursor::new(first)).unwrap();
+    assert!(archive.entries().iter().all(|e| e.modified_time.is_none()));
+}
+
+#[test]
+fn test_reproducible_encryption_ivs_follow_folder_content() {
+    let build = |content: &[u8]| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_reproducible(true);
+        archive.set_compress_header(false);
+        archive.set_encryption(AesEncryptConfig {
+            password: "secret".to_string(),
+            iterations_log2: 4,
+        });
+        archive.add_bytes("data.bin", content).unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+
+        // AES coder ID, then 18 property bytes: two flag bytes and the IV
+        let coder = [0x06, 0xF1, 0x07, 0x01, 18];
+        let at = bytes
+            .windows(coder.len())
+            .rposition(|w| w == coder)
+            .expect("AES coder in the header");
+        bytes[at + coder.len() + 2..at + coder.len() + 18].to_vec()
+    };
+
+    let iv = build(b"first archive");
+    assert_eq!(build(b"first archive"), iv);
+    // Same password, so the same key: the IV must still differ
+    assert_ne!(build(b"second archive"), iv);
+}
+
+#[test]
+fn test_multi_volume_archive() {
+    let dir = TempDir::new().unwrap();
+    let base = dir.path().join("split.7z");
+    let extract_dir = dir.path().join("extracted");
+    // Incompressible, so the archive really spans several volumes
+    let content = pseudo_random_bytes(5 * 1024 * 1024, 8);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::create_volumes(&base, 1024 * 1024).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        ..Default::default()
+    });
+    archive.set_strict_validation(true);
+    archive.add_bytes("big.bin", &content).unwrap();
+    let volumes = archive.finish().unwrap().volume_paths();
+
+    assert_eq!(volumes.len(), 6);
+    for volume in &volumes[..5] {
+        assert_eq!(fs::metadata(volume).unwrap().len(), 1024 * 1024);
+    }
+    assert!(volumes[0].to_str().unwrap().ends_with("split.7z.001"));
+    assert!(!dir.path().join("split.7z.007").exists());
+
+    let first = volumes[0].to_str().unwrap();
+    run_7z(&["t", first]);
+    extract_with_7z(&volumes[0], &extract_dir);
+    assert_eq!(fs::read(extract_dir.join("big.bin")).unwrap(), content);
+}
+
+#[test]
+fn test_open_append_adds_entries() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("append.7z");
+    let extract_dir = dir.path().join("extracted");
+    let big = pseudo_random_bytes(300_000, 9);
+
+    let mut archive =
+        sevenzip_mt::SevenZipWriter::new(fs::File::create(&archive_path).unwrap()).unwrap();
+    archive.set_solid(true);
+    archive.set_compress_header(true);
+    archive.add_bytes("first.txt", b"first file\n").unwrap();
+    archive.add_bytes("second.bin", &big).unwrap();
+    archive.add_bytes("empty.txt", b"").unwrap();
+    archive.add_directory("dir").unwrap();
+    archive.finish().unwrap();
+
+    let open = || {
+        fs::File::options()
+            .read(true)
+            .write(true)
+            .open(&archive_path)
+            .unwrap()
+    };
+
+    // Appending nothing leaves the archive untouched
+    let before = fs::read(&archive_path).unwrap();
+    let archive = sevenzip_mt::SevenZipWriter::open_append(open()).unwrap();
+    let (_, stats) = archive.finish_with_stats().unwrap();
+    assert_eq!(stats.num_files, 0);
+    assert_eq!(fs::read(&archive_path).unwrap(), before);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::open_append(open()).unwrap();
+    archive.add_bytes("dir/third.txt", b"appended\n").unwrap();
+    archive.add_bytes("fourth.bin", &big[..1000]).unwrap();
+    archive.add_directory("new_dir").unwrap();
+    let mut file = archive.finish().unwrap();
+    let end = file.stream_position().unwrap();
+    file.set_len(end).unwrap();
+    drop(file);
+
+    let mut reader =
+        sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "first.txt",
+            "second.bin",
+            "empty.txt",
+            "dir",
+            "dir/third.txt",
+            "fourth.bin",
+            "new_dir"
+        ]
+    );
+    assert_eq!(reader.verify().unwrap(), 4);
+
+    test_with_7z(&archive_path);
+    extract_with_7z(&archive_path, &extract_dir);
+    assert_eq!(
+        fs::read(extract_dir.join("first.txt")).unwrap(),
+        b"first file\n"
+    );
+    assert_eq!(fs::read(extract_dir.join("second.bin")).unwrap(), big);
+    assert_eq!(
+        fs::read(extract_dir.join("dir/third.txt")).unwrap(),
+        b"appended\n"
+    );
+    assert_eq!(
+        fs::read(extract_dir.join("fourth.bin")).unwrap(),
+        &big[..1000]
+    );
+    assert!(extract_dir.join("new_dir").is_dir());
+}
+
+#[test]
+fn test_open_append_rejects_non_archive() {
+    let result = sevenzip_mt::SevenZipWriter::open_append(Cursor::new(b"not a 7z file".repeat(4)));
+    assert!(matches!(result, Err(SevenZipError::HeaderError(_))));
+}
+
+#[test]
+fn test_estimated_peak_memory_scales_with_threads() {
+    let config = Lzma2Config {
+        dict_size: Some(16 << 20),
+        ..Default::default()
+    };
+    let per_thread = config.estimated_memory_per_thread();
+    assert!(per_thread > (16 << 20) * 11);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(config);
+    archive.add_bytes("data.bin", &[7u8; 100_000]).unwrap();
+
+    let one = archive.estimated_peak_memory(Some(1)).unwrap();
+    let four = archive.estimated_peak_memory(Some(4)).unwrap();
+    assert_eq!(one, 100_000 + per_thread);
+    assert_eq!(four - one, 3 * per_thread);
+    assert!(archive.estimated_peak_memory(None).unwrap() >= one);
+}
+
+#[test]
+fn test_cli_expands_globs() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join("src/sub")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), b"fn main() {}\n").unwrap();
+    fs::write(dir.path().join("src/notes.txt"), b"notes\n").unwrap();
+    fs::write(dir.path().join("src/sub/lib.rs"), b"pub fn f() {}\n").unwrap();
+    fs::write(dir.path().join("README"), b"readme\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+        .current_dir(dir.path())
+        .args(["out.7z", "src/**/*.rs", "README"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let archive_path = dir.path().join("out.7z");
+    let reader = sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["src/main.rs", "src/sub/lib.rs", "README"]);
+    test_with_7z(&archive_path);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+        .current_dir(dir.path())
+        .args(["none.7z", "src/*.java"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no files match pattern 'src/*.java'"));
+}
+
+#[test]
+fn test_cli_recursive_directory() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("mydir");
+    fs::create_dir_all(root.join("sub/deep")).unwrap();
+    fs::write(root.join("top.txt"), b"top\n").unwrap();
+    fs::write(root.join("sub/a.txt"), b"a\n").unwrap();
+    fs::write(root.join("sub/deep/b.log"), b"b\n").unwrap();
+    fs::write(root.join("sub/deep/c.txt"), b"c\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+        .current_dir(dir.path())
+        .args(["out.7z", "mydir/", "-r", "-x", "*.log"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Names are relative to the directory's parent
+    let archive_path = dir.path().join("out.7z");
+    let listing = run_7z(&["l", archive_path.to_str().unwrap()]);
+    let listed: std::collections::HashSet<&str> = listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .collect();
+    for name in [
+        "mydir",
+        "mydir/top.txt",
+        "mydir/sub",
+        "mydir/sub/a.txt",
+        "mydir/sub/deep/c.txt",
+    ] {
+        assert!(listed.contains(name), "{name} missing from:\n{listing}");
+    }
+    assert!(!listed.contains("mydir/sub/deep/b.log"));
+    test_with_7z(&archive_path);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+        .current_dir(dir.path())
+        .args(["plain.7z", "mydir"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("mydir is a directory (use --recursive to add its contents)"));
+}
+
+#[test]
+fn test_control_characters_in_names_are_rejected() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    for name in ["bad\0name.txt", "two\nlines.txt"] {
+        let err = archive.add_bytes(name, b"data").unwrap_err();
+        assert!(matches!(err, SevenZipError::InvalidState(_)));
+        assert!(err.to_string().contains("control character"), "{err}");
+    }
+    // Rejected names are not claimed
+    archive.add_bytes("good.txt", b"data").unwrap();
+    let cursor = archive.finish().unwrap();
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    assert_eq!(reader.entries().len(), 1);
+}
+
+#[test]
+fn test_reserved_windows_names() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.add_bytes("CON", b"console").unwrap();
+    archive.add_bytes("dir/aux.txt", b"aux").unwrap();
+    archive.add_bytes("console.txt", b"fine").unwrap();
+    assert_eq!(
+        archive.name_warnings(),
+        [
+            "archive name 'CON' uses the reserved Windows device name 'CON'",
+            "archive name 'dir/aux.txt' uses the reserved Windows device name 'aux.txt'",
+        ]
+    );
+    // A rejected duplicate is never queued, so it adds no warning
+    assert!(matches!(
+        archive.add_bytes("CON", b"again"),
+        Err(SevenZipError::DuplicateEntry(_))
+    ));
+    assert_eq!(archive.name_warnings().len(), 2);
+    archive.finish().unwrap();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_strict_names(true);
+    let err = archive.add_bytes("lpt1.log", b"printer").unwrap_err();
+    assert!(matches!(err, SevenZipError::InvalidState(_)));
+    assert!(err.to_string().contains("reserved Windows device name"));
+    assert!(archive.name_warnings().is_empty());
+}
+
+#[cfg(windows)]
+#[test]
+fn test_windows_read_only_attribute() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("locked.txt");
+    fs::write(&path, b"read only").unwrap();
+    let mut permissions = fs::metadata(&path).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&path, permissions).unwrap();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive
+        .add_file(path.to_str().unwrap(), "locked.txt")
+        .unwrap();
+    let cursor = archive.finish().unwrap();
+
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    let attributes = reader.entries()[0].attributes.unwrap();
+    assert_eq!(attributes & 0x01, 0x01, "read-only bit missing");
+    // No Unix mode is claimed for Windows files
+    assert_eq!(attributes & 0x8000, 0);
+}
+
+#[test]
+fn test_strip_components() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("project");
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("a.txt"), b"a\n").unwrap();
+    fs::write(root.join("src/x.rs"), b"fn x() {}\n").unwrap();
+
+    let names_with = |n: usize| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_path_strip_components(n);
+        let skipped = archive
+            .add_path_recursive(&root, "home/me/project")
+            .unwrap();
+        let cursor = archive.finish().unwrap();
+        let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+        let names: Vec<String> = reader.entries().iter().map(|e| e.name.clone()).collect();
+        (names, skipped)
+    };
+
+    let (names, skipped) = names_with(0);
+    assert_eq!(
+        names,
+        [
+            "home/me/project/a.txt",
+            "home/me/project/src/x.rs",
+            "home/me/project",
+            "home/me/project/src"
+        ]
+    );
+    assert!(skipped.is_empty());
+
+    let (names, skipped) = names_with(2);
+    assert_eq!(
+        names,
+        [
+            "project/a.txt",
+            "project/src/x.rs",
+            "project",
+            "project/src"
+        ]
+    );
+    assert!(skipped.is_empty());
+
+    // a.txt and both directories have no components left
+    let (names, skipped) = names_with(4);
+    assert_eq!(names, ["x.rs"]);
+    assert_eq!(skipped, [root.join("a.txt")]);
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_path_strip_components(2);
+    assert!(matches!(
+        archive.add_bytes("a/b", b"data"),
+        Err(SevenZipError::StrippedAway(name)) if name == "a/b"
+    ));
+    archive.add_bytes("a/b/c", b"data").unwrap();
+
+    // The CLI names entries by their path as given before stripping
+    let output = Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+        .current_dir(dir.path())
+        .args([
+            "out.7z",
+            "project/src/x.rs",
+            "project/a.txt",
+            "--strip-components",
+            "2",
+        ])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Skipped project/a.txt"), "{stderr}");
+    let archive_path = dir.path().join("out.7z");
+    let reader = sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["x.rs"]);
+    test_with_7z(&archive_path);
+}
+
+#[test]
+fn test_add_path_recursive_with_excludes() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path().join("project");
+    fs::create_dir_all(root.join("src/skip")).unwrap();
+    fs::create_dir_all(root.join("skip/inner")).unwrap();
+    fs::write(root.join("main.rs"), b"fn main() {}\n").unwrap();
+    fs::write(root.join("build.log"), b"log\n").unwrap();
+    fs::write(root.join("src/lib.rs"), b"pub fn f() {}\n").unwrap();
+    fs::write(root.join("src/debug.log"), b"log\n").unwrap();
+    fs::write(root.join("src/skip/a.txt"), b"a\n").unwrap();
+    fs::write(root.join("skip/inner/b.txt"), b"b\n").unwrap();
+    // A file named like the excluded directory is kept
+    fs::write(root.join("src/skip.txt"), b"kept\n").unwrap();
+
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_exclude_patterns(vec!["*.log".to_string(), "skip/".to_string()]);
+    archive.add_path_recursive(&root, "").unwrap();
+    let cursor = archive.finish().unwrap();
+
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    // Directories have no data, so they are listed after the files
+    assert_eq!(names, ["main.rs", "src/lib.rs", "src/skip.txt", "src"]);
+}
+
+#[test]
+fn test_verify_after_write_catches_corruption() {
+    let build = |verify: bool, corrupt_at: u64| {
+        let writer = CorruptingCursor {
+            inner: Cursor::new(Vec::new()),
+            corrupt_at,
+        };
+        let mut archive = sevenzip_mt::SevenZipWriter::new(writer).unwrap();
+        archive.set_verify_after_write(verify);
+        archive
+            .add_bytes("text.txt", b"compressible\n".repeat(500).as_slice())
+            .unwrap();
+        // Incompressible, so it is stored and a flipped bit cannot break decoding
+        archive
+            .add_bytes("random.bin", &pseudo_random_bytes(4096, 3))
+            .unwrap();
+        archive.finish()
+    };
+
+    // Both folders intact (corruption beyond the end of the archive)
+    assert!(build(true, u64::MAX).is_ok());
+    let clean = build(false, u64::MAX).unwrap().inner.into_inner();
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(clean)).unwrap();
+    let text_packed = reader.entries()[0].compressed_size;
+
+    let corrupt_stored = 32 + text_packed + 100;
+    assert!(build(false, corrupt_stored).is_ok());
+    assert!(matches!(
+        build(true, corrupt_stored),
+        Err(SevenZipError::VerificationFailed { folder: 1, .. })
+    ));
+
+    // Damage in LZMA2 data may also make it undecodable
+    assert!(matches!(
+        build(true, 32 + text_packed / 2),
+        Err(SevenZipError::VerificationFailed { folder: 0, .. } | SevenZipError::Compression(_))
+    ));
+}
+
+#[test]
+fn test_verify_after_write_rejects_encrypted_archives() {
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_verify_after_write(true);
+    archive.set_encryption(AesEncryptConfig::new("secret"));
+    archive.add_bytes("a.txt", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+}
+
+#[test]
+fn test_max_blocks_in_flight_matches_unbounded() {
+    let data: Vec<u8> = (0..600_000u32).map(|i| (i * 31 / 7) as u8).collect();
+    let build = |max: Option<usize>, streaming: bool| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_num_threads(Some(4));
+        archive.set_streaming_output(streaming);
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            block_size: Some(1 << 16),
+            ..Default::default()
+        });
+        if let Some(max) = max {
+            archive.set_max_blocks_in_flight(max);
+        }
+        archive.add_bytes("data.bin", &data).unwrap();
+        archive.add_bytes("small.txt", b"small file").unwrap();
+        archive.finish().map(|output| output.into_inner())
+    };
+
+    let unbounded = build(None, false).unwrap();
+    assert_eq!(build(Some(1), false).unwrap(), unbounded);
+    assert_eq!(build(Some(3), false).unwrap(), unbounded);
+    assert_eq!(build(Some(1), true).unwrap(), unbounded);
+    assert!(matches!(
+        build(Some(0), false),
+        Err(SevenZipError::InvalidState(_))
+    ));
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(unbounded)).unwrap();
+    assert_eq!(reader.verify().unwrap(), 2);
+}
+
+#[test]
+fn test_pack_crc_recorded_and_checked() {
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("pack_crc.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    archive.set_pack_crc(true);
+    archive.set_compress_header(false);
+    archive
+        .add_bytes("a.txt", &b"pack stream checksums\n".repeat(500))
+        .unwrap();
+    archive.finish().unwrap();
+    test_with_7z(&archive_path);
+
+    let bytes = fs::read(&archive_path).unwrap();
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(reader.verify_pack_crcs().unwrap(), 1);
+
+    // The only packed stream runs from the signature header to the header
+    let next_header_offset = u64::from_le_bytes(bytes[12..20].try_into().unwrap()) as usize;
+    let header_start = 32 + next_header_offset;
+    let packed_crc = crc32fast::hash(&bytes[32..header_start]);
+    assert!(bytes[header_start..]
+        .windows(4)
+        .any(|w| w == packed_crc.to_le_bytes()));
+
+    let mut corrupt = bytes;
+    corrupt[40] ^= 0x01;
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(corrupt)).unwrap();
+    assert!(matches!(
+        reader.verify_pack_crcs(),
+        Err(SevenZipError::VerificationFailed { folder: 0, .. })
+    ));
+}
+
+#[test]
+fn test_auto_dict_shrinks_dictionary() {
+    use sevenzip_mt::compression::lzma2::encode_properties_byte;
+
+    let dir = TempDir::new().unwrap();
+    let data: Vec<u8> = (0..10 << 10).map(|i: u32| (i * 7 / 3) as u8).collect();
+    let build = |auto_dict: bool| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_auto_dict(auto_dict);
+        archive.set_compress_header(false);
+        archive.add_bytes("small.bin", &data).unwrap();
+        let bytes = archive.finish().unwrap().into_inner();
+        let path = dir.path().join(format!("auto_dict_{auto_dict}.7z"));
+        fs::write(&path, &bytes).unwrap();
+        test_with_7z(&path);
+        bytes
+    };
+    // Coder record: LZMA2's 1-byte ID with properties, then its one
+    // property byte
+    let has_coder = |bytes: &[u8], dict_size: u32| {
+        let coder = [0x21, 0x21, 0x01, encode_properties_byte(dict_size)];
+        bytes.windows(4).any(|w| w == coder)
+    };
+
+    // 12 KiB is the smallest dictionary the properties byte stores that
+    // covers 10 KiB
+    let shrunk = build(true);
+    assert!(has_coder(&shrunk, 12 << 10));
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(shrunk)).unwrap();
+    assert_eq!(reader.read_file(0).unwrap(), data);
+    assert!(has_coder(&build(false), 8 << 20));
+}
+
+#[test]
+fn test_file_crc_combined_from_blocks() {
+    let dir = TempDir::new().unwrap();
+    let data: Vec<u8> = (0..1_000_003u32).map(|i| (i * 31 / 7) as u8).collect();
+    let input = dir.path().join("large.bin");
+    fs::write(&input, &data).unwrap();
+    let expected = crc32fast::hash(&data);
+
+    // Deferred to the compression threads, and hashed while reading
+    for (solid, paranoid) in [(false, false), (false, true), (true, false)] {
+        let archive_path = dir.path().join(format!("crc_{solid}_{paranoid}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.set_paranoid(paranoid);
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            block_size: Some(1 << 16),
+            ..Default::default()
+        });
+        archive
+            .add_file(input.to_str().unwrap(), "large.bin")
+            .unwrap();
+        archive.add_bytes("small.txt", b"small file").unwrap();
+        archive.finish().unwrap();
+        test_with_7z(&archive_path);
+
+        let mut reader =
+            sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+        assert_eq!(reader.entries()[0].crc, Some(expected));
+        assert_eq!(reader.verify().unwrap(), 2);
+    }
+}
+
+#[test]
+fn test_archive_from_in_memory_sources_only() {
+    let large: Vec<u8> = (0..300_000u32).map(|i| (i * 31 / 7) as u8).collect();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_config(Lzma2Config {
+        preset: 1,
+        block_size: Some(1 << 16),
+        ..Default::default()
+    });
+    archive.add_directory("docs").unwrap();
+    archive.add_bytes("docs/readme.txt", b"read me").unwrap();
+    archive.add_bytes_owned("large.bin", large.clone()).unwrap();
+    archive
+        .add_reader(
+            "streamed.txt",
+            Box::new(Cursor::new(b"streamed".repeat(100))),
+        )
+        .unwrap();
+    archive.add_symlink("link", "docs/readme.txt").unwrap();
+    let stats = archive.scan().unwrap();
+    assert!(stats.entries.iter().all(|e| e.disk_path.is_none()));
+    let bytes = archive.finish().unwrap().into_inner();
+
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.entries().len(), 5);
+    assert_eq!(reader.verify().unwrap(), 4);
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    let large_index = names.iter().position(|&n| n == "large.bin").unwrap();
+    assert_eq!(reader.read_file(large_index).unwrap(), large);
+}
+
+#[test]
+fn test_many_empty_entries() {
+    const COUNT: usize = 200_000;
+    let dir = TempDir::new().unwrap();
+    let archive_path = dir.path().join("many.7z");
+    let file = fs::File::create(&archive_path).unwrap();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+    // Compressing the multi-megabyte header dominates in debug builds
+    archive.set_compress_header(false);
+    for i in 0..COUNT {
+        archive
+            .add_bytes(&format!("dir{}/empty{i}.txt", i % 100), b"")
+            .unwrap();
+    }
+    let started = std::time::Instant::now();
+    archive.finish().unwrap();
+    // Well under a second in practice; quadratic behavior would take minutes
+    let elapsed = started.elapsed();
+    assert!(elapsed.as_secs() < 30, "finish took {elapsed:?}");
+
+    let listing = run_7z(&["l", archive_path.to_str().unwrap()]);
+    assert!(listing.contains(&format!("{COUNT} files")), "{listing}");
+    let reader = sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    assert_eq!(reader.entries().len(), COUNT);
+    assert_eq!(reader.entries()[COUNT - 1].name, "dir99/empty199999.txt");
+}
+
+#[test]
+fn test_cli_profile() {
+    let dir = TempDir::new().unwrap();
+    let text: Vec<u8> = (0..20_000u32)
+        .flat_map(|i| format!("line {} of {}\n", i % 997, i % 13).into_bytes())
+        .collect();
+    fs::write(dir.path().join("input.txt"), &text).unwrap();
+
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+            .current_dir(dir.path())
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    for profile in ["fast", "ultra"] {
+        let archive_name = format!("{profile}.7z");
+        let output = run(&[&archive_name, "input.txt", "--profile", profile]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        test_with_7z(&dir.path().join(&archive_name));
+    }
+    let fast = fs::metadata(dir.path().join("fast.7z")).unwrap().len();
+    let ultra = fs::metadata(dir.path().join("ultra.7z")).unwrap().len();
+    assert!(ultra <= fast, "ultra {ultra} > fast {fast}");
+
+    assert!(!run(&["x.7z", "input.txt", "--profile", "extreme"])
+        .status
+        .success());
+    assert!(
+        !run(&["x.7z", "input.txt", "--profile", "max", "--level", "9"])
+            .status
+            .success()
+    );
+}
+
+#[test]
+fn test_chunk_size_keeps_archives_extractable() {
+    let data: Vec<u8> = (0..3 << 20u32)
+        .map(|i| ((i / 32) ^ (i % 13)) as u8)
+        .collect();
+    let dir = TempDir::new().unwrap();
+    for chunk_size in [None, Some(1 << 16), Some(300_000), Some(1 << 20)] {
+        let name = format!("chunk_{}", chunk_size.unwrap_or(0));
+        let archive_path = dir.path().join(format!("{name}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_config(Lzma2Config {
+            preset: 1,
+            dict_size: Some(1 << 16),
+            chunk_size,
+            ..Default::default()
+        });
+        archive.add_bytes("data.bin", &data).unwrap();
+        archive.finish().unwrap();
+
+        test_with_7z(&archive_path);
+        let out_dir = dir.path().join(&name);
+        run_7z(&[
+            "x",
+            archive_path.to_str().unwrap(),
+            &format!("-o{}", out_dir.to_str().unwrap()),
+            "-y",
+        ]);
+        assert_eq!(fs::read(out_dir.join("data.bin")).unwrap(), data);
+    }
+}
+
+/// Accepts at most `max_write` bytes per call, like a pipe or socket. Once
+/// `budget` bytes are stored, claims further writes succeeded without
+/// storing them, like a writer swallowing a disk-full error.
+struct ShortWriter {
+    inner: Cursor<Vec<u8>>,
+    max_write: usize,
+    budget: u64,
+}
+
+impl Write for ShortWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.max_write);
+        let stored = len.min(self.budget as usize);
+        self.budget -= stored as u64;
+        self.inner.write_all(&buf[..stored])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ShortWriter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_short_writes_are_retried() {
+    let data = b"short writes, short writes ".repeat(2000);
+    let writer = ShortWriter {
+        inner: Cursor::new(Vec::new()),
+        max_write: 7,
+        budget: u64::MAX,
+    };
+    let mut archive = sevenzip_mt::SevenZipWriter::new(writer).unwrap();
+    archive.add_bytes("data.txt", &data).unwrap();
+    let bytes = archive.finish().unwrap().inner.into_inner();
+
+    let mut reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(reader.read_file(0).unwrap(), data);
+}
+
+#[test]
+fn test_lost_writes_are_detected() {
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    // Lose bytes in the packed data, then in the header
+    for (budget, section) in [(1000, "packed data"), (200_040, "header")] {
+        let writer = ShortWriter {
+            inner: Cursor::new(Vec::new()),
+            max_write: usize::MAX,
+            budget,
+        };
+        let mut archive = sevenzip_mt::SevenZipWriter::new(writer).unwrap();
+        archive.set_config(Lzma2Config {
+            codec: Codec::Copy,
+            ..Default::default()
+        });
+        archive.add_bytes("data.bin", &data[..100_000]).unwrap();
+        archive.add_bytes("more.bin", &data[100_000..]).unwrap();
+        let err = archive.finish().err().expect("lost bytes went unnoticed");
+        let expected = format!("after writing the {section},");
+        assert!(
+            matches!(&err, SevenZipError::InvalidState(msg) if msg.contains(&expected)),
+            "{err}"
+        );
+    }
+}
+
+#[test]
+fn test_finish_with_report() {
+    use sevenzip_mt::archive::header::FolderCodec;
+
+    let small = b"reported ".repeat(1000);
+    let large: Vec<u8> = (0..300_000u32).map(|i| (i % 241) as u8).collect();
+    let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_solid(false);
+    archive.add_bytes("small.txt", &small).unwrap();
+    archive.add_bytes("large.bin", &large).unwrap();
+    archive.add_bytes("empty.txt", b"").unwrap();
+    archive.add_directory("dir").unwrap();
+    let (cursor, report) = archive.finish_with_report().unwrap();
+
+    assert_eq!(report.folders.len(), 2);
+    for folder in &report.folders {
+        assert!(matches!(folder.codec, FolderCodec::Lzma2 { .. }));
+        assert!(folder.dict_size.is_some());
+        assert!(folder.filters.is_empty());
+    }
+    let total: u64 = report.folders.iter().map(|f| f.uncompressed_size).sum();
+    assert_eq!(total, (small.len() + large.len()) as u64);
+
+    let files: Vec<(&str, u64, Option<usize>, bool)> = report
+        .files
+        .iter()
+        .map(|f| (f.name.as_str(), f.size, f.folder, f.is_directory))
+        .collect();
+    assert_eq!(
+        files,
+        [
+            ("small.txt", small.len() as u64, Some(0), false),
+            ("large.bin", large.len() as u64, Some(1), false),
+            ("empty.txt", 0, None, false),
+            ("dir", 0, None, true),
+        ]
+    );
+    assert_eq!(report.files[0].crc, Some(crc32fast::hash(&small)));
+    assert_eq!(report.files[1].crc, report.folders[1].crc);
+    assert_eq!(report.files[2].crc, None);
+
+    let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    assert_eq!(reader.entries().len(), 4);
+}
+
+#[test]
+fn test_cli_reads_stdin() {
+    use std::process::Stdio;
+
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("file.txt"), b"from disk\n").unwrap();
+    let piped: Vec<u8> = (0..1_000_000u32).map(|i| (i % 253) as u8).collect();
+
+    let run_with_stdin = |args: &[&str], input: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+            .current_dir(dir.path())
+            .args(args)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    // Mixed with a regular file, under a chosen name
+    run_with_stdin(
+        &["out.7z", "file.txt", "-", "--stdin-name", "piped.bin"],
+        &piped,
+    );
+    let archive_path = dir.path().join("out.7z");
+    test_with_7z(&archive_path);
+    let out_dir = dir.path().join("out");
+    extract_with_7z(&archive_path, &out_dir);
+    assert_eq!(fs::read(out_dir.join("piped.bin")).unwrap(), piped);
+    assert_eq!(fs::read(out_dir.join("file.txt")).unwrap(), b"from disk\n");
+
+    // On its own, under the default name
+    run_with_stdin(&["only.7z", "-"], b"just stdin");
+    let reader =
+        sevenzip_mt::SevenZipReader::new(fs::File::open(dir.path().join("only.7z")).unwrap())
+            .unwrap();
+    let names: Vec<&str> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["stdin"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_attributes_all_and_partly_defined() {
+    let dir = TempDir::new().unwrap();
+    let disk_file = dir.path().join("disk.txt");
+    fs::write(&disk_file, b"from disk").unwrap();
+
+    for with_bytes_entry in [false, true] {
+        let archive_path = dir.path().join(format!("attrs_{with_bytes_entry}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive
+            .add_file(disk_file.to_str().unwrap(), "disk.txt")
+            .unwrap();
+        if with_bytes_entry {
+            // In-memory entries carry no attributes
+            archive.add_bytes("memory.txt", b"from memory").unwrap();
+        }
+        archive.finish().unwrap();
+
+        test_with_7z(&archive_path);
+        run_7z(&["l", "-slt", archive_path.to_str().unwrap()]);
+        let reader =
+            sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+        let attributes = |name: &str| {
+            reader
+                .entries()
+                .iter()
+                .find(|e| e.name == name)
+                .unwrap()
+                .attributes
+        };
+        // FILE_ATTRIBUTE_UNIX_EXTENSION plus the mode
+        assert_eq!(attributes("disk.txt").unwrap() & 0x8010, 0x8000);
+        if with_bytes_entry {
+            assert_eq!(attributes("memory.txt"), None);
+        }
+    }
+}
+
+#[test]
+fn test_content_defined_blocks_extract() {
+    let mut state = 7u64;
+    let noise: Vec<u8> = (0..3 << 20)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            // Few symbols, so the blocks still compress
+            b'a' + (state >> 60) as u8
+        })
+        .collect();
+    let dir = TempDir::new().unwrap();
+    for solid in [false, true] {
+        let archive_path = dir.path().join(format!("cdc_{solid}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.set_config(Lzma2Config {
+            preset: 0,
+            block_policy: Some(BlockPolicy::ContentDefined {
+                avg_size: 256 << 10,
+                min: 64 << 10,
+                max: 1 << 20,
+            }),
+            ..Default::default()
+        });
+        archive.add_bytes("big.txt", &noise).unwrap();
+        archive.add_bytes("small.txt", b"small").unwrap();
+        archive.add_bytes("tail.txt", &noise[..300_000]).unwrap();
+        archive.finish().unwrap();
+
+        test_with_7z(&archive_path);
+        let out_dir = dir.path().join(format!("out_{solid}"));
+        extract_with_7z(&archive_path, &out_dir);
+        assert_eq!(fs::read(out_dir.join("big.txt")).unwrap(), noise);
+        assert_eq!(fs::read(out_dir.join("small.txt")).unwrap(), b"small");
+        assert_eq!(
+            fs::read(out_dir.join("tail.txt")).unwrap(),
+            &noise[..300_000]
+        );
+    }
+}
+
+#[test]
+fn test_store_crc_disabled() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("disk.bin");
+    let disk: Vec<u8> = (0..500_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&input, &disk).unwrap();
+    let memory = b"stored without a CRC ".repeat(500);
+
+    for solid in [false, true] {
+        let archive_path = dir.path().join(format!("no_crc_{solid}.7z"));
+        let file = fs::File::create(&archive_path).unwrap();
+        let mut archive = sevenzip_mt::SevenZipWriter::new(file).unwrap();
+        archive.set_solid(solid);
+        archive.set_store_crc(false);
+        archive
+            .add_file(input.to_str().unwrap(), "disk.bin")
+            .unwrap();
+        archive.add_bytes("memory.txt", &memory).unwrap();
+        archive.add_bytes("empty.txt", b"").unwrap();
+        let (_, report) = archive.finish_with_report().unwrap();
+        assert!(report.folders.iter().all(|f| f.crc.is_none()));
+        assert!(report.files.iter().all(|f| f.crc.is_none()));
+
+        test_with_7z(&archive_path);
+        let out_dir = dir.path().join(format!("out_{solid}"));
+        extract_with_7z(&archive_path, &out_dir);
+        assert_eq!(fs::read(out_dir.join("disk.bin")).unwrap(), disk);
+        assert_eq!(fs::read(out_dir.join("memory.txt")).unwrap(), memory);
+
+        let mut reader =
+            sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+        assert!(reader.entries().iter().all(|e| e.crc.is_none()));
+        assert_eq!(reader.read_file(0).unwrap(), disk);
+        assert_eq!(reader.read_file(1).unwrap(), memory);
+        // Everything still decodes, but there is nothing to check against
+        assert_eq!(reader.verify().unwrap(), 0);
+        assert_eq!(reader.verify_folders().unwrap(), 0);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_file_names() {
+    use sevenzip_mt::NonUtf8Names;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = TempDir::new().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    let invalid = OsStr::from_bytes(b"caf\xE9 100%.txt");
+    fs::write(tree.join(invalid), b"latin-1 name").unwrap();
+    fs::write(tree.join("plain.txt"), b"plain").unwrap();
+
+    let build = |policy: NonUtf8Names| {
+        let mut archive = sevenzip_mt::SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+        archive.set_non_utf8_names(policy);
+        archive.add_path_recursive(&tree, "")?;
+        archive.finish().map(Cursor::into_inner)
+    };
+    let names = |bytes: Vec<u8>| -> Vec<String> {
+        let reader = sevenzip_mt::SevenZipReader::new(Cursor::new(bytes)).unwrap();
+        reader.entries().iter().map(|e| e.name.clone()).collect()
+    };
+
+    let err = build(NonUtf8Names::Reject).unwrap_err();
+    assert!(err.to_string().contains("non-UTF-8"), "{err}");
+    assert_eq!(
+        names(build(NonUtf8Names::Lossy).unwrap()),
+        ["caf\u{FFFD} 100%.txt", "plain.txt"]
+    );
+    assert_eq!(
+        names(build(NonUtf8Names::PercentEncode).unwrap()),
+        ["caf%E9 100%25.txt", "plain.txt"]
+    );
+
+    // The CLI takes the policy and reads the file through its raw path
+    let run = |args: &[&OsStr]| {
+        Command::new(env!("CARGO_BIN_EXE_sevenzip-mt"))
+            .current_dir(&tree)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let archive = OsStr::new("../cli.7z");
+    assert!(!run(&[archive, invalid]).status.success());
+    let output = run(&[
+        archive,
+        invalid,
+        OsStr::new("--non-utf8"),
+        OsStr::new("percent-encode"),
+    ]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let archive_path = dir.path().join("cli.7z");
+    test_with_7z(&archive_path);
+    let mut reader =
+        sevenzip_mt::SevenZipReader::new(fs::File::open(&archive_path).unwrap()).unwrap();
+    assert_eq!(reader.entries()[0].name, "caf%E9 100%25.txt");
+    assert_eq!(reader.read_file(0).unwrap(), b"latin-1 name");
+}
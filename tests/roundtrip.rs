@@ -0,0 +1,252 @@
+//! Randomized round trips through `SevenZipWriter` and `SevenZipReader`,
+//! and damaged archives fed to the reader. Every case comes from a fixed
+//! seed, so a failure names the seed that reproduces it.
+
+use sevenzip_mt::{Lzma2Config, SevenZipReader, SevenZipWriter};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+/// xorshift64*: small, and identical on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        // Few distinct symbols for some entries, so both compressible and
+        // incompressible data come up
+        let symbols = [2, 16, 256][self.below(3)];
+        (0..len).map(|_| (self.next() % symbols) as u8).collect()
+    }
+}
+
+const NAME_PARTS: [&str; 8] = [
+    "a",
+    "data",
+    "Ünïcödé",
+    "日本語",
+    "emoji 🎉",
+    "with space",
+    "x.tar.gz",
+    "ßøå",
+];
+
+fn random_name(rng: &mut Rng) -> String {
+    let depth = 1 + rng.below(4);
+    let mut parts: Vec<String> = (0..depth)
+        .map(|_| NAME_PARTS[rng.below(NAME_PARTS.len())].to_string())
+        .collect();
+    // Keeps most names distinct without making them unreadable
+    parts.push(format!("{}", rng.below(1000)));
+    parts.join("/")
+}
+
+fn random_size(rng: &mut Rng) -> usize {
+    match rng.below(6) {
+        0 => 0,
+        1 => 1,
+        2 => rng.below(100),
+        3 => rng.below(10_000),
+        4 => rng.below(200_000),
+        _ => 65_536 * (1 + rng.below(3)),
+    }
+}
+
+/// What one entry should read back as: its data, or `None` for a directory.
+type Expected = BTreeMap<String, Option<Vec<u8>>>;
+
+fn write_random_archive(seed: u64) -> (Vec<u8>, Expected) {
+    let mut rng = Rng::new(seed);
+    let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.set_solid(rng.below(2) == 0);
+    archive.set_num_threads(Some(1 + rng.below(4)));
+    archive.set_config(Lzma2Config {
+        preset: rng.below(3) as u32,
+        block_size: Some([4096, 65_536, 1 << 20][rng.below(3)]),
+        ..Default::default()
+    });
+
+    let mut expected = Expected::new();
+    for _ in 0..rng.below(12) {
+        let name = random_name(&mut rng);
+        if expected.contains_key(&name) {
+            continue;
+        }
+        if rng.below(8) == 0 {
+            archive.add_directory(&name).unwrap();
+            expected.insert(name, None);
+        } else {
+            let size = random_size(&mut rng);
+            let data = rng.bytes(size);
+            archive.add_bytes(&name, &data).unwrap();
+            expected.insert(name, Some(data));
+        }
+    }
+    let bytes = archive.finish().unwrap().into_inner();
+    (bytes, expected)
+}
+
+#[test]
+fn test_random_archives_round_trip() {
+    for seed in 0..60 {
+        let (bytes, expected) = write_random_archive(seed);
+        let mut reader =
+            SevenZipReader::new(Cursor::new(bytes)).unwrap_or_else(|e| panic!("seed {seed}: {e}"));
+        assert_eq!(reader.entries().len(), expected.len(), "seed {seed}");
+
+        let mut checked = 0;
+        for index in 0..reader.entries().len() {
+            let entry = &reader.entries()[index];
+            let name = entry.name.clone();
+            let Some(data) = expected.get(&name) else {
+                panic!("seed {seed}: unexpected entry '{name}'");
+            };
+            match data {
+                None => assert!(entry.is_directory, "seed {seed}: {name}"),
+                Some(data) => {
+                    assert!(!entry.is_directory, "seed {seed}: {name}");
+                    assert_eq!(entry.uncompressed_size, data.len() as u64, "seed {seed}");
+                    let crc = (!data.is_empty()).then(|| crc32fast::hash(data));
+                    assert_eq!(entry.crc, crc, "seed {seed}: {name}");
+                    let read = reader
+                        .read_file(index)
+                        .unwrap_or_else(|e| panic!("seed {seed}: {name}: {e}"));
+                    assert!(read == *data, "seed {seed}: {name} differs");
+                    checked += usize::from(!data.is_empty());
+                }
+            }
+        }
+        assert_eq!(reader.verify().unwrap(), checked, "seed {seed}");
+    }
+}
+
+#[test]
+fn test_edge_case_archives_round_trip() {
+    let cases: [&[(&str, &[u8])]; 4] = [
+        // No entries, so no folders and no files info
+        &[],
+        // Only empty files
+        &[("a", b""), ("b/c", b"")],
+        // Single bytes
+        &[("one", b"x"), ("two", b"y")],
+        // Empty and non-empty mixed
+        &[("empty", b""), ("byte", b"\0"), ("more", b"some data")],
+    ];
+    for (case, files) in cases.iter().enumerate() {
+        for solid in [false, true] {
+            let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+            archive.set_solid(solid);
+            for (name, data) in files.iter() {
+                archive.add_bytes(name, data).unwrap();
+            }
+            let bytes = archive.finish().unwrap().into_inner();
+
+            let mut reader = SevenZipReader::new(Cursor::new(bytes)).unwrap();
+            assert_eq!(reader.entries().len(), files.len(), "case {case}");
+            for index in 0..files.len() {
+                let name = reader.entries()[index].name.clone();
+                let (_, data) = files.iter().find(|(n, _)| *n == name).unwrap();
+                assert_eq!(reader.read_file(index).unwrap(), *data, "case {case}");
+            }
+            reader.verify().unwrap();
+            reader.verify_folders().unwrap();
+        }
+    }
+}
+
+/// Recomputes the next header's CRC and the start header's CRC after the
+/// next header was changed, so the damage reaches the header parser
+/// instead of failing the CRC checks in front of it.
+fn fix_header_crcs(bytes: &mut [u8]) {
+    let field = |bytes: &[u8], at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+    let start = 32 + field(bytes, 12);
+    let end = start.saturating_add(field(bytes, 20));
+    if let Some(header) = bytes.get(start as usize..end.min(usize::MAX as u64) as usize) {
+        let crc = crc32fast::hash(header);
+        bytes[28..32].copy_from_slice(&crc.to_le_bytes());
+    }
+    let crc = crc32fast::hash(&bytes[12..32]);
+    bytes[8..12].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// An archive whose next header is `header`, with valid CRCs.
+fn archive_with_header(header: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C, 0, 4];
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    bytes.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(header);
+    fix_header_crcs(&mut bytes);
+    bytes
+}
+
+/// Reads everything a damaged archive offers, ignoring errors.
+fn read_damaged(damaged: Vec<u8>) {
+    let Ok(mut reader) = SevenZipReader::new(Cursor::new(damaged)) else {
+        return;
+    };
+    for index in 0..reader.entries().len() {
+        let _ = reader.read_file(index);
+    }
+    let _ = reader.verify();
+    let _ = reader.verify_folders();
+}
+
+/// Damaged archives must give an error (or, when the damage misses
+/// everything that is checked, some data), never a panic.
+#[test]
+fn test_damaged_archives_do_not_panic() {
+    for seed in 0..40 {
+        let (bytes, _) = write_random_archive(seed);
+        let mut rng = Rng::new(seed ^ 0x0DA3_A6ED);
+        for _ in 0..50 {
+            let mut damaged = bytes.clone();
+            match rng.below(4) {
+                0 => damaged.truncate(rng.below(bytes.len())),
+                1 => {
+                    let index = rng.below(bytes.len());
+                    damaged[index] ^= 1 << rng.below(8);
+                }
+                2 => {
+                    // Overwrite a few bytes in the header, at the end
+                    let start = bytes.len() - 1 - rng.below(bytes.len().min(200));
+                    for byte in &mut damaged[start..] {
+                        *byte = rng.next() as u8;
+                        if rng.below(4) == 0 {
+                            break;
+                        }
+                    }
+                    fix_header_crcs(&mut damaged);
+                }
+                _ => {
+                    // Flip a bit in the header, keeping its CRCs valid
+                    let header_len = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+                    let index = bytes.len() - 1 - rng.below(header_len as usize);
+                    damaged[index] ^= 1 << rng.below(8);
+                    fix_header_crcs(&mut damaged);
+                }
+            }
+            read_damaged(damaged);
+        }
+    }
+
+    // SubStreamsInfo ahead of the UnpackInfo it describes
+    read_damaged(archive_with_header(&[
+        0x01, 0x04, 0x08, 0x00, 0x06, 0x00, 0x01, 0x09, 0x01, 0x00, 0x07, 0x0B, 0x01, 0x00, 0x01,
+        0x01, 0x00, 0x0C, 0x01, 0x00, 0x00, 0x00,
+    ]));
+}
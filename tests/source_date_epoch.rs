@@ -0,0 +1,48 @@
+//! Kept apart from the other integration tests: setting `SOURCE_DATE_EPOCH`
+//! affects every archive finished in the same process.
+
+use sevenzip_mt::{SevenZipError, SevenZipReader, SevenZipWriter};
+use std::fs;
+use std::io::Cursor;
+use tempfile::TempDir;
+
+#[test]
+fn test_source_date_epoch_clamps_mtimes() {
+    let dir = TempDir::new().unwrap();
+    let disk_file = dir.path().join("a.txt");
+    fs::write(&disk_file, b"from disk").unwrap();
+
+    let epoch = 1_600_000_000u64;
+    let filetime = (epoch + 11_644_473_600) * 10_000_000;
+    std::env::set_var("SOURCE_DATE_EPOCH", epoch.to_string());
+
+    let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive
+        .add_file(disk_file.to_str().unwrap(), "a.txt")
+        .unwrap();
+    archive.add_bytes("b.txt", b"from memory").unwrap();
+    archive.add_bytes("empty.txt", b"").unwrap();
+    archive.add_directory("dir").unwrap();
+    let cursor = archive.finish().unwrap();
+
+    let reader = SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    assert_eq!(reader.entries().len(), 4);
+    for entry in reader.entries() {
+        assert_eq!(entry.modified_time, Some(filetime), "{}", entry.name);
+    }
+
+    std::env::set_var("SOURCE_DATE_EPOCH", "yesterday");
+    let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.add_bytes("b.txt", b"data").unwrap();
+    assert!(matches!(
+        archive.finish(),
+        Err(SevenZipError::InvalidState(_))
+    ));
+
+    std::env::remove_var("SOURCE_DATE_EPOCH");
+    let mut archive = SevenZipWriter::new(Cursor::new(Vec::new())).unwrap();
+    archive.add_bytes("b.txt", b"data").unwrap();
+    let cursor = archive.finish().unwrap();
+    let reader = SevenZipReader::new(Cursor::new(cursor.into_inner())).unwrap();
+    assert_eq!(reader.entries()[0].modified_time, None);
+}